@@ -0,0 +1,112 @@
+// The random numbers of every sampling decision. See src/sampler.rs, which computes the same integers
+
+#ifndef SAMPLER_HLSLI
+#define SAMPLER_HLSLI
+
+// sampler::SamplerKind
+#define SAMPLER_PCG 0
+#define SAMPLER_SOBOL 1
+#define SAMPLER_BLUE_NOISE 2
+
+// The dimensions of the camera ray, and those of every bounce, see sampler::bounce_dimension()
+#define DIMENSION_PIXEL 0
+#define DIMENSION_LENS 1
+#define DIMENSION_DIRECTION 0
+#define DIMENSION_LOBE 1
+#define DIMENSION_LIGHTS 2
+
+#define BLUE_NOISE_SIZE 64
+
+// sampler::blue_noise_tile(), bound by the global root signature
+StructuredBuffer<uint> gBlueNoise : register(t2, space1);
+
+uint bounceDimension(uint bounce, uint dimension) {
+    return (bounce << 16) + dimension + 2;
+}
+
+// See sampler::pcg_hash()
+uint pcgHash(uint v) {
+    uint state = v * 747796405u + 2891336453u;
+    uint word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    return (word >> 22u) ^ word;
+}
+
+uint pixelHash(uint2 pixel, uint v) {
+    return pcgHash(pixel.x ^ pcgHash(pixel.y ^ pcgHash(v)));
+}
+
+// See sampler::unorm2()
+float2 unorm2(uint h0, uint h1) {
+    return float2(h0 >> 8, h1 >> 8) * (1.0 / 16777216.0);
+}
+
+// See sampler::sobol_2d()
+uint2 sobol2d(uint index) {
+    uint y = 0;
+    uint v = 1u << 31;
+    for (uint bit = 0; bit < 32; bit++) {
+        if (index & (1u << bit)) {
+            y ^= v;
+        }
+        v ^= v >> 1;
+    }
+    return uint2(reversebits(index), y);
+}
+
+// See sampler::nested_uniform_scramble()
+uint nestedUniformScramble(uint x, uint seed) {
+    x = reversebits(x) + seed;
+    x ^= x * 0x6c50b47cu;
+    x ^= x * 0xb82f1e52u;
+    x ^= x * 0xc7afe638u;
+    x ^= x * 0x8d22f6e6u;
+    return reversebits(x);
+}
+
+uint blueNoiseRank(uint2 pixel, uint offset, uint shift) {
+    uint x = (pixel.x + (offset >> shift)) % BLUE_NOISE_SIZE;
+    uint y = (pixel.y + (offset >> (shift + 6))) % BLUE_NOISE_SIZE;
+    return gBlueNoise[y * BLUE_NOISE_SIZE + x];
+}
+
+// See sampler::blue_noise_2d()
+uint2 blueNoise2d(uint2 pixel, uint sampleIndex, uint dimension) {
+    uint offset = pcgHash(dimension + 0x3c6ef372u);
+    uint h0 = pcgHash(pixelHash(pixel, sampleIndex) ^ offset);
+    uint x = ((blueNoiseRank(pixel, offset, 0) << 20) | (h0 >> 12)) + sampleIndex * 0xc13fa9a9u;
+    uint y = ((blueNoiseRank(pixel, offset, 12) << 20) | (pcgHash(h0) >> 12)) + sampleIndex * 0x91e10da6u;
+    return uint2(x, y);
+}
+
+// See sampler::Sampler
+struct Sampler {
+    uint kind;
+    uint2 pixel;
+    uint sampleIndex;
+};
+
+Sampler makeSampler(uint kind, uint2 pixel, uint sampleIndex) {
+    Sampler s;
+    s.kind = kind;
+    s.pixel = pixel;
+    s.sampleIndex = sampleIndex;
+    return s;
+}
+
+// Two numbers in [0, 1) for the dimension, see sampler::Sampler::get_2d()
+float2 sample2d(Sampler s, uint dimension) {
+    uint2 h;
+    if (s.kind == SAMPLER_SOBOL) {
+        uint seed = pixelHash(s.pixel, dimension + 0x9e3779b9u);
+        uint2 x = sobol2d(nestedUniformScramble(s.sampleIndex, seed));
+        h = uint2(nestedUniformScramble(x.x, pcgHash(seed ^ 1)), nestedUniformScramble(x.y, pcgHash(seed ^ 2)));
+    } else if (s.kind == SAMPLER_BLUE_NOISE) {
+        h = blueNoise2d(s.pixel, s.sampleIndex, dimension);
+    } else {
+        uint h0 = pcgHash(pixelHash(s.pixel, s.sampleIndex) ^ pcgHash(dimension + 0x3c6ef372u));
+        h = uint2(h0, pcgHash(h0));
+    }
+    return unorm2(h.x, h.y);
+}
+
+#endif
@@ -1,5 +1,9 @@
 RaytracingAccelerationStructure gRtScene : register(t0);
 RWTexture2D<float4> gOutput : register(u0);
+// The running average of the linear color, see gAccumulatedSamples
+RWTexture2D<float4> gAccumulation : register(u1);
+// The exposed and tone-mapped linear color, before gOutput's encoding
+RWTexture2D<float4> gHdrOutput : register(u2);
 
 cbuffer PerFrame : register(b0) {
     float3 A;
@@ -7,41 +11,288 @@ cbuffer PerFrame : register(b0) {
     float3 C;
 }
 
-float3 linearToSrgb(float3 c) {
-    // Based on http://chilliant.blogspot.com/2012/08/srgb-approximations-for-hlsl.html
-    float3 sq1 = sqrt(c);
-    float3 sq2 = sqrt(sq1);
-    float3 sq3 = sqrt(sq2);
-    float3 srgb = 0.662002687 * sq1 + 0.684122060 * sq2 - 0.323583601 * sq3 - 0.0225411470 * c;
-    return srgb;
+#include "sampler.hlsli"
+
+// scene::GEOMETRY_*
+#define GEOMETRY_NORMALS 1
+#define GEOMETRY_TANGENTS 2
+#define GEOMETRY_TEX_COORDS 4
+
+// The geometry of the hit, see shader_table::RootArgument::GeometryConstants and GeometryBuffer. The attributes are
+// only bound when gAttributeFlags has them
+cbuffer GeometryConstants : register(b2) {
+    // 2 or 4 bytes per index of gIndices
+    uint gIndexSize;
+    uint gAttributeFlags;
+}
+StructuredBuffer<float3> gVertices : register(t1);
+ByteAddressBuffer gIndices : register(t2);
+StructuredBuffer<float3> gNormals : register(t3);
+StructuredBuffer<float4> gTangents : register(t4);
+StructuredBuffer<float2> gTexCoords : register(t5);
+
+// light::LightData
+#define LIGHT_DIRECTIONAL 0
+#define LIGHT_POINT 1
+#define LIGHT_SPOT 2
+#define LIGHT_AREA 3
+
+struct Light {
+    float3 position;
+    uint kind;
+    float3 direction;
+    float cosInner;
+    float3 color;
+    float cosOuter;
+    float3 u;
+    float pad0;
+    float3 v;
+    float pad1;
+};
+
+// material::MaterialData
+#define SURFACE_OPAQUE 0
+#define SURFACE_MIRROR 1
+#define SURFACE_GLOSSY 2
+#define SURFACE_DIELECTRIC 3
+
+struct Material {
+    float3 baseColor;
+    float metallic;
+    float3 emission;
+    float roughness;
+    uint normalTexture;
+    uint surface;
+    float ior;
+    uint baseColorTexture;
+    uint metallicRoughnessTexture;
+    uint3 pad;
+};
+
+// See material::NO_TEXTURE and material::MIN_ROUGHNESS
+#define NO_TEXTURE 0xffffffff
+#define MIN_ROUGHNESS 0.05
+
+// The global root signature. Space 1 keeps the registers apart from the local root signatures
+cbuffer SceneConstants : register(b0, space1) {
+    uint gLightCount;
+    // The reflection and refraction rays a path may trace, see pipeline::PipelineDesc::max_depth
+    uint gMaxDepth;
+}
+StructuredBuffer<Light> gLights : register(t0, space1);
+// One per geometry of every instance, starting at InstanceID()
+StructuredBuffer<Material> gMaterials : register(t1, space1);
+// scene::Scene::textures, indexed by the texture indices of Material. sRGB textures are decoded by their format
+Texture2D<float4> gTextures[] : register(t0, space2);
+// Trilinear with wrapping, see texture::Texture::sample()
+SamplerState gTextureSampler : register(s0, space1);
+
+// Mirrors frame::FrameConstants. u and v are scaled to the half-size of the image plane at distance 1
+cbuffer FrameConstants : register(b1) {
+    float3 gCameraPosition;
+    float gLensRadius;
+    float3 gCameraU;
+    float gFocusDistance;
+    float3 gCameraV;
+    float gTMin;
+    float3 gCameraW;
+    float gTMax;
+    uint gSampleIndex;
+    uint gAccumulatedSamples;
+    uint gSamplesPerFrame;
+    // The output transform, see color::ColorSettings. gExposure is the linear scale
+    float gExposure;
+    uint gToneMapper;
+    uint gEncoding;
+    // integrator::IntegratorSettings
+    uint gIntegrator;
+    uint gMaxBounces;
+    // sampler::SamplerKind
+    uint gSampler;
+}
+
+// integrator::Integrator
+#define INTEGRATOR_DIRECT 0
+#define INTEGRATOR_PATH 1
+
+// color::ToneMapper and color::Encoding
+#define TONE_MAPPER_NONE 0
+#define TONE_MAPPER_REINHARD 1
+#define TONE_MAPPER_ACES_FITTED 2
+#define TONE_MAPPER_AGX 3
+#define ENCODING_SRGB 0
+#define ENCODING_REC709 1
+#define ENCODING_PQ 2
+
+// See color::aces_fitted()
+float3 acesFitted(float3 c) {
+    float3x3 inputMat = float3x3(0.59719, 0.35458, 0.04823, 0.07600, 0.90834, 0.01566, 0.02840, 0.13383, 0.83777);
+    float3x3 outputMat = float3x3(1.60475, -0.53108, -0.07367, -0.10208, 1.10813, -0.00605, -0.00327, -0.07276, 1.07602);
+    float3 v = mul(inputMat, c);
+    float3 a = v * (v + 0.0245786) - 0.000090537;
+    float3 b = v * (0.983729 * v + 0.4329510) + 0.238081;
+    return saturate(mul(outputMat, a / b));
+}
+
+// See color::agx()
+float3 agx(float3 c) {
+    float3x3 inset = float3x3(0.842479062253094, 0.0784335999999992, 0.0792237451477643,
+                              0.0423282422610123, 0.878468636469772, 0.0791661274605434,
+                              0.0423756549057051, 0.0784336, 0.879142973793104);
+    float3x3 outset = float3x3(1.19687900512017, -0.0980208811401368, -0.0990297440797205,
+                               -0.0528968517574562, 1.15190312990417, -0.0989611768448433,
+                               -0.0529716355144438, -0.0980434501171241, 1.15107367264116);
+    const float minEv = -12.47393;
+    const float maxEv = 4.026069;
+    float3 v = max(mul(inset, c), 1e-10);
+    v = (clamp(log2(v), minEv, maxEv) - minEv) / (maxEv - minEv);
+    float3 v2 = v * v;
+    float3 v4 = v2 * v2;
+    v = 15.5 * v4 * v2 - 40.14 * v4 * v + 31.96 * v4 - 6.868 * v2 * v + 0.4298 * v2 + 0.1191 * v - 0.00232;
+    return pow(max(mul(outset, v), 0), 2.2);
+}
+
+float3 toneMap(float3 c, uint toneMapper) {
+    switch (toneMapper) {
+    case TONE_MAPPER_REINHARD: return c / (1 + max(c, 0));
+    case TONE_MAPPER_ACES_FITTED: return acesFitted(c);
+    case TONE_MAPPER_AGX: return agx(c);
+    default: return c;
+    }
+}
+
+// Scalar, because a vector ?: needs select() with HLSL 2021 and is component-wise before
+float srgbOetf(float x) {
+    return x <= 0.0031308 ? 12.92 * x : 1.055 * pow(x, 1.0 / 2.4) - 0.055;
+}
+
+float rec709Oetf(float x) {
+    return x < 0.018 ? 4.5 * x : 1.099 * pow(x, 0.45) - 0.099;
+}
+
+// x is the luminance over 10000 nits
+float3 pqOetf(float3 x) {
+    const float m1 = 2610.0 / 16384.0;
+    const float m2 = 2523.0 / 4096.0 * 128.0;
+    const float c1 = 3424.0 / 4096.0;
+    const float c2 = 2413.0 / 4096.0 * 32.0;
+    const float c3 = 2392.0 / 4096.0 * 32.0;
+    float3 p = pow(min(x, 1), m1);
+    return pow((c1 + c2 * p) / (1 + c3 * p), m2);
+}
+
+// See color::encode(). PQ uses Rec.2020 primaries and maps 1.0 to color::PQ_REFERENCE_WHITE nits
+float3 encode(float3 c, uint encoding) {
+    c = max(c, 0);
+    switch (encoding) {
+    case ENCODING_REC709: return float3(rec709Oetf(c.r), rec709Oetf(c.g), rec709Oetf(c.b));
+    case ENCODING_PQ: {
+        float3x3 rec709ToRec2020 = float3x3(0.6274040, 0.3292820, 0.0433136, 0.0690970, 0.9195400, 0.0113612, 0.0163916, 0.0880132, 0.8955950);
+        return pqOetf(mul(rec709ToRec2020, c) * (203.0 / 10000.0));
+    }
+    default: return float3(srgbOetf(c.r), srgbOetf(c.g), srgbOetf(c.b));
+    }
+}
+
+// The sampler of a subsample of this pixel, see cpu_tracer::CpuTracer::sampler()
+Sampler raySampler(uint sampleIndex) {
+    return makeSampler(gSampler, DispatchRaysIndex().xy, sampleIndex);
 }
 
 struct RayPayload {
     float3 color;
+    // The subsample the ray belongs to, seeds the light samples
+    uint sampleIndex;
+    // What the color is multiplied with on its way to the camera, and the reflection and refraction rays before it
+    float3 throughput;
+    uint depth;
+    uint mode;
+    // The surface a RAY_SURFACE ray hit: the position, the index into gMaterials, the outward normal and the base color
+    // with the hit group's tint, the metallic factor and the roughness, all with the textures applied
+    float3 position;
+    uint material;
+    float3 normal;
+    float3 baseColor;
+    float metallic;
+    float roughness;
+    // The ray cone the textures are filtered with: its width at the origin of the ray, which the closest-hit shaders
+    // widen to the hit, and the angle it widens by
+    float coneWidth;
+    float coneSpread;
 };
 
+// The values of RayPayload::mode. The path tracer only asks the closest-hit shaders for the surface
+#define RAY_SHADE 0
+#define RAY_SURFACE 1
+
+// RayPayload::material after a miss
+#define NO_MATERIAL 0xffffffff
+
+// The primary ray through d (-1..1, +y down), see frame::FrameConstants::ray(). s picks the point on the lens
+RayDesc primaryRay(float2 d, float2 s) {
+    RayDesc ray;
+    ray.Origin = gCameraPosition;
+    ray.Direction = normalize(d.x * gCameraU - d.y * gCameraV + gCameraW);
+    if (gLensRadius > 0) {
+        // Every ray through the pixel converges on the plane of focus
+        float3 focusPoint = gCameraPosition + ray.Direction * (gFocusDistance / dot(ray.Direction, gCameraW));
+        float r = gLensRadius * sqrt(s.x);
+        float theta = 2 * 3.14159265 * s.y;
+        ray.Origin += normalize(gCameraU) * (r * cos(theta)) + normalize(gCameraV) * (r * sin(theta));
+        ray.Direction = normalize(focusPoint - ray.Origin);
+    }
+
+    ray.TMin = gTMin;
+    ray.TMax = gTMax;
+    return ray;
+}
+
+// The path tracer, see cpu_tracer::CpuTracer::path_trace(). Defined after the hit programs it shares its helpers with
+float3 pathTrace(RayDesc ray, uint sampleIndex, float coneSpread);
+
 [shader("raygeneration")]
 void rayGen() {
     uint3 launchIndex = DispatchRaysIndex();
     uint3 launchDim = DispatchRaysDimensions();
 
-    float2 crd = float2(launchIndex.xy);
     float2 dims = float2(launchDim.xy);
+    // The angle between the rays of neighbouring pixels, see frame::FrameConstants::pixel_spread()
+    float coneSpread = 2 * length(gCameraV) / dims.y;
 
-    float2 d = ((crd/dims) * 2.f - 1.f);
-    float aspectRatio = dims.x / dims.y;
+    // Every subsample is jittered inside the pixel
+    float3 sum = 0;
+    for (uint i = 0; i < gSamplesPerFrame; i++) {
+        uint sampleIndex = gSampleIndex + i;
+        Sampler s = raySampler(sampleIndex);
+        float2 crd = float2(launchIndex.xy) + sample2d(s, DIMENSION_PIXEL);
+        float2 d = ((crd/dims) * 2.f - 1.f);
+        RayDesc ray = primaryRay(d, sample2d(s, DIMENSION_LENS));
+        if (gIntegrator == INTEGRATOR_PATH) {
+            sum += pathTrace(ray, sampleIndex, coneSpread);
+            continue;
+        }
 
-    RayDesc ray;
-    ray.Origin = float3(0, 0, -2);
-    ray.Direction = normalize(float3(d.x * aspectRatio, -d.y, 1));
+        RayPayload payload;
+        payload.sampleIndex = sampleIndex;
+        payload.throughput = 1;
+        payload.depth = 0;
+        payload.mode = RAY_SHADE;
+        payload.coneWidth = 0;
+        payload.coneSpread = coneSpread;
+        TraceRay( gRtScene, 0 /*rayFlags*/, 0xFF, 0 /* ray index*/, 2, 0, ray, payload );
+        sum += payload.color;
+    }
 
-    ray.TMin = 0;
-    ray.TMax = 100000;
+    // Add the new samples to the running average. After a reset the old content is ignored
+    float3 previous = gAccumulatedSamples > 0 ? gAccumulation[launchIndex.xy].rgb : float3(0, 0, 0);
+    uint total = max(gAccumulatedSamples + gSamplesPerFrame, 1);
+    float3 color = (previous * gAccumulatedSamples + sum) / total;
+    gAccumulation[launchIndex.xy] = float4(color, 1);
 
-    RayPayload payload;
-    TraceRay( gRtScene, 0 /*rayFlags*/, 0xFF, 0 /* ray index*/, 2, 0, ray, payload );
-    float3 col = linearToSrgb(payload.color);
-    gOutput[launchIndex.xy] = float4(col, 1);
+    // The output transform, see color::ColorSettings
+    float3 hdr = toneMap(color * gExposure, gToneMapper);
+    gHdrOutput[launchIndex.xy] = float4(hdr, 1);
+    gOutput[launchIndex.xy] = float4(encode(hdr, gEncoding), 1);
 }
 
 [shader("miss")]
@@ -49,16 +300,405 @@ void miss(inout RayPayload payload) {
     payload.color = float3(0.4, 0.6, 0.2);
 }
 
+struct ShadowPayload {
+    bool hit;
+};
+
+// See light::spot_falloff()
+float spotFalloff(float edge0, float edge1, float x) {
+    if (edge0 == edge1) {
+        return x >= edge1 ? 1 : 0;
+    }
+    return smoothstep(edge0, edge1, x);
+}
+
+// See light::LightData::sample(). Returns the irradiance and sets the direction towards the light and its distance
+float3 sampleLight(Light light, float3 position, float2 u, out float3 direction, out float distance) {
+    if (light.kind == LIGHT_DIRECTIONAL) {
+        direction = light.direction;
+        distance = 100000;
+        return light.color;
+    }
+    float3 target = light.kind == LIGHT_AREA ? light.position + light.u * u.x + light.v * u.y : light.position;
+    float3 offset = target - position;
+    distance = length(offset);
+    direction = offset / distance;
+    if (light.kind == LIGHT_SPOT) {
+        float cosAngle = -dot(direction, light.direction);
+        return light.color * spotFalloff(light.cosOuter, light.cosInner, cosAngle) / (distance * distance);
+    }
+    if (light.kind == LIGHT_AREA) {
+        float cosLight = max(-dot(direction, light.direction), 0);
+        return light.color * cosLight * length(cross(light.u, light.v)) / (3.14159265 * distance * distance);
+    }
+    return light.color / (distance * distance);
+}
+
+// See light::LightData::pdf()
+float lightPdf(Light light, float3 direction, float distance) {
+    float cosLight = -dot(direction, light.direction);
+    if (light.kind != LIGHT_AREA || cosLight <= 0) {
+        return 0;
+    }
+    return distance * distance / (cosLight * length(cross(light.u, light.v)));
+}
+
+// See light::LightData::intersect(). Returns the distance along the normalized direction, or -1 on a miss
+float intersectLight(Light light, float3 origin, float3 direction) {
+    float cosLight = dot(direction, light.direction);
+    if (light.kind != LIGHT_AREA || cosLight >= 0) {
+        return -1;
+    }
+    float t = dot(light.position - origin, light.direction) / cosLight;
+    float3 p = origin + direction * t - light.position;
+    float area = length(cross(light.u, light.v));
+    float a = dot(cross(p, light.v), light.direction) / area;
+    float b = dot(cross(light.u, p), light.direction) / area;
+    return t > 0 && a >= 0 && a <= 1 && b >= 0 && b <= 1 ? t : -1;
+}
+
+// The vertex indices of the hit triangle. ByteAddressBuffer loads are 4-byte aligned, so 16-bit indices are read
+// in pairs from the aligned address below
+uint3 hitIndices() {
+    if (gIndexSize == 4) {
+        return gIndices.Load3(PrimitiveIndex() * 3 * 4);
+    }
+    uint offset = PrimitiveIndex() * 3 * 2;
+    uint2 words = gIndices.Load2(offset & ~3);
+    if (offset & 2) {
+        return uint3(words.x >> 16, words.y & 0xffff, words.y >> 16);
+    }
+    return uint3(words.x & 0xffff, words.x >> 16, words.y & 0xffff);
+}
+
+// The world-space normal of the hit. It points out of counter-clockwise triangles in a right-handed space, whichever
+// side the ray comes from. Geometries with normals interpolate them with the barycentrics, flipped to the outside if
+// need be. See cpu_tracer::CpuTracer::hit_normal()
+float3 hitNormal(float2 barycentrics) {
+    uint3 indices = hitIndices();
+    float3 p0 = gVertices[indices.x];
+    float3 p1 = gVertices[indices.y];
+    float3 p2 = gVertices[indices.z];
+    // Normals transform with the inverse transpose, so we multiply with WorldToObject from the left
+    float3x3 normalMatrix = (float3x3)WorldToObject3x4();
+    float3 face = normalize(mul(cross(p1 - p0, p2 - p0), normalMatrix));
+    if (!(gAttributeFlags & GEOMETRY_NORMALS)) {
+        return face;
+    }
+    float3 n = gNormals[indices.x] * (1 - barycentrics.x - barycentrics.y) + gNormals[indices.y] * barycentrics.x + gNormals[indices.z] * barycentrics.y;
+    float3 shading = mul(n, normalMatrix);
+    if (dot(shading, shading) == 0) {
+        return face;
+    }
+    shading = normalize(shading);
+    return dot(shading, face) < 0 ? -shading : shading;
+}
+
+// See texture::cone_lod(). Non-finite levels fall back to the full resolution like texture::Texture::sample()
+float textureLod(Texture2D<float4> tex, float coneWidth, float cosine, float uvArea, float worldArea) {
+    float width, height;
+    tex.GetDimensions(width, height);
+    float lod = 0.5 * log2(uvArea * width * height / worldArea) + log2(coneWidth / max(abs(cosine), 1e-3));
+    return isfinite(lod) ? lod : 0;
+}
+
+float4 sampleTexture(uint index, float2 uv, float coneWidth, float cosine, float uvArea, float worldArea) {
+    Texture2D<float4> tex = gTextures[NonUniformResourceIndex(index)];
+    return tex.SampleLevel(gTextureSampler, uv, textureLod(tex, coneWidth, cosine, uvArea, worldArea));
+}
+
+// See material::DIELECTRIC_F0
+#define DIELECTRIC_F0 0.04
+
+// See material::ggx_distribution()
+float ggxDistribution(float nDotH, float a2) {
+    float d = nDotH * nDotH * (a2 - 1) + 1;
+    return a2 / (3.14159265 * d * d);
+}
+
+// See material::MaterialData::eval(). The BRDF times pi, n is the normal, v points towards the viewer, l towards the light
+float3 evalBrdf(Material material, float3 baseColor, float3 n, float3 v, float3 l) {
+    float nDotL = dot(n, l);
+    if (nDotL <= 0) {
+        return 0;
+    }
+    float nDotV = max(dot(n, v), 1e-4);
+    float3 h = normalize(v + l);
+    float nDotH = max(dot(n, h), 0);
+    float vDotH = max(dot(v, h), 0);
+
+    float3 f0 = lerp(DIELECTRIC_F0, baseColor, material.metallic);
+    float3 fresnel = f0 + (1 - f0) * pow(1 - vDotH, 5);
+
+    // GGX distribution with the height-correlated Smith visibility term
+    float alpha = material.roughness * material.roughness;
+    float a2 = alpha * alpha;
+    float distribution = ggxDistribution(nDotH, a2);
+    float visibility = 0.5 / (nDotL * sqrt(nDotV * nDotV * (1 - a2) + a2) + nDotV * sqrt(nDotL * nDotL * (1 - a2) + a2));
+
+    float3 diffuse = baseColor * (1 - material.metallic) * (1 - fresnel);
+    return diffuse + fresnel * (distribution * visibility * 3.14159265);
+}
+
+// See material::MaterialData::specular_fresnel()
+float3 specularFresnel(Material material, float3 baseColor, float vDotH) {
+    float3 f0 = lerp(DIELECTRIC_F0, baseColor, material.metallic);
+    return f0 + (1 - f0) * pow(1 - max(vDotH, 0), 5);
+}
+
+// See material::dielectric_fresnel()
+float dielectricFresnel(float cosI, float eta) {
+    float sin2T = eta * eta * (1 - cosI * cosI);
+    if (sin2T >= 1) {
+        return 1;
+    }
+    float cosT = sqrt(1 - sin2T);
+    float rs = (eta * cosI - cosT) / (eta * cosI + cosT);
+    float rp = (cosI - eta * cosT) / (cosI + eta * cosT);
+    return 0.5 * (rs * rs + rp * rp);
+}
+
+// See material::around_normal()
+float3 aroundNormal(float3 n, float cosTheta, float phi) {
+    float sinTheta = sqrt(max(1 - cosTheta * cosTheta, 0));
+    float sign = n.z >= 0 ? 1 : -1;
+    float a = -1 / (sign + n.z);
+    float b = n.x * n.y * a;
+    float3 t = float3(1 + sign * n.x * n.x * a, sign * b, -sign * n.x);
+    float3 bt = float3(b, sign + n.y * n.y * a, -n.y);
+    return normalize(t * (sinTheta * cos(phi)) + bt * (sinTheta * sin(phi)) + n * cosTheta);
+}
+
+// See material::sample_ggx()
+float3 sampleGgx(float3 n, float alpha, float2 u) {
+    float cosTheta = sqrt((1 - u.y) / (1 + (alpha * alpha - 1) * u.y));
+    return aroundNormal(n, cosTheta, 2 * 3.14159265 * u.x);
+}
+
+// See material::sample_cosine()
+float3 sampleCosine(float3 n, float2 u) {
+    return aroundNormal(n, sqrt(1 - u.y), 2 * 3.14159265 * u.x);
+}
+
+// See material::MaterialData::specular_probability()
+float specularProbability(Material material) {
+    return 0.5 + 0.5 * material.metallic;
+}
+
+// See material::MaterialData::sample()
+float3 sampleMaterial(Material material, float3 n, float3 v, float2 u, float lobe) {
+    if (lobe < specularProbability(material)) {
+        return reflect(-v, sampleGgx(n, material.roughness * material.roughness, u));
+    }
+    return sampleCosine(n, u);
+}
+
+// See material::MaterialData::pdf()
+float materialPdf(Material material, float3 n, float3 v, float3 l) {
+    float nDotL = dot(n, l);
+    if (nDotL <= 0) {
+        return 0;
+    }
+    float3 h = normalize(v + l);
+    float alpha = material.roughness * material.roughness;
+    float nDotH = max(dot(n, h), 0);
+    float specular = ggxDistribution(nDotH, alpha * alpha) * nDotH / (4 * max(dot(v, h), 1e-4));
+    float p = specularProbability(material);
+    return p * specular + (1 - p) * nDotL / 3.14159265;
+}
+
+// The material of the hit geometry
+Material hitMaterial() {
+    return gMaterials[InstanceID() + GeometryIndex()];
+}
+
+// The material of the hit with its textures applied, and the outward shading normal with the normal map in `normal`.
+// Widens the ray cone of the payload to the hit, the textures are filtered with it. See
+// cpu_tracer::CpuTracer::hit_surface()
+Material hitSurface(float2 barycentrics, inout RayPayload payload, out float3 normal) {
+    normal = hitNormal(barycentrics);
+    Material material = hitMaterial();
+    payload.coneWidth += payload.coneSpread * RayTCurrent();
+    if (!(gAttributeFlags & GEOMETRY_TEX_COORDS)) {
+        return material;
+    }
+    uint3 indices = hitIndices();
+    float3 b = float3(1 - barycentrics.x - barycentrics.y, barycentrics.x, barycentrics.y);
+    float2 uv0 = gTexCoords[indices.x];
+    float2 uv1 = gTexCoords[indices.y];
+    float2 uv2 = gTexCoords[indices.z];
+    float2 uv = uv0 * b.x + uv1 * b.y + uv2 * b.z;
+
+    // The level of detail, see texture::cone_lod()
+    float3x3 objectToWorld = (float3x3)ObjectToWorld3x4();
+    float3 p0 = mul(objectToWorld, gVertices[indices.x]);
+    float3 dp1 = mul(objectToWorld, gVertices[indices.y]) - p0;
+    float3 dp2 = mul(objectToWorld, gVertices[indices.z]) - p0;
+    float2 duv1 = uv1 - uv0;
+    float2 duv2 = uv2 - uv0;
+    float worldArea = length(cross(dp1, dp2));
+    float uvArea = abs(duv1.x * duv2.y - duv1.y * duv2.x);
+    float cosine = dot(normal, WorldRayDirection());
+
+    if (material.baseColorTexture != NO_TEXTURE) {
+        material.baseColor *= sampleTexture(material.baseColorTexture, uv, payload.coneWidth, cosine, uvArea, worldArea).rgb;
+    }
+    if (material.metallicRoughnessTexture != NO_TEXTURE) {
+        float4 texel = sampleTexture(material.metallicRoughnessTexture, uv, payload.coneWidth, cosine, uvArea, worldArea);
+        material.metallic *= texel.b;
+        material.roughness = max(material.roughness * texel.g, MIN_ROUGHNESS);
+    }
+    if (material.normalTexture != NO_TEXTURE) {
+        // The tangent of the vertices, or the direction of +u across the triangle. The bitangent points up in the
+        // texture, towards -v, like the tangent-space normals of glTF expect
+        float3 tangent;
+        float handedness;
+        if (gAttributeFlags & GEOMETRY_TANGENTS) {
+            float4 t0 = gTangents[indices.x];
+            tangent = mul(objectToWorld, t0.xyz * b.x + gTangents[indices.y].xyz * b.y + gTangents[indices.z].xyz * b.z);
+            // A mirroring transform flips the handedness
+            handedness = t0.w * (determinant(objectToWorld) < 0 ? -1 : 1);
+        } else {
+            float r = 1 / (duv1.x * duv2.y - duv1.y * duv2.x);
+            tangent = (dp1 * duv2.y - dp2 * duv1.y) * r;
+            float3 bitangent = (dp1 * duv2.x - dp2 * duv1.x) * r;
+            handedness = dot(cross(normal, tangent), bitangent) < 0 ? -1 : 1;
+        }
+        float3 t = tangent - normal * dot(normal, tangent);
+        float length2 = dot(t, t);
+        if (length2 > 0 && isfinite(length2)) {
+            t *= rsqrt(length2);
+            float3 m = sampleTexture(material.normalTexture, uv, payload.coneWidth, cosine, uvArea, worldArea).rgb * 2 - 1;
+            float3 mapped = t * m.x + cross(normal, t) * (handedness * m.y) + normal * m.z;
+            if (dot(mapped, mapped) > 0) {
+                normal = normalize(mapped);
+            }
+        }
+    }
+    return material;
+}
+
+// Ambient light, so that surfaces in the shadow of every light aren't black
+#define AMBIENT 0.1
+
+// Paths that would add less than this to the pixel don't trace further
+#define MIN_THROUGHPUT 0.001
+
+// Traces a reflection or refraction ray from a closest-hit shader. `weight` is what the caller multiplies the result
+// with. Returns black once the path reached gMaxDepth
+float3 traceSecondary(float3 origin, float3 direction, float3 weight, RayPayload parent) {
+    float3 throughput = parent.throughput * weight;
+    if (parent.depth >= gMaxDepth || max(throughput.r, max(throughput.g, throughput.b)) < MIN_THROUGHPUT) {
+        return 0;
+    }
+    RayDesc ray;
+    ray.Origin = origin;
+    ray.Direction = direction;
+    ray.TMin = 0.01;
+    ray.TMax = 100000;
+    RayPayload payload;
+    payload.sampleIndex = parent.sampleIndex;
+    payload.throughput = throughput;
+    payload.depth = parent.depth + 1;
+    payload.mode = RAY_SHADE;
+    payload.coneWidth = parent.coneWidth;
+    payload.coneSpread = parent.coneSpread;
+    TraceRay(gRtScene, 0 /*rayFlags*/, 0xFF, 0 /* ray index*/, 2, 0, ray, payload);
+    return payload.color;
+}
+
+// Whether anything blocks the way towards a light sample
+bool traceShadow(float3 origin, float3 direction, float distance) {
+    RayDesc ray;
+    ray.Origin = origin;
+    ray.Direction = direction;
+    ray.TMin = 0.01;
+    ray.TMax = distance - 0.01;
+    ShadowPayload shadowPayload;
+    TraceRay(gRtScene, 0  /*rayFlags*/, 0xFF, 1 /* ray index*/, 0, 1, ray, shadowPayload);
+    return shadowPayload.hit;
+}
+
+// The emission and the reflection of every light that isn't shadowed. See cpu_tracer::CpuTracer::direct_lighting()
+float3 directLighting(Material material, float3 baseColor, float3 position, float3 normal, RayPayload payload) {
+    float3 v = -WorldRayDirection();
+    float3 color = material.emission + baseColor * AMBIENT;
+    Sampler s = raySampler(payload.sampleIndex);
+    for (uint i = 0; i < gLightCount; i++) {
+        float3 direction;
+        float distance;
+        float2 u = sample2d(s, bounceDimension(payload.depth, DIMENSION_LIGHTS + i));
+        float3 e = sampleLight(gLights[i], position, u, direction, distance);
+        float cosSurface = dot(normal, direction);
+        if (cosSurface <= 0 || all(e == 0)) {
+            continue;
+        }
+        if (!traceShadow(position, direction, distance)) {
+            color += e * cosSurface * evalBrdf(material, baseColor, normal, v, direction);
+        }
+    }
+    return color;
+}
+
+// The color of a hit by the surface type of the material. `normal` is the outward normal from hitNormal(). See
+// cpu_tracer::CpuTracer::shade()
+float3 shade(Material material, float3 baseColor, float3 position, float3 normal, RayPayload payload) {
+    float3 d = WorldRayDirection();
+    bool frontFace = dot(normal, d) < 0;
+    float3 n = frontFace ? normal : -normal;
+    float2 u = sample2d(raySampler(payload.sampleIndex), bounceDimension(payload.depth, DIMENSION_DIRECTION));
+    switch (material.surface) {
+    case SURFACE_MIRROR:
+        return material.emission + baseColor * traceSecondary(position, reflect(d, n), baseColor, payload);
+    case SURFACE_GLOSSY: {
+        float3 color = directLighting(material, baseColor, position, n, payload);
+        // Reflect around a microfacet normal, the accumulation averages the blur
+        float3 h = sampleGgx(n, material.roughness * material.roughness, u);
+        float3 direction = reflect(d, h);
+        if (dot(direction, n) > 0) {
+            float3 weight = specularFresnel(material, baseColor, dot(-d, h));
+            color += weight * traceSecondary(position, direction, weight, payload);
+        }
+        return color;
+    }
+    case SURFACE_DIELECTRIC: {
+        // Reflect with the probability of the Fresnel term and refract otherwise, which is always the case on total
+        // internal reflection. Leaving the surface, the ratio of the indices flips
+        float eta = frontFace ? 1 / material.ior : material.ior;
+        if (u.x < dielectricFresnel(dot(-d, n), eta)) {
+            return material.emission + traceSecondary(position, reflect(d, n), 1, payload);
+        }
+        return material.emission + baseColor * traceSecondary(position, refract(d, n, eta), baseColor, payload);
+    }
+    default:
+        return directLighting(material, baseColor, position, n, payload);
+    }
+}
+
+// What a closest-hit shader returns to the path tracer instead of the color
+void reportSurface(inout RayPayload payload, float3 position, float3 normal, Material material, float3 baseColor) {
+    payload.position = position;
+    payload.material = InstanceID() + GeometryIndex();
+    payload.normal = normal;
+    payload.baseColor = baseColor;
+    payload.metallic = material.metallic;
+    payload.roughness = material.roughness;
+}
+
 [shader("closesthit")]
 void triangleChs(inout RayPayload payload, in BuiltInTriangleIntersectionAttributes attribs) {
     float3 barycentrics = float3(1.0 - attribs.barycentrics.x - attribs.barycentrics.y, attribs.barycentrics.x, attribs.barycentrics.y);
-    payload.color = A * barycentrics.x + B * barycentrics.y + C * barycentrics.z;
+    float3 tint = A * barycentrics.x + B * barycentrics.y + C * barycentrics.z;
+    float3 normal;
+    Material material = hitSurface(attribs.barycentrics, payload, normal);
+    float3 posW = WorldRayOrigin() + RayTCurrent() * WorldRayDirection();
+    if (payload.mode == RAY_SURFACE) {
+        reportSurface(payload, posW, normal, material, material.baseColor * tint);
+        return;
+    }
+    payload.color = shade(material, material.baseColor * tint, posW, normal, payload);
 }
 
-struct ShadowPayload {
-    bool hit;
-};
-
 [shader("closesthit")]
 void planeChs(inout RayPayload payload, in BuiltInTriangleIntersectionAttributes attribs) {
     float hitT = RayTCurrent();
@@ -68,17 +708,130 @@ void planeChs(inout RayPayload payload, in BuiltInTriangleIntersectionAttributes
     // Find the world-space hit position
     float3 posW = rayOriginW + hitT * rayDirW;
 
-    // Fire a shadow ray. The direction is hard-coded here, but can be fetched from a constant-buffer
-    RayDesc ray;
-    ray.Origin = posW;
-    ray.Direction = normalize(float3(0.5, 0.5, -0.5));
-    ray.TMin = 0.01;
-    ray.TMax = 100000;
-    ShadowPayload shadowPayload;
-    TraceRay(gRtScene, 0  /*rayFlags*/, 0xFF, 1 /* ray index*/, 0, 1, ray, shadowPayload);
+    float3 normal;
+    Material material = hitSurface(attribs.barycentrics, payload, normal);
+    if (payload.mode == RAY_SURFACE) {
+        reportSurface(payload, posW, normal, material, material.baseColor);
+        return;
+    }
+    payload.color = shade(material, material.baseColor, posW, normal, payload);
+}
+
+// See integrator::power_heuristic()
+float powerHeuristic(float a, float b) {
+    float a2 = a * a;
+    float b2 = b * b;
+    return a2 + b2 > 0 ? a2 / (a2 + b2) : 0;
+}
+
+// See integrator::RUSSIAN_ROULETTE_BOUNCES
+#define RUSSIAN_ROULETTE_BOUNCES 3
+
+// The next-event estimation of a path vertex, see cpu_tracer::CpuTracer::sample_lights(). `surface` is the payload
+// that reported the vertex, n its normal facing v
+float3 sampleLights(Material material, RayPayload surface, float3 n, float3 v) {
+    float3 color = 0;
+    Sampler s = raySampler(surface.sampleIndex);
+    for (uint i = 0; i < gLightCount; i++) {
+        float2 u = sample2d(s, bounceDimension(surface.depth, DIMENSION_LIGHTS + i));
+        float3 direction;
+        float distance;
+        float3 e = sampleLight(gLights[i], surface.position, u, direction, distance);
+        float cosSurface = dot(n, direction);
+        if (cosSurface <= 0 || all(e == 0) || traceShadow(surface.position, direction, distance)) {
+            continue;
+        }
+        float pdf = lightPdf(gLights[i], direction, distance);
+        float weight = pdf > 0 ? powerHeuristic(pdf, materialPdf(material, n, v, direction)) : 1;
+        color += e * cosSurface * evalBrdf(material, surface.baseColor, n, v, direction) * weight;
+    }
+    return color;
+}
+
+float3 pathTrace(RayDesc ray, uint sampleIndex, float coneSpread) {
+    Sampler s = raySampler(sampleIndex);
+    float3 color = 0;
+    float3 throughput = 1;
+    // The pdf of the BRDF sample the ray follows. 0 for the camera ray and after mirrors and glass, which the light
+    // samples can't find, so the lights they hit count fully
+    float brdfPdf = 0;
+    // The ray cone continues from every hit with the same spread, which ignores the curvature of the surfaces
+    float coneWidth = 0;
+    for (uint bounce = 0; ; bounce++) {
+        RayPayload payload;
+        payload.sampleIndex = sampleIndex;
+        payload.throughput = throughput;
+        payload.depth = bounce;
+        payload.mode = RAY_SURFACE;
+        payload.material = NO_MATERIAL;
+        payload.coneWidth = coneWidth;
+        payload.coneSpread = coneSpread;
+        TraceRay(gRtScene, 0 /*rayFlags*/, 0xFF, 0 /* ray index*/, 2, 0, ray, payload);
+
+        // The area lights in front of the hit
+        float hitDistance = payload.material == NO_MATERIAL ? 1e30 : length(payload.position - ray.Origin);
+        for (uint i = 0; i < gLightCount; i++) {
+            float t = intersectLight(gLights[i], ray.Origin, ray.Direction);
+            if (t > 0 && t < hitDistance) {
+                float weight = brdfPdf > 0 ? powerHeuristic(brdfPdf, lightPdf(gLights[i], ray.Direction, t)) : 1;
+                color += throughput * gLights[i].color * weight;
+            }
+        }
+        if (payload.material == NO_MATERIAL) {
+            return color + throughput * payload.color;
+        }
+        Material material = gMaterials[payload.material];
+        material.metallic = payload.metallic;
+        material.roughness = payload.roughness;
+        color += throughput * material.emission;
+        if (bounce >= gMaxBounces) {
+            return color;
+        }
+
+        float3 d = ray.Direction;
+        bool frontFace = dot(payload.normal, d) < 0;
+        float3 n = frontFace ? payload.normal : -payload.normal;
+        float2 lobe = sample2d(s, bounceDimension(bounce, DIMENSION_LOBE));
+        float3 direction;
+        if (material.surface == SURFACE_MIRROR) {
+            throughput *= payload.baseColor;
+            brdfPdf = 0;
+            direction = reflect(d, n);
+        } else if (material.surface == SURFACE_DIELECTRIC) {
+            float eta = frontFace ? 1 / material.ior : material.ior;
+            brdfPdf = 0;
+            if (lobe.x < dielectricFresnel(dot(-d, n), eta)) {
+                direction = reflect(d, n);
+            } else {
+                throughput *= payload.baseColor;
+                direction = refract(d, n, eta);
+            }
+        } else {
+            color += throughput * sampleLights(material, payload, n, -d);
+            float2 u = sample2d(s, bounceDimension(bounce, DIMENSION_DIRECTION));
+            direction = sampleMaterial(material, n, -d, u, lobe.x);
+            brdfPdf = materialPdf(material, n, -d, direction);
+            if (brdfPdf <= 0) {
+                return color;
+            }
+            // The BRDF is evalBrdf() / pi
+            throughput *= evalBrdf(material, payload.baseColor, n, -d, direction) * dot(n, direction) / (3.14159265 * brdfPdf);
+        }
 
-    float factor = shadowPayload.hit ? 0.1 : 1.0;
-    payload.color = float4(0.8f, 0.8f, 0.8f, 1.0f) * factor;
+        // Russian roulette, the surviving paths make up for the terminated ones
+        if (bounce + 1 >= RUSSIAN_ROULETTE_BOUNCES) {
+            float survival = min(max(throughput.r, max(throughput.g, throughput.b)), 0.95);
+            if (lobe.y >= survival) {
+                return color;
+            }
+            throughput /= survival;
+        }
+        ray.Origin = payload.position;
+        ray.Direction = direction;
+        ray.TMin = 0.01;
+        ray.TMax = 100000;
+        coneWidth = payload.coneWidth;
+    }
 }
 
 [shader("closesthit")]
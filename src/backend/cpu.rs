@@ -0,0 +1,159 @@
+use std::time::Instant;
+
+use crate::backend::Backend;
+use crate::bvh::TraversalStats;
+use crate::frame::FrameConstants;
+use crate::cpu_tracer::{CpuTracer, HitGroup, MissProgram};
+use crate::error::{Error, Result};
+use crate::framebuffer::{HdrImage, Image};
+use crate::pipeline::*;
+use crate::profiler::{FrameTimings, Pass, PassTiming};
+use crate::scene::{Instance, Scene};
+use crate::shader_table::{RootArgument, ShaderRecord, ShaderTable, ShaderTableLayout};
+
+// Runs the tutorial on the CPU reference tracer. Pipeline exports and shader records are mapped to the Rust
+// implementations of the programs, so an inconsistent pipeline or shader table fails here just like it would on the GPU.
+// Such errors are reported as Error::InvalidScene
+pub struct CpuBackend {
+    width: u32,
+    height: u32,
+    tracer: CpuTracer,
+    pipeline: Option<PipelineDesc>,
+    output: Image,
+    hdr_output: HdrImage,
+    // Pass timings are measured on the CPU, relative to the creation of the backend
+    start_time: Instant,
+    timings: FrameTimings,
+    finished_timings: Vec<FrameTimings>,
+}
+
+impl CpuBackend {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            tracer: CpuTracer::default(),
+            pipeline: None,
+            output: Image::new(width, height),
+            hdr_output: HdrImage::new(width, height),
+            start_time: Instant::now(),
+            timings: FrameTimings::default(),
+            finished_timings: Vec::new(),
+        }
+    }
+
+    // Runs `f` as `pass` of the current frame
+    fn timed<T>(&mut self, pass: Pass, f: impl FnOnce(&mut Self) -> T) -> T {
+        let start = Instant::now();
+        let result = f(self);
+        let ms = |t: Instant| (t - self.start_time).as_secs_f64() * 1000.0;
+        let timing = PassTiming { pass, start_ms: ms(start), duration_ms: start.elapsed().as_secs_f64() * 1000.0 };
+        self.timings.passes.push(timing);
+        result
+    }
+
+    fn miss_program(record: &ShaderRecord) -> Result<MissProgram> {
+        match record.export.as_str() {
+            MISS_SHADER => Ok(MissProgram::Miss),
+            SHADOW_MISS => Ok(MissProgram::ShadowMiss),
+            other => Err(Error::InvalidScene(format!("{} is not a miss shader", other))),
+        }
+    }
+
+    fn hit_group(pipeline: &PipelineDesc, record: &ShaderRecord) -> Result<HitGroup> {
+        let closest_hit = pipeline.closest_hit(&record.export).ok_or_else(|| Error::InvalidScene(format!("{} is not a hit group", record.export)))?;
+        match (closest_hit, record.root_arguments.as_slice()) {
+            // The constant buffer holds 3 float3, each padded to a float4. The tracer reads the geometry buffers from the
+            // scene itself
+            (TRIANGLE_CHS, [RootArgument::ConstantBuffer(cb), RootArgument::DescriptorTable(_), RootArgument::GeometryConstants { blas, geometry }, ..]) if cb.len() >= 3 => {
+                Ok(HitGroup::Triangle { colors: [cb[0].truncate(), cb[1].truncate(), cb[2].truncate()], geometry: (*blas, *geometry) })
+            }
+            (PLANE_CHS, [RootArgument::DescriptorTable(_), RootArgument::GeometryConstants { blas, geometry }, ..]) => Ok(HitGroup::Plane { geometry: (*blas, *geometry) }),
+            (SHADOW_CHS, _) => Ok(HitGroup::Shadow),
+            (other, _) => Err(Error::InvalidScene(format!("{} has no CPU implementation or the wrong root arguments", other))),
+        }
+    }
+}
+
+impl Backend for CpuBackend {
+    fn build_acceleration_structures(&mut self, scene: &Scene) -> Result<()> {
+        self.tracer.build_acceleration_structures(scene);
+        Ok(())
+    }
+
+    fn create_pipeline(&mut self, pipeline: &PipelineDesc) -> Result<()> {
+        for export in &pipeline.exports {
+            let known = [RAY_GEN_SHADER, MISS_SHADER, SHADOW_MISS, TRIANGLE_CHS, PLANE_CHS, SHADOW_CHS];
+            if !known.contains(&export.as_str()) {
+                return Err(Error::InvalidScene(format!("{} has no CPU implementation", export)));
+            }
+        }
+        for hit_group in &pipeline.hit_groups {
+            if !pipeline.exports.contains(&hit_group.closest_hit) {
+                return Err(Error::InvalidScene(format!("{} imports {} which is not exported", hit_group.name, hit_group.closest_hit)));
+            }
+        }
+        self.tracer.max_recursion_depth = pipeline.max_recursion_depth;
+        self.tracer.max_depth = pipeline.max_depth;
+        self.pipeline = Some(pipeline.clone());
+        Ok(())
+    }
+
+    fn bind_shader_table(&mut self, shader_table: &ShaderTable) -> Result<()> {
+        let pipeline = self.pipeline.as_ref().expect("create_pipeline() must be called before bind_shader_table()");
+        let ray_gen = &shader_table.ray_gen;
+        if ray_gen.export != RAY_GEN_SHADER || !matches!(ray_gen.root_arguments.as_slice(), [RootArgument::DescriptorTable(_), RootArgument::FrameConstants]) {
+            return Err(Error::InvalidScene(format!("the ray-gen record must be {} with the output/TLAS descriptor table and the frame constants", RAY_GEN_SHADER)));
+        }
+        // We index the records directly, but the table must still be one the GPU could use
+        ShaderTableLayout::new(shader_table).map_err(Error::InvalidScene)?;
+        self.tracer.miss_table = shader_table.miss.iter().map(Self::miss_program).collect::<Result<_>>()?;
+        self.tracer.hit_group_table = shader_table.hit_groups.iter().map(|r| Self::hit_group(pipeline, r)).collect::<Result<_>>()?;
+        Ok(())
+    }
+
+    fn refit_tlas(&mut self, instances: &[Instance]) -> Result<()> {
+        self.timed(Pass::TlasRefit, |backend| backend.tracer.refit_tlas(instances));
+        Ok(())
+    }
+
+    fn update_frame_constants(&mut self, constants: &FrameConstants) {
+        self.tracer.frame = *constants;
+    }
+
+    fn dispatch_rays(&mut self) -> Result<()> {
+        (self.output, self.hdr_output) = self.timed(Pass::DispatchRays, |backend| backend.tracer.dispatch_rays(backend.width, backend.height));
+        let timings = std::mem::take(&mut self.timings);
+        self.finished_timings.push(timings);
+        Ok(())
+    }
+
+    fn output_size(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    #[cfg(windows)]
+    fn resize(&mut self, width: u32, height: u32) -> Result<()> {
+        self.width = width;
+        self.height = height;
+        self.output = Image::new(width, height);
+        self.hdr_output = HdrImage::new(width, height);
+        Ok(())
+    }
+
+    fn readback(&mut self) -> Result<Image> {
+        Ok(self.output.clone())
+    }
+
+    fn readback_hdr(&mut self) -> Result<HdrImage> {
+        Ok(self.hdr_output.clone())
+    }
+
+    fn traversal_stats(&self) -> Option<TraversalStats> {
+        Some(self.tracer.stats())
+    }
+
+    fn take_frame_timings(&mut self) -> Vec<FrameTimings> {
+        std::mem::take(&mut self.finished_timings)
+    }
+}
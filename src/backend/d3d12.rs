@@ -0,0 +1,1842 @@
+use windows::{
+    core::*, Win32::Foundation::*, Win32::Graphics::Direct3D::Dxc::*, Win32::Graphics::Direct3D::*,
+    Win32::Graphics::Direct3D12::*, Win32::Graphics::Dxgi::Common::*, Win32::Graphics::Dxgi::*,
+    Win32::System::LibraryLoader::*, Win32::System::Threading::*, Win32::System::WindowsProgramming::*,
+};
+
+use glam::*;
+
+use std::mem::{size_of, size_of_val, ManuallyDrop};
+use std::collections::HashMap;
+use std::ffi::c_void;
+use std::path::{Path, PathBuf};
+
+use crate::backend::{Backend, MAX_FRAMES_IN_FLIGHT};
+use crate::frame::FrameConstants;
+use crate::dxil_cache::{self, CachedDxil};
+use crate::error::{Error, Result};
+use crate::framebuffer::{HdrImage, Image};
+use crate::light::LightData;
+use crate::material::MaterialData;
+use crate::pipeline::{LocalRootSignature, PipelineDesc};
+use crate::profiler::{FrameTimings, Pass, PassTiming};
+use crate::sampler::blue_noise_tile;
+use crate::scene::{Geometry, Instance, Scene};
+use crate::texture::{Texture, TextureFormat};
+use crate::shader_compiler::{parse_diagnostics, CompileOptions, Diagnostic, Severity};
+use crate::shader_table::{align_to, GeometryBuffer, RootArgument, ACCUMULATION_UAV_SLOT, HDR_OUTPUT_UAV_SLOT, OUTPUT_UAV_SLOT, TEXTURE_SRV_SLOT, TLAS_SRV_SLOT, ShaderRecord, ShaderTable, ShaderTableLayout, SHADER_IDENTIFIER_SIZE_IN_BYTES};
+
+const DEFAULT_SWAP_CHAIN_BUFFERS: u32 = 3;
+const RTV_HEAP_SIZE: u32 = 3;
+
+const DEBUG_MODE: bool = true;
+
+// The map_err() adapters for failing calls. Allocation failures are kept apart, they usually mean the scene is too big
+fn api(call: &'static str) -> impl FnOnce(windows::core::Error) -> Error {
+    move |e| Error::Api { call, message: e.to_string() }
+}
+
+fn alloc(call: &'static str) -> impl FnOnce(windows::core::Error) -> Error {
+    move |e| Error::ResourceAllocation { call, message: e.to_string() }
+}
+
+unsafe fn memcpy<T, U>(dst: *mut T, src: *const U, count: usize) {
+    std::ptr::copy_nonoverlapping::<u8>(
+        src as *const _,
+        dst as *mut _,
+        count,
+    );
+}
+
+unsafe fn create_device(factory: IDXGIFactory4) -> Result<ID3D12Device5> {
+    // Reported if no adapter can create a device at all
+    let mut device_error = None;
+    for i in 0.. {
+        // Find the HW adapter. EnumAdapters1() fails once we run out of adapters
+        let Ok(adapter) = factory.EnumAdapters1(i) else { break };
+        let desc = adapter.GetDesc1().map_err(api("GetDesc1()"))?;
+
+        // Skip SW adapters
+        if (DXGI_ADAPTER_FLAG(desc.Flags) & DXGI_ADAPTER_FLAG_SOFTWARE) != DXGI_ADAPTER_FLAG_NONE {
+            continue;
+        }
+        let mut device: Option<ID3D12Device5> = None;
+        match D3D12CreateDevice(&adapter, D3D_FEATURE_LEVEL_12_0, &mut device).map(|_| device.unwrap()) {
+            Ok(device) => {
+                let mut features5 = D3D12_FEATURE_DATA_D3D12_OPTIONS5::default();
+                let featuresupportdatasize = size_of::<D3D12_FEATURE_DATA_D3D12_OPTIONS5>() as u32;
+                device.CheckFeatureSupport(D3D12_FEATURE_D3D12_OPTIONS5, &mut features5 as *mut _ as _, featuresupportdatasize).map_err(api("CheckFeatureSupport()"))?;
+                if features5.RaytracingTier != D3D12_RAYTRACING_TIER_NOT_SUPPORTED {
+                    return Ok(device);
+                }
+                device_error = Some(Error::RaytracingNotSupported);
+            }
+            Err(e) => {
+                device_error.get_or_insert(Error::DeviceCreation(e.to_string()));
+            }
+        }
+    }
+    Err(device_error.unwrap_or_else(|| Error::DeviceCreation("no hardware adapter found".into())))
+}
+
+unsafe fn create_command_queue(device: ID3D12Device5) -> Result<ID3D12CommandQueue> {
+    let mut cq_desc = D3D12_COMMAND_QUEUE_DESC::default();
+    cq_desc.Flags = D3D12_COMMAND_QUEUE_FLAG_NONE;
+    cq_desc.Type = D3D12_COMMAND_LIST_TYPE_DIRECT;
+    device.CreateCommandQueue(&cq_desc as _).map_err(api("CreateCommandQueue()"))
+}
+
+unsafe fn create_dxgi_swap_chain(factory: IDXGIFactory4, hwnd: HWND, width: i32, height: i32, format: DXGI_FORMAT, command_queue: ID3D12CommandQueue) -> Result<IDXGISwapChain3> {
+    let mut swap_chain_desc = DXGI_SWAP_CHAIN_DESC1::default();
+    swap_chain_desc.BufferCount = DEFAULT_SWAP_CHAIN_BUFFERS;
+    swap_chain_desc.Width = width as u32;
+    swap_chain_desc.Height = height as u32;
+    swap_chain_desc.Format = format;
+    swap_chain_desc.BufferUsage = DXGI_USAGE_RENDER_TARGET_OUTPUT;
+    swap_chain_desc.SwapEffect = DXGI_SWAP_EFFECT_FLIP_DISCARD;
+    swap_chain_desc.SampleDesc.Count = 1;
+
+    // CreateSwapChainForHwnd() doesn't accept IDXGISwapChain3 (Why MS? Why?)
+    factory.CreateSwapChainForHwnd(&command_queue, hwnd, &swap_chain_desc, None, None)
+    .map_err(alloc("CreateSwapChainForHwnd()"))?
+    .cast()
+    .map_err(api("IDXGISwapChain1::cast()"))
+}
+
+unsafe fn create_descriptor_heap(device: &ID3D12Device5, count: u32, heap_type: D3D12_DESCRIPTOR_HEAP_TYPE, shader_visible: bool) -> Result<ID3D12DescriptorHeap> {
+    let mut desc = D3D12_DESCRIPTOR_HEAP_DESC::default();
+    desc.NumDescriptors = count;
+    desc.Type = heap_type;
+    desc.Flags = if shader_visible { D3D12_DESCRIPTOR_HEAP_FLAG_SHADER_VISIBLE } else { D3D12_DESCRIPTOR_HEAP_FLAG_NONE };
+    device.CreateDescriptorHeap(&desc).map_err(alloc("CreateDescriptorHeap()"))
+}
+
+unsafe fn create_rtv(device: ID3D12Device5, resource: &ID3D12Resource, rtv_heap: &mut HeapData, format: DXGI_FORMAT) -> D3D12_CPU_DESCRIPTOR_HANDLE {
+    let mut desc = D3D12_RENDER_TARGET_VIEW_DESC::default();
+    desc.ViewDimension = D3D12_RTV_DIMENSION_TEXTURE2D;
+    desc.Format = format;
+    desc.Anonymous.Texture2D.MipSlice = 0;
+    let mut rtv_handle = rtv_heap.heap.GetCPUDescriptorHandleForHeapStart();
+    rtv_handle.ptr += (rtv_heap.used_entries * device.GetDescriptorHandleIncrementSize(D3D12_DESCRIPTOR_HEAP_TYPE_RTV)) as usize;
+    rtv_heap.used_entries += 1;
+    device.CreateRenderTargetView(resource, Some(&desc), rtv_handle);
+    rtv_handle
+}
+
+unsafe fn create_buffer(
+    device: &ID3D12Device5,
+    size: u64,
+    flags: D3D12_RESOURCE_FLAGS,
+    init_state: D3D12_RESOURCE_STATES,
+    heap_props: &D3D12_HEAP_PROPERTIES,
+) -> Result<ID3D12Resource> {
+    let buf_desc = D3D12_RESOURCE_DESC {
+        Dimension: D3D12_RESOURCE_DIMENSION_BUFFER,
+        Alignment: 0,
+        Width: size,
+        Height: 1,
+        DepthOrArraySize: 1,
+        MipLevels: 1,
+        Format: DXGI_FORMAT_UNKNOWN,
+        SampleDesc: DXGI_SAMPLE_DESC { Count: 1, Quality: 0 },
+        Layout: D3D12_TEXTURE_LAYOUT_ROW_MAJOR,
+        Flags: flags,
+    };
+    let mut buffer: Option<ID3D12Resource> = None;
+    device.CreateCommittedResource(heap_props, D3D12_HEAP_FLAG_NONE, &buf_desc, init_state, None, &mut buffer).map_err(alloc("CreateCommittedResource()"))?;
+    Ok(buffer.unwrap())
+}
+
+const UPLOAD_HEAP_PROPS: D3D12_HEAP_PROPERTIES  = D3D12_HEAP_PROPERTIES {
+    Type: D3D12_HEAP_TYPE_UPLOAD,
+    CPUPageProperty: D3D12_CPU_PAGE_PROPERTY_UNKNOWN,
+    MemoryPoolPreference: D3D12_MEMORY_POOL_UNKNOWN,
+    CreationNodeMask: 0,
+    VisibleNodeMask: 0,
+};
+
+const READBACK_HEAP_PROPS: D3D12_HEAP_PROPERTIES = D3D12_HEAP_PROPERTIES {
+    Type: D3D12_HEAP_TYPE_READBACK,
+    CPUPageProperty: D3D12_CPU_PAGE_PROPERTY_UNKNOWN,
+    MemoryPoolPreference: D3D12_MEMORY_POOL_UNKNOWN,
+    CreationNodeMask: 0,
+    VisibleNodeMask: 0,
+};
+
+const DEFAULT_HEAP_PROPS: D3D12_HEAP_PROPERTIES = D3D12_HEAP_PROPERTIES {
+    Type: D3D12_HEAP_TYPE_DEFAULT,
+    CPUPageProperty: D3D12_CPU_PAGE_PROPERTY_UNKNOWN,
+    MemoryPoolPreference: D3D12_MEMORY_POOL_UNKNOWN,
+    CreationNodeMask: 0,
+    VisibleNodeMask: 0,
+};
+
+// The GPU copies of a scene::Geometry. Geometries without indices get a sequential index buffer, which the hit
+// programs read through their root SRVs. The indices are 16-bit when index_size is 2, padded to a multiple of 4 bytes
+// for the ByteAddressBuffer. The attributes the geometry doesn't have are None and bound as a null address, which the
+// hit programs never read because of attribute_flags
+struct GeometryBuffers {
+    vertex_buffer: ID3D12Resource,
+    vertex_count: u32,
+    index_buffer: ID3D12Resource,
+    index_count: u32,
+    index_size: u32,
+    normal_buffer: Option<ID3D12Resource>,
+    tangent_buffer: Option<ID3D12Resource>,
+    tex_coord_buffer: Option<ID3D12Resource>,
+    attribute_flags: u32,
+}
+
+struct BLASBuffers {
+    scratch: ID3D12Resource,
+    result: ID3D12Resource,
+}
+// The result and the scratch are only touched by the GPU, and the queue runs the refits in order, so one of each
+// is enough. The CPU writes the instance descs while older frames are still in flight, so every frame has its own
+// slice of instance_desc
+struct TLASBuffers {
+    scratch: ID3D12Resource,
+    result: ID3D12Resource,
+    instance_desc: ID3D12Resource,
+    instance_count: usize,
+}
+
+pub struct D3D12Backend {
+    swap_chain_size: IVec2,
+    dxgi_factory: IDXGIFactory4,
+    device: ID3D12Device5,
+    cmd_queue: ID3D12CommandQueue,
+    // None when rendering headless
+    swap_chain: Option<IDXGISwapChain3>,
+    rtv_heap: HeapData,
+    frame_objects: [FrameObject; DEFAULT_SWAP_CHAIN_BUFFERS as usize],
+    // One per frame in flight. frame_index is the one being recorded
+    frames: Vec<FrameContext>,
+    frame_index: usize,
+    cmd_list: ID3D12GraphicsCommandList4,
+    fence: ID3D12Fence,
+    fence_event: HANDLE,
+    fence_value: u64,
+    // Indexed by BLAS and geometry, like RootArgument::GeometryBuffer
+    geometry_buffers: Vec<Vec<GeometryBuffers>>,
+    tlas: Option<TLASBuffers>,
+    blas: Vec<ID3D12Resource>,
+    pipeline_state: Option<ID3D12StateObject>,
+    global_root_sig: Option<ID3D12RootSignature>,
+    // The light::LightData of gLights, never empty so there is always an address to bind
+    light_buffer: Option<ID3D12Resource>,
+    light_count: u32,
+    // gMaxDepth, which belongs to the pipeline
+    max_depth: u32,
+    // The material::MaterialData of gMaterials, also never empty
+    material_buffer: Option<ID3D12Resource>,
+    // gTextures, with SRVs from TEXTURE_SRV_SLOT on
+    textures: Vec<ID3D12Resource>,
+    // sampler::blue_noise_tile() in gBlueNoise
+    blue_noise_buffer: Option<ID3D12Resource>,
+    shader_table: Option<ID3D12Resource>,
+    shader_table_layout: Option<ShaderTableLayout>,
+    output_resource: Option<ID3D12Resource>,
+    // RGBA32F, the running average rayGen() keeps. Always in the unordered-access state
+    accumulation_resource: Option<ID3D12Resource>,
+    // RGBA32F, the color before the output encoding. Also in the unordered-access state, except during readback_hdr()
+    hdr_output_resource: Option<ID3D12Resource>,
+    srv_uav_heap: Option<ID3D12DescriptorHeap>,
+    constant_buffers: Vec<ID3D12Resource>,
+    // The FrameConstants of the ray-gen record, a FRAME_CONSTANTS_STRIDE slot per frame in flight. It stays mapped
+    frame_constants_buffer: Option<ID3D12Resource>,
+    frame_constants_data: *mut u8,
+    // Created on the first compile, see library_bytecode()
+    dxc: Option<D3D12ShaderCompilerInfo>,
+    // TIMESTAMPS_PER_FRAME queries per frame in flight, resolved into the readback buffer at the same index
+    timestamp_heap: ID3D12QueryHeap,
+    timestamp_readback: ID3D12Resource,
+    // Ticks per second
+    timestamp_frequency: u64,
+    // The first timestamp we read, the timings start from there
+    timestamp_origin: Option<u64>,
+    // The timings of the finished frames that haven't been taken yet
+    frame_timings: Vec<FrameTimings>,
+}
+
+struct HeapData {
+    heap: ID3D12DescriptorHeap,
+    used_entries: u32,
+}
+struct FrameObject {
+    pub swap_chain_buffer: Option<ID3D12Resource>,
+    pub rtv_handle: D3D12_CPU_DESCRIPTOR_HANDLE,
+}
+
+// What a frame in flight needs until the GPU is done with it
+struct FrameContext {
+    cmd_allocator: ID3D12CommandAllocator,
+    // Signaled when the frame's command list finished
+    fence_value: u64,
+    // The passes that wrote their timestamps in this frame, in order
+    timed_passes: Vec<Pass>,
+}
+
+// A begin and an end timestamp for every pass
+const TIMESTAMPS_PER_FRAME: usize = 2 * Pass::ALL.len();
+
+// CBVs must be aligned to D3D12_CONSTANT_BUFFER_DATA_PLACEMENT_ALIGNMENT
+const FRAME_CONSTANTS_STRIDE: usize = align_to(D3D12_CONSTANT_BUFFER_DATA_PLACEMENT_ALIGNMENT, size_of::<FrameConstants>() as u32) as usize;
+
+struct PipelineConfig {
+    config: D3D12_RAYTRACING_PIPELINE_CONFIG,
+    subobject: D3D12_STATE_SUBOBJECT,
+}
+
+impl PipelineConfig {
+    unsafe fn new() -> Self {
+        Self {
+            config: std::mem::zeroed(),
+            subobject: std::mem::zeroed(),
+        }
+    }
+    unsafe fn init(&mut self, max_trace_recursion_depth: u32) {
+        self.config = D3D12_RAYTRACING_PIPELINE_CONFIG { MaxTraceRecursionDepth: max_trace_recursion_depth };
+        self.subobject = D3D12_STATE_SUBOBJECT {
+            Type: D3D12_STATE_SUBOBJECT_TYPE_RAYTRACING_PIPELINE_CONFIG,
+            pDesc: &self.config as *const _ as _,
+        };
+    }
+}
+struct ShaderConfig {
+    shader_config: D3D12_RAYTRACING_SHADER_CONFIG,
+    subobject: D3D12_STATE_SUBOBJECT,
+}
+
+impl ShaderConfig {
+    unsafe fn new() -> Self {
+        Self {
+            shader_config: std::mem::zeroed(),
+            subobject: std::mem::zeroed(),
+        }
+    }
+    unsafe fn init(&mut self, max_attribute_size_in_bytes: u32, max_payload_size_in_bytes: u32) {
+        self.shader_config = D3D12_RAYTRACING_SHADER_CONFIG {
+            MaxPayloadSizeInBytes: max_payload_size_in_bytes,
+            MaxAttributeSizeInBytes: max_attribute_size_in_bytes,
+        };
+        self.subobject = D3D12_STATE_SUBOBJECT {
+            Type: D3D12_STATE_SUBOBJECT_TYPE_RAYTRACING_SHADER_CONFIG,
+            pDesc: &self.shader_config as *const _ as _,
+        };
+    }
+}
+
+struct ExportAssociation {
+    subobject: D3D12_STATE_SUBOBJECT,
+    association: D3D12_SUBOBJECT_TO_EXPORTS_ASSOCIATION,
+    export_names: Vec<HSTRING>,
+    export_name_ptrs: Vec<PWSTR>,
+}
+
+impl ExportAssociation {
+    unsafe fn new() -> Self {
+        Self {
+            subobject: std::mem::zeroed(),
+            association: std::mem::zeroed(),
+            export_names: Vec::new(),
+            export_name_ptrs: Vec::new(),
+        }
+    }
+    unsafe fn init(&mut self, export_names: &[String], subobject_to_associate: *const D3D12_STATE_SUBOBJECT) {
+        self.export_names = export_names.iter().map( |s| s.into()).collect();
+        for n in &self.export_names {
+            let n = PCWSTR::from(n);
+            let n: PWSTR = std::mem::transmute(n);
+            self.export_name_ptrs.push(n);
+        }
+
+        self.association.NumExports = export_names.len() as _;
+        self.association.pExports = self.export_name_ptrs.as_mut_ptr();
+        self.association.pSubobjectToAssociate = subobject_to_associate;
+
+        self.subobject.Type = D3D12_STATE_SUBOBJECT_TYPE_SUBOBJECT_TO_EXPORTS_ASSOCIATION;
+        self.subobject.pDesc = &self.association as *const _ as _;
+    }
+}
+
+struct RootSignatureDesc {
+    desc: D3D12_ROOT_SIGNATURE_DESC,
+    range: Vec<D3D12_DESCRIPTOR_RANGE>,
+    root_params: Vec<D3D12_ROOT_PARAMETER>,
+    static_samplers: Vec<D3D12_STATIC_SAMPLER_DESC>,
+}
+
+impl RootSignatureDesc {
+    unsafe fn new() -> Self {
+        Self {
+            desc: std::mem::zeroed(),
+            range: Vec::new(),
+            root_params: Vec::new(),
+            static_samplers: Vec::new(),
+        }
+    }
+    fn ray_gen_root_signature_desc(&mut self) {
+        // gOutput
+        self.range.push(D3D12_DESCRIPTOR_RANGE {
+            RangeType: D3D12_DESCRIPTOR_RANGE_TYPE_UAV,
+            NumDescriptors: 1,
+            BaseShaderRegister: 0,
+            RegisterSpace: 0,
+            OffsetInDescriptorsFromTableStart: 0,
+        });
+
+        // gRtScene
+        self.range.push(D3D12_DESCRIPTOR_RANGE {
+            RangeType: D3D12_DESCRIPTOR_RANGE_TYPE_SRV,
+            NumDescriptors: 1,
+            BaseShaderRegister: 0,
+            RegisterSpace: 0,
+            OffsetInDescriptorsFromTableStart: TLAS_SRV_SLOT - OUTPUT_UAV_SLOT,
+        });
+
+        // gAccumulation
+        self.range.push(D3D12_DESCRIPTOR_RANGE {
+            RangeType: D3D12_DESCRIPTOR_RANGE_TYPE_UAV,
+            NumDescriptors: 1,
+            BaseShaderRegister: 1,
+            RegisterSpace: 0,
+            OffsetInDescriptorsFromTableStart: ACCUMULATION_UAV_SLOT - OUTPUT_UAV_SLOT,
+        });
+
+        // gHdrOutput
+        self.range.push(D3D12_DESCRIPTOR_RANGE {
+            RangeType: D3D12_DESCRIPTOR_RANGE_TYPE_UAV,
+            NumDescriptors: 1,
+            BaseShaderRegister: 2,
+            RegisterSpace: 0,
+            OffsetInDescriptorsFromTableStart: HDR_OUTPUT_UAV_SLOT - OUTPUT_UAV_SLOT,
+        });
+
+        // Create the desc
+        self.root_params.push(D3D12_ROOT_PARAMETER{
+            ParameterType: D3D12_ROOT_PARAMETER_TYPE_DESCRIPTOR_TABLE,
+            Anonymous: D3D12_ROOT_PARAMETER_0 {
+                DescriptorTable: D3D12_ROOT_DESCRIPTOR_TABLE {
+                    NumDescriptorRanges: 4,
+                    pDescriptorRanges: self.range.as_ptr(),
+                },
+            },
+            ShaderVisibility: D3D12_SHADER_VISIBILITY_ALL,
+        });
+
+        // gCamera
+        self.root_params.push(D3D12_ROOT_PARAMETER{
+            ParameterType: D3D12_ROOT_PARAMETER_TYPE_CBV,
+            Anonymous: D3D12_ROOT_PARAMETER_0 {
+                Descriptor: D3D12_ROOT_DESCRIPTOR {
+                    ShaderRegister: 1,
+                    RegisterSpace: 0,
+                },
+            },
+            ShaderVisibility: D3D12_SHADER_VISIBILITY_ALL,
+        });
+
+        self.desc = D3D12_ROOT_SIGNATURE_DESC {
+            NumParameters: 2,
+            pParameters: self.root_params.as_ptr(),
+            Flags: D3D12_ROOT_SIGNATURE_FLAG_LOCAL_ROOT_SIGNATURE,
+            ..Default::default()
+        };
+    }
+    // gRtScene, for the shadow rays
+    fn tlas_table_param(&mut self) {
+        self.range.push(D3D12_DESCRIPTOR_RANGE {
+            RangeType: D3D12_DESCRIPTOR_RANGE_TYPE_SRV,
+            NumDescriptors: 1,
+            BaseShaderRegister: 0,
+            RegisterSpace: 0,
+            OffsetInDescriptorsFromTableStart: 0,
+        });
+
+        self.root_params.push(D3D12_ROOT_PARAMETER{
+            ParameterType: D3D12_ROOT_PARAMETER_TYPE_DESCRIPTOR_TABLE,
+            Anonymous: D3D12_ROOT_PARAMETER_0 {
+                DescriptorTable: D3D12_ROOT_DESCRIPTOR_TABLE {
+                    NumDescriptorRanges: 1,
+                    pDescriptorRanges: self.range.as_ptr(),
+                },
+            },
+            ShaderVisibility: D3D12_SHADER_VISIBILITY_ALL,
+        });
+    }
+    fn root_descriptor_param(&mut self, parameter_type: D3D12_ROOT_PARAMETER_TYPE, register: u32, space: u32) {
+        self.root_params.push(D3D12_ROOT_PARAMETER{
+            ParameterType: parameter_type,
+            Anonymous: D3D12_ROOT_PARAMETER_0 {
+                Descriptor: D3D12_ROOT_DESCRIPTOR {
+                    ShaderRegister: register,
+                    RegisterSpace: space,
+                },
+            },
+            ShaderVisibility: D3D12_SHADER_VISIBILITY_ALL,
+        });
+    }
+    fn root_constants_param(&mut self, register: u32, space: u32, count: u32) {
+        self.root_params.push(D3D12_ROOT_PARAMETER{
+            ParameterType: D3D12_ROOT_PARAMETER_TYPE_32BIT_CONSTANTS,
+            Anonymous: D3D12_ROOT_PARAMETER_0 {
+                Constants: D3D12_ROOT_CONSTANTS {
+                    ShaderRegister: register,
+                    RegisterSpace: space,
+                    Num32BitValues: count,
+                },
+            },
+            ShaderVisibility: D3D12_SHADER_VISIBILITY_ALL,
+        });
+    }
+    // gIndexSize and gAttributeFlags, then gVertices, gIndices, gNormals, gTangents and gTexCoords, see
+    // shader_table::RootArgument::GeometryConstants and GeometryBuffer
+    fn geometry_params(&mut self) {
+        self.root_constants_param(2, 0, 2);
+        for register in 1..=5 {
+            self.root_descriptor_param(D3D12_ROOT_PARAMETER_TYPE_SRV, register, 0);
+        }
+    }
+    fn local_desc(&mut self) {
+        self.desc = D3D12_ROOT_SIGNATURE_DESC {
+            NumParameters: self.root_params.len() as u32,
+            pParameters: self.root_params.as_ptr(),
+            Flags: D3D12_ROOT_SIGNATURE_FLAG_LOCAL_ROOT_SIGNATURE,
+            ..Default::default()
+        };
+    }
+    fn plane_hit_root_desc(&mut self) {
+        self.tlas_table_param();
+        self.geometry_params();
+        self.local_desc();
+    }
+    fn triangle_hit_root_desc(&mut self) {
+        // The colors
+        self.root_descriptor_param(D3D12_ROOT_PARAMETER_TYPE_CBV, 0, 0);
+        self.tlas_table_param();
+        self.geometry_params();
+        self.local_desc();
+    }
+    // gLightCount and gMaxDepth as root constants, gLights, gMaterials and gBlueNoise as root SRVs and
+    // gTextureSampler, all in space 1. gTextures is an unbounded descriptor table in space 2
+    fn global_root_desc(&mut self) {
+        self.root_constants_param(0, 1, 2);
+        self.root_descriptor_param(D3D12_ROOT_PARAMETER_TYPE_SRV, 0, 1);
+        self.root_descriptor_param(D3D12_ROOT_PARAMETER_TYPE_SRV, 1, 1);
+        self.root_descriptor_param(D3D12_ROOT_PARAMETER_TYPE_SRV, 2, 1);
+
+        self.range.push(D3D12_DESCRIPTOR_RANGE {
+            RangeType: D3D12_DESCRIPTOR_RANGE_TYPE_SRV,
+            NumDescriptors: u32::MAX,
+            BaseShaderRegister: 0,
+            RegisterSpace: 2,
+            OffsetInDescriptorsFromTableStart: 0,
+        });
+        self.root_params.push(D3D12_ROOT_PARAMETER{
+            ParameterType: D3D12_ROOT_PARAMETER_TYPE_DESCRIPTOR_TABLE,
+            Anonymous: D3D12_ROOT_PARAMETER_0 {
+                DescriptorTable: D3D12_ROOT_DESCRIPTOR_TABLE {
+                    NumDescriptorRanges: 1,
+                    pDescriptorRanges: self.range.as_ptr(),
+                },
+            },
+            ShaderVisibility: D3D12_SHADER_VISIBILITY_ALL,
+        });
+
+        // Trilinear and wrapping, like texture::Texture::sample()
+        self.static_samplers.push(D3D12_STATIC_SAMPLER_DESC {
+            Filter: D3D12_FILTER_MIN_MAG_MIP_LINEAR,
+            AddressU: D3D12_TEXTURE_ADDRESS_MODE_WRAP,
+            AddressV: D3D12_TEXTURE_ADDRESS_MODE_WRAP,
+            AddressW: D3D12_TEXTURE_ADDRESS_MODE_WRAP,
+            MaxLOD: f32::MAX,
+            ShaderRegister: 0,
+            RegisterSpace: 1,
+            ShaderVisibility: D3D12_SHADER_VISIBILITY_ALL,
+            ..Default::default()
+        });
+
+        self.desc = D3D12_ROOT_SIGNATURE_DESC {
+            NumParameters: self.root_params.len() as u32,
+            pParameters: self.root_params.as_ptr(),
+            NumStaticSamplers: self.static_samplers.len() as u32,
+            pStaticSamplers: self.static_samplers.as_ptr(),
+            ..Default::default()
+        };
+    }
+    fn empty_root_desc(&mut self) {
+        self.desc = D3D12_ROOT_SIGNATURE_DESC {
+            Flags: D3D12_ROOT_SIGNATURE_FLAG_LOCAL_ROOT_SIGNATURE,
+            ..Default::default()
+        };
+    }
+    fn local_root_desc(&mut self, root_signature: LocalRootSignature) {
+        match root_signature {
+            LocalRootSignature::RayGen => self.ray_gen_root_signature_desc(),
+            LocalRootSignature::TriangleHit => self.triangle_hit_root_desc(),
+            LocalRootSignature::PlaneHit => self.plane_hit_root_desc(),
+            LocalRootSignature::Empty => self.empty_root_desc(),
+        }
+    }
+}
+
+
+unsafe fn create_root_signature(device: &ID3D12Device5, desc: &D3D12_ROOT_SIGNATURE_DESC) -> Result<ID3D12RootSignature> {
+    let mut sig_blob = None;
+    D3D12SerializeRootSignature(
+        desc as _,
+        D3D_ROOT_SIGNATURE_VERSION_1,
+        &mut sig_blob,
+        None
+    ).map_err(api("D3D12SerializeRootSignature()"))?;
+    let sig_blob = sig_blob.unwrap();
+
+    let root_sig = device.CreateRootSignature(
+        0,
+        std::slice::from_raw_parts(
+            sig_blob.GetBufferPointer() as _,
+            sig_blob.GetBufferSize(),
+        ),
+    ).map_err(alloc("CreateRootSignature()"))?;
+    Ok(root_sig)
+}
+
+struct RootSignature {
+    root_sig: ID3D12RootSignature,
+    interface: *mut c_void,
+    subobject: D3D12_STATE_SUBOBJECT,
+}
+
+impl RootSignature {
+    unsafe fn new(device: &ID3D12Device5, desc: &D3D12_ROOT_SIGNATURE_DESC) -> Result<Self> {
+        let root_sig = create_root_signature(device, desc)?;
+        Ok(Self {
+            root_sig,
+            interface: std::ptr::null_mut(),
+            subobject: std::mem::zeroed(),
+        })
+    }
+    fn init(&mut self, type_: D3D12_STATE_SUBOBJECT_TYPE) {
+        self.interface = self.root_sig.as_raw();
+        self.subobject.pDesc = &self.interface as *const *mut _ as _;
+        self.subobject.Type = type_;
+    }
+    fn init_local(&mut self) {
+        self.init(D3D12_STATE_SUBOBJECT_TYPE_LOCAL_ROOT_SIGNATURE);
+    }
+    fn init_global(&mut self) {
+        self.init(D3D12_STATE_SUBOBJECT_TYPE_GLOBAL_ROOT_SIGNATURE);
+    }
+}
+
+struct HitProgram {
+    export_name: HSTRING,
+    desc: D3D12_HIT_GROUP_DESC,
+    subobject: D3D12_STATE_SUBOBJECT,
+}
+
+impl HitProgram {
+    unsafe fn new(name: &str) -> Self {
+        Self {
+            export_name: name.into(),
+            desc: std::mem::zeroed(),
+            subobject: std::mem::zeroed(),
+        }
+    }
+    unsafe fn init(&mut self, ahs_export: PCWSTR, chs_export: PCWSTR) {
+        self.desc = D3D12_HIT_GROUP_DESC {
+            HitGroupExport: (&self.export_name).into(),
+            AnyHitShaderImport: ahs_export,
+            ClosestHitShaderImport: chs_export,
+            ..Default::default()
+        };
+        self.subobject = D3D12_STATE_SUBOBJECT {
+            Type: D3D12_STATE_SUBOBJECT_TYPE_HIT_GROUP,
+            pDesc: &self.desc as *const _ as _,
+        };
+    }
+}
+
+struct D3D12ShaderCompilerInfo {
+    pub library: IDxcLibrary,
+    pub compiler: IDxcCompiler,
+    pub version: (u32, u32),
+}
+
+// The signature of DxcCreateInstance(). dxcompiler.dll is loaded when the first library has to be compiled instead of
+// being imported, so a build with an up to date DXIL cache runs on machines without the DXC redistributable
+type DxcCreateInstanceProc = unsafe extern "system" fn(*const GUID, *const GUID, *mut *mut c_void) -> HRESULT;
+
+unsafe fn dxc_create_instance<T: Interface>(create_instance: DxcCreateInstanceProc, clsid: &GUID, call: &'static str) -> Result<T> {
+    let mut instance = std::ptr::null_mut();
+    create_instance(clsid, &T::IID, &mut instance).ok().map_err(api(call))?;
+    Ok(T::from_raw(instance))
+}
+
+impl D3D12ShaderCompilerInfo {
+    fn new() -> Result<Self> {
+        unsafe {
+            let dll = LoadLibraryW(w!("dxcompiler.dll")).map_err(api("LoadLibraryW(dxcompiler.dll)"))?;
+            let proc = GetProcAddress(dll, s!("DxcCreateInstance"))
+                .ok_or_else(|| Error::Api { call: "GetProcAddress(DxcCreateInstance)", message: windows::core::Error::from_win32().message().to_string() })?;
+            let create_instance: DxcCreateInstanceProc = std::mem::transmute(proc);
+            let library: IDxcLibrary = dxc_create_instance(create_instance, &CLSID_DxcLibrary, "DxcCreateInstance(CLSID_DxcLibrary)")?;
+            let compiler: IDxcCompiler = dxc_create_instance(create_instance, &CLSID_DxcCompiler, "DxcCreateInstance(CLSID_DxcCompiler)")?;
+            let mut version = (0, 0);
+            let version_info: IDxcVersionInfo = compiler.cast().map_err(api("IDxcCompiler::cast::<IDxcVersionInfo>()"))?;
+            version_info.GetVersion(&mut version.0, &mut version.1).map_err(api("IDxcVersionInfo::GetVersion()"))?;
+            Ok(Self { library, compiler, version })
+        }
+    }
+
+    // Compiles a file with the given options. Fails with the parsed DXC diagnostics, on success the diagnostics
+    // are the warnings
+    fn compile_shader_file(&self, path: &str, entry_point: &str, target_profile: &str, options: &CompileOptions) -> Result<(IDxcBlob, Vec<Diagnostic>)> {
+        options.validate().map_err(|msg| compile_error(path, &msg))?;
+        let wpath: HSTRING = path.into();
+        let entry_point: HSTRING = entry_point.into();
+        let target_profile: HSTRING = target_profile.into();
+
+        // DXC wants mutable, null-terminated wide strings for the arguments
+        let mut arguments: Vec<Vec<u16>> = options.arguments().iter().map(|a| a.encode_utf16().chain([0]).collect()).collect();
+        let argument_ptrs: Vec<PWSTR> = arguments.iter_mut().map(|a| PWSTR(a.as_mut_ptr())).collect();
+        let define_strings: Vec<(HSTRING, HSTRING)> = options.define_values().map(|(name, value)| (name.into(), value.into())).collect();
+        let defines: Vec<DxcDefine> = define_strings.iter().map(|(name, value)| DxcDefine { Name: name.into(), Value: value.into() }).collect();
+
+        unsafe {
+            let source_blob = self.library.CreateBlobFromFile(&wpath, Some(&DXC_CP_UTF8)).map_err(|e| Error::Asset(format!("{}: {}", path, e)))?;
+            // The default handler resolves includes relative to the including file and the -I directories
+            let include_handler = self.library.CreateIncludeHandler().map_err(api("CreateIncludeHandler()"))?;
+            let result = self.compiler.Compile(
+                &source_blob,
+                &wpath,
+                &entry_point,
+                &target_profile,
+                Some(&argument_ptrs),
+                &defines,
+                &include_handler
+            ).map_err(|e| compile_error(path, &e.to_string()))?;
+
+            // The error buffer holds the warnings too
+            let diagnostics = match result.GetErrorBuffer() {
+                Ok(errors) if errors.GetBufferSize() > 0 => {
+                    let text = std::slice::from_raw_parts(errors.GetBufferPointer() as *const u8, errors.GetBufferSize());
+                    let text = String::from_utf8_lossy(text);
+                    let text = text.trim_end_matches('\0');
+                    let diagnostics = parse_diagnostics(text);
+                    // Keep the raw output if it isn't in the format we expect
+                    if diagnostics.is_empty() && !text.trim().is_empty() {
+                        vec![Diagnostic { file: String::new(), line: 0, column: 0, severity: Severity::Warning, message: text.trim().into() }]
+                    } else {
+                        diagnostics
+                    }
+                }
+                _ => Vec::new(),
+            };
+            let status = result.GetStatus().map_err(|e| compile_error(path, &e.to_string()))?;
+            if status.is_err() {
+                let diagnostics = diagnostics
+                    .into_iter()
+                    .map(|d| if d.file.is_empty() { Diagnostic { severity: Severity::Error, ..d } } else { d })
+                    .collect();
+                return Err(Error::ShaderCompile { path: path.into(), diagnostics });
+            }
+            let blob = result.GetResult().map_err(|e| compile_error(path, &e.to_string()))?;
+            Ok((blob, diagnostics))
+        }
+    }
+}
+
+// A compile that failed without output, e.g. because the compiler itself couldn't run
+fn compile_error(path: &str, message: &str) -> Error {
+    let diagnostic = Diagnostic { file: String::new(), line: 0, column: 0, severity: Severity::Error, message: message.into() };
+    Error::ShaderCompile { path: path.into(), diagnostics: vec![diagnostic] }
+}
+
+// Compiles a pipeline library, warnings go to stderr
+fn compile_library(dxc: &D3D12ShaderCompilerInfo, desc: &PipelineDesc) -> Result<Vec<u8>> {
+    let (blob, warnings) = dxc.compile_shader_file(&desc.library, "", "lib_6_3", &desc.compile_options)?;
+    for warning in warnings {
+        eprintln!("{}", warning);
+    }
+    Ok(unsafe { std::slice::from_raw_parts(blob.GetBufferPointer() as *const u8, blob.GetBufferSize()) }.to_vec())
+}
+
+// Compiles the library of a pipeline into its DXIL cache, without creating a device. This is the build step for
+// machines without dxcompiler.dll. Returns the path of the cache file
+pub fn precompile_library(desc: &PipelineDesc) -> Result<PathBuf> {
+    let library = Path::new(&desc.library);
+    let source_hash = dxil_cache::source_hash(library, &desc.compile_options).map_err(|e| Error::Asset(e.to_string()))?;
+    let dxc = D3D12ShaderCompilerInfo::new()?;
+    let entry = CachedDxil { source_hash, compiler_version: dxc.version, bytecode: compile_library(&dxc, desc)? };
+    let path = dxil_cache::cache_path(library);
+    dxil_cache::store(&path, &entry).map_err(|e| Error::Asset(format!("{}: {}", path.display(), e)))?;
+    Ok(path)
+}
+
+struct DxilLibrary {
+    dxil_lib_desc: D3D12_DXIL_LIBRARY_DESC,
+    state_subobject: D3D12_STATE_SUBOBJECT,
+    bytecode: Vec<u8>,
+    export_desc: Vec<D3D12_EXPORT_DESC>,
+    export_name: Vec<HSTRING>,
+}
+
+impl DxilLibrary {
+    unsafe fn new() -> Self {
+        Self {
+            dxil_lib_desc: std::mem::zeroed(),
+            state_subobject: std::mem::zeroed(),
+            bytecode: Vec::new(),
+            export_desc: Vec::new(),
+            export_name: Vec::new(),
+        }
+    }
+    unsafe fn init(&mut self, bytecode: Vec<u8>, export_point: &[String]) {
+        self.bytecode = bytecode;
+        self.state_subobject = D3D12_STATE_SUBOBJECT {
+            Type: D3D12_STATE_SUBOBJECT_TYPE_DXIL_LIBRARY,
+            pDesc: &self.dxil_lib_desc as *const _ as _,
+        };
+
+        self.export_name = export_point.iter().map( |s| s.into()).collect();
+        for name in &self.export_name {
+            self.export_desc.push(D3D12_EXPORT_DESC {
+                Name: name.into(),
+                Flags: D3D12_EXPORT_FLAG_NONE,
+                ..Default::default()
+            });
+        }
+
+        self.dxil_lib_desc = D3D12_DXIL_LIBRARY_DESC {
+            DXILLibrary: D3D12_SHADER_BYTECODE {
+                pShaderBytecode: self.bytecode.as_ptr() as _,
+                BytecodeLength: self.bytecode.len(),
+            },
+            NumExports: export_point.len() as _,
+            pExports: self.export_desc.as_mut_ptr(),
+        };
+    }
+}
+
+impl D3D12Backend {
+    unsafe fn resource_barrier(&self, resource: ID3D12Resource, state_before: D3D12_RESOURCE_STATES, state_after: D3D12_RESOURCE_STATES) {
+        let mut barrier = D3D12_RESOURCE_BARRIER::default();
+        barrier.Type = D3D12_RESOURCE_BARRIER_TYPE_TRANSITION;
+        barrier.Anonymous = D3D12_RESOURCE_BARRIER_0 {
+            Transition: std::mem::ManuallyDrop::new(D3D12_RESOURCE_TRANSITION_BARRIER {
+                pResource: Some(resource.clone()),
+                StateBefore: state_before,
+                StateAfter: state_after,
+                Subresource: D3D12_RESOURCE_BARRIER_ALL_SUBRESOURCES,
+            })
+        };
+        self.cmd_list.ResourceBarrier(&[barrier]);
+    }
+    unsafe fn resource_barrier_uav(&self, resource: ID3D12Resource) {
+        let barrier = D3D12_RESOURCE_BARRIER {
+            Type: D3D12_RESOURCE_BARRIER_TYPE_UAV,
+            Anonymous: D3D12_RESOURCE_BARRIER_0 {
+                UAV: ManuallyDrop::new(D3D12_RESOURCE_UAV_BARRIER{ pResource: Some(resource) }),
+            },
+            ..Default::default()
+        };
+        self.cmd_list.ResourceBarrier(&[barrier]);
+    }
+    // Looks up the identifiers of every export the table refers to
+    unsafe fn shader_identifiers(&self, table: &ShaderTable) -> Result<HashMap<String, [u8; SHADER_IDENTIFIER_SIZE_IN_BYTES as usize]>> {
+        let rtso_prop: ID3D12StateObjectProperties = self.pipeline_state.as_ref().unwrap().cast().map_err(api("ID3D12StateObject::cast()"))?;
+        let mut identifiers = HashMap::new();
+        for record in std::iter::once(&table.ray_gen).chain(&table.miss).chain(&table.hit_groups) {
+            let id: HSTRING = record.export.as_str().into();
+            let ptr = rtso_prop.GetShaderIdentifier(&id);
+            if ptr.is_null() {
+                return Err(Error::InvalidScene(format!("{} is not an export of the pipeline", record.export)));
+            }
+            let mut identifier = [0u8; SHADER_IDENTIFIER_SIZE_IN_BYTES as usize];
+            memcpy(identifier.as_mut_ptr(), ptr, identifier.len());
+            identifiers.insert(record.export.clone(), identifier);
+        }
+        Ok(identifiers)
+    }
+    // Translates a record's root argument into the 8 bytes we store after the program identifier, for the copy of
+    // the table the given frame dispatches with
+    unsafe fn root_argument_addr(&self, root_argument: &RootArgument, constant_buffer: &mut usize, frame: usize) -> u64 {
+        match root_argument {
+            RootArgument::DescriptorTable(slot) => {
+                let heap_start = self.srv_uav_heap.as_ref().unwrap().GetGPUDescriptorHandleForHeapStart().ptr;
+                heap_start + (*slot * self.device.GetDescriptorHandleIncrementSize(D3D12_DESCRIPTOR_HEAP_TYPE_CBV_SRV_UAV)) as u64
+            }
+            RootArgument::ConstantBuffer(_) => {
+                *constant_buffer += 1;
+                self.constant_buffers[*constant_buffer - 1].GetGPUVirtualAddress()
+            }
+            RootArgument::FrameConstants => self.frame_constants_buffer.as_ref().unwrap().GetGPUVirtualAddress() + (frame * FRAME_CONSTANTS_STRIDE) as u64,
+            RootArgument::GeometryBuffer { blas, geometry, buffer } => {
+                let buffers = &self.geometry_buffers[*blas][*geometry];
+                let resource = match buffer {
+                    GeometryBuffer::Vertices => Some(&buffers.vertex_buffer),
+                    GeometryBuffer::Indices => Some(&buffers.index_buffer),
+                    GeometryBuffer::Normals => buffers.normal_buffer.as_ref(),
+                    GeometryBuffer::Tangents => buffers.tangent_buffer.as_ref(),
+                    GeometryBuffer::TexCoords => buffers.tex_coord_buffer.as_ref(),
+                };
+                resource.map_or(0, |r| r.GetGPUVirtualAddress())
+            }
+            RootArgument::GeometryConstants { blas, geometry } => {
+                let buffers = &self.geometry_buffers[*blas][*geometry];
+                buffers.index_size as u64 | (buffers.attribute_flags as u64) << 32
+            }
+        }
+    }
+    unsafe fn create_shader_table(&mut self, table: &ShaderTable) -> Result<()> {
+        // The layout gives every section (ray-gen, miss, hit groups) its own stride, see ShaderTableLayout
+        let layout = ShaderTableLayout::new(table).map_err(Error::InvalidScene)?;
+
+        // Every frame in flight has its own copy of the table, because the ray-gen record points to its frame constants
+        let table_stride = self.shader_table_stride(&layout);
+
+        // For simplicity, we create the shader-table on the upload heap. You can also create it on the default heap
+        let shader_table = self.create_buffer(table_stride * self.frames.len() as u64, D3D12_RESOURCE_FLAG_NONE, D3D12_RESOURCE_STATE_GENERIC_READ, &UPLOAD_HEAP_PROPS)?;
+
+        // Map the buffer
+        let mut data: *mut u8 = std::ptr::null_mut();
+        shader_table.Map(0, None, Some(&mut data as *mut *mut u8 as _)).map_err(api("Map()"))?;
+
+        // Write the program ID and the root argument of each entry. Constant buffers were created in record order
+        let identifiers = self.shader_identifiers(table)?;
+        for frame in 0..self.frames.len() {
+            let mut constant_buffer = 0;
+            let records = layout.serialize(
+                table,
+                |export| identifiers[export],
+                |root_argument| self.root_argument_addr(root_argument, &mut constant_buffer, frame),
+            );
+            memcpy(data.add(frame * table_stride as usize), records.as_ptr(), records.len());
+        }
+
+        // Unmap
+        shader_table.Unmap(0, None);
+
+        // move
+        self.shader_table = Some(shader_table);
+        self.shader_table_layout = Some(layout);
+        Ok(())
+    }
+    fn shader_table_stride(&self, layout: &ShaderTableLayout) -> u64 {
+        align_to(D3D12_RAYTRACING_SHADER_TABLE_BYTE_ALIGNMENT, layout.size as u32) as u64
+    }
+    // The DXIL of the pipeline library. The cache is used when its hash matches the source and, if dxcompiler.dll is
+    // there, it was built by the same compiler. Otherwise the library is compiled and the cache rewritten
+    unsafe fn library_bytecode(&mut self, desc: &PipelineDesc) -> Result<Vec<u8>> {
+        let library = Path::new(&desc.library);
+        let cache_path = dxil_cache::cache_path(library);
+        let cached = dxil_cache::load(&cache_path);
+        // A build can ship without the HLSL, the cache is then used as it is
+        let source_hash = match dxil_cache::source_hash(library, &desc.compile_options) {
+            Ok(hash) => Some(hash),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound && !library.exists() => match cached {
+                Ok(cached) => return Ok(cached.bytecode),
+                Err(_) => return Err(Error::Asset(format!("{}: neither the library nor {} exist", desc.library, cache_path.display()))),
+            },
+            Err(e) => return Err(Error::Asset(e.to_string())),
+        };
+        if self.dxc.is_none() {
+            match D3D12ShaderCompilerInfo::new() {
+                Ok(dxc) => self.dxc = Some(dxc),
+                Err(e) => {
+                    // Without a compiler, a cache of the same source is good enough whatever compiled it
+                    return match cached {
+                        Ok(cached) if Some(cached.source_hash) == source_hash => Ok(cached.bytecode),
+                        _ => Err(compile_error(&desc.library, &format!("{} is missing or out of date and the compiler is unavailable: {}", cache_path.display(), e))),
+                    };
+                }
+            }
+        }
+        let dxc = self.dxc.as_ref().unwrap();
+        match cached {
+            Ok(cached) if Some(cached.source_hash) == source_hash && cached.compiler_version == dxc.version => return Ok(cached.bytecode),
+            _ => {}
+        }
+        let entry = CachedDxil { source_hash: source_hash.unwrap(), compiler_version: dxc.version, bytecode: compile_library(dxc, desc)? };
+        // Failing to write the cache only costs the next start a compile
+        if let Err(e) = dxil_cache::store(&cache_path, &entry) {
+            eprintln!("Failed to write {}: {}", cache_path.display(), e);
+        }
+        Ok(entry.bytecode)
+    }
+
+    // Leaves the current pipeline in place if the library doesn't compile or the state object can't be created
+    unsafe fn create_rt_pipeline_state(&mut self, desc: &PipelineDesc) -> Result<()> {
+        // We need the following subobjects:
+        //  1 for the DXIL library
+        //  1 for each hit-group
+        //  2 for each local root-signature (root-signature and the subobject association)
+        //  2 for shader config (shared between all programs. 1 for the config, 1 for association)
+        //  1 for pipeline config
+        //  1 for the global root signature
+        // The associations point into this vector, so it must not reallocate
+        let subobject_count = 1 + desc.hit_groups.len() + 2 * desc.local_root_signatures.len() + 2 + 1 + 1;
+        let mut subobjects: Vec<D3D12_STATE_SUBOBJECT> = Vec::with_capacity(subobject_count);
+
+        // Create the DXIL library
+        let mut dxil_lib = DxilLibrary::new();
+        dxil_lib.init(self.library_bytecode(desc)?, &desc.exports);
+        subobjects.push(dxil_lib.state_subobject);
+
+        // Create the hit groups. The subobjects point into the programs, so we box them to keep them in place
+        let mut hit_programs = Vec::new();
+        for hit_group in &desc.hit_groups {
+            let closest_hit: HSTRING = hit_group.closest_hit.as_str().into();
+            let mut hit_program = Box::new(HitProgram::new(&hit_group.name));
+            hit_program.init(PCWSTR::null(), (&closest_hit).into());
+            subobjects.push(hit_program.subobject);
+            hit_programs.push((hit_program, closest_hit));
+        }
+
+        // Create the local root-signatures and associate them with their programs
+        let mut root_signatures = Vec::new();
+        for (root_signature, exports) in &desc.local_root_signatures {
+            let mut root_desc = RootSignatureDesc::new();
+            root_desc.local_root_desc(*root_signature);
+            let mut local_root_signature = Box::new(RootSignature::new(&self.device, &root_desc.desc)?);
+            local_root_signature.init_local();
+            subobjects.push(local_root_signature.subobject);
+
+            let mut root_association = Box::new(ExportAssociation::new());
+            root_association.init(exports, &subobjects[subobjects.len() - 1]);
+            subobjects.push(root_association.subobject);
+            root_signatures.push((local_root_signature, root_association));
+        }
+
+        // Bind the payload size to the programs
+        let mut shader_config = ShaderConfig::new();
+        shader_config.init(desc.max_attribute_size, desc.max_payload_size);
+        subobjects.push(shader_config.subobject);
+
+        let mut config_association = ExportAssociation::new();
+        config_association.init(&desc.exports, &subobjects[subobjects.len() - 1]);
+        subobjects.push(config_association.subobject);
+
+        // Create the pipeline config
+        let mut config = PipelineConfig::new();
+        config.init(desc.max_recursion_depth);
+        subobjects.push(config.subobject);
+
+        // Create the global root signature
+        let mut global_desc = RootSignatureDesc::new();
+        global_desc.global_root_desc();
+        let mut root = RootSignature::new(&self.device, &global_desc.desc)?;
+        root.init_global();
+        subobjects.push(root.subobject);
+        debug_assert_eq!(subobjects.len(), subobject_count);
+
+        // Create the state
+        let max_depth = desc.max_depth;
+        let desc = D3D12_STATE_OBJECT_DESC {
+            Type: D3D12_STATE_OBJECT_TYPE_RAYTRACING_PIPELINE,
+            NumSubobjects: subobjects.len() as _,
+            pSubobjects: subobjects.as_ptr(),
+        };
+
+        let pipeline_state = self.device.CreateStateObject(&desc).map_err(alloc("CreateStateObject()"))?;
+
+        // Store the state object and the global signature
+        self.pipeline_state = Some(pipeline_state);
+        self.global_root_sig = Some(root.root_sig.clone());
+        self.max_depth = max_depth;
+        Ok(())
+    }
+    unsafe fn create_buffer(
+        &self,
+        size: u64,
+        flags: D3D12_RESOURCE_FLAGS,
+        init_state: D3D12_RESOURCE_STATES,
+        heap_props: &D3D12_HEAP_PROPERTIES,
+    ) -> Result<ID3D12Resource> {
+        create_buffer(&self.device, size, flags, init_state, heap_props)
+    }
+    unsafe fn create_vertex_buffer(&self, vertices: &[Vec3]) -> Result<ID3D12Resource> {
+        // Note: using upload heaps to transfer static data like vert buffers is
+        // not recommended. Every time the GPU needs it, the upload heap will be
+        // marshalled over. Please read up on Default Heap usage. An upload heap
+        // is used here for code simplicity and because there are very few verts
+        // to actually transfer.
+        self.create_upload_buffer(vertices)
+    }
+    unsafe fn create_index_buffer(&self, indices: &[u32], index_size: u32) -> Result<ID3D12Resource> {
+        // Same as the vertex buffer, this belongs in a default heap for anything bigger than a sample
+        if index_size == 4 {
+            return self.create_upload_buffer(indices);
+        }
+        let mut short: Vec<u16> = indices.iter().map(|i| *i as u16).collect();
+        if short.len() % 2 == 1 {
+            short.push(0);
+        }
+        self.create_upload_buffer(&short)
+    }
+    unsafe fn create_upload_buffer<T: Copy>(&self, data: &[T]) -> Result<ID3D12Resource> {
+        let buffer = self.create_buffer(size_of_val(data) as u64, D3D12_RESOURCE_FLAG_NONE, D3D12_RESOURCE_STATE_GENERIC_READ, &UPLOAD_HEAP_PROPS)?;
+        let mut mapped = std::ptr::null_mut();
+        buffer.Map(0, None, Some(&mut mapped)).map_err(api("Map()"))?;
+        memcpy(mapped, data.as_ptr(), size_of_val(data));
+        buffer.Unmap(0, None);
+        Ok(buffer)
+    }
+    // Records the upload of every mip level of a texture. The upload buffer has to stay alive until the copies are done
+    unsafe fn create_texture(&self, texture: &Texture) -> Result<(ID3D12Resource, ID3D12Resource)> {
+        let desc = D3D12_RESOURCE_DESC {
+            Dimension: D3D12_RESOURCE_DIMENSION_TEXTURE2D,
+            Alignment: 0,
+            Width: texture.width() as _,
+            Height: texture.height(),
+            DepthOrArraySize: 1,
+            MipLevels: texture.mips.len() as _,
+            Format: match texture.format {
+                TextureFormat::Rgba8Unorm => DXGI_FORMAT_R8G8B8A8_UNORM,
+                TextureFormat::Rgba8Srgb => DXGI_FORMAT_R8G8B8A8_UNORM_SRGB,
+                TextureFormat::Rgba32Float => DXGI_FORMAT_R32G32B32A32_FLOAT,
+            },
+            SampleDesc: DXGI_SAMPLE_DESC { Count: 1, Quality: 0 },
+            Layout: D3D12_TEXTURE_LAYOUT_UNKNOWN,
+            Flags: D3D12_RESOURCE_FLAG_NONE,
+        };
+        let mut resource: Option<ID3D12Resource> = None;
+        self.device.CreateCommittedResource(&DEFAULT_HEAP_PROPS, D3D12_HEAP_FLAG_NONE, &desc, D3D12_RESOURCE_STATE_COPY_DEST, None, &mut resource).map_err(alloc("CreateCommittedResource()"))?;
+        let resource = resource.unwrap();
+
+        // The rows of every level are padded to D3D12_TEXTURE_DATA_PITCH_ALIGNMENT in the upload buffer
+        let mut footprints = vec![D3D12_PLACED_SUBRESOURCE_FOOTPRINT::default(); texture.mips.len()];
+        let mut upload_size = 0;
+        self.device.GetCopyableFootprints(&desc, 0, footprints.len() as u32, 0, Some(footprints.as_mut_ptr()), None, None, Some(&mut upload_size));
+        let upload = self.create_buffer(upload_size, D3D12_RESOURCE_FLAG_NONE, D3D12_RESOURCE_STATE_GENERIC_READ, &UPLOAD_HEAP_PROPS)?;
+        let mut mapped = std::ptr::null_mut();
+        upload.Map(0, None, Some(&mut mapped)).map_err(api("Map()"))?;
+        for (level, (mip, footprint)) in texture.mips.iter().zip(&footprints).enumerate() {
+            let row_size = (mip.width * texture.format.bytes_per_texel()) as usize;
+            for (y, row) in mip.data.chunks_exact(row_size).enumerate() {
+                let dst = (mapped as *mut u8).add(footprint.Offset as usize + y * footprint.Footprint.RowPitch as usize);
+                memcpy(dst, row.as_ptr(), row_size);
+            }
+            let dst = D3D12_TEXTURE_COPY_LOCATION {
+                pResource: Some(resource.clone()),
+                Type: D3D12_TEXTURE_COPY_TYPE_SUBRESOURCE_INDEX,
+                Anonymous: D3D12_TEXTURE_COPY_LOCATION_0 { SubresourceIndex: level as u32 },
+            };
+            let src = D3D12_TEXTURE_COPY_LOCATION {
+                pResource: Some(upload.clone()),
+                Type: D3D12_TEXTURE_COPY_TYPE_PLACED_FOOTPRINT,
+                Anonymous: D3D12_TEXTURE_COPY_LOCATION_0 { PlacedFootprint: *footprint },
+            };
+            self.cmd_list.CopyTextureRegion(&dst, 0, 0, 0, &src, None);
+        }
+        upload.Unmap(0, None);
+        self.resource_barrier(resource.clone(), D3D12_RESOURCE_STATE_COPY_DEST, D3D12_RESOURCE_STATE_NON_PIXEL_SHADER_RESOURCE);
+        Ok((resource, upload))
+    }
+    unsafe fn create_geometry_buffers(&self, geometry: &Geometry) -> Result<GeometryBuffers> {
+        let indices = match &geometry.indices {
+            Some(indices) => indices.clone(),
+            None => (0..geometry.vertices.len() as u32).collect(),
+        };
+        let index_size = geometry.index_size();
+        Ok(GeometryBuffers {
+            vertex_buffer: self.create_vertex_buffer(&geometry.vertices)?,
+            vertex_count: geometry.vertices.len() as u32,
+            index_buffer: self.create_index_buffer(&indices, index_size)?,
+            index_count: indices.len() as u32,
+            index_size,
+            normal_buffer: geometry.normals.as_deref().map(|n| self.create_upload_buffer(n)).transpose()?,
+            tangent_buffer: geometry.tangents.as_deref().map(|t| self.create_upload_buffer(t)).transpose()?,
+            tex_coord_buffer: geometry.tex_coords.as_deref().map(|t| self.create_upload_buffer(t)).transpose()?,
+            attribute_flags: geometry.attribute_flags(),
+        })
+    }
+
+    unsafe fn create_blas(&self, geometries: &[GeometryBuffers]) -> Result<BLASBuffers> {
+        let mut geom_descs = Vec::new();
+        for geometry in geometries {
+            geom_descs.push(D3D12_RAYTRACING_GEOMETRY_DESC {
+                Type: D3D12_RAYTRACING_GEOMETRY_TYPE_TRIANGLES,
+                Flags: D3D12_RAYTRACING_GEOMETRY_FLAG_OPAQUE,
+                Anonymous: D3D12_RAYTRACING_GEOMETRY_DESC_0 {
+                    Triangles: D3D12_RAYTRACING_GEOMETRY_TRIANGLES_DESC {
+                        IndexFormat: if geometry.index_size == 2 { DXGI_FORMAT_R16_UINT } else { DXGI_FORMAT_R32_UINT },
+                        VertexFormat: DXGI_FORMAT_R32G32B32_FLOAT,
+                        IndexCount: geometry.index_count,
+                        VertexCount: geometry.vertex_count,
+                        IndexBuffer: geometry.index_buffer.GetGPUVirtualAddress(),
+                        VertexBuffer: D3D12_GPU_VIRTUAL_ADDRESS_AND_STRIDE {
+                            StartAddress: geometry.vertex_buffer.GetGPUVirtualAddress(),
+                            StrideInBytes: size_of::<Vec3>() as u64,
+                        },
+                        ..Default::default()
+                    }
+                },
+            });
+        }
+
+        // Get the size requirements for the scratch and AS buffers
+        let inputs = D3D12_BUILD_RAYTRACING_ACCELERATION_STRUCTURE_INPUTS {
+            Type: D3D12_RAYTRACING_ACCELERATION_STRUCTURE_TYPE_BOTTOM_LEVEL,
+            Flags: D3D12_RAYTRACING_ACCELERATION_STRUCTURE_BUILD_FLAG_NONE,
+            NumDescs: geom_descs.len() as _,
+            DescsLayout: D3D12_ELEMENTS_LAYOUT_ARRAY,
+            Anonymous: D3D12_BUILD_RAYTRACING_ACCELERATION_STRUCTURE_INPUTS_0 {
+                pGeometryDescs: geom_descs.as_ptr(),
+            },
+        };
+        let mut info = D3D12_RAYTRACING_ACCELERATION_STRUCTURE_PREBUILD_INFO::default();
+        self.device.GetRaytracingAccelerationStructurePrebuildInfo(&inputs, &mut info);
+
+        // Create the buffers. They need to support UAV, and since we are going to immediately use them, we create them with an unordered-access state
+        let buffers = BLASBuffers {
+            scratch: self.create_buffer(info.ScratchDataSizeInBytes, D3D12_RESOURCE_FLAG_ALLOW_UNORDERED_ACCESS, D3D12_RESOURCE_STATE_UNORDERED_ACCESS, &DEFAULT_HEAP_PROPS)?,
+            result: self.create_buffer(info.ResultDataMaxSizeInBytes, D3D12_RESOURCE_FLAG_ALLOW_UNORDERED_ACCESS, D3D12_RESOURCE_STATE_RAYTRACING_ACCELERATION_STRUCTURE, &DEFAULT_HEAP_PROPS)?,
+        };
+
+        // Create the bottom-level AS
+        let as_desc = D3D12_BUILD_RAYTRACING_ACCELERATION_STRUCTURE_DESC {
+            DestAccelerationStructureData: buffers.result.GetGPUVirtualAddress(),
+            Inputs: inputs,
+            ScratchAccelerationStructureData: buffers.scratch.GetGPUVirtualAddress(),
+            ..Default::default()
+        };
+
+        self.cmd_list.BuildRaytracingAccelerationStructure(&as_desc, None);
+
+        // We need to insert a UAV barrier before using the acceleration structures in a raytracing operation
+        self.resource_barrier_uav(buffers.result.clone());
+        Ok(buffers)
+    }
+    unsafe fn build_tlas(&mut self, update: bool, instances: &[Instance]) -> Result<()> {
+        // First, get the size of the TLAS buffers and create them
+        let mut inputs = D3D12_BUILD_RAYTRACING_ACCELERATION_STRUCTURE_INPUTS {
+            Type: D3D12_RAYTRACING_ACCELERATION_STRUCTURE_TYPE_TOP_LEVEL,
+            Flags: D3D12_RAYTRACING_ACCELERATION_STRUCTURE_BUILD_FLAG_ALLOW_UPDATE,
+            NumDescs: instances.len() as _,
+            DescsLayout: D3D12_ELEMENTS_LAYOUT_ARRAY,
+            ..Default::default()
+        };
+        let mut info = D3D12_RAYTRACING_ACCELERATION_STRUCTURE_PREBUILD_INFO::default();
+        self.device.GetRaytracingAccelerationStructurePrebuildInfo(&inputs, &mut info);
+
+        if update {
+            let tlas = self.tlas.as_ref().unwrap();
+            // If this a request for an update, then the TLAS was already used in a DispatchRay() call. We need a UAV barrier to make sure the read operation ends before updating the buffer
+            self.resource_barrier_uav(tlas.result.clone());
+        } else {
+            // If this is not an update operation then we need to create the buffers, otherwise we will refit in-place
+            let buffers = TLASBuffers {
+                scratch: self.create_buffer(info.ScratchDataSizeInBytes, D3D12_RESOURCE_FLAG_ALLOW_UNORDERED_ACCESS, D3D12_RESOURCE_STATE_UNORDERED_ACCESS, &DEFAULT_HEAP_PROPS)?,
+                result: self.create_buffer(info.ResultDataMaxSizeInBytes, D3D12_RESOURCE_FLAG_ALLOW_UNORDERED_ACCESS, D3D12_RESOURCE_STATE_RAYTRACING_ACCELERATION_STRUCTURE, &DEFAULT_HEAP_PROPS)?,
+                instance_desc: self.create_buffer((self.frames.len() * instances.len() * size_of::<D3D12_RAYTRACING_INSTANCE_DESC>()) as u64, D3D12_RESOURCE_FLAG_NONE, D3D12_RESOURCE_STATE_GENERIC_READ, &UPLOAD_HEAP_PROPS)?,
+                instance_count: instances.len(),
+            };
+            self.tlas = Some(buffers);
+        }
+        let buffers = self.tlas.as_ref().unwrap();
+        if instances.len() != buffers.instance_count {
+            return Err(Error::InvalidScene(format!("the TLAS was built with {} instances, not {}", buffers.instance_count, instances.len())));
+        }
+
+        // The instance desc should be inside a buffer, map the frame's slice of it
+        let slice_offset = self.frame_index * instances.len();
+        let mut instance_desc = std::ptr::null_mut();
+        buffers.instance_desc.Map(0, None, Some(&mut instance_desc)).map_err(api("Map()"))?;
+        let instance_desc: *mut D3D12_RAYTRACING_INSTANCE_DESC = (instance_desc as *mut D3D12_RAYTRACING_INSTANCE_DESC).add(slice_offset);
+
+        // Initialize the instance descs
+        for (i, instance) in instances.iter().enumerate() {
+            let instance_desc = instance_desc.add(i);
+            let m = instance.transform.transpose();
+            memcpy((*instance_desc).Transform.as_mut_ptr(), m.as_ref(), size_of_val(&((*instance_desc).Transform)));
+            (*instance_desc).AccelerationStructure = self.blas[instance.blas].GetGPUVirtualAddress();
+            (*instance_desc)._bitfield1 = ((instance.instance_mask as u32) << 24) | instance.instance_id;
+            (*instance_desc)._bitfield2 = instance.instance_contribution;
+        }
+
+        // Unmap
+        buffers.instance_desc.Unmap(0, None);
+
+        // Create the TLAS
+        inputs.Anonymous = D3D12_BUILD_RAYTRACING_ACCELERATION_STRUCTURE_INPUTS_0 {
+             InstanceDescs: buffers.instance_desc.GetGPUVirtualAddress() + (slice_offset * size_of::<D3D12_RAYTRACING_INSTANCE_DESC>()) as u64,
+        };
+        let mut as_desc = D3D12_BUILD_RAYTRACING_ACCELERATION_STRUCTURE_DESC {
+            DestAccelerationStructureData: buffers.result.GetGPUVirtualAddress(),
+            Inputs: inputs,
+            ScratchAccelerationStructureData: buffers.scratch.GetGPUVirtualAddress(),
+            ..Default::default()
+        };
+        // If this is an update operation, set the source buffer and the perform_update flag
+        if update {
+            as_desc.Inputs.Flags |= D3D12_RAYTRACING_ACCELERATION_STRUCTURE_BUILD_FLAG_PERFORM_UPDATE;
+            as_desc.SourceAccelerationStructureData = buffers.result.GetGPUVirtualAddress();
+        }
+
+        self.cmd_list.BuildRaytracingAccelerationStructure(&as_desc, None);
+
+        // We need to insert a UAV barrier before using the acceleration structures in a raytracing operation
+        let uav_barrier = D3D12_RESOURCE_BARRIER {
+            Type: D3D12_RESOURCE_BARRIER_TYPE_UAV,
+            Anonymous: D3D12_RESOURCE_BARRIER_0 {
+                UAV: ManuallyDrop::new(D3D12_RESOURCE_UAV_BARRIER{ pResource: Some(buffers.result.clone()) }),
+            },
+            ..Default::default()
+        };
+        self.cmd_list.ResourceBarrier(&[uav_barrier]);
+        Ok(())
+    }
+    unsafe fn create_acceleration_structures(&mut self, scene: &Scene) -> Result<()> {
+        // The scratch buffers must stay alive until the build is done
+        let mut bottom_level_buffers = Vec::new();
+        for blas in &scene.blas {
+            let geometry_buffers = blas.geometries.iter().map(|g| self.create_geometry_buffers(g)).collect::<Result<Vec<_>>>()?;
+            bottom_level_buffers.push(self.create_blas(&geometry_buffers)?);
+            self.geometry_buffers.push(geometry_buffers);
+        }
+
+        // A scene without lights gets a dummy element, gLightCount keeps the shaders from reading it
+        let mut lights: Vec<LightData> = scene.lights.iter().map(|l| l.data()).collect();
+        self.light_count = lights.len() as u32;
+        if lights.is_empty() {
+            lights.push(LightData::default());
+        }
+        self.light_buffer = Some(self.create_upload_buffer(&lights)?);
+
+        // The instances find their materials through InstanceID(), which only has 24 bits
+        if scene.materials.len() > 1 << 24 {
+            return Err(Error::InvalidScene(format!("{} materials don't fit in the 24-bit InstanceID()", scene.materials.len())));
+        }
+        let mut materials: Vec<MaterialData> = scene.materials.iter().map(|m| m.data()).collect();
+        if materials.is_empty() {
+            materials.push(MaterialData::default());
+        }
+        self.material_buffer = Some(self.create_upload_buffer(&materials)?);
+
+        // The upload buffers must stay alive until the copies are done, like the scratch buffers
+        let mut texture_uploads = Vec::new();
+        for texture in &scene.textures {
+            let (resource, upload) = self.create_texture(texture)?;
+            self.textures.push(resource);
+            texture_uploads.push(upload);
+        }
+
+        for bottom_level_buffer in &bottom_level_buffers {
+            self.blas.push(bottom_level_buffer.result.clone())
+        }
+
+        self.build_tlas(false, &scene.instances)?;
+
+        self.submit_cmd_list()?;
+        self.wait_for_gpu()?;
+        self.reset_cmd_list()
+    }
+    unsafe fn submit_cmd_list(&mut self) -> Result<()> {
+        self.cmd_list.Close().map_err(api("Close()"))?;
+        let command_list = ID3D12CommandList::from(&self.cmd_list);
+        self.cmd_queue.ExecuteCommandLists(&[Some(command_list)]);
+        self.fence_value += 1;
+        self.cmd_queue.Signal(&self.fence, self.fence_value).map_err(api("Signal()"))
+    }
+    unsafe fn wait_for_gpu(&self) -> Result<()> {
+        self.wait_for_fence(self.fence_value)
+    }
+    unsafe fn wait_for_fence(&self, value: u64) -> Result<()> {
+        if self.fence.GetCompletedValue() < value {
+            self.fence.SetEventOnCompletion(value, self.fence_event).map_err(api("SetEventOnCompletion()"))?;
+            WaitForSingleObject(self.fence_event, INFINITE);
+        }
+        Ok(())
+    }
+    // Starts recording with the allocator of the current frame, which the GPU must be done with
+    unsafe fn reset_cmd_list(&mut self) -> Result<()> {
+        let allocator = &self.frames[self.frame_index].cmd_allocator;
+        allocator.Reset().map_err(api("Reset()"))?;
+        self.cmd_list.Reset(allocator, None).map_err(api("Reset()"))
+    }
+    // Pass no window to render headless. Fails with Error::RaytracingNotSupported if there is no DXR capable device.
+    // With more than one frame in flight the CPU records the next frame while the GPU traces the previous ones, up to
+    // MAX_FRAMES_IN_FLIGHT, more fail with Error::InvalidConfig
+    pub unsafe fn new(hwnd: Option<HWND>, width: i32, height: i32, frames_in_flight: usize) -> Result<Self> {
+        if !(1..=MAX_FRAMES_IN_FLIGHT).contains(&frames_in_flight) {
+            return Err(Error::InvalidConfig(format!("frames_in_flight must be between 1 and {}, got {}", MAX_FRAMES_IN_FLIGHT, frames_in_flight)));
+        }
+        if DEBUG_MODE {
+            let mut debug: Option<ID3D12Debug> = None;
+            if let Some(debug) = D3D12GetDebugInterface(&mut debug).ok().and(debug) {
+                debug.EnableDebugLayer();
+            }
+        }
+        let dxgi_factory_flags = if cfg!(debug_assertions) { DXGI_CREATE_FACTORY_DEBUG } else { 0 };
+        let dxgi_factory: IDXGIFactory4 = CreateDXGIFactory2(dxgi_factory_flags).map_err(api("CreateDXGIFactory2()"))?;
+        let device = create_device(dxgi_factory.clone())?;
+        let cmd_queue = create_command_queue(device.clone())?;
+        let swap_chain = hwnd.map(|hwnd| create_dxgi_swap_chain(dxgi_factory.clone(), hwnd, width, height, DXGI_FORMAT_R8G8B8A8_UNORM, cmd_queue.clone())).transpose()?;
+        if let Some(hwnd) = hwnd {
+            // The window does its own borderless fullscreen on alt-enter, DXGI's exclusive mode would bypass resize()
+            dxgi_factory.MakeWindowAssociation(hwnd, DXGI_MWA_NO_ALT_ENTER).map_err(api("MakeWindowAssociation()"))?;
+        }
+        let mut rtv_heap = HeapData {
+            heap: create_descriptor_heap(&device, RTV_HEAP_SIZE, D3D12_DESCRIPTOR_HEAP_TYPE_RTV, false)?,
+            used_entries: 0,
+        };
+
+        let frame_objects: [FrameObject; DEFAULT_SWAP_CHAIN_BUFFERS as usize] = array_init::try_array_init(|i: usize| -> Result<FrameObject> {
+            let swap_chain_buffer: Option<ID3D12Resource> = swap_chain.as_ref().map(|swap_chain| swap_chain.GetBuffer(i as u32)).transpose().map_err(api("GetBuffer()"))?;
+            let rtv_handle = match &swap_chain_buffer {
+                Some(buffer) => create_rtv(device.clone(), buffer, &mut rtv_heap, DXGI_FORMAT_R8G8B8A8_UNORM_SRGB),
+                None => D3D12_CPU_DESCRIPTOR_HANDLE::default(),
+            };
+            Ok(FrameObject {
+                swap_chain_buffer,
+                rtv_handle,
+            })
+        })?;
+        let frames = (0..frames_in_flight)
+            .map(|_| -> Result<FrameContext> {
+                let cmd_allocator = device.CreateCommandAllocator(D3D12_COMMAND_LIST_TYPE_DIRECT).map_err(alloc("CreateCommandAllocator()"))?;
+                Ok(FrameContext { cmd_allocator, fence_value: 0, timed_passes: Vec::new() })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let cmd_list: ID3D12GraphicsCommandList4 = device.CreateCommandList(0, D3D12_COMMAND_LIST_TYPE_DIRECT, &frames[0].cmd_allocator, None).map_err(alloc("CreateCommandList()"))?;
+        let fence: ID3D12Fence = device.CreateFence(0, D3D12_FENCE_FLAG_NONE).map_err(alloc("CreateFence()"))?;
+        let fence_event: HANDLE = CreateEventW(None, false, false, None).map_err(api("CreateEventW()"))?;
+        let timestamp_count = TIMESTAMPS_PER_FRAME * frames_in_flight;
+        let heap_desc = D3D12_QUERY_HEAP_DESC { Type: D3D12_QUERY_HEAP_TYPE_TIMESTAMP, Count: timestamp_count as u32, NodeMask: 0 };
+        let mut timestamp_heap: Option<ID3D12QueryHeap> = None;
+        device.CreateQueryHeap(&heap_desc, &mut timestamp_heap).map_err(alloc("CreateQueryHeap()"))?;
+        let timestamp_readback = create_buffer(&device, (timestamp_count * size_of::<u64>()) as u64, D3D12_RESOURCE_FLAG_NONE, D3D12_RESOURCE_STATE_COPY_DEST, &READBACK_HEAP_PROPS)?;
+        let timestamp_frequency = cmd_queue.GetTimestampFrequency().map_err(api("GetTimestampFrequency()"))?;
+        Ok(Self {
+            swap_chain_size: ivec2(width, height),
+            dxgi_factory,
+            device,
+            cmd_queue,
+            swap_chain,
+            rtv_heap,
+            frame_objects,
+            frames,
+            frame_index: 0,
+            cmd_list,
+            fence,
+            fence_event,
+            fence_value: 0,
+            geometry_buffers: Vec::new(),
+            tlas: None,
+            blas: Vec::new(),
+            pipeline_state: None,
+            global_root_sig: None,
+            light_buffer: None,
+            light_count: 0,
+            max_depth: 0,
+            material_buffer: None,
+            textures: Vec::new(),
+            blue_noise_buffer: None,
+            shader_table: None,
+            shader_table_layout: None,
+            output_resource: None,
+            accumulation_resource: None,
+            hdr_output_resource: None,
+            srv_uav_heap: None,
+            constant_buffers: Vec::new(),
+            frame_constants_buffer: None,
+            frame_constants_data: std::ptr::null_mut(),
+            dxc: None,
+            timestamp_heap: timestamp_heap.unwrap(),
+            timestamp_readback,
+            timestamp_frequency,
+            timestamp_origin: None,
+            frame_timings: Vec::new(),
+        })
+    }
+    unsafe fn create_constant_buffers(&mut self, shader_table: &ShaderTable) -> Result<()> {
+        let records = std::iter::once(&shader_table.ray_gen).chain(&shader_table.miss).chain(&shader_table.hit_groups);
+        for argument in records.flat_map(|record| &record.root_arguments) {
+            if let RootArgument::ConstantBuffer(buffer_data) = argument {
+                let buffer_size = size_of_val(buffer_data.as_slice());
+                let constant_buffer = self.create_buffer(buffer_size as u64, D3D12_RESOURCE_FLAG_NONE, D3D12_RESOURCE_STATE_GENERIC_READ, &UPLOAD_HEAP_PROPS)?;
+                let mut data = std::ptr::null_mut();
+                constant_buffer.Map(0, None, Some(&mut data)).map_err(api("Map()"))?;
+                memcpy(data, buffer_data.as_ptr(), buffer_size);
+                constant_buffer.Unmap(0, None);
+                self.constant_buffers.push(constant_buffer);
+            }
+        }
+
+        let frame_constants_size = FRAME_CONSTANTS_STRIDE * self.frames.len();
+        let frame_constants_buffer = self.create_buffer(frame_constants_size as u64, D3D12_RESOURCE_FLAG_NONE, D3D12_RESOURCE_STATE_GENERIC_READ, &UPLOAD_HEAP_PROPS)?;
+        let mut data = std::ptr::null_mut();
+        frame_constants_buffer.Map(0, None, Some(&mut data)).map_err(api("Map()"))?;
+        self.frame_constants_data = data as *mut u8;
+        self.frame_constants_buffer = Some(frame_constants_buffer);
+        Ok(())
+    }
+    unsafe fn begin_frame(&mut self) -> usize {
+        // Bind the descriptor heaps
+        self.cmd_list.SetDescriptorHeaps(&[self.srv_uav_heap.clone()]);
+        self.back_buffer_index()
+    }
+    unsafe fn back_buffer_index(&self) -> usize {
+        // Without a swap chain we always record into the first frame object
+        self.swap_chain.as_ref().map_or(0, |swap_chain| swap_chain.GetCurrentBackBufferIndex() as usize)
+    }
+    unsafe fn end_frame(&mut self, rtv_index: usize) -> Result<()> {
+        if let Some(back_buffer) = self.frame_objects[rtv_index].swap_chain_buffer.clone() {
+            self.resource_barrier(back_buffer, D3D12_RESOURCE_STATE_COPY_DEST, D3D12_RESOURCE_STATE_PRESENT);
+        }
+        self.submit_cmd_list()?;
+        self.frames[self.frame_index].fence_value = self.fence_value;
+        if let Some(swap_chain) = &self.swap_chain {
+            swap_chain.Present(0, 0).ok().map_err(api("Present()"))?;
+        }
+
+        // Prepare the command list for the next frame. Only the frame that last used its resources has to be done,
+        // the others keep the GPU busy while we record
+        self.frame_index = (self.frame_index + 1) % self.frames.len();
+        self.wait_for_fence(self.frames[self.frame_index].fence_value)?;
+        self.collect_timestamps()?;
+        self.reset_cmd_list()
+    }
+    unsafe fn on_frame_render(&mut self) -> Result<()> {
+        let rtv_index: usize = self.begin_frame();
+
+        // Let's raytrace
+        self.resource_barrier(self.output_resource.clone().unwrap(), D3D12_RESOURCE_STATE_COPY_SOURCE, D3D12_RESOURCE_STATE_UNORDERED_ACCESS);
+        let layout = self.shader_table_layout.unwrap();
+        let st_gpu_address = self.shader_table.as_ref().unwrap().GetGPUVirtualAddress() + self.frame_index as u64 * self.shader_table_stride(&layout);
+        let ranges = layout.dispatch_ranges(st_gpu_address);
+        let raytrace_desc = D3D12_DISPATCH_RAYS_DESC {
+            // RayGen is the first entry in the shader-table
+            RayGenerationShaderRecord: D3D12_GPU_VIRTUAL_ADDRESS_RANGE {
+                StartAddress: ranges.ray_gen.start_address,
+                SizeInBytes: ranges.ray_gen.size_in_bytes,
+            },
+            MissShaderTable: D3D12_GPU_VIRTUAL_ADDRESS_RANGE_AND_STRIDE {
+                StartAddress: ranges.miss.start_address,
+                SizeInBytes: ranges.miss.size_in_bytes,
+                StrideInBytes: ranges.miss.stride_in_bytes,
+            },
+            HitGroupTable: D3D12_GPU_VIRTUAL_ADDRESS_RANGE_AND_STRIDE {
+                StartAddress: ranges.hit_group.start_address,
+                SizeInBytes: ranges.hit_group.size_in_bytes,
+                StrideInBytes: ranges.hit_group.stride_in_bytes,
+            },
+            Width: self.swap_chain_size.x as _,
+            Height: self.swap_chain_size.y as _,
+            Depth: 1,
+            ..Default::default()
+        };
+
+        // Bind the global root signature, the lights, the materials, the blue-noise tile and the textures
+        self.begin_pass(Pass::DispatchRays);
+        self.cmd_list.SetComputeRootSignature(self.global_root_sig.as_ref().unwrap());
+        self.cmd_list.SetComputeRoot32BitConstant(0, self.light_count, 0);
+        self.cmd_list.SetComputeRoot32BitConstant(0, self.max_depth, 1);
+        self.cmd_list.SetComputeRootShaderResourceView(1, self.light_buffer.as_ref().unwrap().GetGPUVirtualAddress());
+        self.cmd_list.SetComputeRootShaderResourceView(2, self.material_buffer.as_ref().unwrap().GetGPUVirtualAddress());
+        self.cmd_list.SetComputeRootShaderResourceView(3, self.blue_noise_buffer.as_ref().unwrap().GetGPUVirtualAddress());
+        let mut textures = self.srv_uav_heap.as_ref().unwrap().GetGPUDescriptorHandleForHeapStart();
+        textures.ptr += (TEXTURE_SRV_SLOT * self.device.GetDescriptorHandleIncrementSize(D3D12_DESCRIPTOR_HEAP_TYPE_CBV_SRV_UAV)) as u64;
+        self.cmd_list.SetComputeRootDescriptorTable(4, textures);
+
+        // Dispatch
+        self.cmd_list.SetPipelineState1(self.pipeline_state.as_ref().unwrap());
+        self.cmd_list.DispatchRays(&raytrace_desc);
+        self.end_pass(Pass::DispatchRays);
+
+        // Copy the results to the back-buffer
+        self.resource_barrier(self.output_resource.clone().unwrap(), D3D12_RESOURCE_STATE_UNORDERED_ACCESS, D3D12_RESOURCE_STATE_COPY_SOURCE);
+        if let Some(back_buffer) = self.frame_objects[rtv_index].swap_chain_buffer.clone() {
+            self.begin_pass(Pass::BackBufferCopy);
+            self.resource_barrier(back_buffer.clone(), D3D12_RESOURCE_STATE_PRESENT, D3D12_RESOURCE_STATE_COPY_DEST);
+            self.cmd_list.CopyResource(&back_buffer, self.output_resource.as_ref().unwrap());
+            self.end_pass(Pass::BackBufferCopy);
+        }
+
+        self.end_frame(rtv_index)
+    }
+    // The timestamps of a pass are at 2 * pass and 2 * pass + 1 in the frame's range of the query heap
+    fn timestamp_index(&self, pass: Pass) -> u32 {
+        (self.frame_index * TIMESTAMPS_PER_FRAME + 2 * pass as usize) as u32
+    }
+    unsafe fn begin_pass(&mut self, pass: Pass) {
+        self.cmd_list.EndQuery(&self.timestamp_heap, D3D12_QUERY_TYPE_TIMESTAMP, self.timestamp_index(pass));
+    }
+    unsafe fn end_pass(&mut self, pass: Pass) {
+        let index = self.timestamp_index(pass);
+        self.cmd_list.EndQuery(&self.timestamp_heap, D3D12_QUERY_TYPE_TIMESTAMP, index + 1);
+        self.cmd_list.ResolveQueryData(&self.timestamp_heap, D3D12_QUERY_TYPE_TIMESTAMP, index, 2, &self.timestamp_readback, index as u64 * size_of::<u64>() as u64);
+        let timed_passes = &mut self.frames[self.frame_index].timed_passes;
+        if !timed_passes.contains(&pass) {
+            timed_passes.push(pass);
+        }
+    }
+    // Reads the timestamps of the current frame context, whose last frame the GPU has finished
+    unsafe fn collect_timestamps(&mut self) -> Result<()> {
+        let passes = std::mem::take(&mut self.frames[self.frame_index].timed_passes);
+        if passes.is_empty() {
+            return Ok(());
+        }
+        let mut data = std::ptr::null_mut();
+        self.timestamp_readback.Map(0, None, Some(&mut data)).map_err(api("Map()"))?;
+        let first = self.frame_index * TIMESTAMPS_PER_FRAME;
+        let ticks = std::slice::from_raw_parts((data as *const u64).add(first), TIMESTAMPS_PER_FRAME).to_vec();
+        self.timestamp_readback.Unmap(0, None);
+
+        let origin = *self.timestamp_origin.get_or_insert(ticks[2 * passes[0] as usize]);
+        let ms = |t: u64| (t as f64 - origin as f64) * 1000.0 / self.timestamp_frequency as f64;
+        let passes = passes
+            .into_iter()
+            .map(|pass| {
+                let (begin, end) = (ticks[2 * pass as usize], ticks[2 * pass as usize + 1]);
+                PassTiming { pass, start_ms: ms(begin), duration_ms: ms(end) - ms(begin) }
+            })
+            .collect();
+        self.frame_timings.push(FrameTimings { passes });
+        Ok(())
+    }
+    unsafe fn on_shutdown(&mut self) -> Result<()> {
+        // Wait for the command queue to finish execution
+        self.flush_cmd_queue()
+    }
+    unsafe fn flush_cmd_queue(&mut self) -> Result<()> {
+        self.fence_value += 1;
+        self.cmd_queue.Signal(&self.fence, self.fence_value).map_err(api("Signal()"))?;
+        self.wait_for_gpu()
+    }
+    // Recreates everything that depends on the window size. ResizeBuffers() fails while anything references the
+    // back buffers, so we wait for the GPU and drop our references first. The command list is empty between frames
+    unsafe fn resize_output(&mut self, width: i32, height: i32) -> Result<()> {
+        if ivec2(width, height) == self.swap_chain_size {
+            return Ok(());
+        }
+        self.flush_cmd_queue()?;
+        self.swap_chain_size = ivec2(width, height);
+        if let Some(swap_chain) = self.swap_chain.clone() {
+            for frame in &mut self.frame_objects {
+                frame.swap_chain_buffer = None;
+            }
+            swap_chain.ResizeBuffers(0, width as u32, height as u32, DXGI_FORMAT_UNKNOWN, 0).map_err(api("ResizeBuffers()"))?;
+            self.rtv_heap.used_entries = 0;
+            for (i, frame) in self.frame_objects.iter_mut().enumerate() {
+                let buffer: ID3D12Resource = swap_chain.GetBuffer(i as u32).map_err(api("GetBuffer()"))?;
+                frame.rtv_handle = create_rtv(self.device.clone(), &buffer, &mut self.rtv_heap, DXGI_FORMAT_R8G8B8A8_UNORM_SRGB);
+                frame.swap_chain_buffer = Some(buffer);
+            }
+        }
+        self.create_output_resource()
+    }
+    // Copies a texture in the copy-source state into a readback buffer and returns its rows without padding. Must be
+    // called between frames
+    unsafe fn readback_texture(&mut self, resource: ID3D12Resource) -> Result<Vec<u8>> {
+        let desc = resource.GetDesc();
+
+        // Texture rows in a buffer are aligned to D3D12_TEXTURE_DATA_PITCH_ALIGNMENT, so we ask for the footprint
+        let mut footprint = D3D12_PLACED_SUBRESOURCE_FOOTPRINT::default();
+        let mut row_size = 0u64;
+        let mut total_size = 0u64;
+        self.device.GetCopyableFootprints(&desc, 0, 1, 0, Some(&mut footprint), None, Some(&mut row_size), Some(&mut total_size));
+        let readback_buffer = self.create_buffer(total_size, D3D12_RESOURCE_FLAG_NONE, D3D12_RESOURCE_STATE_COPY_DEST, &READBACK_HEAP_PROPS)?;
+
+        let dst = D3D12_TEXTURE_COPY_LOCATION {
+            pResource: Some(readback_buffer.clone()),
+            Type: D3D12_TEXTURE_COPY_TYPE_PLACED_FOOTPRINT,
+            Anonymous: D3D12_TEXTURE_COPY_LOCATION_0 { PlacedFootprint: footprint },
+        };
+        let src = D3D12_TEXTURE_COPY_LOCATION {
+            pResource: Some(resource),
+            Type: D3D12_TEXTURE_COPY_TYPE_SUBRESOURCE_INDEX,
+            Anonymous: D3D12_TEXTURE_COPY_LOCATION_0 { SubresourceIndex: 0 },
+        };
+        self.cmd_list.CopyTextureRegion(&dst, 0, 0, 0, &src, None);
+
+        self.submit_cmd_list()?;
+        self.wait_for_gpu()?;
+        self.reset_cmd_list()?;
+
+        // Remove the row padding
+        let row_size = row_size as usize;
+        let mut rows = vec![0u8; row_size * desc.Height as usize];
+        let mut data: *mut u8 = std::ptr::null_mut();
+        readback_buffer.Map(0, None, Some(&mut data as *mut *mut u8 as _)).map_err(api("Map()"))?;
+        for y in 0..desc.Height as usize {
+            let src = data.add(footprint.Offset as usize + y * footprint.Footprint.RowPitch as usize);
+            memcpy(rows[y * row_size..].as_mut_ptr(), src, row_size);
+        }
+        readback_buffer.Unmap(0, None);
+        Ok(rows)
+    }
+
+    // The output resource is in the copy-source state between frames
+    unsafe fn readback_output(&mut self) -> Result<Image> {
+        let (width, height) = self.output_size();
+        let data = self.readback_texture(self.output_resource.clone().unwrap())?;
+        Ok(Image { width, height, data })
+    }
+
+    unsafe fn readback_hdr_output(&mut self) -> Result<HdrImage> {
+        let (width, height) = self.output_size();
+        let resource = self.hdr_output_resource.clone().unwrap();
+        self.resource_barrier(resource.clone(), D3D12_RESOURCE_STATE_UNORDERED_ACCESS, D3D12_RESOURCE_STATE_COPY_SOURCE);
+        let data = self.readback_texture(resource.clone())?;
+        self.resource_barrier(resource, D3D12_RESOURCE_STATE_COPY_SOURCE, D3D12_RESOURCE_STATE_UNORDERED_ACCESS);
+        let texels = data.chunks_exact(4).map(|c| f32::from_le_bytes(c.try_into().unwrap())).collect::<Vec<_>>();
+        Ok(HdrImage { width, height, data: texels.chunks_exact(4).map(Vec4::from_slice).collect() })
+    }
+
+    // A texture of the output size with a UAV in the given slot of the SRV/UAV heap
+    unsafe fn create_uav_texture(&self, format: DXGI_FORMAT, initial_state: D3D12_RESOURCE_STATES, slot: u32) -> Result<ID3D12Resource> {
+        let res_desc = D3D12_RESOURCE_DESC {
+            Dimension: D3D12_RESOURCE_DIMENSION_TEXTURE2D,
+            Alignment: 0,
+            Width: self.swap_chain_size.x as _,
+            Height: self.swap_chain_size.y as _,
+            DepthOrArraySize: 1,
+            MipLevels: 1,
+            Format: format,
+            SampleDesc: DXGI_SAMPLE_DESC { Count: 1, Quality: 0 },
+            Layout: D3D12_TEXTURE_LAYOUT_UNKNOWN,
+            Flags: D3D12_RESOURCE_FLAG_ALLOW_UNORDERED_ACCESS,
+        };
+        let mut resource: Option<ID3D12Resource> = None;
+        self.device.CreateCommittedResource(
+            &DEFAULT_HEAP_PROPS,
+            D3D12_HEAP_FLAG_NONE,
+            &res_desc,
+            initial_state,
+            None,
+            &mut resource).map_err(alloc("CreateCommittedResource()"))?;
+
+        let uav_desc = D3D12_UNORDERED_ACCESS_VIEW_DESC {
+            ViewDimension: D3D12_UAV_DIMENSION_TEXTURE2D,
+            ..Default::default()
+        };
+        let mut handle = self.srv_uav_heap.as_ref().unwrap().GetCPUDescriptorHandleForHeapStart();
+        handle.ptr += (slot * self.device.GetDescriptorHandleIncrementSize(D3D12_DESCRIPTOR_HEAP_TYPE_CBV_SRV_UAV)) as usize;
+        self.device.CreateUnorderedAccessView(resource.as_ref().unwrap(), None, Some(&uav_desc), handle);
+        Ok(resource.unwrap())
+    }
+
+    // Creates the output, accumulation and HDR output textures and their UAVs. Called again when the size changes
+    unsafe fn create_output_resource(&mut self) -> Result<()> {
+        // The dimensions and format should match the swap-chain. The backbuffer is actually DXGI_FORMAT_R8G8B8A8_UNORM_SRGB,
+        // but sRGB formats can't be used with UAVs. We will convert to sRGB ourselves in the shader
+        self.output_resource = Some(self.create_uav_texture(DXGI_FORMAT_R8G8B8A8_UNORM, D3D12_RESOURCE_STATE_COPY_SOURCE, OUTPUT_UAV_SLOT)?);
+        // The running average in linear color. rayGen() starts over after a resize, so its content doesn't matter
+        self.accumulation_resource = Some(self.create_uav_texture(DXGI_FORMAT_R32G32B32A32_FLOAT, D3D12_RESOURCE_STATE_UNORDERED_ACCESS, ACCUMULATION_UAV_SLOT)?);
+        self.hdr_output_resource = Some(self.create_uav_texture(DXGI_FORMAT_R32G32B32A32_FLOAT, D3D12_RESOURCE_STATE_UNORDERED_ACCESS, HDR_OUTPUT_UAV_SLOT)?);
+        Ok(())
+    }
+
+    unsafe fn create_shader_resources(&mut self) -> Result<()> {
+        // Create an SRV/UAV descriptor heap. Need 4 entries - 1 SRV for the scene, 1 UAV for the output, 1 for the accumulation
+        // and 1 for the HDR output - and then 1 SRV per texture. A scene without textures gets a null SRV, so gTextures
+        // isn't empty
+        let texture_count = self.textures.len().max(1) as u32;
+        let srv_uav_heap = create_descriptor_heap(&self.device, TEXTURE_SRV_SLOT + texture_count, D3D12_DESCRIPTOR_HEAP_TYPE_CBV_SRV_UAV, true)?;
+
+        // Create the TLAS SRV right after the output UAV. Note that we are using a different SRV desc here
+        let srv_desc = D3D12_SHADER_RESOURCE_VIEW_DESC {
+            Format: DXGI_FORMAT_UNKNOWN,
+            ViewDimension: D3D12_SRV_DIMENSION_RAYTRACING_ACCELERATION_STRUCTURE,
+            Shader4ComponentMapping: D3D12_DEFAULT_SHADER_4_COMPONENT_MAPPING,
+            Anonymous: D3D12_SHADER_RESOURCE_VIEW_DESC_0 {
+                RaytracingAccelerationStructure: D3D12_RAYTRACING_ACCELERATION_STRUCTURE_SRV {
+                    Location: self.tlas.as_ref().unwrap().result.GetGPUVirtualAddress(),
+                },
+            },
+        };
+        let mut srv_handle = srv_uav_heap.GetCPUDescriptorHandleForHeapStart();
+        srv_handle.ptr += (TLAS_SRV_SLOT * self.device.GetDescriptorHandleIncrementSize(D3D12_DESCRIPTOR_HEAP_TYPE_CBV_SRV_UAV)) as usize;
+        self.device.CreateShaderResourceView(None, Some(&srv_desc), srv_handle);
+
+        // The textures follow the fixed slots
+        let increment = self.device.GetDescriptorHandleIncrementSize(D3D12_DESCRIPTOR_HEAP_TYPE_CBV_SRV_UAV) as usize;
+        for slot in 0..texture_count {
+            let texture = self.textures.get(slot as usize);
+            let (format, mip_levels) = texture.map_or((DXGI_FORMAT_R8G8B8A8_UNORM, 1), |t| {
+                let desc = t.GetDesc();
+                (desc.Format, desc.MipLevels as u32)
+            });
+            let srv_desc = D3D12_SHADER_RESOURCE_VIEW_DESC {
+                Format: format,
+                ViewDimension: D3D12_SRV_DIMENSION_TEXTURE2D,
+                Shader4ComponentMapping: D3D12_DEFAULT_SHADER_4_COMPONENT_MAPPING,
+                Anonymous: D3D12_SHADER_RESOURCE_VIEW_DESC_0 {
+                    Texture2D: D3D12_TEX2D_SRV { MostDetailedMip: 0, MipLevels: mip_levels, PlaneSlice: 0, ResourceMinLODClamp: 0.0 },
+                },
+            };
+            let mut handle = srv_uav_heap.GetCPUDescriptorHandleForHeapStart();
+            handle.ptr += (TEXTURE_SRV_SLOT + slot) as usize * increment;
+            self.device.CreateShaderResourceView(texture, Some(&srv_desc), handle);
+        }
+
+        self.srv_uav_heap = Some(srv_uav_heap);
+        self.blue_noise_buffer = Some(self.create_upload_buffer(blue_noise_tile())?);
+        self.create_output_resource()
+    }
+}
+
+impl Backend for D3D12Backend {
+    fn build_acceleration_structures(&mut self, scene: &Scene) -> Result<()> {
+        unsafe {
+            self.create_acceleration_structures(scene)?;
+            self.create_shader_resources()
+        }
+    }
+    fn create_pipeline(&mut self, pipeline: &PipelineDesc) -> Result<()> {
+        unsafe { self.create_rt_pipeline_state(pipeline) }
+    }
+    fn reload_pipeline(&mut self, pipeline: &PipelineDesc, shader_table: &ShaderTable) -> Result<()> {
+        // Frames in flight may still use the old state object and shader table
+        unsafe {
+            self.flush_cmd_queue()?;
+            self.create_rt_pipeline_state(pipeline)?;
+            // The records hold the identifiers of the old state object. The constant buffers are reused
+            self.create_shader_table(shader_table)
+        }
+    }
+    fn bind_shader_table(&mut self, shader_table: &ShaderTable) -> Result<()> {
+        unsafe {
+            self.create_constant_buffers(shader_table)?;
+            self.create_shader_table(shader_table)
+        }
+    }
+    fn refit_tlas(&mut self, instances: &[Instance]) -> Result<()> {
+        unsafe {
+            self.begin_pass(Pass::TlasRefit);
+            self.build_tlas(true, instances)?;
+            self.end_pass(Pass::TlasRefit);
+        }
+        Ok(())
+    }
+    fn update_frame_constants(&mut self, constants: &FrameConstants) {
+        assert!(!self.frame_constants_data.is_null(), "bind_shader_table() must be called before update_frame_constants()");
+        // The slot of the frame being recorded, which the GPU is done with
+        unsafe { (self.frame_constants_data.add(self.frame_index * FRAME_CONSTANTS_STRIDE) as *mut FrameConstants).write(*constants) };
+    }
+    fn dispatch_rays(&mut self) -> Result<()> {
+        unsafe { self.on_frame_render() }
+    }
+    fn output_size(&self) -> (u32, u32) {
+        (self.swap_chain_size.x as u32, self.swap_chain_size.y as u32)
+    }
+    fn resize(&mut self, width: u32, height: u32) -> Result<()> {
+        unsafe { self.resize_output(width as _, height as _) }
+    }
+    fn readback(&mut self) -> Result<Image> {
+        unsafe { self.readback_output() }
+    }
+    fn readback_hdr(&mut self) -> Result<HdrImage> {
+        unsafe { self.readback_hdr_output() }
+    }
+    fn shutdown(&mut self) -> Result<()> {
+        unsafe { self.on_shutdown() }
+    }
+    fn take_frame_timings(&mut self) -> Vec<FrameTimings> {
+        std::mem::take(&mut self.frame_timings)
+    }
+}
@@ -0,0 +1,60 @@
+use crate::bvh::TraversalStats;
+use crate::frame::FrameConstants;
+use crate::error::Result;
+use crate::framebuffer::{HdrImage, Image};
+use crate::pipeline::PipelineDesc;
+use crate::profiler::FrameTimings;
+use crate::scene::{Instance, Scene};
+use crate::shader_table::ShaderTable;
+
+pub mod cpu;
+#[cfg(windows)]
+pub mod d3d12;
+
+// The most frames a backend records ahead of the GPU
+pub const MAX_FRAMES_IN_FLIGHT: usize = 3;
+
+// The operations the tutorial needs from a raytracing API. The calls are made in the order they are declared:
+// acceleration structures, pipeline and shader table once, followed by refit_tlas()/dispatch_rays() every frame
+pub trait Backend {
+    // Builds the BLASes and the TLAS, and creates the resources the shaders access (the output UAVs, the TLAS SRV, the
+    // geometry buffers, the lights, the materials and the textures)
+    fn build_acceleration_structures(&mut self, scene: &Scene) -> Result<()>;
+    fn create_pipeline(&mut self, pipeline: &PipelineDesc) -> Result<()>;
+    // Recompiles the library and rebuilds the pipeline, then rewrites the shader table with the new shader
+    // identifiers. On failure the previous pipeline stays in use. Backends whose programs aren't compiled from the
+    // library have nothing to reload
+    fn reload_pipeline(&mut self, _pipeline: &PipelineDesc, _shader_table: &ShaderTable) -> Result<()> {
+        Ok(())
+    }
+    // Writes the shader table. Records refer to the exports and hit groups of the last pipeline
+    fn bind_shader_table(&mut self, shader_table: &ShaderTable) -> Result<()>;
+    // Updates the TLAS with new instance transforms
+    fn refit_tlas(&mut self, instances: &[Instance]) -> Result<()>;
+    // Updates the constant buffer of the ray-gen record
+    fn update_frame_constants(&mut self, constants: &FrameConstants);
+    // Traces the frame into the output UAV and presents it if the backend has a swap chain
+    fn dispatch_rays(&mut self) -> Result<()>;
+    // The size of the output UAV, which is also the size of the dispatch
+    fn output_size(&self) -> (u32, u32);
+    // Recreates the output, and the swap chain buffers if there are any, for a new window size. Waits for the GPU
+    #[cfg(windows)]
+    fn resize(&mut self, width: u32, height: u32) -> Result<()>;
+    // Returns the content of the output UAV
+    fn readback(&mut self) -> Result<Image>;
+    // Returns the content of the HDR output UAV, the linear color before the output encoding
+    fn readback_hdr(&mut self) -> Result<HdrImage>;
+    // Waits for any outstanding work
+    fn shutdown(&mut self) -> Result<()> {
+        Ok(())
+    }
+    // The work done traversing the acceleration structures, for backends that can count it
+    fn traversal_stats(&self) -> Option<TraversalStats> {
+        None
+    }
+    // The pass timings of the frames that finished since the last call. A backend with frames in flight reports a
+    // frame some time after dispatch_rays() returned
+    fn take_frame_timings(&mut self) -> Vec<FrameTimings> {
+        Vec::new()
+    }
+}
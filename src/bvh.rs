@@ -0,0 +1,640 @@
+// A two-level bounding volume hierarchy for the CPU tracer, built with a binned SAH. The BLAS level holds the triangles
+// of a scene::Blas, the TLAS level the instances, just like the acceleration structures D3D12 builds from the same data.
+
+use glam::*;
+use std::ops::AddAssign;
+
+use crate::cpu_tracer::RayDesc;
+use crate::scene::{Blas, Instance};
+
+// SAH bins per axis, and the largest leaf we accept when no split is cheaper than intersecting everything
+const BIN_COUNT: usize = 16;
+const MAX_LEAF_SIZE: u32 = 8;
+// The cost of visiting a node relative to intersecting a primitive
+const TRAVERSAL_COST: f32 = 1.0;
+const TRAVERSAL_STACK_SIZE: usize = 64;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Aabb {
+    pub const EMPTY: Aabb = Aabb { min: Vec3::splat(f32::INFINITY), max: Vec3::splat(f32::NEG_INFINITY) };
+
+    pub fn from_points(points: &[Vec3]) -> Self {
+        points.iter().fold(Self::EMPTY, |b, p| b.grow_point(*p))
+    }
+
+    pub fn grow_point(self, p: Vec3) -> Self {
+        Self { min: self.min.min(p), max: self.max.max(p) }
+    }
+
+    pub fn union(self, other: Aabb) -> Self {
+        Self { min: self.min.min(other.min), max: self.max.max(other.max) }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.min.cmpgt(self.max).any()
+    }
+
+    pub fn center(&self) -> Vec3 {
+        (self.min + self.max) * 0.5
+    }
+
+    pub fn surface_area(&self) -> f32 {
+        if self.is_empty() {
+            return 0.0;
+        }
+        let d = self.max - self.min;
+        2.0 * (d.x * d.y + d.y * d.z + d.z * d.x)
+    }
+
+    pub fn transform(&self, m: &Mat4) -> Self {
+        let corners: [Vec3; 8] = std::array::from_fn(|i| {
+            let pick = |bit: usize, axis: usize| if i & bit == 0 { self.min[axis] } else { self.max[axis] };
+            m.transform_point3(vec3(pick(1, 0), pick(2, 1), pick(4, 2)))
+        });
+        Self::from_points(&corners)
+    }
+
+    // Slab test. Returns the entry distance if the ray overlaps the box within [t_min, t_max]. The exit distance is
+    // pushed out by a few ulps so rounding can't cull a triangle lying on a face of its box (Ize, "Robust BVH Ray Traversal")
+    fn intersect(&self, origin: Vec3, inv_direction: Vec3, t_min: f32, t_max: f32) -> Option<f32> {
+        let (mut near, mut far) = (t_min, t_max);
+        for axis in 0..3 {
+            // A ray parallel to the slab either always or never overlaps it. Handling this separately avoids the
+            // 0 * inf = NaN we'd get for rays starting on one of the slab's planes
+            if inv_direction[axis].is_infinite() {
+                if origin[axis] < self.min[axis] || origin[axis] > self.max[axis] {
+                    return None;
+                }
+                continue;
+            }
+            let t0 = (self.min[axis] - origin[axis]) * inv_direction[axis];
+            let t1 = (self.max[axis] - origin[axis]) * inv_direction[axis];
+            let (t0, t1) = if t0 > t1 { (t1, t0) } else { (t0, t1) };
+            if t0 > near {
+                near = t0;
+            }
+            if t1 * (1.0 + 4.0 * f32::EPSILON) < far {
+                far = t1 * (1.0 + 4.0 * f32::EPSILON);
+            }
+        }
+        (near <= far).then_some(near)
+    }
+}
+
+#[derive(Clone, Copy, Default, Debug)]
+pub struct TraversalStats {
+    pub rays: u64,
+    pub nodes_visited: u64,
+    pub primitives_tested: u64,
+}
+
+impl AddAssign for TraversalStats {
+    fn add_assign(&mut self, other: Self) {
+        self.rays += other.rays;
+        self.nodes_visited += other.nodes_visited;
+        self.primitives_tested += other.primitives_tested;
+    }
+}
+
+// Interior nodes have count == 0 and their children at `first` and `first + 1`,
+// leaves hold `count` primitives starting at `first` in Bvh::primitives
+#[derive(Clone, Copy, Debug)]
+struct Node {
+    bounds: Aabb,
+    first: u32,
+    count: u32,
+}
+
+#[derive(Default)]
+pub struct Bvh {
+    nodes: Vec<Node>,
+    // Primitive indices in leaf order
+    primitives: Vec<u32>,
+}
+
+#[derive(Clone, Copy)]
+struct Bin {
+    bounds: Aabb,
+    count: u32,
+}
+
+impl Bvh {
+    pub fn build(primitive_bounds: &[Aabb]) -> Self {
+        let mut bvh = Self { nodes: Vec::new(), primitives: (0..primitive_bounds.len() as u32).collect() };
+        if !primitive_bounds.is_empty() {
+            bvh.nodes.push(Node { bounds: Aabb::EMPTY, first: 0, count: primitive_bounds.len() as u32 });
+            bvh.subdivide(0, primitive_bounds);
+        }
+        bvh
+    }
+
+    pub fn bounds(&self) -> Aabb {
+        self.nodes.first().map_or(Aabb::EMPTY, |n| n.bounds)
+    }
+
+    #[cfg(test)]
+    pub fn node_count(&self) -> usize {
+        self.nodes.len()
+    }
+
+    fn subdivide(&mut self, node_index: usize, primitive_bounds: &[Aabb]) {
+        let Node { first, count, .. } = self.nodes[node_index];
+        let range = first as usize..(first + count) as usize;
+        let bounds = self.primitives[range.clone()].iter().fold(Aabb::EMPTY, |b, p| b.union(primitive_bounds[*p as usize]));
+        self.nodes[node_index].bounds = bounds;
+        if count == 1 {
+            return;
+        }
+
+        // Bin the centroids along each axis and find the cheapest split plane
+        let centroid_bounds = self.primitives[range.clone()].iter().fold(Aabb::EMPTY, |b, p| b.grow_point(primitive_bounds[*p as usize].center()));
+        let bin_of = |p: u32, axis: usize| {
+            let extent = centroid_bounds.max[axis] - centroid_bounds.min[axis];
+            (((primitive_bounds[p as usize].center()[axis] - centroid_bounds.min[axis]) / extent * BIN_COUNT as f32) as usize).min(BIN_COUNT - 1)
+        };
+        let mut best: Option<(f32, usize, usize)> = None;
+        for axis in 0..3 {
+            if centroid_bounds.max[axis] <= centroid_bounds.min[axis] {
+                continue;
+            }
+            let mut bins = [Bin { bounds: Aabb::EMPTY, count: 0 }; BIN_COUNT];
+            for p in &self.primitives[range.clone()] {
+                let bin = &mut bins[bin_of(*p, axis)];
+                bin.bounds = bin.bounds.union(primitive_bounds[*p as usize]);
+                bin.count += 1;
+            }
+
+            // Sweep from the right to get the cost of everything right of each plane, then from the left
+            let mut right_cost = [0.0; BIN_COUNT];
+            let mut right = Bin { bounds: Aabb::EMPTY, count: 0 };
+            for i in (1..BIN_COUNT).rev() {
+                right.bounds = right.bounds.union(bins[i].bounds);
+                right.count += bins[i].count;
+                right_cost[i] = right.count as f32 * right.bounds.surface_area();
+            }
+            let mut left = Bin { bounds: Aabb::EMPTY, count: 0 };
+            for split in 1..BIN_COUNT {
+                left.bounds = left.bounds.union(bins[split - 1].bounds);
+                left.count += bins[split - 1].count;
+                if left.count == 0 || left.count == count {
+                    continue;
+                }
+                let cost = left.count as f32 * left.bounds.surface_area() + right_cost[split];
+                if best.is_none_or(|(best_cost, _, _)| cost < best_cost) {
+                    best = Some((cost, axis, split));
+                }
+            }
+        }
+
+        // Compare against making this node a leaf, both relative to the node's surface area
+        let leaf_cost = count as f32 * bounds.surface_area();
+        let Some((split_cost, axis, split)) = best else { return };
+        if count <= MAX_LEAF_SIZE && TRAVERSAL_COST * bounds.surface_area() + split_cost >= leaf_cost {
+            return;
+        }
+
+        let left_count = partition(&mut self.primitives[range], |p| bin_of(*p, axis) < split) as u32;
+
+        let left_index = self.nodes.len();
+        self.nodes.push(Node { bounds: Aabb::EMPTY, first, count: left_count });
+        self.nodes.push(Node { bounds: Aabb::EMPTY, first: first + left_count, count: count - left_count });
+        self.nodes[node_index].first = left_index as u32;
+        self.nodes[node_index].count = 0;
+        self.subdivide(left_index, primitive_bounds);
+        self.subdivide(left_index + 1, primitive_bounds);
+    }
+
+    // Visits the leaves the ray overlaps, nearest child first. `intersect` is called with a primitive index and the
+    // current t_max and returns the distance of a closer hit, which then culls the remaining nodes
+    pub fn traverse(&self, origin: Vec3, direction: Vec3, t_min: f32, mut t_max: f32, stats: &mut TraversalStats, mut intersect: impl FnMut(u32, f32) -> Option<f32>) {
+        if self.nodes.is_empty() {
+            return;
+        }
+        let inv_direction = direction.recip();
+        // Degenerate inputs can make the tree deeper than the usual 64 levels, so the stack can grow
+        let mut stack: Vec<u32> = Vec::with_capacity(TRAVERSAL_STACK_SIZE);
+        let mut node = &self.nodes[0];
+        stats.nodes_visited += 1;
+        if node.bounds.intersect(origin, inv_direction, t_min, t_max).is_none() {
+            return;
+        }
+        loop {
+            if node.count > 0 {
+                for p in &self.primitives[node.first as usize..(node.first + node.count) as usize] {
+                    stats.primitives_tested += 1;
+                    if let Some(t) = intersect(*p, t_max) {
+                        t_max = t;
+                    }
+                }
+            } else {
+                let (a, b) = (node.first, node.first + 1);
+                stats.nodes_visited += 2;
+                let hit_a = self.nodes[a as usize].bounds.intersect(origin, inv_direction, t_min, t_max);
+                let hit_b = self.nodes[b as usize].bounds.intersect(origin, inv_direction, t_min, t_max);
+                match (hit_a, hit_b) {
+                    (Some(ta), Some(tb)) => {
+                        let (near, far) = if ta <= tb { (a, b) } else { (b, a) };
+                        stack.push(far);
+                        node = &self.nodes[near as usize];
+                        continue;
+                    }
+                    (Some(_), None) => {
+                        node = &self.nodes[a as usize];
+                        continue;
+                    }
+                    (None, Some(_)) => {
+                        node = &self.nodes[b as usize];
+                        continue;
+                    }
+                    (None, None) => {}
+                }
+            }
+            // Pop the next node, skipping the ones a closer hit has culled since they were pushed
+            loop {
+                let Some(next) = stack.pop() else { return };
+                node = &self.nodes[next as usize];
+                if node.bounds.intersect(origin, inv_direction, t_min, t_max).is_some() {
+                    break;
+                }
+            }
+        }
+    }
+}
+
+// Moves the elements matching `pred` to the front and returns how many there are
+fn partition(items: &mut [u32], pred: impl Fn(&u32) -> bool) -> usize {
+    let mut left = 0;
+    for i in 0..items.len() {
+        if pred(&items[i]) {
+            items.swap(left, i);
+            left += 1;
+        }
+    }
+    left
+}
+
+// Woop, Benthin and Wald, "Watertight Ray/Triangle Intersection". Rays hitting a shared edge or vertex always hit at
+// least one of the triangles. Returns the distance and the barycentrics of vertices 1 and 2, like
+// BuiltInTriangleIntersectionAttributes. Triangles are double-sided, we don't use RAY_FLAG_CULL_BACK_FACING_TRIANGLES
+pub fn intersect_triangle(origin: Vec3, direction: Vec3, v: &[Vec3; 3]) -> Option<(f32, Vec2)> {
+    // Permute the axes so z is the dominant direction, keeping the winding
+    let abs = direction.abs();
+    let kz = if abs.x > abs.y { if abs.x > abs.z { 0 } else { 2 } } else if abs.y > abs.z { 1 } else { 2 };
+    let mut kx = (kz + 1) % 3;
+    let mut ky = (kx + 1) % 3;
+    if direction[kz] < 0.0 {
+        std::mem::swap(&mut kx, &mut ky);
+    }
+
+    // Shear so the ray points down +z in a space where its origin is at 0
+    let sz = 1.0 / direction[kz];
+    let sx = direction[kx] * sz;
+    let sy = direction[ky] * sz;
+    let a = v[0] - origin;
+    let b = v[1] - origin;
+    let c = v[2] - origin;
+    let ax = a[kx] - sx * a[kz];
+    let ay = a[ky] - sy * a[kz];
+    let bx = b[kx] - sx * b[kz];
+    let by = b[ky] - sy * b[kz];
+    let cx = c[kx] - sx * c[kz];
+    let cy = c[ky] - sy * c[kz];
+
+    // Scaled barycentrics, recomputed in double precision when an edge is hit exactly
+    let mut u = cx * by - cy * bx;
+    let mut w0 = ax * cy - ay * cx;
+    let mut w1 = bx * ay - by * ax;
+    if u == 0.0 || w0 == 0.0 || w1 == 0.0 {
+        u = (cx as f64 * by as f64 - cy as f64 * bx as f64) as f32;
+        w0 = (ax as f64 * cy as f64 - ay as f64 * cx as f64) as f32;
+        w1 = (bx as f64 * ay as f64 - by as f64 * ax as f64) as f32;
+    }
+    if (u < 0.0 || w0 < 0.0 || w1 < 0.0) && (u > 0.0 || w0 > 0.0 || w1 > 0.0) {
+        return None;
+    }
+    let det = u + w0 + w1;
+    if det == 0.0 {
+        return None;
+    }
+
+    let t = (u * sz * a[kz] + w0 * sz * b[kz] + w1 * sz * c[kz]) / det;
+    Some((t, vec2(w0 / det, w1 / det)))
+}
+
+pub struct BlasHit {
+    pub t: f32,
+    pub barycentrics: Vec2,
+    pub geometry_index: usize,
+    // The triangle within the geometry, like PrimitiveIndex()
+    pub primitive_index: usize,
+}
+
+// The triangles of a scene::Blas, flattened, with a BVH over them
+#[derive(Default)]
+pub struct BlasBvh {
+    bvh: Bvh,
+    triangles: Vec<[Vec3; 3]>,
+    geometry_indices: Vec<u32>,
+    primitive_indices: Vec<u32>,
+}
+
+impl BlasBvh {
+    pub fn build(blas: &Blas) -> Self {
+        let mut triangles = Vec::new();
+        let mut geometry_indices = Vec::new();
+        let mut primitive_indices = Vec::new();
+        for (geometry_index, geometry) in blas.geometries.iter().enumerate() {
+            for i in 0..geometry.triangle_count() {
+                triangles.push(geometry.triangle(i));
+                geometry_indices.push(geometry_index as u32);
+                primitive_indices.push(i as u32);
+            }
+        }
+        let bounds: Vec<Aabb> = triangles.iter().map(|t| Aabb::from_points(t)).collect();
+        Self { bvh: Bvh::build(&bounds), triangles, geometry_indices, primitive_indices }
+    }
+
+    pub fn bounds(&self) -> Aabb {
+        self.bvh.bounds()
+    }
+
+    #[cfg(test)]
+    pub fn node_count(&self) -> usize {
+        self.bvh.node_count()
+    }
+
+    pub fn intersect(&self, origin: Vec3, direction: Vec3, t_min: f32, t_max: f32, stats: &mut TraversalStats) -> Option<BlasHit> {
+        let mut closest = None;
+        self.bvh.traverse(origin, direction, t_min, t_max, stats, |p, t_max| {
+            let (t, barycentrics) = intersect_triangle(origin, direction, &self.triangles[p as usize])?;
+            if t <= t_min || t >= t_max {
+                return None;
+            }
+            let (geometry_index, primitive_index) = (self.geometry_indices[p as usize] as usize, self.primitive_indices[p as usize] as usize);
+            closest = Some(BlasHit { t, barycentrics, geometry_index, primitive_index });
+            Some(t)
+        });
+        closest
+    }
+}
+
+pub struct TlasHit {
+    pub instance: usize,
+    pub hit: BlasHit,
+}
+
+// Mirrors the parts of D3D12_RAYTRACING_INSTANCE_DESC that traversal needs
+struct TlasInstance {
+    world_to_object: Mat4,
+    instance_mask: u8,
+    blas: usize,
+}
+
+#[derive(Default)]
+pub struct Tlas {
+    bvh: Bvh,
+    instances: Vec<TlasInstance>,
+}
+
+impl Tlas {
+    pub fn build(instances: &[Instance], blas: &[BlasBvh]) -> Self {
+        let bounds: Vec<Aabb> = instances.iter().map(|i| blas[i.blas].bounds().transform(&i.transform)).collect();
+        let instances = instances.iter().map(|i| TlasInstance {
+            world_to_object: i.transform.inverse(),
+            instance_mask: i.instance_mask,
+            blas: i.blas,
+        }).collect();
+        Self { bvh: Bvh::build(&bounds), instances }
+    }
+
+    pub fn intersect(&self, blas: &[BlasBvh], ray: &RayDesc, instance_inclusion_mask: u8, stats: &mut TraversalStats) -> Option<TlasHit> {
+        stats.rays += 1;
+        let mut closest = None;
+        // The BLAS traversals are counted separately since the TLAS traversal holds on to `stats`
+        let mut blas_stats = TraversalStats::default();
+        self.bvh.traverse(ray.origin, ray.direction, ray.t_min, ray.t_max, stats, |i, t_max| {
+            let instance = &self.instances[i as usize];
+            if instance.instance_mask & instance_inclusion_mask == 0 {
+                return None;
+            }
+            // Intersection happens in object space. The direction is not normalized, so t is the same in both spaces
+            let object_origin = instance.world_to_object.transform_point3(ray.origin);
+            let object_direction = instance.world_to_object.transform_vector3(ray.direction);
+            let hit = blas[instance.blas].intersect(object_origin, object_direction, ray.t_min, t_max, &mut blas_stats)?;
+            let t = hit.t;
+            closest = Some(TlasHit { instance: i as usize, hit });
+            Some(t)
+        });
+        *stats += blas_stats;
+        closest
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sampler::pcg_hash;
+    use crate::scene::Geometry;
+
+    // Deterministic values in [0, 1)
+    struct Random(u32);
+
+    impl Random {
+        fn next(&mut self) -> f32 {
+            self.0 = pcg_hash(self.0);
+            (self.0 >> 8) as f32 / (1 << 24) as f32
+        }
+
+        fn vec3(&mut self) -> Vec3 {
+            vec3(self.next(), self.next(), self.next())
+        }
+    }
+
+    fn blas(triangles: &[[Vec3; 3]]) -> Blas {
+        let geometry = Geometry {
+            vertices: triangles.iter().flatten().copied().collect(),
+            indices: None,
+            normals: None,
+            tangents: None,
+            tex_coords: None,
+        };
+        Blas { geometries: vec![geometry] }
+    }
+
+    fn brute_force(triangles: &[[Vec3; 3]], origin: Vec3, direction: Vec3, t_min: f32, t_max: f32) -> Option<(f32, usize)> {
+        triangles.iter().enumerate()
+            .filter_map(|(i, v)| intersect_triangle(origin, direction, v).map(|(t, _)| (t, i)))
+            .filter(|(t, _)| *t > t_min && *t < t_max)
+            .min_by(|a, b| a.0.total_cmp(&b.0))
+    }
+
+    #[test]
+    fn matches_brute_force() {
+        let mut random = Random(1);
+        let triangles: Vec<[Vec3; 3]> = (0..300).map(|_| {
+            let center = random.vec3() * 4.0;
+            [0, 1, 2].map(|_| center + (random.vec3() - 0.5) * 0.5)
+        }).collect();
+        let bvh = BlasBvh::build(&blas(&triangles));
+        assert!(bvh.node_count() > 1);
+        assert!(triangles.iter().flatten().all(|v| v.cmpge(bvh.bounds().min).all() && v.cmple(bvh.bounds().max).all()));
+
+        let mut hits = 0;
+        for _ in 0..2000 {
+            let origin = random.vec3() * 6.0 - 1.0;
+            let direction = random.vec3() * 2.0 - 1.0;
+            let t_max = if random.next() < 0.5 { f32::INFINITY } else { random.next() * 4.0 };
+            let expected = brute_force(&triangles, origin, direction, 0.0, t_max);
+            let mut stats = TraversalStats::default();
+            let hit = bvh.intersect(origin, direction, 0.0, t_max, &mut stats);
+            assert_eq!(hit.as_ref().map(|h| (h.t, h.primitive_index)), expected, "origin {} direction {}", origin, direction);
+            assert!(stats.primitives_tested <= triangles.len() as u64);
+            hits += hit.is_some() as u32;
+        }
+        assert!(hits > 100, "only {} rays hit", hits);
+    }
+
+    #[test]
+    fn shared_edges_and_vertices_are_watertight() {
+        // A grid of quads split into two triangles each
+        const N: usize = 8;
+        let p = |x: usize, y: usize| vec3(x as f32 / N as f32, y as f32 / N as f32, 0.0);
+        let triangles: Vec<[Vec3; 3]> = (0..N * N).flat_map(|i| {
+            let (x, y) = (i % N, i / N);
+            [[p(x, y), p(x + 1, y), p(x + 1, y + 1)], [p(x, y), p(x + 1, y + 1), p(x, y + 1)]]
+        }).collect();
+        let bvh = BlasBvh::build(&blas(&triangles));
+
+        // Aim at every interior vertex and at points along the shared edges, from straight above and at an angle
+        let mut random = Random(7);
+        for i in 1..N * 4 {
+            for j in 1..N * 4 {
+                let target = vec3(i as f32 / (N * 4) as f32, j as f32 / (N * 4) as f32, 0.0);
+                for direction in [vec3(0.0, 0.0, -1.0), vec3(0.3, -0.2, -1.0), random.vec3() - vec3(0.5, 0.5, 1.5)] {
+                    let origin = target - direction * 2.0;
+                    let hit = bvh.intersect(origin, direction, 0.0, f32::INFINITY, &mut TraversalStats::default());
+                    assert!(hit.is_some(), "missed {} from {}", target, origin);
+                }
+            }
+        }
+
+        // A fan around a single vertex
+        let fan: Vec<[Vec3; 3]> = (0..7).map(|i| {
+            let corner = |k: u32| {
+                let angle = k as f32 / 7.0 * std::f32::consts::TAU;
+                vec3(angle.cos(), 0.3, angle.sin())
+            };
+            [vec3(0.0, 0.3, 0.0), corner(i), corner(i + 1)]
+        }).collect();
+        let bvh = BlasBvh::build(&blas(&fan));
+        for _ in 0..200 {
+            let direction = random.vec3() * 2.0 - 1.0;
+            let origin = vec3(0.0, 0.3, 0.0) - direction;
+            if direction.y.abs() < 1e-3 {
+                continue;
+            }
+            let hit = bvh.intersect(origin, direction, 0.0, f32::INFINITY, &mut TraversalStats::default());
+            assert!(hit.is_some_and(|h| (h.t - 1.0).abs() < 1e-5), "missed the fan center from {}", origin);
+        }
+    }
+
+    #[test]
+    fn equal_centroids() {
+        // More triangles than fit a leaf, nested and all centered on the same point, so there is no plane to split them at
+        let count = MAX_LEAF_SIZE as usize * 3;
+        let triangles: Vec<[Vec3; 3]> = (0..count).map(|i| {
+            let size = 1.0 + i as f32;
+            [vec3(-size, -size, 0.0), vec3(size, -size, 0.0), vec3(0.0, size, 0.0)]
+        }).collect();
+        let bounds: Vec<Aabb> = triangles.iter().map(|t| Aabb::from_points(t)).collect();
+        assert!(bounds.iter().all(|b| b.center() == Vec3::ZERO));
+        let bvh = BlasBvh::build(&blas(&triangles));
+        assert_eq!(bvh.node_count(), 1);
+
+        // Every triangle is tested exactly once
+        let mut stats = TraversalStats::default();
+        let hit = bvh.intersect(vec3(0.1, 0.1, -10.0), Vec3::Z, 0.0, f32::INFINITY, &mut stats);
+        assert!(hit.is_some_and(|h| h.t == 10.0));
+        assert_eq!(stats.primitives_tested, count as u64);
+
+        // Identical triangles
+        let same = vec![[Vec3::ZERO, Vec3::X, Vec3::Y]; count];
+        let bvh = BlasBvh::build(&blas(&same));
+        assert_eq!(bvh.node_count(), 1);
+        let hit = bvh.intersect(vec3(0.25, 0.25, 1.0), -Vec3::Z, 0.0, f32::INFINITY, &mut TraversalStats::default());
+        assert!(hit.is_some_and(|h| h.t == 1.0));
+    }
+
+    #[test]
+    fn degenerate_triangles() {
+        let triangles = [[Vec3::ZERO, Vec3::X, Vec3::X * 2.0], [Vec3::ONE; 3], [Vec3::ZERO, Vec3::X, Vec3::Y]];
+        let bvh = BlasBvh::build(&blas(&triangles));
+        let hit = bvh.intersect(vec3(0.25, 0.25, -1.0), Vec3::Z, 0.0, f32::INFINITY, &mut TraversalStats::default());
+        assert_eq!(hit.map(|h| h.primitive_index), Some(2));
+        assert!(intersect_triangle(vec3(1.0, 1.0, 0.0), Vec3::Z, &[Vec3::ONE; 3]).is_none());
+
+        let empty = BlasBvh::build(&Blas { geometries: Vec::new() });
+        assert!(empty.bounds().is_empty());
+        assert!(empty.intersect(Vec3::ZERO, Vec3::Z, 0.0, f32::INFINITY, &mut TraversalStats::default()).is_none());
+    }
+
+    #[test]
+    fn parallel_rays_on_a_slab_plane() {
+        let aabb = Aabb { min: Vec3::ZERO, max: Vec3::ONE };
+        let inv = |d: Vec3| d.recip();
+        // Starting on the min and max x faces, moving along z
+        assert_eq!(aabb.intersect(vec3(0.0, 0.5, -1.0), inv(Vec3::Z), 0.0, f32::INFINITY), Some(1.0));
+        assert_eq!(aabb.intersect(vec3(1.0, 0.5, -1.0), inv(Vec3::Z), 0.0, f32::INFINITY), Some(1.0));
+        assert_eq!(aabb.intersect(vec3(1.0, 1.0, 0.5), inv(-Vec3::Z), 0.0, f32::INFINITY), Some(0.0));
+        // Negative zero components
+        assert_eq!(aabb.intersect(vec3(0.0, 0.0, 2.0), inv(vec3(-0.0, -0.0, -1.0)), 0.0, f32::INFINITY), Some(1.0));
+        // Just outside the slab
+        assert_eq!(aabb.intersect(vec3(-1e-6, 0.5, -1.0), inv(Vec3::Z), 0.0, f32::INFINITY), None);
+        assert_eq!(aabb.intersect(vec3(0.5, 1.0 + 1e-6, -1.0), inv(Vec3::Z), 0.0, f32::INFINITY), None);
+        // Outside [t_min, t_max]
+        assert_eq!(aabb.intersect(vec3(0.5, 0.5, -1.0), inv(Vec3::Z), 0.0, 0.5), None);
+
+        // A triangle in a flat box, hit along its edge by a ray starting on the box's face
+        let bvh = BlasBvh::build(&blas(&[[Vec3::ZERO, Vec3::X, Vec3::Y]]));
+        let hit = bvh.intersect(vec3(0.0, 0.25, -1.0), Vec3::Z, 0.0, f32::INFINITY, &mut TraversalStats::default());
+        assert!(hit.is_some_and(|h| h.t == 1.0));
+        let hit = bvh.intersect(vec3(0.25, 0.0, 1.0), -Vec3::Z, 0.0, f32::INFINITY, &mut TraversalStats::default());
+        assert!(hit.is_some_and(|h| h.t == 1.0));
+    }
+
+    #[test]
+    fn tlas_masks_and_transforms() {
+        let blas = [BlasBvh::build(&blas(&[[Vec3::ZERO, Vec3::X, Vec3::Y]]))];
+        let near = Instance { instance_mask: 0x1, ..Instance::new(0, 0, Mat4::from_translation(vec3(0.0, 0.0, 5.0))) };
+        let far = Instance {
+            instance_mask: 0x2,
+            ..Instance::new(1, 0, Mat4::from_scale_rotation_translation(Vec3::splat(2.0), Quat::IDENTITY, vec3(0.0, 0.0, 10.0)))
+        };
+        // Rotated to face +x, so only rays along x reach it
+        let side = Instance { instance_mask: 0x4, ..Instance::new(2, 0, Mat4::from_translation(vec3(3.0, 0.0, 0.0)) * Mat4::from_rotation_y(-std::f32::consts::FRAC_PI_2)) };
+        let tlas = Tlas::build(&[near, far, side], &blas);
+
+        let ray = RayDesc { origin: vec3(0.25, 0.25, 0.0), t_min: 0.0, direction: Vec3::Z, t_max: f32::INFINITY };
+        let intersect = |ray: &RayDesc, mask: u8| tlas.intersect(&blas, ray, mask, &mut TraversalStats::default()).map(|h| (h.instance, h.hit.t));
+        assert_eq!(intersect(&ray, 0xFF), Some((0, 5.0)));
+        assert_eq!(intersect(&ray, 0x2), Some((1, 10.0)));
+        assert_eq!(intersect(&ray, 0x4), None);
+        assert_eq!(intersect(&RayDesc { t_max: 8.0, ..ray }, 0x2), None);
+        // Beyond the unscaled triangle but within the scaled one
+        assert_eq!(intersect(&RayDesc { origin: vec3(1.5, 0.25, 0.0), ..ray }, 0xFF), Some((1, 10.0)));
+        // t is measured in units of the unnormalized direction, in world space
+        assert_eq!(intersect(&RayDesc { direction: Vec3::Z * 2.0, ..ray }, 0xFF), Some((0, 2.5)));
+
+        let ray = RayDesc { origin: vec3(0.0, 0.25, 0.25), t_min: 0.0, direction: Vec3::X, t_max: f32::INFINITY };
+        let hit = tlas.intersect(&blas, &ray, 0xFF, &mut TraversalStats::default()).unwrap();
+        assert_eq!(hit.instance, 2);
+        assert!((hit.hit.t - 3.0).abs() < 1e-5);
+        assert!(hit.hit.barycentrics.abs_diff_eq(vec2(0.25, 0.25), 1e-5), "{}", hit.hit.barycentrics);
+        assert_eq!(intersect(&ray, 0x3), None);
+    }
+}
@@ -0,0 +1,333 @@
+// The camera and the orbit/fly controls. Nothing here depends on Windows: the window translates its messages into
+// InputEvents.
+
+use glam::*;
+use serde::{Deserialize, Serialize};
+
+use crate::frame::FrameConstants;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Camera {
+    pub position: Vec3,
+    pub target: Vec3,
+    pub up: Vec3,
+    // The vertical field of view in degrees
+    pub fov_y: f32,
+    // Width over height. None follows the output size
+    pub aspect: Option<f32>,
+    // The ray extents, TMin and TMax of the primary rays
+    pub near: f32,
+    pub far: f32,
+    // A lens radius of 0 is a pinhole camera. Otherwise the focus is at `focus_distance`, or at the target if that is None
+    pub lens_radius: f32,
+    pub focus_distance: Option<f32>,
+}
+
+impl Default for Camera {
+    // The camera rayGen() used to hard-code
+    fn default() -> Self {
+        Self {
+            position: vec3(0.0, 0.0, -2.0),
+            target: Vec3::ZERO,
+            up: Vec3::Y,
+            fov_y: 90.0,
+            aspect: None,
+            near: 0.0,
+            far: 100000.0,
+            lens_radius: 0.0,
+            focus_distance: None,
+        }
+    }
+}
+
+impl Camera {
+    pub fn forward(&self) -> Vec3 {
+        (self.target - self.position).normalize()
+    }
+
+    // Left-handed, X is right when looking down +Z with +Y up
+    pub fn right(&self) -> Vec3 {
+        self.up.cross(self.forward()).normalize()
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if !(self.fov_y > 0.0 && self.fov_y < 180.0) {
+            return Err(format!("fov_y must be between 0 and 180 degrees, got {}", self.fov_y));
+        }
+        if self.position == self.target {
+            return Err("position and target are the same point".into());
+        }
+        if self.up.cross(self.target - self.position) == Vec3::ZERO {
+            return Err("up is parallel to the view direction".into());
+        }
+        if let Some(aspect) = self.aspect {
+            if aspect <= 0.0 {
+                return Err(format!("aspect must be positive, got {}", aspect));
+            }
+        }
+        if !(self.near >= 0.0 && self.near < self.far) {
+            return Err(format!("near ({}) must be positive and less than far ({})", self.near, self.far));
+        }
+        if self.lens_radius < 0.0 || self.focus_distance.is_some_and(|f| f <= 0.0) {
+            return Err("lens_radius and focus_distance can't be negative".into());
+        }
+        Ok(())
+    }
+
+    // The camera fields of the frame constants. `output_aspect` is used unless the camera has its own. The caller fills
+    // in the rest: the sample index, the accumulation, the integrator and the color settings
+    pub fn constants(&self, output_aspect: f32) -> FrameConstants {
+        let forward = self.forward();
+        let right = self.right();
+        let up = forward.cross(right);
+        let tan_half_fov = (self.fov_y.to_radians() * 0.5).tan();
+        FrameConstants {
+            position: self.position,
+            lens_radius: self.lens_radius,
+            u: right * tan_half_fov * self.aspect.unwrap_or(output_aspect),
+            focus_distance: self.focus_distance.unwrap_or((self.target - self.position).length()),
+            v: up * tan_half_fov,
+            t_min: self.near,
+            w: forward,
+            t_max: self.far,
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Key {
+    Forward,
+    Back,
+    Left,
+    Right,
+    Up,
+    Down,
+    Fast,
+}
+
+const KEY_COUNT: usize = 7;
+
+#[cfg(any(windows, test))]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MouseButton {
+    // Orbits around the target
+    Left,
+    // Looks around from the current position
+    Right,
+}
+
+#[cfg(any(windows, test))]
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum InputEvent {
+    KeyDown(Key),
+    KeyUp(Key),
+    // Mouse movement in pixels while a button is held
+    MouseDrag { button: MouseButton, dx: f32, dy: f32 },
+    // Wheel notches, positive away from the user
+    Wheel(f32),
+}
+
+// Orbit controls on the left mouse button and the wheel, fly controls on the right mouse button and WASD (plus Q/E
+// for down/up). Moving keeps the offset between the position and the target, so orbiting continues to work after flying
+pub struct CameraController {
+    held: [bool; KEY_COUNT],
+    // Radians per pixel of mouse movement
+    #[cfg(any(windows, test))]
+    pub rotate_speed: f32,
+    // Units per second, multiplied by FAST_MULTIPLIER while Key::Fast is held
+    pub move_speed: f32,
+}
+
+const FAST_MULTIPLIER: f32 = 4.0;
+// The fraction of the distance to the target a wheel notch moves
+#[cfg(any(windows, test))]
+const ZOOM_STEP: f32 = 0.1;
+// Keeps the view direction away from the up vector
+#[cfg(any(windows, test))]
+const MAX_PITCH_COS: f32 = 0.995;
+
+impl Default for CameraController {
+    fn default() -> Self {
+        Self {
+            held: [false; KEY_COUNT],
+            #[cfg(any(windows, test))]
+            rotate_speed: 0.005,
+            move_speed: 2.0,
+        }
+    }
+}
+
+impl CameraController {
+    #[cfg(any(windows, test))]
+    pub fn handle(&mut self, camera: &mut Camera, event: InputEvent) {
+        match event {
+            InputEvent::KeyDown(key) => self.held[key as usize] = true,
+            InputEvent::KeyUp(key) => self.held[key as usize] = false,
+            InputEvent::MouseDrag { button: MouseButton::Left, dx, dy } => {
+                let offset = rotate(camera.position - camera.target, camera.up, -dx * self.rotate_speed, -dy * self.rotate_speed);
+                camera.position = camera.target + offset;
+            }
+            InputEvent::MouseDrag { button: MouseButton::Right, dx, dy } => {
+                let view = rotate(camera.target - camera.position, camera.up, dx * self.rotate_speed, dy * self.rotate_speed);
+                camera.target = camera.position + view;
+            }
+            InputEvent::Wheel(notches) => {
+                let offset = camera.position - camera.target;
+                let scale = (1.0 - ZOOM_STEP).powf(notches);
+                // Don't zoom into the target, the view direction would become undefined
+                if (offset * scale).length() > 1e-3 {
+                    camera.position = camera.target + offset * scale;
+                }
+            }
+        }
+    }
+
+    // Applies the held keys. `dt` is in seconds
+    pub fn update(&mut self, camera: &mut Camera, dt: f32) {
+        let held = |key: Key| if self.held[key as usize] { 1.0 } else { 0.0 };
+        let movement = camera.forward() * (held(Key::Forward) - held(Key::Back))
+            + camera.right() * (held(Key::Right) - held(Key::Left))
+            + camera.up * (held(Key::Up) - held(Key::Down));
+        if movement == Vec3::ZERO {
+            return;
+        }
+        let speed = self.move_speed * if self.held[Key::Fast as usize] { FAST_MULTIPLIER } else { 1.0 };
+        let delta = movement.normalize() * speed * dt;
+        camera.position += delta;
+        camera.target += delta;
+    }
+}
+
+// Rotates `v` by `yaw` around `up` and by `pitch` around the axis perpendicular to both. Positive pitches turn away
+// from `up`. The pitch stops where the cosine to `up` reaches MAX_PITCH_COS, so `v` never crosses the poles
+#[cfg(any(windows, test))]
+fn rotate(v: Vec3, up: Vec3, yaw: f32, pitch: f32) -> Vec3 {
+    let up = up.normalize();
+    let v = Quat::from_axis_angle(up, yaw) * v;
+    let axis = up.cross(v).normalize();
+    let limit = MAX_PITCH_COS.acos();
+    let angle = v.angle_between(up);
+    let pitch = (angle + pitch).clamp(limit, std::f32::consts::PI - limit) - angle;
+    Quat::from_axis_angle(axis, pitch) * v
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn camera() -> Camera {
+        Camera { position: vec3(1.0, 2.0, -4.0), target: vec3(1.0, 0.5, 0.0), ..Camera::default() }
+    }
+
+    fn assert_near(a: Vec3, b: Vec3) {
+        assert!(a.abs_diff_eq(b, 1e-4), "{} != {}", a, b);
+    }
+
+    #[test]
+    fn orbit_keeps_the_distance() {
+        let mut camera = camera();
+        let mut controller = CameraController::default();
+        let distance = camera.position.distance(camera.target);
+        for (dx, dy) in [(40.0, 0.0), (0.0, 25.0), (-130.0, -60.0), (500.0, 300.0)] {
+            controller.handle(&mut camera, InputEvent::MouseDrag { button: MouseButton::Left, dx, dy });
+            assert!((camera.position.distance(camera.target) - distance).abs() < 1e-4);
+            assert_eq!(camera.target, vec3(1.0, 0.5, 0.0));
+        }
+    }
+
+    #[test]
+    fn pitch_stops_short_of_the_poles() {
+        for (button, dy) in [(MouseButton::Left, 50.0), (MouseButton::Left, -50.0), (MouseButton::Right, 50.0), (MouseButton::Right, -50.0)] {
+            let mut camera = camera();
+            let mut controller = CameraController::default();
+            for _ in 0..100 {
+                controller.handle(&mut camera, InputEvent::MouseDrag { button, dx: 3.0, dy });
+                assert!(camera.forward().dot(camera.up).abs() <= MAX_PITCH_COS + 1e-5, "{:?} {}", button, dy);
+                assert!(camera.validate().is_ok());
+            }
+            assert!((camera.forward().dot(camera.up).abs() - MAX_PITCH_COS).abs() < 1e-4, "{:?} {}", button, dy);
+        }
+        // A single rotation past the pole stops at the limit instead of flipping over, with the yaw applied
+        let v = rotate(Vec3::Z, Vec3::Y, 0.5, -2.0);
+        assert!((v.dot(Vec3::Y) - MAX_PITCH_COS).abs() < 1e-5);
+        assert_near(vec3(v.x, 0.0, v.z).normalize(), Quat::from_rotation_y(0.5) * Vec3::Z);
+        assert_eq!(rotate(Vec3::Z, Vec3::Y, 0.0, 0.0), Vec3::Z);
+    }
+
+    #[test]
+    fn wheel_zoom_never_reaches_the_target() {
+        let mut camera = camera();
+        let mut controller = CameraController::default();
+        let direction = camera.forward();
+        controller.handle(&mut camera, InputEvent::Wheel(1.0));
+        assert!((camera.position.distance(camera.target) - 0.9 * 4.272_002).abs() < 1e-4);
+        for _ in 0..1000 {
+            controller.handle(&mut camera, InputEvent::Wheel(5.0));
+        }
+        assert!(camera.position.distance(camera.target) > 1e-3);
+        assert_near(camera.forward(), direction);
+        // Zooming out moves away again
+        controller.handle(&mut camera, InputEvent::Wheel(-3.0));
+        assert!(camera.position.distance(camera.target) > 1e-3 / 0.9f32.powi(2));
+    }
+
+    #[test]
+    fn keys_move_position_and_target_together() {
+        let mut camera = camera();
+        let mut controller = CameraController::default();
+        let (start, offset, forward, right) = (camera.position, camera.target - camera.position, camera.forward(), camera.right());
+
+        controller.handle(&mut camera, InputEvent::KeyDown(Key::Forward));
+        controller.update(&mut camera, 0.5);
+        assert_near(camera.position, start + forward * controller.move_speed * 0.5);
+        assert_near(camera.target - camera.position, offset);
+
+        // Diagonals aren't faster, Key::Fast is
+        controller.handle(&mut camera, InputEvent::KeyDown(Key::Right));
+        controller.handle(&mut camera, InputEvent::KeyDown(Key::Fast));
+        let before = camera.position;
+        controller.update(&mut camera, 0.25);
+        assert_near(camera.position, before + (forward + right).normalize() * controller.move_speed * FAST_MULTIPLIER * 0.25);
+        assert_near(camera.target - camera.position, offset);
+
+        // Opposite keys cancel, released keys stop
+        controller.handle(&mut camera, InputEvent::KeyDown(Key::Back));
+        controller.handle(&mut camera, InputEvent::KeyUp(Key::Right));
+        let before = camera.position;
+        controller.update(&mut camera, 1.0);
+        assert_eq!(camera.position, before);
+    }
+
+    #[test]
+    fn center_ray_is_forward() {
+        let camera = camera();
+        let constants = camera.constants(16.0 / 9.0);
+        let (origin, direction) = constants.ray(Vec2::ZERO, vec2(0.3, 0.7));
+        assert_eq!(origin, camera.position);
+        assert_near(direction, camera.forward());
+        // +y is down, +x right
+        let (_, down_right) = constants.ray(vec2(1.0, 1.0), Vec2::ZERO);
+        assert!(down_right.dot(camera.right()) > 0.0 && down_right.dot(camera.up) < 0.0);
+    }
+
+    #[test]
+    fn lens_rays_converge_on_the_focus_plane() {
+        let camera = Camera { lens_radius: 0.2, focus_distance: Some(3.0), ..camera() };
+        let constants = camera.constants(1.5);
+        let pinhole = FrameConstants { lens_radius: 0.0, ..constants };
+        for ndc in [Vec2::ZERO, vec2(0.5, -0.25), vec2(-1.0, 1.0)] {
+            let (origin, direction) = pinhole.ray(ndc, Vec2::ZERO);
+            let on_plane = |origin: Vec3, direction: Vec3| origin + direction * ((3.0 - (origin - camera.position).dot(constants.w)) / direction.dot(constants.w));
+            let focus = on_plane(origin, direction);
+            for lens_sample in [vec2(0.0, 0.0), vec2(1.0, 0.0), vec2(0.5, 0.25), vec2(0.9, 0.8)] {
+                let (origin, direction) = constants.ray(ndc, lens_sample);
+                // The origins are on the lens, which lies in the plane of u and v
+                assert!(origin.distance(camera.position) <= 0.2 + 1e-5);
+                assert!((origin - camera.position).dot(constants.w).abs() < 1e-5);
+                assert_near(on_plane(origin, direction), focus);
+            }
+        }
+    }
+}
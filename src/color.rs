@@ -0,0 +1,214 @@
+// The output transform: exposure, tone mapping and the encoding of the output UAV. rayGen() applies it to the
+// accumulated linear color, the CPU tracer uses these functions, so the screenshots of both backends match. Every
+// function is mirrored in res/shaders.hlsl, the enums are passed as their discriminants
+
+use glam::*;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum ToneMapper {
+    // Clips at 1
+    #[default]
+    None = 0,
+    // c / (1 + c) per channel
+    Reinhard = 1,
+    // Stephen Hill's fit of the ACES RRT and sRGB ODT
+    AcesFitted = 2,
+    // The polynomial approximation of Blender's AgX base contrast
+    AgX = 3,
+}
+
+impl ToneMapper {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "none" => Some(ToneMapper::None),
+            "reinhard" => Some(ToneMapper::Reinhard),
+            "aces" => Some(ToneMapper::AcesFitted),
+            "agx" => Some(ToneMapper::AgX),
+            _ => None,
+        }
+    }
+}
+
+// The transfer function of the 8-bit output. The HDR output always holds the linear values
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Encoding {
+    // The IEC 61966-2-1 curve, what the swap chain and PNG viewers expect
+    #[default]
+    Srgb = 0,
+    // The BT.709 OETF
+    Rec709 = 1,
+    // SMPTE ST 2084 with Rec.2020 primaries. 1.0 is PQ_REFERENCE_WHITE nits
+    Pq = 2,
+}
+
+impl Encoding {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "srgb" => Some(Encoding::Srgb),
+            "rec709" => Some(Encoding::Rec709),
+            "pq" => Some(Encoding::Pq),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct ColorSettings {
+    // In stops, 0 leaves the color as traced
+    pub exposure: f32,
+    pub tone_mapper: ToneMapper,
+    pub encoding: Encoding,
+}
+
+impl ColorSettings {
+    // The factor the linear color is multiplied with
+    pub fn exposure_scale(&self) -> f32 {
+        self.exposure.exp2()
+    }
+}
+
+// The diffuse white of SDR content in a PQ signal, as recommended by ITU-R BT.2408
+pub const PQ_REFERENCE_WHITE: f32 = 203.0;
+
+// Like mul(float3x3, float3) in HLSL with the matrix given as rows
+fn mul(rows: &[Vec3; 3], v: Vec3) -> Vec3 {
+    vec3(rows[0].dot(v), rows[1].dot(v), rows[2].dot(v))
+}
+
+pub fn tone_map(c: Vec3, tone_mapper: ToneMapper) -> Vec3 {
+    match tone_mapper {
+        ToneMapper::None => c,
+        ToneMapper::Reinhard => c / (Vec3::ONE + c.max(Vec3::ZERO)),
+        ToneMapper::AcesFitted => aces_fitted(c),
+        ToneMapper::AgX => agx(c),
+    }
+}
+
+// https://github.com/TheRealMJP/BakingLab/blob/master/BakingLab/ACES.hlsl
+#[allow(clippy::excessive_precision)]
+fn aces_fitted(c: Vec3) -> Vec3 {
+    // sRGB to ACEScg-ish, with the RRT saturation folded in
+    const INPUT: [Vec3; 3] = [vec3(0.59719, 0.35458, 0.04823), vec3(0.07600, 0.90834, 0.01566), vec3(0.02840, 0.13383, 0.83777)];
+    // Back to sRGB, with the ODT saturation folded in
+    const OUTPUT: [Vec3; 3] = [vec3(1.60475, -0.53108, -0.07367), vec3(-0.10208, 1.10813, -0.00605), vec3(-0.00327, -0.07276, 1.07602)];
+    let v = mul(&INPUT, c);
+    let a = v * (v + 0.0245786) - 0.000090537;
+    let b = v * (0.983729 * v + 0.4329510) + 0.238081;
+    mul(&OUTPUT, a / b).clamp(Vec3::ZERO, Vec3::ONE)
+}
+
+// https://iolite-engine.com/blog_posts/minimal_agx_implementation. The curve produces display values for a 2.2 gamma,
+// which we undo so the encoding can be applied like for the other tone mappers
+#[allow(clippy::excessive_precision)]
+fn agx(c: Vec3) -> Vec3 {
+    const INSET: [Vec3; 3] = [
+        vec3(0.842479062253094, 0.0784335999999992, 0.0792237451477643),
+        vec3(0.0423282422610123, 0.878468636469772, 0.0791661274605434),
+        vec3(0.0423756549057051, 0.0784336, 0.879142973793104),
+    ];
+    const OUTSET: [Vec3; 3] = [
+        vec3(1.19687900512017, -0.0980208811401368, -0.0990297440797205),
+        vec3(-0.0528968517574562, 1.15190312990417, -0.0989611768448433),
+        vec3(-0.0529716355144438, -0.0980434501171241, 1.15107367264116),
+    ];
+    const MIN_EV: f32 = -12.47393;
+    const MAX_EV: f32 = 4.026069;
+    // The log2 encoding, on a range of 16.5 stops around middle gray
+    let v = mul(&INSET, c).max(Vec3::splat(1e-10));
+    let v = (vec3(v.x.log2(), v.y.log2(), v.z.log2()).clamp(Vec3::splat(MIN_EV), Vec3::splat(MAX_EV)) - MIN_EV) / (MAX_EV - MIN_EV);
+    // The sigmoid, a 6th order polynomial fit
+    let v2 = v * v;
+    let v4 = v2 * v2;
+    let v = 15.5 * v4 * v2 - 40.14 * v4 * v + 31.96 * v4 - 6.868 * v2 * v + 0.4298 * v2 + 0.1191 * v - 0.00232;
+    let v = mul(&OUTSET, v).max(Vec3::ZERO);
+    vec3(v.x.powf(2.2), v.y.powf(2.2), v.z.powf(2.2))
+}
+
+#[allow(clippy::excessive_precision)]
+pub fn encode(c: Vec3, encoding: Encoding) -> Vec3 {
+    let c = c.max(Vec3::ZERO);
+    match encoding {
+        Encoding::Srgb => vec3(srgb_oetf(c.x), srgb_oetf(c.y), srgb_oetf(c.z)),
+        Encoding::Rec709 => vec3(rec709_oetf(c.x), rec709_oetf(c.y), rec709_oetf(c.z)),
+        Encoding::Pq => {
+            const REC709_TO_REC2020: [Vec3; 3] = [vec3(0.6274040, 0.3292820, 0.0433136), vec3(0.0690970, 0.9195400, 0.0113612), vec3(0.0163916, 0.0880132, 0.8955950)];
+            let c = mul(&REC709_TO_REC2020, c) * (PQ_REFERENCE_WHITE / 10000.0);
+            vec3(pq_oetf(c.x), pq_oetf(c.y), pq_oetf(c.z))
+        }
+    }
+}
+
+pub fn srgb_oetf(x: f32) -> f32 {
+    if x <= 0.0031308 { 12.92 * x } else { 1.055 * x.powf(1.0 / 2.4) - 0.055 }
+}
+
+fn rec709_oetf(x: f32) -> f32 {
+    if x < 0.018 { 4.5 * x } else { 1.099 * x.powf(0.45) - 0.099 }
+}
+
+// `x` is the luminance over 10000 nits
+fn pq_oetf(x: f32) -> f32 {
+    const M1: f32 = 2610.0 / 16384.0;
+    const M2: f32 = 2523.0 / 4096.0 * 128.0;
+    const C1: f32 = 3424.0 / 4096.0;
+    const C2: f32 = 2413.0 / 4096.0 * 32.0;
+    const C3: f32 = 2392.0 / 4096.0 * 32.0;
+    let p = x.min(1.0).powf(M1);
+    ((C1 + C2 * p) / (1.0 + C3 * p)).powf(M2)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_near(a: f32, b: f32, tolerance: f32) {
+        assert!((a - b).abs() <= tolerance, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn srgb() {
+        // The linear segment ends where the curve starts, at 0.0404 encoded
+        assert_near(srgb_oetf(0.0031308), 0.0404, 1e-4);
+        assert_near(srgb_oetf(0.0031308), 1.055 * 0.0031308f32.powf(1.0 / 2.4) - 0.055, 1e-5);
+        assert_eq!(srgb_oetf(0.0), 0.0);
+        assert_near(srgb_oetf(1.0), 1.0, 1e-6);
+    }
+
+    #[test]
+    fn rec709() {
+        assert_near(rec709_oetf(0.018), 0.081, 1e-3);
+        assert_near(rec709_oetf(1.0), 1.0, 1e-6);
+    }
+
+    #[test]
+    fn pq() {
+        // 100 and 10000 nits
+        assert_near(pq_oetf(0.01), 0.508, 1e-3);
+        assert_near(pq_oetf(1.0), 1.0, 1e-6);
+        // Negative values are clipped before the curve, which isn't defined for them
+        assert_eq!(encode(-Vec3::ONE, Encoding::Pq), encode(Vec3::ZERO, Encoding::Pq));
+        // The primaries change, but white stays white at the reference luminance
+        let white = encode(Vec3::ONE, Encoding::Pq);
+        assert!(white.abs_diff_eq(Vec3::splat(pq_oetf(PQ_REFERENCE_WHITE / 10000.0)), 1e-4), "{}", white);
+    }
+
+    #[test]
+    fn aces_maps_black_to_black() {
+        assert_eq!(tone_map(Vec3::ZERO, ToneMapper::AcesFitted), Vec3::ZERO);
+        assert_near(tone_map(Vec3::splat(1e4), ToneMapper::AcesFitted).x, 1.0, 1e-3);
+    }
+
+    #[test]
+    fn tone_mappers_are_monotonic() {
+        for tone_mapper in [ToneMapper::Reinhard, ToneMapper::AcesFitted, ToneMapper::AgX] {
+            let mut previous = Vec3::ZERO;
+            // From far below to far above the display range, in quarter stops
+            for stop in -80..=80 {
+                let c = tone_map(Vec3::splat((stop as f32 / 4.0).exp2()), tone_mapper);
+                assert!(c.cmpge(previous).all(), "{:?} decreases at stop {}: {} < {}", tone_mapper, stop as f32 / 4.0, c, previous);
+                assert!(c.cmpge(Vec3::ZERO).all() && c.cmple(Vec3::ONE).all(), "{:?} maps stop {} outside [0, 1]: {}", tone_mapper, stop as f32 / 4.0, c);
+                previous = c;
+            }
+        }
+    }
+}
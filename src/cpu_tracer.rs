@@ -0,0 +1,599 @@
+// A CPU implementation of the programs in res/shaders.hlsl. It renders the same scene as the D3D12 path and
+// follows the DXR execution model (instance contributions, hit-group indexing, miss indices) so the two can be diffed.
+
+use glam::*;
+use std::cell::Cell;
+
+use crate::bvh::{BlasBvh, Tlas, TlasHit, TraversalStats};
+use crate::frame::FrameConstants;
+use crate::color::{encode, tone_map, Encoding, ToneMapper};
+use crate::framebuffer::{float_to_unorm8, HdrImage, Image};
+use crate::integrator::{power_heuristic, INTEGRATOR_PATH, RUSSIAN_ROULETTE_BOUNCES};
+use crate::light::LightData;
+use crate::material::{dielectric_fresnel, reflect, refract, sample_ggx, MaterialData, MIN_ROUGHNESS, NO_TEXTURE, SURFACE_DIELECTRIC, SURFACE_GLOSSY, SURFACE_MIRROR};
+use crate::sampler::{bounce_dimension, Sampler, SamplerKind, DIMENSION_DIRECTION, DIMENSION_LENS, DIMENSION_LIGHTS, DIMENSION_LOBE, DIMENSION_PIXEL, SAMPLER_BLUE_NOISE, SAMPLER_SOBOL};
+use crate::scene::{Blas, Instance, Scene};
+use crate::texture::{cone_lod, Texture};
+
+// Ambient light, so that surfaces in the shadow of every light aren't black
+const AMBIENT: f32 = 0.1;
+
+// Paths that would add less than this to the pixel don't trace further
+const MIN_THROUGHPUT: f32 = 0.001;
+
+// The values of RayPayload::mode. The path tracer only asks the closest-hit shaders for the surface
+const RAY_SHADE: u32 = 0;
+const RAY_SURFACE: u32 = 1;
+
+// RayPayload::material after a miss
+const NO_MATERIAL: u32 = u32::MAX;
+
+pub struct RayDesc {
+    pub origin: Vec3,
+    pub t_min: f32,
+    pub direction: Vec3,
+    pub t_max: f32,
+}
+
+struct RayPayload {
+    color: Vec3,
+    // The subsample the ray belongs to, seeds the light samples
+    sample_index: u32,
+    // What the color is multiplied with on its way to the camera, and the reflection and refraction rays before it
+    throughput: Vec3,
+    depth: u32,
+    mode: u32,
+    // The surface a RAY_SURFACE ray hit: the position, the index of the material in gMaterials, the outward normal
+    // and the base color with the hit group's tint, the metallic factor and the roughness, all with the textures
+    // applied
+    position: Vec3,
+    material: u32,
+    normal: Vec3,
+    base_color: Vec3,
+    metallic: f32,
+    roughness: f32,
+    // The ray cone the textures are filtered with: its width at the origin of the ray, which the closest-hit shaders
+    // widen to the hit, and the angle it widens by
+    cone_width: f32,
+    cone_spread: f32,
+}
+
+impl RayPayload {
+    fn new(sample_index: u32, throughput: Vec3, depth: u32, mode: u32) -> Self {
+        Self {
+            color: Vec3::ZERO,
+            sample_index,
+            throughput,
+            depth,
+            mode,
+            position: Vec3::ZERO,
+            material: NO_MATERIAL,
+            normal: Vec3::ZERO,
+            base_color: Vec3::ZERO,
+            metallic: 0.0,
+            roughness: 0.0,
+            cone_width: 0.0,
+            cone_spread: 0.0,
+        }
+    }
+}
+
+struct ShadowPayload {
+    hit: bool,
+}
+
+enum Payload<'a> {
+    Ray(&'a mut RayPayload),
+    Shadow(&'a mut ShadowPayload),
+}
+
+// What the hit programs can query about the intersection (RayTCurrent(), WorldRayOrigin(), etc.)
+struct HitInfo {
+    t: f32,
+    barycentrics: Vec2,
+    world_ray_origin: Vec3,
+    world_ray_direction: Vec3,
+    instance: usize,
+    geometry_index: usize,
+    primitive_index: usize,
+}
+
+#[derive(Clone, Copy)]
+pub enum MissProgram {
+    Miss,
+    ShadowMiss,
+}
+
+// The programs in the hit-group table. We only implement the closest-hit shaders of res/shaders.hlsl. `geometry` is
+// the BLAS and geometry index the vertex and index root SRVs refer to
+#[derive(Clone, Copy)]
+pub enum HitGroup {
+    // The triangle hit group with the content of its constant buffer
+    Triangle { colors: [Vec3; 3], geometry: (usize, usize) },
+    Plane { geometry: (usize, usize) },
+    Shadow,
+}
+
+#[derive(Default)]
+pub struct CpuTracer {
+    blas: Vec<BlasBvh>,
+    tlas: Tlas,
+    instances: Vec<Instance>,
+    // The geometry the hit programs read through their root SRVs
+    geometries: Vec<Blas>,
+    // The content of gLights
+    lights: Vec<LightData>,
+    // The content of gMaterials and gTextures
+    materials: Vec<MaterialData>,
+    textures: Vec<Texture>,
+    pub miss_table: Vec<MissProgram>,
+    pub hit_group_table: Vec<HitGroup>,
+    // The content of the ray-gen constant buffer
+    pub frame: FrameConstants,
+    // The running average of the linear color per pixel, like gAccumulation. Reallocated when the size changes
+    accumulation: Vec<Vec3>,
+    // DispatchRaysIndex() of the pixel being traced
+    launch_index: Cell<UVec2>,
+    // The pipeline's recursion limit, which the GPU doesn't check, and gMaxDepth
+    pub max_recursion_depth: u32,
+    pub max_depth: u32,
+    // The TraceRay() calls on the stack
+    recursion: Cell<u32>,
+    // Accumulated over every ray traced since the acceleration structures were built
+    stats: Cell<TraversalStats>,
+}
+
+impl CpuTracer {
+    fn closest_hit(&self, ray: &RayDesc, instance_inclusion_mask: u8) -> Option<HitInfo> {
+        let mut stats = self.stats.get();
+        let hit = self.tlas.intersect(&self.blas, ray, instance_inclusion_mask, &mut stats);
+        self.stats.set(stats);
+        hit.map(|TlasHit { instance, hit }| HitInfo {
+            t: hit.t,
+            barycentrics: hit.barycentrics,
+            world_ray_origin: ray.origin,
+            world_ray_direction: ray.direction,
+            instance,
+            geometry_index: hit.geometry_index,
+            primitive_index: hit.primitive_index,
+        })
+    }
+
+    pub fn build_acceleration_structures(&mut self, scene: &Scene) {
+        self.blas = scene.blas.iter().map(BlasBvh::build).collect();
+        self.geometries = scene.blas.clone();
+        self.lights = scene.lights.iter().map(|l| l.data()).collect();
+        self.materials = scene.materials.iter().map(|m| m.data()).collect();
+        self.textures = scene.textures.clone();
+        self.refit_tlas(&scene.instances);
+        self.stats.set(TraversalStats::default());
+    }
+
+    // Rebuilding is cheap enough for the instance counts we have, so unlike the D3D12 path there is no refit
+    pub fn refit_tlas(&mut self, instances: &[Instance]) {
+        self.tlas = Tlas::build(instances, &self.blas);
+        self.instances = instances.to_vec();
+    }
+
+    pub fn stats(&self) -> TraversalStats {
+        self.stats.get()
+    }
+
+    // TraceRay(). The hit-group record is found the same way the hardware does:
+    // instance contribution + ray contribution + geometry multiplier * geometry index
+    fn trace_ray(&self, instance_inclusion_mask: u8, ray_contribution: usize, multiplier: usize, miss_index: usize, ray: &RayDesc, payload: Payload) {
+        // Exceeding the limit is undefined behavior on the GPU, usually a removed device
+        let recursion = self.recursion.get() + 1;
+        assert!(recursion <= self.max_recursion_depth, "TraceRay() recursion {} exceeds the pipeline's limit of {}", recursion, self.max_recursion_depth);
+        self.recursion.set(recursion);
+        self.run_hit_or_miss(instance_inclusion_mask, ray_contribution, multiplier, miss_index, ray, payload);
+        self.recursion.set(recursion - 1);
+    }
+
+    fn run_hit_or_miss(&self, instance_inclusion_mask: u8, ray_contribution: usize, multiplier: usize, miss_index: usize, ray: &RayDesc, payload: Payload) {
+        match self.closest_hit(ray, instance_inclusion_mask) {
+            Some(hit) => {
+                let contribution = self.instances[hit.instance].instance_contribution as usize;
+                let index = contribution + ray_contribution + multiplier * hit.geometry_index;
+                match (self.hit_group_table[index], payload) {
+                    (HitGroup::Triangle { colors, geometry }, Payload::Ray(payload)) => self.triangle_chs(payload, &hit, &colors, geometry),
+                    (HitGroup::Plane { geometry }, Payload::Ray(payload)) => self.plane_chs(payload, &hit, geometry),
+                    (HitGroup::Shadow, Payload::Shadow(payload)) => shadow_chs(payload, &hit),
+                    _ => panic!("hit group {} doesn't match the payload type", index),
+                }
+            }
+            None => match (self.miss_table[miss_index], payload) {
+                (MissProgram::Miss, Payload::Ray(payload)) => miss(payload),
+                (MissProgram::ShadowMiss, Payload::Shadow(payload)) => shadow_miss(payload),
+                _ => panic!("miss shader {} doesn't match the payload type", miss_index),
+            },
+        }
+    }
+
+    // Returns the new average for the pixel, `previous` is its accumulated color
+    fn ray_gen(&self, launch_index: UVec2, launch_dim: UVec2, previous: Vec3) -> Vec3 {
+        let dims = launch_dim.as_vec2();
+
+        // Every subsample is jittered inside the pixel
+        let mut sum = Vec3::ZERO;
+        for i in 0..self.frame.samples_per_frame {
+            let sample_index = self.frame.sample_index.wrapping_add(i);
+            let sampler = self.sampler(sample_index);
+            let crd = launch_index.as_vec2() + sampler.get_2d(DIMENSION_PIXEL);
+            let d = (crd / dims) * 2.0 - 1.0;
+            let (origin, direction) = self.frame.ray(d, sampler.get_2d(DIMENSION_LENS));
+            let ray = RayDesc { origin, direction, t_min: self.frame.t_min, t_max: self.frame.t_max };
+
+            let cone_spread = self.frame.pixel_spread(launch_dim.y);
+            if self.frame.integrator == INTEGRATOR_PATH {
+                sum += self.path_trace(ray, sample_index, cone_spread);
+                continue;
+            }
+            let mut payload = RayPayload { cone_spread, ..RayPayload::new(sample_index, Vec3::ONE, 0, RAY_SHADE) };
+            self.trace_ray(0xFF, 0, 2, 0, &ray, Payload::Ray(&mut payload));
+            sum += payload.color;
+        }
+
+        // Add the new samples to the running average. After a reset the old content is ignored
+        let accumulated = self.frame.accumulated_samples;
+        let previous = if accumulated > 0 { previous } else { Vec3::ZERO };
+        let total = (accumulated + self.frame.samples_per_frame).max(1);
+        (previous * accumulated as f32 + sum) / total as f32
+    }
+
+    // hitNormal(): the world-space normal of the hit, pointing out of counter-clockwise triangles. Geometries with
+    // normals interpolate them with the barycentrics, flipped to the outside if need be
+    fn hit_normal(&self, hit: &HitInfo, (blas, geometry): (usize, usize)) -> Vec3 {
+        let geometry = &self.geometries[blas].geometries[geometry];
+        let [p0, p1, p2] = geometry.triangle(hit.primitive_index);
+        // Normals transform with the inverse transpose of the object-to-world matrix
+        let normal_matrix = self.instances[hit.instance].transform.inverse().transpose();
+        let face = normal_matrix.transform_vector3((p1 - p0).cross(p2 - p0)).normalize();
+        let Some(normals) = &geometry.normals else { return face };
+        let [n0, n1, n2] = geometry.triangle_indices(hit.primitive_index).map(|v| normals[v]);
+        let b = hit.barycentrics;
+        let shading = normal_matrix.transform_vector3(n0 * (1.0 - b.x - b.y) + n1 * b.x + n2 * b.y);
+        if shading.length_squared() == 0.0 {
+            return face;
+        }
+        let shading = shading.normalize();
+        if shading.dot(face) < 0.0 { -shading } else { shading }
+    }
+
+    // hitSurface(): the material of the hit with its textures applied, and the outward shading normal with the normal
+    // map. Widens the ray cone of the payload to the hit, the textures are filtered with it
+    fn hit_surface(&self, hit: &HitInfo, (blas, geometry): (usize, usize), payload: &mut RayPayload) -> (MaterialData, Vec3) {
+        let mut normal = self.hit_normal(hit, (blas, geometry));
+        let mut material = *self.hit_material(hit);
+        payload.cone_width += payload.cone_spread * hit.t;
+        let geometry = &self.geometries[blas].geometries[geometry];
+        let Some(tex_coords) = &geometry.tex_coords else { return (material, normal) };
+        let indices = geometry.triangle_indices(hit.primitive_index);
+        let [uv0, uv1, uv2] = indices.map(|v| tex_coords[v]);
+        let b = hit.barycentrics;
+        let uv = uv0 * (1.0 - b.x - b.y) + uv1 * b.x + uv2 * b.y;
+
+        // The level of detail, see texture::cone_lod()
+        let transform = self.instances[hit.instance].transform;
+        let [p0, p1, p2] = geometry.triangle(hit.primitive_index).map(|p| transform.transform_point3(p));
+        let (dp1, dp2, duv1, duv2) = (p1 - p0, p2 - p0, uv1 - uv0, uv2 - uv0);
+        let world_area = dp1.cross(dp2).length();
+        let uv_area = duv1.perp_dot(duv2).abs();
+        let cos = normal.dot(hit.world_ray_direction);
+        let sample = |index: u32| {
+            let texture = &self.textures[index as usize];
+            texture.sample(uv, cone_lod(payload.cone_width, cos, uv_area, world_area, uvec2(texture.width(), texture.height())))
+        };
+
+        if material.base_color_texture != NO_TEXTURE {
+            material.base_color *= sample(material.base_color_texture).truncate();
+        }
+        if material.metallic_roughness_texture != NO_TEXTURE {
+            let texel = sample(material.metallic_roughness_texture);
+            material.metallic *= texel.z;
+            material.roughness = (material.roughness * texel.y).max(MIN_ROUGHNESS);
+        }
+        if material.normal_texture != NO_TEXTURE {
+            // The tangent of the vertices, or the direction of +u across the triangle. The bitangent points up in the
+            // texture, towards -v, like the tangent-space normals of glTF expect
+            let (tangent, sign) = match &geometry.tangents {
+                Some(tangents) => {
+                    let [t0, t1, t2] = indices.map(|v| tangents[v]);
+                    let tangent = transform.transform_vector3((t0 * (1.0 - b.x - b.y) + t1 * b.x + t2 * b.y).truncate());
+                    // A mirroring transform flips the handedness
+                    (tangent, t0.w * transform.determinant().signum())
+                }
+                None => {
+                    let r = 1.0 / duv1.perp_dot(duv2);
+                    let tangent = (dp1 * duv2.y - dp2 * duv1.y) * r;
+                    let bitangent = (dp1 * duv2.x - dp2 * duv1.x) * r;
+                    (tangent, normal.cross(tangent).dot(bitangent).signum())
+                }
+            };
+            let t = (tangent - normal * normal.dot(tangent)).normalize();
+            if t.is_finite() {
+                let m = sample(material.normal_texture).truncate() * 2.0 - 1.0;
+                let mapped = (t * m.x + normal.cross(t) * (sign * m.y) + normal * m.z).normalize();
+                if mapped.is_finite() {
+                    normal = mapped;
+                }
+            }
+        }
+        (material, normal)
+    }
+
+    // The sampler of a subsample of the pixel being traced
+    fn sampler(&self, sample_index: u32) -> Sampler {
+        Sampler::new(sampler_kind(self.frame.sampler), self.launch_index.get(), sample_index)
+    }
+
+    // hitMaterial(): the index of the material at InstanceID() + GeometryIndex()
+    fn hit_material_index(&self, hit: &HitInfo) -> u32 {
+        self.instances[hit.instance].instance_id + hit.geometry_index as u32
+    }
+
+    fn hit_material(&self, hit: &HitInfo) -> &MaterialData {
+        &self.materials[self.hit_material_index(hit) as usize]
+    }
+
+    // traceShadow(): whether anything blocks the way towards a light sample
+    fn trace_shadow(&self, origin: Vec3, direction: Vec3, distance: f32) -> bool {
+        let ray = RayDesc { origin, direction, t_min: 0.01, t_max: distance - 0.01 };
+        let mut shadow_payload = ShadowPayload { hit: false };
+        self.trace_ray(0xFF, 1, 0, 1, &ray, Payload::Shadow(&mut shadow_payload));
+        shadow_payload.hit
+    }
+
+    // The emission and the reflection of every light that isn't shadowed
+    fn direct_lighting(&self, material: &MaterialData, base_color: Vec3, position: Vec3, normal: Vec3, v: Vec3, payload: &RayPayload) -> Vec3 {
+        let mut color = material.emission + base_color * AMBIENT;
+        let sampler = self.sampler(payload.sample_index);
+        for (i, light) in self.lights.iter().enumerate() {
+            let sample = light.sample(position, sampler.get_2d(bounce_dimension(payload.depth, DIMENSION_LIGHTS + i as u32)));
+            let cos_surface = normal.dot(sample.direction);
+            if cos_surface <= 0.0 || sample.irradiance == Vec3::ZERO {
+                continue;
+            }
+            if !self.trace_shadow(position, sample.direction, sample.distance) {
+                color += sample.irradiance * cos_surface * material.eval(base_color, normal, v, sample.direction);
+            }
+        }
+        color
+    }
+
+    // sampleLights(): the next-event estimation of a path vertex. The reflection of every light that isn't shadowed,
+    // with the area lights weighted against the BRDF samples that can hit them too. `surface` is the payload that
+    // reported the vertex, `n` its normal facing `v`
+    fn sample_lights(&self, material: &MaterialData, surface: &RayPayload, n: Vec3, v: Vec3) -> Vec3 {
+        let mut color = Vec3::ZERO;
+        let sampler = self.sampler(surface.sample_index);
+        for (i, light) in self.lights.iter().enumerate() {
+            let sample = light.sample(surface.position, sampler.get_2d(bounce_dimension(surface.depth, DIMENSION_LIGHTS + i as u32)));
+            let cos_surface = n.dot(sample.direction);
+            if cos_surface <= 0.0 || sample.irradiance == Vec3::ZERO || self.trace_shadow(surface.position, sample.direction, sample.distance) {
+                continue;
+            }
+            let light_pdf = light.pdf(sample.direction, sample.distance);
+            let weight = if light_pdf > 0.0 { power_heuristic(light_pdf, material.pdf(n, v, sample.direction)) } else { 1.0 };
+            color += sample.irradiance * cos_surface * material.eval(surface.base_color, n, v, sample.direction) * weight;
+        }
+        color
+    }
+
+    // pathTrace(): the radiance along a camera ray, by a loop in rayGen that asks the closest-hit shaders for the
+    // surfaces. See integrator
+    fn path_trace(&self, mut ray: RayDesc, sample_index: u32, cone_spread: f32) -> Vec3 {
+        let sampler = self.sampler(sample_index);
+        let mut color = Vec3::ZERO;
+        let mut throughput = Vec3::ONE;
+        // The pdf of the BRDF sample the ray follows. 0 for the camera ray and after mirrors and glass, which the light
+        // samples can't find, so the lights they hit count fully
+        let mut brdf_pdf = 0.0;
+        let mut bounce = 0;
+        // The ray cone continues from every hit with the same spread, which ignores the curvature of the surfaces
+        let mut cone_width = 0.0;
+        loop {
+            let mut payload = RayPayload { cone_width, cone_spread, ..RayPayload::new(sample_index, throughput, bounce, RAY_SURFACE) };
+            self.trace_ray(0xFF, 0, 2, 0, &ray, Payload::Ray(&mut payload));
+
+            // The area lights in front of the hit
+            let hit_distance = if payload.material == NO_MATERIAL { f32::INFINITY } else { (payload.position - ray.origin).length() };
+            for light in &self.lights {
+                if let Some(t) = light.intersect(ray.origin, ray.direction).filter(|&t| t < hit_distance) {
+                    let weight = if brdf_pdf > 0.0 { power_heuristic(brdf_pdf, light.pdf(ray.direction, t)) } else { 1.0 };
+                    color += throughput * light.color * weight;
+                }
+            }
+            if payload.material == NO_MATERIAL {
+                return color + throughput * payload.color;
+            }
+            let material = &MaterialData { metallic: payload.metallic, roughness: payload.roughness, ..self.materials[payload.material as usize] };
+            color += throughput * material.emission;
+            if bounce >= self.frame.max_bounces {
+                return color;
+            }
+
+            let d = ray.direction;
+            let front_face = payload.normal.dot(d) < 0.0;
+            let n = if front_face { payload.normal } else { -payload.normal };
+            let lobe = sampler.get_2d(bounce_dimension(bounce, DIMENSION_LOBE));
+            let direction = match material.surface {
+                SURFACE_MIRROR => {
+                    throughput *= payload.base_color;
+                    brdf_pdf = 0.0;
+                    reflect(d, n)
+                }
+                SURFACE_DIELECTRIC => {
+                    let eta = if front_face { 1.0 / material.ior } else { material.ior };
+                    brdf_pdf = 0.0;
+                    if lobe.x < dielectric_fresnel((-d).dot(n), eta) {
+                        reflect(d, n)
+                    } else {
+                        throughput *= payload.base_color;
+                        refract(d, n, eta)
+                    }
+                }
+                _ => {
+                    color += throughput * self.sample_lights(material, &payload, n, -d);
+                    let u = sampler.get_2d(bounce_dimension(bounce, DIMENSION_DIRECTION));
+                    let direction = material.sample(n, -d, u, lobe.x);
+                    brdf_pdf = material.pdf(n, -d, direction);
+                    if brdf_pdf <= 0.0 {
+                        return color;
+                    }
+                    // The BRDF is eval() / pi
+                    throughput *= material.eval(payload.base_color, n, -d, direction) * n.dot(direction) / (std::f32::consts::PI * brdf_pdf);
+                    direction
+                }
+            };
+
+            // Russian roulette, the surviving paths make up for the terminated ones
+            if bounce + 1 >= RUSSIAN_ROULETTE_BOUNCES {
+                let survival = throughput.max_element().min(0.95);
+                if lobe.y >= survival {
+                    return color;
+                }
+                throughput /= survival;
+            }
+            ray = RayDesc { origin: payload.position, direction, t_min: 0.01, t_max: 100000.0 };
+            cone_width = payload.cone_width;
+            bounce += 1;
+        }
+    }
+
+    // traceSecondary(): a reflection or refraction ray, `weight` is what the caller multiplies the result with
+    fn trace_secondary(&self, origin: Vec3, direction: Vec3, weight: Vec3, parent: &RayPayload) -> Vec3 {
+        let throughput = parent.throughput * weight;
+        if parent.depth >= self.max_depth || throughput.max_element() < MIN_THROUGHPUT {
+            return Vec3::ZERO;
+        }
+        let ray = RayDesc { origin, direction, t_min: 0.01, t_max: 100000.0 };
+        let mut payload = RayPayload {
+            cone_width: parent.cone_width,
+            cone_spread: parent.cone_spread,
+            ..RayPayload::new(parent.sample_index, throughput, parent.depth + 1, RAY_SHADE)
+        };
+        self.trace_ray(0xFF, 0, 2, 0, &ray, Payload::Ray(&mut payload));
+        payload.color
+    }
+
+    // The color of a hit by the surface type of the material. `normal` is the outward normal from hit_normal()
+    fn shade(&self, material: &MaterialData, base_color: Vec3, position: Vec3, normal: Vec3, hit: &HitInfo, payload: &RayPayload) -> Vec3 {
+        let d = hit.world_ray_direction;
+        let front_face = normal.dot(d) < 0.0;
+        let n = if front_face { normal } else { -normal };
+        let u = self.sampler(payload.sample_index).get_2d(bounce_dimension(payload.depth, DIMENSION_DIRECTION));
+        match material.surface {
+            SURFACE_MIRROR => material.emission + base_color * self.trace_secondary(position, reflect(d, n), base_color, payload),
+            SURFACE_GLOSSY => {
+                let mut color = self.direct_lighting(material, base_color, position, n, -d, payload);
+                // Reflect around a microfacet normal, the accumulation averages the blur
+                let h = sample_ggx(n, material.roughness * material.roughness, u);
+                let direction = reflect(d, h);
+                if direction.dot(n) > 0.0 {
+                    let weight = material.specular_fresnel(base_color, (-d).dot(h));
+                    color += weight * self.trace_secondary(position, direction, weight, payload);
+                }
+                color
+            }
+            SURFACE_DIELECTRIC => {
+                // Reflect with the probability of the Fresnel term and refract otherwise. Leaving the surface, the
+                // ratio of the indices flips
+                let eta = if front_face { 1.0 / material.ior } else { material.ior };
+                if u.x < dielectric_fresnel((-d).dot(n), eta) {
+                    return material.emission + self.trace_secondary(position, reflect(d, n), Vec3::ONE, payload);
+                }
+                material.emission + base_color * self.trace_secondary(position, refract(d, n, eta), base_color, payload)
+            }
+            _ => self.direct_lighting(material, base_color, position, n, -d, payload),
+        }
+    }
+
+    fn triangle_chs(&self, payload: &mut RayPayload, hit: &HitInfo, cb: &[Vec3; 3], geometry: (usize, usize)) {
+        let barycentrics = vec3(1.0 - hit.barycentrics.x - hit.barycentrics.y, hit.barycentrics.x, hit.barycentrics.y);
+        let tint = cb[0] * barycentrics.x + cb[1] * barycentrics.y + cb[2] * barycentrics.z;
+        let (material, normal) = self.hit_surface(hit, geometry, payload);
+        let pos_w = hit.world_ray_origin + hit.t * hit.world_ray_direction;
+        if payload.mode == RAY_SURFACE {
+            return self.report_surface(payload, hit, pos_w, normal, &material, material.base_color * tint);
+        }
+        payload.color = self.shade(&material, material.base_color * tint, pos_w, normal, hit, payload);
+    }
+
+    fn plane_chs(&self, payload: &mut RayPayload, hit: &HitInfo, geometry: (usize, usize)) {
+        // Find the world-space hit position
+        let pos_w = hit.world_ray_origin + hit.t * hit.world_ray_direction;
+
+        let (material, normal) = self.hit_surface(hit, geometry, payload);
+        if payload.mode == RAY_SURFACE {
+            return self.report_surface(payload, hit, pos_w, normal, &material, material.base_color);
+        }
+        payload.color = self.shade(&material, material.base_color, pos_w, normal, hit, payload);
+    }
+
+    // reportSurface(): what a closest-hit shader returns to the path tracer instead of the color
+    fn report_surface(&self, payload: &mut RayPayload, hit: &HitInfo, position: Vec3, normal: Vec3, material: &MaterialData, base_color: Vec3) {
+        payload.position = position;
+        payload.material = self.hit_material_index(hit);
+        payload.normal = normal;
+        payload.base_color = base_color;
+        payload.metallic = material.metallic;
+        payload.roughness = material.roughness;
+    }
+
+    // Runs rayGen for every pixel, like DispatchRays() with the given dimensions. Returns the output and the HDR output
+    pub fn dispatch_rays(&mut self, width: u32, height: u32) -> (Image, HdrImage) {
+        let mut image = Image::new(width, height);
+        let mut hdr_image = HdrImage::new(width, height);
+        let launch_dim = uvec2(width, height);
+        let tone_mapper = tone_mapper(self.frame.tone_mapper);
+        let encoding = encoding(self.frame.encoding);
+        let mut accumulation = std::mem::take(&mut self.accumulation);
+        accumulation.resize(width as usize * height as usize, Vec3::ZERO);
+        for y in 0..height {
+            for x in 0..width {
+                self.launch_index.set(uvec2(x, y));
+                let color = &mut accumulation[(y * width + x) as usize];
+                *color = self.ray_gen(uvec2(x, y), launch_dim, *color);
+
+                // The output transform, see color::ColorSettings
+                let hdr = tone_map(*color * self.frame.exposure, tone_mapper);
+                hdr_image.set_pixel(x, y, hdr.extend(1.0));
+                let col = encode(hdr, encoding);
+                image.set_pixel(x, y, [float_to_unorm8(col.x), float_to_unorm8(col.y), float_to_unorm8(col.z), float_to_unorm8(1.0)]);
+            }
+        }
+        self.accumulation = accumulation;
+        (image, hdr_image)
+    }
+}
+
+// The discriminants in the constant buffer. Like the switch statements in res/shaders.hlsl, unknown values fall back
+// to the first variant
+fn tone_mapper(value: u32) -> ToneMapper {
+    [ToneMapper::Reinhard, ToneMapper::AcesFitted, ToneMapper::AgX].into_iter().find(|&t| t as u32 == value).unwrap_or(ToneMapper::None)
+}
+
+fn encoding(value: u32) -> Encoding {
+    [Encoding::Rec709, Encoding::Pq].into_iter().find(|&e| e as u32 == value).unwrap_or(Encoding::Srgb)
+}
+
+fn sampler_kind(value: u32) -> SamplerKind {
+    match value {
+        SAMPLER_SOBOL => SamplerKind::Sobol,
+        SAMPLER_BLUE_NOISE => SamplerKind::BlueNoise,
+        _ => SamplerKind::Pcg,
+    }
+}
+
+fn miss(payload: &mut RayPayload) {
+    payload.color = vec3(0.4, 0.6, 0.2);
+}
+
+fn shadow_chs(payload: &mut ShadowPayload, _hit: &HitInfo) {
+    payload.hit = true;
+}
+
+fn shadow_miss(payload: &mut ShadowPayload) {
+    payload.hit = false;
+}
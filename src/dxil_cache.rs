@@ -0,0 +1,188 @@
+// Compiled shader libraries, stored next to their HLSL so a build can run without dxcompiler.dll. A cache entry is
+// keyed by a hash of everything that goes into the compile: the source, every file it includes, the defines and the
+// other arguments. The compiler version is stored too, and a different compiler rebuilds the entry when one is present.
+// `--compile-shaders` fills the cache ahead of time
+
+use std::collections::HashSet;
+use std::io;
+#[cfg(windows)]
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use crate::shader_compiler::CompileOptions;
+
+#[cfg(windows)]
+const MAGIC: &[u8; 8] = b"DXILLIB\0";
+#[cfg(windows)]
+const FORMAT_VERSION: u32 = 1;
+// Magic, format version, source hash, compiler major and minor version
+#[cfg(windows)]
+const HEADER_SIZE: usize = 8 + 4 + 8 + 4 + 4;
+
+#[cfg(windows)]
+pub struct CachedDxil {
+    pub source_hash: u64,
+    pub compiler_version: (u32, u32),
+    pub bytecode: Vec<u8>,
+}
+
+// res/shaders.hlsl is cached in res/shaders.dxil
+#[cfg(windows)]
+pub fn cache_path(library: &Path) -> PathBuf {
+    library.with_extension("dxil")
+}
+
+// FNV-1a, because the hash has to be stable between builds and Rust versions, which DefaultHasher isn't
+#[cfg(any(windows, test))]
+struct Fnv1a(u64);
+
+#[cfg(any(windows, test))]
+impl Fnv1a {
+    fn new() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 ^= byte as u64;
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    // Strings are terminated so that ("ab", "c") and ("a", "bc") hash differently
+    fn write_str(&mut self, s: &str) {
+        self.write(s.as_bytes());
+        self.write(&[0]);
+    }
+}
+
+// Hashes the library, its includes and the compile options. Fails if the library or one of its includes can't be read
+#[cfg(any(windows, test))]
+pub fn source_hash(library: &Path, options: &CompileOptions) -> io::Result<u64> {
+    let mut hasher = Fnv1a::new();
+    walk_includes(library, options, &mut HashSet::new(), &mut |source| match source {
+        Source::File(_, text) => hasher.write_str(text),
+        Source::Unresolved(include) => hasher.write_str(include),
+    })?;
+    for (name, value) in &options.defines {
+        hasher.write_str(name);
+        hasher.write_str(value);
+    }
+    for argument in options.arguments() {
+        hasher.write_str(&argument);
+    }
+    Ok(hasher.0)
+}
+
+// The library and every file it includes, which is what the shader hot-reload watches. Fails like source_hash()
+pub fn source_files(library: &Path, options: &CompileOptions) -> io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    walk_includes(library, options, &mut HashSet::new(), &mut |source| {
+        if let Source::File(path, _) = source {
+            files.push(path.to_path_buf());
+        }
+    })?;
+    Ok(files)
+}
+
+#[cfg_attr(not(any(windows, test)), allow(dead_code))]
+enum Source<'a> {
+    File(&'a Path, &'a str),
+    // An include that isn't found in any of the search directories
+    Unresolved(&'a str),
+}
+
+// Visits a file and then its includes, depth first in the order they appear
+fn walk_includes(path: &Path, options: &CompileOptions, visited: &mut HashSet<PathBuf>, visit: &mut impl FnMut(Source)) -> io::Result<()> {
+    // Like #pragma once, a file that is included twice only counts once
+    if !visited.insert(path.canonicalize().unwrap_or_else(|_| path.to_path_buf())) {
+        return Ok(());
+    }
+    let source = std::fs::read_to_string(path).map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))?;
+    visit(Source::File(path, &source));
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    for include in source.lines().filter_map(include_name) {
+        // The same search order as DXC: the directory of the including file first, then the -I directories
+        let resolved = std::iter::once(dir).chain(options.include_dirs.iter().map(PathBuf::as_path)).map(|d| d.join(include)).find(|p| p.is_file());
+        match resolved {
+            Some(resolved) => walk_includes(&resolved, options, visited, visit)?,
+            // Probably inside an #if that is never taken, the compiler reports it if it isn't
+            None => visit(Source::Unresolved(include)),
+        }
+    }
+    Ok(())
+}
+
+// The file name of an `#include "file"` or `#include <file>` line
+fn include_name(line: &str) -> Option<&str> {
+    let rest = line.trim_start().strip_prefix('#')?.trim_start().strip_prefix("include")?.trim_start();
+    let (open, rest) = rest.split_at(rest.chars().next()?.len_utf8());
+    let close = match open {
+        "\"" => '"',
+        "<" => '>',
+        _ => return None,
+    };
+    rest.find(close).map(|end| &rest[..end])
+}
+
+#[cfg(windows)]
+pub fn load(path: &Path) -> io::Result<CachedDxil> {
+    let data = std::fs::read(path)?;
+    if data.len() < HEADER_SIZE || &data[..8] != MAGIC {
+        return Err(io::Error::new(ErrorKind::InvalidData, format!("{} is not a DXIL cache file", path.display())));
+    }
+    let u32_at = |offset: usize| u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap());
+    if u32_at(8) != FORMAT_VERSION {
+        return Err(io::Error::new(ErrorKind::InvalidData, format!("{} has an unsupported format version", path.display())));
+    }
+    Ok(CachedDxil {
+        source_hash: u64::from_le_bytes(data[12..20].try_into().unwrap()),
+        compiler_version: (u32_at(20), u32_at(24)),
+        bytecode: data[HEADER_SIZE..].to_vec(),
+    })
+}
+
+#[cfg(windows)]
+pub fn store(path: &Path, entry: &CachedDxil) -> io::Result<()> {
+    let mut data = Vec::with_capacity(HEADER_SIZE + entry.bytecode.len());
+    data.extend_from_slice(MAGIC);
+    data.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+    data.extend_from_slice(&entry.source_hash.to_le_bytes());
+    data.extend_from_slice(&entry.compiler_version.0.to_le_bytes());
+    data.extend_from_slice(&entry.compiler_version.1.to_le_bytes());
+    data.extend_from_slice(&entry.bytecode);
+    // Write and rename, so a running build never loads a half written file
+    let temp = path.with_extension("dxil.tmp");
+    std::fs::write(&temp, &data)?;
+    std::fs::rename(&temp, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn source_files_follow_includes() {
+        let dir = std::env::temp_dir().join(format!("dxil_cache_test_{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("common")).unwrap();
+        let write = |name: &str, text: &str| std::fs::write(dir.join(name), text).unwrap();
+        write("main.hlsl", "#include \"local.hlsli\"\n#include <shared.hlsli>\n#include \"missing.hlsli\"\n");
+        write("local.hlsli", "  #  include \"common/shared.hlsli\"\n");
+        write("common/shared.hlsli", "#include \"../local.hlsli\"\n");
+        let options = CompileOptions { include_dirs: vec![dir.join("common")], ..Default::default() };
+
+        let files = source_files(&dir.join("main.hlsl"), &options).unwrap();
+        assert_eq!(files, [dir.join("main.hlsl"), dir.join("local.hlsli"), dir.join("common/shared.hlsli")]);
+
+        // Changing any of them changes the hash
+        let hash = source_hash(&dir.join("main.hlsl"), &options).unwrap();
+        write("common/shared.hlsli", "#include \"../local.hlsli\"\n// edited\n");
+        assert_ne!(source_hash(&dir.join("main.hlsl"), &options).unwrap(), hash);
+
+        // A missing include is left to the compiler, a missing library is an error
+        std::fs::remove_file(dir.join("local.hlsli")).unwrap();
+        assert_eq!(source_files(&dir.join("main.hlsl"), &options).unwrap(), [dir.join("main.hlsl"), dir.join("common/shared.hlsli")]);
+        assert!(source_files(&dir.join("other.hlsl"), &options).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,75 @@
+use std::fmt;
+
+#[cfg(windows)]
+use crate::shader_compiler::Diagnostic;
+
+// Everything that can go wrong while loading and rendering. Failing D3D12/DXGI calls are reported with the name
+// of the call, so an HRESULT can be traced back to the code that produced it
+#[derive(Debug)]
+pub enum Error {
+    // No hardware adapter could create a D3D12 device
+    #[cfg(windows)]
+    DeviceCreation(String),
+    // There is a device, but its raytracing tier is D3D12_RAYTRACING_TIER_NOT_SUPPORTED
+    #[cfg(windows)]
+    RaytracingNotSupported,
+    // The messages DXC reported, at least one of which is an error
+    #[cfg(windows)]
+    ShaderCompile { path: String, diagnostics: Vec<Diagnostic> },
+    // Creating a buffer, texture, heap, root signature or state object failed
+    #[cfg(windows)]
+    ResourceAllocation { call: &'static str, message: String },
+    // Any other failing API call
+    #[cfg(windows)]
+    Api { call: &'static str, message: String },
+    // A backend setting out of the range it supports, like the number of frames in flight
+    #[cfg(windows)]
+    InvalidConfig(String),
+    // A scene or shader file that can't be read or parsed
+    Asset(String),
+    // The scene, the shader table and the pipeline don't fit together
+    InvalidScene(String),
+    // A feature this platform or build doesn't have, like D3D12 anywhere but Windows
+    #[cfg(not(windows))]
+    Unsupported(String),
+    #[cfg(windows)]
+    Window(String),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            #[cfg(windows)]
+            Error::DeviceCreation(msg) => write!(f, "Failed to create a D3D12 device: {}", msg),
+            #[cfg(windows)]
+            Error::RaytracingNotSupported => write!(
+                f,
+                "Raytracing is not supported on this device. Make sure your GPU supports DXR (such as Nvidia's Volta or Turing RTX) and you're on the latest drivers. The DXR fallback layer is not supported."
+            ),
+            #[cfg(windows)]
+            Error::ShaderCompile { path, diagnostics } => {
+                write!(f, "Failed to compile {}:", path)?;
+                for diagnostic in diagnostics {
+                    write!(f, "\n{}", diagnostic)?;
+                }
+                Ok(())
+            }
+            #[cfg(windows)]
+            Error::ResourceAllocation { call, message } => write!(f, "{} failed to allocate: {}", call, message),
+            #[cfg(windows)]
+            Error::Api { call, message } => write!(f, "{} failed: {}", call, message),
+            #[cfg(windows)]
+            Error::InvalidConfig(msg) => write!(f, "Invalid configuration: {}", msg),
+            Error::Asset(msg) => write!(f, "Failed to load {}", msg),
+            Error::InvalidScene(msg) => write!(f, "Invalid scene: {}", msg),
+            #[cfg(not(windows))]
+            Error::Unsupported(msg) => write!(f, "{}", msg),
+            #[cfg(windows)]
+            Error::Window(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
@@ -0,0 +1,85 @@
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+// Watches a set of files by polling their modification times. This is what drives the shader hot-reload: it needs no
+// platform specific notifications, and a few metadata calls per second are nothing next to a frame
+pub struct FileWatcher {
+    files: Vec<(PathBuf, Option<SystemTime>)>,
+    last_poll: Instant,
+}
+
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+impl FileWatcher {
+    pub fn new(paths: impl IntoIterator<Item = PathBuf>) -> Self {
+        let files = paths.into_iter().map(|path| {
+            let modified = modified_time(&path);
+            (path, modified)
+        }).collect();
+        Self { files, last_poll: Instant::now() }
+    }
+
+    // Returns the first file that changed since the last call, once for every change. A file that can't be read (e.g.
+    // while an editor replaces it) doesn't count as a change, the write that follows does
+    pub fn changed(&mut self) -> Option<&Path> {
+        if self.last_poll.elapsed() < POLL_INTERVAL {
+            return None;
+        }
+        self.last_poll = Instant::now();
+        let mut changed = None;
+        for (i, (path, modified)) in self.files.iter_mut().enumerate() {
+            match modified_time(path) {
+                Some(time) if Some(time) != *modified => {
+                    *modified = Some(time);
+                    changed.get_or_insert(i);
+                }
+                _ => {}
+            }
+        }
+        changed.map(|i| self.files[i].0.as_path())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_changes_to_any_file() {
+        let dir = std::env::temp_dir().join(format!("file_watcher_test_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let paths = [dir.join("a.hlsl"), dir.join("b.hlsli")];
+        for path in &paths {
+            std::fs::write(path, "").unwrap();
+        }
+        let touch = |path: &Path, seconds: u64| {
+            let file = std::fs::File::options().write(true).open(path).unwrap();
+            file.set_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(seconds)).unwrap();
+        };
+        let mut watcher = FileWatcher::new(paths.clone());
+        // Polls are rate limited
+        watcher.last_poll -= POLL_INTERVAL;
+        assert_eq!(watcher.changed(), None);
+
+        touch(&paths[1], 1000);
+        assert_eq!(watcher.changed(), None);
+        watcher.last_poll -= POLL_INTERVAL;
+        assert_eq!(watcher.changed(), Some(paths[1].as_path()));
+        watcher.last_poll -= POLL_INTERVAL;
+        assert_eq!(watcher.changed(), None);
+
+        // A file that disappears isn't a change until it is written again
+        std::fs::remove_file(&paths[0]).unwrap();
+        watcher.last_poll -= POLL_INTERVAL;
+        assert_eq!(watcher.changed(), None);
+        std::fs::write(&paths[0], "").unwrap();
+        touch(&paths[0], 2000);
+        watcher.last_poll -= POLL_INTERVAL;
+        assert_eq!(watcher.changed(), Some(paths[0].as_path()));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
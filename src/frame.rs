@@ -0,0 +1,77 @@
+// The constant buffer rayGen() reads every frame: the camera, which Camera::constants() fills in, and the per-frame
+// state of the accumulation, the integrator, the sampler and the output transform, which the tutorial sets.
+
+use glam::*;
+
+// The FrameConstants cbuffer of res/shaders.hlsl (b1 in the ray-gen local root signature). Every float3 is followed
+// by a scalar to keep the HLSL packing
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct FrameConstants {
+    pub position: Vec3,
+    pub lens_radius: f32,
+    // Right, scaled to the half-width of the image plane at distance 1
+    pub u: Vec3,
+    pub focus_distance: f32,
+    // Up, scaled to the half-height of the image plane at distance 1
+    pub v: Vec3,
+    pub t_min: f32,
+    pub w: Vec3,
+    pub t_max: f32,
+    // The seed of the first subsample of this frame, the others follow it
+    pub sample_index: u32,
+    // How many samples the accumulation buffer holds. 0 starts over
+    pub accumulated_samples: u32,
+    // The jittered subsamples traced per pixel. 0 only rewrites the output from the accumulation buffer
+    pub samples_per_frame: u32,
+    // The output transform of color::ColorSettings, with the exposure as a linear scale
+    pub exposure: f32,
+    pub tone_mapper: u32,
+    pub encoding: u32,
+    // integrator::IntegratorSettings
+    pub integrator: u32,
+    pub max_bounces: u32,
+    // sampler::SamplerKind
+    pub sampler: u32,
+    pub _pad: [u32; 3],
+}
+
+impl FrameConstants {
+    // The primary ray through `ndc` (-1..1, +y down like the launch index). `lens_sample` is a uniform sample of
+    // the unit square, only used with a lens. This is mirrored by rayGen()
+    pub fn ray(&self, ndc: Vec2, lens_sample: Vec2) -> (Vec3, Vec3) {
+        let direction = (ndc.x * self.u + -ndc.y * self.v + self.w).normalize();
+        if self.lens_radius <= 0.0 {
+            return (self.position, direction);
+        }
+        // Every ray through the pixel converges on the plane of focus
+        let focus_point = self.position + direction * (self.focus_distance / direction.dot(self.w));
+        let r = self.lens_radius * lens_sample.x.sqrt();
+        let theta = 2.0 * std::f32::consts::PI * lens_sample.y;
+        let origin = self.position + self.u.normalize() * (r * theta.cos()) + self.v.normalize() * (r * theta.sin());
+        (origin, (focus_point - origin).normalize())
+    }
+
+    // The angle a pixel covers, how fast the ray cones of the camera rays widen. rayGen() computes the same
+    pub fn pixel_spread(&self, height: u32) -> f32 {
+        2.0 * self.v.length() / height as f32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::mem::{offset_of, size_of};
+
+    // The cbuffer packing of res/shaders.hlsl: float3 + scalar rows, then the uints in rows of 4
+    #[test]
+    fn layout_matches_the_cbuffer() {
+        assert_eq!(size_of::<FrameConstants>(), 112);
+        assert_eq!(offset_of!(FrameConstants, u), 16);
+        assert_eq!(offset_of!(FrameConstants, t_max), 60);
+        assert_eq!(offset_of!(FrameConstants, sample_index), 64);
+        assert_eq!(offset_of!(FrameConstants, exposure), 76);
+        assert_eq!(offset_of!(FrameConstants, integrator), 88);
+        assert_eq!(offset_of!(FrameConstants, sampler), 96);
+    }
+}
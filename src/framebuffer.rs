@@ -0,0 +1,215 @@
+use std::fs::File;
+use std::io::{BufWriter, Error, ErrorKind, Write};
+use std::path::Path;
+
+use glam::Vec4;
+
+use crate::color::Encoding;
+
+// An RGBA8 image, laid out like the output UAV (DXGI_FORMAT_R8G8B8A8_UNORM, rows top to bottom)
+#[derive(Clone, PartialEq, Eq)]
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    pub data: Vec<u8>,
+}
+
+impl Image {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            data: vec![0; width as usize * height as usize * 4],
+        }
+    }
+
+    #[cfg(test)]
+    pub fn pixel(&self, x: u32, y: u32) -> [u8; 4] {
+        let offset = (y as usize * self.width as usize + x as usize) * 4;
+        [self.data[offset], self.data[offset + 1], self.data[offset + 2], self.data[offset + 3]]
+    }
+
+    pub fn set_pixel(&mut self, x: u32, y: u32, rgba: [u8; 4]) {
+        let offset = (y as usize * self.width as usize + x as usize) * 4;
+        self.data[offset..offset + 4].copy_from_slice(&rgba);
+    }
+
+    // Returns the largest per-channel difference and the number of pixels that differ at all
+    pub fn diff(&self, other: &Image) -> Option<(u8, usize)> {
+        if self.width != other.width || self.height != other.height {
+            return None;
+        }
+        let mut max_diff = 0;
+        let mut diff_pixels = 0;
+        for (a, b) in self.data.chunks_exact(4).zip(other.data.chunks_exact(4)) {
+            let d = a.iter().zip(b).map(|(a, b)| a.abs_diff(*b)).max().unwrap();
+            if d > 0 {
+                diff_pixels += 1;
+            }
+            max_diff = max_diff.max(d);
+        }
+        Some((max_diff, diff_pixels))
+    }
+
+    // Writes the image to disk. The format is picked from the file extension, `encoding` is the one the shaders used.
+    // EXR files are written from the HdrImage instead
+    pub fn save(&self, path: &Path, encoding: Encoding) -> std::io::Result<()> {
+        let ext = path.extension().and_then(|e| e.to_str()).map(|e| e.to_ascii_lowercase());
+        match ext.as_deref() {
+            Some("png") => self.save_png(path, encoding),
+            Some("ppm") => self.save_ppm(path),
+            _ => Err(Error::new(ErrorKind::InvalidInput, format!("unsupported image format: {}", path.display()))),
+        }
+    }
+
+    pub fn save_png(&self, path: &Path, encoding: Encoding) -> std::io::Result<()> {
+        let mut encoder = png::Encoder::new(BufWriter::new(File::create(path)?), self.width, self.height);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        // Only tag the data we know is sRGB, viewers would display the other encodings wrong anyway
+        if encoding == Encoding::Srgb {
+            encoder.set_source_srgb(png::SrgbRenderingIntent::Perceptual);
+        }
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&self.data)?;
+        writer.finish().map_err(Error::from)
+    }
+
+    // Reads an 8-bit RGB or RGBA PNG, like the ones save_png() writes, to compare renders with
+    pub fn load_png(path: &Path) -> std::io::Result<Self> {
+        let mut reader = png::Decoder::new(File::open(path)?).read_info()?;
+        let mut buffer = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buffer)?;
+        let pixels = &buffer[..info.buffer_size()];
+        let data = match (info.color_type, info.bit_depth) {
+            (png::ColorType::Rgba, png::BitDepth::Eight) => pixels.to_vec(),
+            (png::ColorType::Rgb, png::BitDepth::Eight) => pixels.chunks_exact(3).flat_map(|rgb| [rgb[0], rgb[1], rgb[2], 255]).collect(),
+            (color, depth) => {
+                return Err(Error::new(ErrorKind::InvalidData, format!("{}: {:?} {:?}-bit PNGs are not supported", path.display(), color, depth as u8)));
+            }
+        };
+        Ok(Self { width: info.width, height: info.height, data })
+    }
+
+    // Binary PPM (P6). It has no alpha channel, so alpha is dropped
+    pub fn save_ppm(&self, path: &Path) -> std::io::Result<()> {
+        let mut file = BufWriter::new(File::create(path)?);
+        write!(file, "P6\n{} {}\n255\n", self.width, self.height)?;
+        for rgba in self.data.chunks_exact(4) {
+            file.write_all(&rgba[..3])?;
+        }
+        file.flush()
+    }
+}
+
+// A linear float image, laid out like the HDR output UAV (DXGI_FORMAT_R32G32B32A32_FLOAT)
+#[derive(Clone, PartialEq)]
+pub struct HdrImage {
+    pub width: u32,
+    pub height: u32,
+    pub data: Vec<Vec4>,
+}
+
+impl HdrImage {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self { width, height, data: vec![Vec4::ZERO; width as usize * height as usize] }
+    }
+
+    pub fn pixel(&self, x: u32, y: u32) -> Vec4 {
+        self.data[y as usize * self.width as usize + x as usize]
+    }
+
+    pub fn set_pixel(&mut self, x: u32, y: u32, rgba: Vec4) {
+        self.data[y as usize * self.width as usize + x as usize] = rgba;
+    }
+
+    // OpenEXR stores linear values, so this is the exact output without the 8-bit encoding
+    pub fn save_exr(&self, path: &Path) -> std::io::Result<()> {
+        exr::prelude::write_rgba_file(path, self.width as usize, self.height as usize, |x, y| {
+            let rgba = self.pixel(x as u32, y as u32);
+            (rgba.x, rgba.y, rgba.z, rgba.w)
+        }).map_err(|e| Error::other(e.to_string()))
+    }
+}
+
+pub fn srgb_to_linear(v: u8) -> f32 {
+    let v = v as f32 / 255.0;
+    if v <= 0.04045 { v / 12.92 } else { ((v + 0.055) / 1.055).powf(2.4) }
+}
+
+// Float to UNORM conversion as done when writing to an R8G8B8A8_UNORM UAV
+pub fn float_to_unorm8(v: f32) -> u8 {
+    if v.is_nan() {
+        return 0;
+    }
+    (v.clamp(0.0, 1.0) * 255.0 + 0.5) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gradient(width: u32, height: u32) -> Image {
+        let mut image = Image::new(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                image.set_pixel(x, y, [(x * 40) as u8, (y * 60) as u8, 7, 255]);
+            }
+        }
+        image
+    }
+
+    #[test]
+    fn diff() {
+        let image = gradient(5, 3);
+        assert_eq!(image.diff(&image), Some((0, 0)));
+        let mut other = image.clone();
+        other.set_pixel(1, 2, [40, 100, 7, 255]);
+        other.set_pixel(4, 0, [160, 0, 10, 250]);
+        assert_eq!(image.diff(&other), Some((20, 2)));
+        assert_eq!(other.diff(&image), Some((20, 2)));
+        assert_eq!(image.diff(&gradient(3, 5)), None);
+    }
+
+    #[test]
+    fn png_round_trip() {
+        let path = std::env::temp_dir().join(format!("framebuffer_test_{}.png", std::process::id()));
+        let image = gradient(5, 3);
+        image.save(&path, Encoding::Srgb).unwrap();
+        let loaded = Image::load_png(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert!(loaded.unwrap() == image);
+        assert!(Image::load_png(Path::new("missing.png")).is_err());
+    }
+
+    #[test]
+    fn ppm_drops_alpha() {
+        let path = std::env::temp_dir().join(format!("framebuffer_test_{}.ppm", std::process::id()));
+        let mut image = Image::new(2, 1);
+        image.set_pixel(0, 0, [1, 2, 3, 4]);
+        image.set_pixel(1, 0, [250, 251, 252, 253]);
+        image.save(&path, Encoding::Srgb).unwrap();
+        let data = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(data, b"P6\n2 1\n255\n\x01\x02\x03\xfa\xfb\xfc");
+        assert!(image.save(Path::new("image.bmp"), Encoding::Srgb).is_err());
+    }
+
+    #[test]
+    fn exr_round_trip() {
+        let path = std::env::temp_dir().join(format!("framebuffer_test_{}.exr", std::process::id()));
+        let mut image = HdrImage::new(3, 2);
+        for (i, pixel) in image.data.iter_mut().enumerate() {
+            *pixel = Vec4::new(i as f32 * 0.25, 100.0 + i as f32, -1.5, 1.0);
+        }
+        image.save_exr(&path).unwrap();
+        let loaded = exr::prelude::read_first_rgba_layer_from_file(
+            &path,
+            |resolution, _| HdrImage::new(resolution.width() as u32, resolution.height() as u32),
+            |image: &mut HdrImage, position, (r, g, b, a): (f32, f32, f32, f32)| image.set_pixel(position.x() as u32, position.y() as u32, Vec4::new(r, g, b, a)),
+        );
+        std::fs::remove_file(&path).unwrap();
+        // Written as 32-bit floats, so nothing is lost
+        assert!(loaded.unwrap().layer_data.channel_data.pixels == image);
+    }
+}
@@ -0,0 +1,246 @@
+use glam::*;
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::pipeline::*;
+use crate::light::Light;
+use crate::material::Material;
+use crate::scene::{Blas, Geometry, Instance, Scene};
+use crate::scene_file::validate_geometry;
+use crate::shader_table::{ShaderRecord, ShaderTable};
+use crate::texture::{ColorSpace, Texture};
+
+// A glTF 2.0 importer. Every mesh becomes a BLAS with one geometry per primitive, with the normals, tangents and the
+// first texture coordinates of the primitive, and every node that references a mesh becomes a TLAS instance with the
+// node's world transform. Materials keep their metallic-roughness factors, emission
+// and their base color, metallic-roughness and normal textures. The textures are sampled with the first texture
+// coordinates and the static sampler, whatever the glTF sampler says. Lights aren't imported, the scene gets the
+// default one.
+
+// glTF is right-handed with the camera looking down -Z, our camera looks down +Z in a left-handed space
+const GLTF_TO_SCENE: Mat4 = Mat4::from_cols(Vec4::X, Vec4::Y, Vec4::NEG_Z, Vec4::W);
+
+pub fn load_gltf(path: &Path) -> Result<(Scene, ShaderTable), String> {
+    let (document, buffers, images) = gltf::import(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    build_scene(&document, &buffers, &images).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+fn build_scene(document: &gltf::Document, buffers: &[gltf::buffer::Data], images: &[gltf::image::Data]) -> Result<(Scene, ShaderTable), String> {
+    let mut scene = Scene { lights: vec![Light::default()], ..Default::default() };
+    let mut textures = Textures { images, loaded: HashMap::new(), textures: Vec::new() };
+    // The material of each primitive, indexed like the BLAS geometries
+    let mut materials: Vec<Vec<Material>> = Vec::new();
+    for mesh in document.meshes() {
+        let mut geometries = Vec::new();
+        let mut mesh_materials = Vec::new();
+        for primitive in mesh.primitives() {
+            if primitive.mode() != gltf::mesh::Mode::Triangles {
+                return Err(format!("mesh {}: primitive mode {:?} is not supported", mesh.index(), primitive.mode()));
+            }
+            let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+            let vertices: Vec<Vec3> = reader.read_positions().ok_or(format!("mesh {}: a primitive has no positions", mesh.index()))?.map(Vec3::from).collect();
+            let mut geometry = match reader.read_indices() {
+                Some(indices) => Geometry::indexed(vertices, indices.into_u32().collect()),
+                None => Geometry::new(&vertices),
+            };
+            geometry.normals = reader.read_normals().map(|n| n.map(Vec3::from).collect());
+            geometry.tangents = reader.read_tangents().map(|t| t.map(Vec4::from).collect());
+            geometry.tex_coords = reader.read_tex_coords(0).map(|t| t.into_f32().map(Vec2::from).collect());
+            validate_geometry(&geometry).map_err(|e| format!("mesh {}: primitive {}: {}", mesh.index(), primitive.index(), e))?;
+            geometries.push(geometry);
+            mesh_materials.push(material(&primitive.material(), &mut textures)?);
+        }
+        scene.blas.push(Blas { geometries });
+        materials.push(mesh_materials);
+    }
+
+    scene.textures = textures.textures;
+
+    let gltf_scene = document.default_scene().or_else(|| document.scenes().next()).ok_or("the file has no scenes")?;
+    let mut shader_table = ShaderTable::with_default_programs();
+    for node in gltf_scene.nodes() {
+        add_node(&node, GLTF_TO_SCENE, &materials, &mut scene, &mut shader_table);
+    }
+    Ok((scene, shader_table))
+}
+
+// Walks the node hierarchy, accumulating the transforms
+fn add_node(node: &gltf::Node, parent: Mat4, materials: &[Vec<Material>], scene: &mut Scene, shader_table: &mut ShaderTable) {
+    let transform = parent * Mat4::from_cols_array_2d(&node.transform().matrix());
+    if let Some(mesh) = node.mesh() {
+        let first_material = scene.materials.len() as u32;
+        let mut records = Vec::new();
+        for (geometry, material) in materials[mesh.index()].iter().enumerate() {
+            scene.materials.push(*material);
+            records.push(ShaderRecord::plane_hit(mesh.index(), geometry));
+            records.push(ShaderRecord::new(SHADOW_HIT_GROUP, vec![]));
+        }
+        shader_table.add_instance(records);
+        scene.instances.push(Instance::new(first_material, mesh.index(), transform));
+    }
+    for child in node.children() {
+        add_node(&child, transform, materials, scene, shader_table);
+    }
+}
+
+fn material(material: &gltf::Material, textures: &mut Textures) -> Result<Material, String> {
+    let pbr = material.pbr_metallic_roughness();
+    Ok(Material {
+        base_color: Vec4::from(pbr.base_color_factor()).truncate(),
+        metallic: pbr.metallic_factor(),
+        roughness: pbr.roughness_factor(),
+        emission: Vec3::from(material.emissive_factor()),
+        base_color_texture: pbr.base_color_texture().map(|info| textures.get(&info.texture(), ColorSpace::Srgb)).transpose()?,
+        metallic_roughness_texture: pbr.metallic_roughness_texture().map(|info| textures.get(&info.texture(), ColorSpace::Linear)).transpose()?,
+        normal_texture: material.normal_texture().map(|info| textures.get(&info.texture(), ColorSpace::Linear)).transpose()?,
+        ..Default::default()
+    })
+}
+
+// The textures of the scene, with every image once per color space it is used with
+struct Textures<'a> {
+    images: &'a [gltf::image::Data],
+    loaded: HashMap<(usize, ColorSpace), u32>,
+    textures: Vec<Texture>,
+}
+
+impl Textures<'_> {
+    fn get(&mut self, texture: &gltf::Texture, color_space: ColorSpace) -> Result<u32, String> {
+        let image = texture.source().index();
+        if let Some(index) = self.loaded.get(&(image, color_space)) {
+            return Ok(*index);
+        }
+        let texture = image_texture(&self.images[image], color_space).map_err(|e| format!("image {}: {}", image, e))?;
+        self.textures.push(texture.with_mips());
+        self.loaded.insert((image, color_space), self.textures.len() as u32 - 1);
+        Ok(self.textures.len() as u32 - 1)
+    }
+}
+
+// The images gltf::import() decoded, widened to RGBA. One and two channels are luminance and alpha, 16-bit channels
+// keep their high byte
+fn image_texture(image: &gltf::image::Data, color_space: ColorSpace) -> Result<Texture, String> {
+    use gltf::image::Format;
+    let (width, height) = (image.width, image.height);
+    let rgba8 = |channels: usize, bytes: usize, expand: fn(&[u8]) -> [u8; 4]| {
+        // The high byte of a native-endian u16
+        let high = if cfg!(target_endian = "little") { bytes - 1 } else { 0 };
+        let data = image
+            .pixels
+            .chunks_exact(channels * bytes)
+            .flat_map(|p| {
+                let mut channels = [0u8; 4];
+                for (c, byte) in channels.iter_mut().zip(p.iter().skip(high).step_by(bytes)) {
+                    *c = *byte;
+                }
+                expand(&channels)
+            })
+            .collect();
+        Texture::from_rgba8(width, height, data, color_space)
+    };
+    let float = |channels: usize| {
+        let texels: Vec<Vec4> = image
+            .pixels
+            .chunks_exact(channels * 4)
+            .map(|p| {
+                let c = |i: usize| if i < channels { f32::from_ne_bytes(p[i * 4..i * 4 + 4].try_into().unwrap()) } else { 1.0 };
+                vec4(c(0), c(1), c(2), c(3))
+            })
+            .collect();
+        Texture::from_rgba32f(width, height, &texels)
+    };
+    match image.format {
+        Format::R8 => rgba8(1, 1, |p| [p[0], p[0], p[0], 255]),
+        Format::R8G8 => rgba8(2, 1, |p| [p[0], p[0], p[0], p[1]]),
+        Format::R8G8B8 => rgba8(3, 1, |p| [p[0], p[1], p[2], 255]),
+        Format::R8G8B8A8 => rgba8(4, 1, |p| [p[0], p[1], p[2], p[3]]),
+        Format::R16 => rgba8(1, 2, |p| [p[0], p[0], p[0], 255]),
+        Format::R16G16 => rgba8(2, 2, |p| [p[0], p[0], p[0], p[1]]),
+        Format::R16G16B16 => rgba8(3, 2, |p| [p[0], p[1], p[2], 255]),
+        Format::R16G16B16A16 => rgba8(4, 2, |p| [p[0], p[1], p[2], p[3]]),
+        Format::R32G32B32FLOAT => float(3),
+        Format::R32G32B32A32FLOAT => float(4),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::texture::TextureFormat;
+
+    // A triangle with texture coordinates, drawn by a child node, and a 1x1 PNG used as every texture
+    const GLTF: &str = r#"{
+        "asset": { "version": "2.0" },
+        "scene": 0,
+        "scenes": [{ "nodes": [0] }],
+        "nodes": [
+            { "translation": [1, 2, 3], "children": [1] },
+            { "mesh": 0, "scale": [2, 2, 2] }
+        ],
+        "meshes": [{ "primitives": [{ "attributes": { "POSITION": 0, "TEXCOORD_0": 1 }, "material": 0, "mode": MODE }] }],
+        "materials": [{
+            "pbrMetallicRoughness": {
+                "baseColorFactor": [0.5, 0.25, 1, 1],
+                "metallicFactor": 0.75,
+                "roughnessFactor": 0.5,
+                "baseColorTexture": { "index": 0 },
+                "metallicRoughnessTexture": { "index": 1 }
+            },
+            "normalTexture": { "index": 0 },
+            "emissiveFactor": [0.25, 0.5, 1]
+        }],
+        "textures": [{ "source": 0 }, { "source": 0 }],
+        "images": [{ "uri": "data:image/png;base64,iVBORw0KGgoAAAANSUhEUgAAAAEAAAABCAIAAACQd1PeAAAADElEQVR4nGP438AAAAQBAYDFKhhdAAAAAElFTkSuQmCC" }],
+        "accessors": [
+            { "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3", "min": [0, 0, 0], "max": [1, 1, 0] },
+            { "bufferView": 1, "componentType": 5126, "count": 3, "type": "VEC2" }
+        ],
+        "bufferViews": [{ "buffer": 0, "byteLength": 36 }, { "buffer": 0, "byteOffset": 36, "byteLength": 24 }],
+        "buffers": [{
+            "byteLength": 60,
+            "uri": "data:application/octet-stream;base64,AAAAAAAAAAAAAAAAAACAPwAAAAAAAAAAAAAAAAAAgD8AAAAAAAAAAAAAAAAAAIA/AAAAAAAAAAAAAIA/"
+        }]
+    }"#;
+
+    // `mode` is the primitive mode, 4 for triangles
+    fn load(mode: u32) -> Result<(Scene, ShaderTable), String> {
+        let gltf = gltf::Gltf::from_slice(GLTF.replace("MODE", &mode.to_string()).as_bytes()).unwrap();
+        let buffers = gltf::import_buffers(&gltf.document, None, gltf.blob).unwrap();
+        // Images with a data URI are only decoded with a base directory, nothing is read from it
+        let images = gltf::import_images(&gltf.document, Some(Path::new(".")), &buffers).unwrap();
+        build_scene(&gltf.document, &buffers, &images)
+    }
+
+    #[test]
+    fn composes_node_transforms() {
+        let (scene, _) = load(4).unwrap();
+        assert_eq!(scene.instances.len(), 1);
+        // Scaled by the child, moved by the parent and then mirrored along Z
+        let transform = scene.instances[0].transform;
+        assert_eq!(transform, Mat4::from_scale(vec3(1.0, 1.0, -1.0)) * Mat4::from_translation(vec3(1.0, 2.0, 3.0)) * Mat4::from_scale(Vec3::splat(2.0)));
+        assert_eq!(transform.transform_point3(Vec3::X), vec3(3.0, 2.0, -3.0));
+        assert_eq!(scene.blas[0].geometries[0].vertices, [Vec3::ZERO, Vec3::X, Vec3::Y]);
+        // glTF puts the origin of the texture at the top left like D3D, so unlike OBJ's the coordinates stay
+        assert_eq!(scene.blas[0].geometries[0].tex_coords.as_deref(), Some(&[Vec2::ZERO, Vec2::X, Vec2::Y][..]));
+    }
+
+    #[test]
+    fn converts_materials() {
+        let (scene, _) = load(4).unwrap();
+        let material = scene.materials[0];
+        assert_eq!(material.base_color, vec3(0.5, 0.25, 1.0));
+        assert_eq!((material.metallic, material.roughness), (0.75, 0.5));
+        assert_eq!(material.emission, vec3(0.25, 0.5, 1.0));
+
+        // The base color is sRGB, the metallic-roughness and the normal map linear and share a texture
+        assert_eq!((material.base_color_texture, material.metallic_roughness_texture, material.normal_texture), (Some(0), Some(1), Some(1)));
+        assert_eq!(scene.textures.iter().map(|t| t.format).collect::<Vec<_>>(), [TextureFormat::Rgba8Srgb, TextureFormat::Rgba8Unorm]);
+        assert_eq!(scene.textures[0].mips[0].data, [255, 128, 0, 255]);
+    }
+
+    #[test]
+    fn rejects_other_primitive_modes() {
+        assert_eq!(load(1).err().as_deref(), Some("mesh 0: primitive mode Lines is not supported"));
+        assert_eq!(load(5).err().as_deref(), Some("mesh 0: primitive mode TriangleStrip is not supported"));
+    }
+}
@@ -0,0 +1,174 @@
+// How rayGen turns a camera ray into a color. The direct integrator lets the closest-hit shaders shade the first hit,
+// with the reflection and refraction rays of pipeline::PipelineDesc::max_depth. The path tracer loops in rayGen
+// instead: the hit programs only report the surface, and every bounce samples the lights with shadow rays
+// (next-event estimation) and the BRDF for the next direction, weighted against each other with multiple importance
+// sampling. Both run in res/shaders.hlsl and in cpu_tracer::CpuTracer
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Integrator {
+    #[default]
+    Direct,
+    Path,
+}
+
+impl Integrator {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "direct" => Some(Integrator::Direct),
+            "path" => Some(Integrator::Path),
+            _ => None,
+        }
+    }
+}
+
+// The values of FrameConstants::integrator, and the defines in res/shaders.hlsl. Nothing compares against the default
+#[allow(dead_code)]
+pub const INTEGRATOR_DIRECT: u32 = Integrator::Direct as u32;
+pub const INTEGRATOR_PATH: u32 = Integrator::Path as u32;
+
+// The bounces a path takes at most, unless configured otherwise
+pub const DEFAULT_MAX_BOUNCES: u32 = 8;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct IntegratorSettings {
+    pub integrator: Integrator,
+    // The surfaces a path of the path tracer reflects off at most. 1 is direct lighting only
+    pub max_bounces: u32,
+}
+
+impl Default for IntegratorSettings {
+    fn default() -> Self {
+        Self { integrator: Integrator::Direct, max_bounces: DEFAULT_MAX_BOUNCES }
+    }
+}
+
+// Paths that survive this many bounces play Russian roulette with their throughput
+pub const RUSSIAN_ROULETTE_BOUNCES: u32 = 3;
+
+// The weight of a sample with the pdf `a` against another strategy with the pdf `b` (Veach's power heuristic)
+pub fn power_heuristic(a: f32, b: f32) -> f32 {
+    let (a2, b2) = (a * a, b * b);
+    if a2 + b2 > 0.0 { a2 / (a2 + b2) } else { 0.0 }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::*;
+    use std::f32::consts::PI;
+
+    use super::*;
+    use crate::backend::cpu::CpuBackend;
+    use crate::camera::Camera;
+    use crate::light::Light;
+    use crate::material::{Material, MaterialData};
+    use crate::pipeline::{DEFAULT_MAX_DEPTH, SHADOW_HIT_GROUP};
+    use crate::sampler::SamplerKind;
+    use crate::scene::{Blas, Geometry, Instance, Scene};
+    use crate::shader_table::{ShaderRecord, ShaderTable};
+    use crate::tutorial::{Sampling, Tutorial};
+
+    // The color rayGen() returns for the camera rays that miss
+    const SKY: Vec3 = vec3(0.4, 0.6, 0.2);
+
+    // The two triangles of the parallelogram spanned by `u` and `v`
+    fn quad(corner: Vec3, u: Vec3, v: Vec3) -> Vec<Vec3> {
+        vec![corner, corner + u, corner + u + v, corner, corner + u + v, corner + v]
+    }
+
+    // The floor of the scenes, around the origin and facing up
+    fn floor() -> Vec<Vec3> {
+        quad(vec3(-2.0, 0.0, -2.0), vec3(0.0, 0.0, 4.0), vec3(4.0, 0.0, 0.0))
+    }
+
+    // The average of a path-traced render of a few pixels around the origin, seen from straight above
+    fn render(objects: Vec<(Vec<Vec3>, Material)>, lights: Vec<Light>, max_bounces: u32) -> Vec3 {
+        let camera = Camera { position: vec3(0.0, 1.0, 0.0), target: Vec3::ZERO, up: Vec3::Z, fov_y: 10.0, ..Default::default() };
+        let mut scene = Scene { camera, lights, ..Default::default() };
+        let mut shader_table = ShaderTable::with_default_programs();
+        for (vertices, material) in objects {
+            shader_table.add_instance(vec![ShaderRecord::plane_hit(scene.blas.len(), 0), ShaderRecord::new(SHADOW_HIT_GROUP, vec![])]);
+            scene.instances.push(Instance::new(scene.materials.len() as u32, scene.blas.len(), Mat4::IDENTITY));
+            scene.materials.push(material);
+            scene.blas.push(Blas { geometries: vec![Geometry::new(&vertices)] });
+        }
+        let mut tutorial = Tutorial::on_load(Box::new(CpuBackend::new(4, 4)), scene, shader_table, DEFAULT_MAX_DEPTH).unwrap();
+        tutorial.set_animation(false);
+        tutorial.set_integrator(IntegratorSettings { integrator: Integrator::Path, max_bounces });
+        tutorial.set_sampling(Sampling { samples_per_frame: 256, max_samples: 4096, sampler: SamplerKind::Sobol });
+        for _ in 0..16 {
+            tutorial.on_frame_render().unwrap();
+        }
+        let image = tutorial.readback_hdr().unwrap();
+        image.data.iter().map(|c| c.truncate()).sum::<Vec3>() / image.data.len() as f32
+    }
+
+    // The fraction of a white environment the material reflects towards `v`, integrated over the hemisphere above
+    // the floor
+    fn albedo(material: &MaterialData, v: Vec3) -> Vec3 {
+        const SIZE: u32 = 512;
+        let mut sum = Vec3::ZERO;
+        for i in 0..SIZE * SIZE {
+            let (cos_theta, phi) = (((i / SIZE) as f32 + 0.5) / SIZE as f32, ((i % SIZE) as f32 + 0.5) / SIZE as f32 * 2.0 * PI);
+            let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+            let l = vec3(sin_theta * phi.cos(), cos_theta, sin_theta * phi.sin());
+            sum += material.eval(material.base_color, Vec3::Y, v, l) * cos_theta / PI;
+        }
+        sum * 2.0 * PI / (SIZE * SIZE) as f32
+    }
+
+    fn assert_near(a: Vec3, b: Vec3, tolerance: f32) {
+        assert!(a.abs_diff_eq(b, tolerance), "{} != {}", a, b);
+    }
+
+    #[test]
+    fn lambertian_plane_under_a_directional_light() {
+        let material = Material::diffuse(vec3(0.8, 0.5, 0.2));
+        for angle in [0f32, 30.0, 60.0] {
+            let l = vec3(angle.to_radians().sin(), angle.to_radians().cos(), 0.0);
+            let color = vec3(1.0, 2.0, 3.0);
+            let rendered = render(vec![(floor(), material)], vec![Light::Directional { direction: l, color }], 1);
+
+            // The light's color times the cosine times the BRDF, which is the base color less the Fresnel
+            // reflection plus the specular lobe. The BRDF samples add the sky
+            let data = material.data();
+            let direct = color * l.y * data.eval(material.base_color, Vec3::Y, Vec3::Y, l);
+            assert_near(direct, material.base_color * color * l.y, 0.05 * color.max_element());
+            assert_near(rendered, direct + SKY * albedo(&data, Vec3::Y), 0.01);
+        }
+    }
+
+    #[test]
+    fn white_furnace() {
+        // A white floor in a closed box that emits white and reflects nothing
+        let emitter = Material { base_color: Vec3::ZERO, metallic: 1.0, emission: Vec3::ONE, ..Default::default() };
+        let box_faces = [
+            quad(vec3(-2.0, 2.0, -2.0), vec3(4.0, 0.0, 0.0), vec3(0.0, 0.0, 4.0)),
+            quad(vec3(-2.0, 0.0, -2.0), vec3(4.0, 0.0, 0.0), vec3(0.0, 2.0, 0.0)),
+            quad(vec3(-2.0, 0.0, 2.0), vec3(4.0, 0.0, 0.0), vec3(0.0, 2.0, 0.0)),
+            quad(vec3(-2.0, 0.0, -2.0), vec3(0.0, 0.0, 4.0), vec3(0.0, 2.0, 0.0)),
+            quad(vec3(2.0, 0.0, -2.0), vec3(0.0, 0.0, 4.0), vec3(0.0, 2.0, 0.0)),
+        ];
+        let white = Material::diffuse(Vec3::ONE);
+        let rendered = render(vec![(floor(), white), (box_faces.concat(), emitter)], Vec::new(), 1);
+
+        // Every path reaches the box after one bounce, so the floor shows what its BRDF reflects: 1, less the
+        // energy the single-scattering GGX lobe loses
+        let expected = albedo(&white.data(), Vec3::Y);
+        assert_near(rendered, expected, 0.01);
+        assert_near(rendered, Vec3::ONE, 0.05);
+    }
+
+    #[test]
+    fn power_heuristic_weights() {
+        for (a, b) in [(1.0, 1.0), (0.5, 2.0), (3.0, 0.25), (1e-3, 1e3), (4.0, 0.0)] {
+            // Swapping the strategies swaps the weights, which only depend on the ratio of the pdfs
+            assert!((power_heuristic(a, b) + power_heuristic(b, a) - 1.0).abs() < 1e-6, "{} {}", a, b);
+            assert!((power_heuristic(a, b) - power_heuristic(8.0 * a, 8.0 * b)).abs() < 1e-6, "{} {}", a, b);
+        }
+        assert_eq!(power_heuristic(2.0, 2.0), 0.5);
+        assert_eq!(power_heuristic(2.0, 0.0), 1.0);
+        assert_eq!(power_heuristic(0.0, 2.0), 0.0);
+        // Neither strategy can produce the sample
+        assert_eq!(power_heuristic(0.0, 0.0), 0.0);
+    }
+}
@@ -0,0 +1,275 @@
+// The scene lights and the structured buffer the hit programs shade with. Both backends evaluate the lights from
+// LightData, with LightData::sample() mirrored by sampleLight() in res/shaders.hlsl
+
+use glam::*;
+use serde::{Deserialize, Serialize};
+
+fn white() -> Vec3 {
+    Vec3::ONE
+}
+
+// Punctual lights (directional, point and spot) are given by the brightness a white surface facing them reflects,
+// area lights by their radiance. The hit programs don't divide by pi, so a white surface under an area light that
+// covers its whole hemisphere reflects the light's color too
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Light {
+    // `direction` points towards the light
+    Directional {
+        direction: Vec3,
+        #[serde(default = "white")]
+        color: Vec3,
+    },
+    // Falls off with the squared distance, `color` is the brightness at distance 1
+    Point {
+        position: Vec3,
+        #[serde(default = "white")]
+        color: Vec3,
+    },
+    // A point light shining along `direction`. It fades out between the inner and the outer angle, which are in
+    // degrees from the axis
+    Spot {
+        position: Vec3,
+        direction: Vec3,
+        inner_angle: f32,
+        outer_angle: f32,
+        #[serde(default = "white")]
+        color: Vec3,
+    },
+    // The parallelogram spanned by `u` and `v` from `corner`. It emits on the side u x v points to, and casts soft
+    // shadows as the frames accumulate
+    Area {
+        corner: Vec3,
+        u: Vec3,
+        v: Vec3,
+        #[serde(default = "white")]
+        color: Vec3,
+    },
+}
+
+impl Default for Light {
+    // The light planeChs() used to hard-code
+    fn default() -> Self {
+        Light::Directional { direction: vec3(0.5, 0.5, -0.5), color: Vec3::ONE }
+    }
+}
+
+impl Light {
+    pub fn validate(&self) -> Result<(), String> {
+        match *self {
+            Light::Directional { direction, .. } if direction == Vec3::ZERO => Err("direction is zero".into()),
+            Light::Spot { direction, .. } if direction == Vec3::ZERO => Err("direction is zero".into()),
+            Light::Spot { inner_angle, outer_angle, .. } if !(0.0 <= inner_angle && inner_angle <= outer_angle && outer_angle < 180.0) => {
+                Err(format!("the angles must satisfy 0 <= inner_angle ({}) <= outer_angle ({}) < 180", inner_angle, outer_angle))
+            }
+            Light::Area { u, v, .. } if u.cross(v) == Vec3::ZERO => Err("u and v don't span an area".into()),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn data(&self) -> LightData {
+        let base = LightData::default();
+        match *self {
+            Light::Directional { direction, color } => LightData { kind: LIGHT_DIRECTIONAL, direction: direction.normalize(), color, ..base },
+            Light::Point { position, color } => LightData { kind: LIGHT_POINT, position, color, ..base },
+            Light::Spot { position, direction, inner_angle, outer_angle, color } => LightData {
+                kind: LIGHT_SPOT,
+                position,
+                direction: direction.normalize(),
+                color,
+                cos_inner: inner_angle.to_radians().cos(),
+                cos_outer: outer_angle.to_radians().cos(),
+                ..base
+            },
+            Light::Area { corner, u, v, color } => LightData { kind: LIGHT_AREA, position: corner, direction: u.cross(v).normalize(), color, u, v, ..base },
+        }
+    }
+}
+
+// The values of LightData::kind, and the defines in res/shaders.hlsl
+pub const LIGHT_DIRECTIONAL: u32 = 0;
+pub const LIGHT_POINT: u32 = 1;
+pub const LIGHT_SPOT: u32 = 2;
+pub const LIGHT_AREA: u32 = 3;
+
+// The struct of the gLights structured buffer. Structured buffers are tightly packed, but every float3 is followed by
+// a scalar anyway so the members line up with the HLSL
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct LightData {
+    // The corner of an area light
+    pub position: Vec3,
+    pub kind: u32,
+    // Towards a directional light, along the axis of a spot light, the normal of an area light
+    pub direction: Vec3,
+    pub cos_inner: f32,
+    pub color: Vec3,
+    pub cos_outer: f32,
+    // The edges of an area light
+    pub u: Vec3,
+    pub _pad0: f32,
+    pub v: Vec3,
+    pub _pad1: f32,
+}
+
+// The distance shadow rays towards a directional light are traced to
+pub const DIRECTIONAL_LIGHT_DISTANCE: f32 = 100000.0;
+
+pub struct LightSample {
+    // Normalized, from the surface towards the light
+    pub direction: Vec3,
+    pub distance: f32,
+    // What reaches the surface, before the cosine at the surface and the shadow ray
+    pub irradiance: Vec3,
+}
+
+impl LightData {
+    // `position` is the shaded point, `u` a uniform sample of the unit square that picks the point on an area light
+    pub fn sample(&self, position: Vec3, u: Vec2) -> LightSample {
+        if self.kind == LIGHT_DIRECTIONAL {
+            return LightSample { direction: self.direction, distance: DIRECTIONAL_LIGHT_DISTANCE, irradiance: self.color };
+        }
+        let target = if self.kind == LIGHT_AREA { self.position + self.u * u.x + self.v * u.y } else { self.position };
+        let offset = target - position;
+        let distance = offset.length();
+        let direction = offset / distance;
+        let irradiance = match self.kind {
+            LIGHT_SPOT => {
+                let cos = -direction.dot(self.direction);
+                self.color * spot_falloff(self.cos_outer, self.cos_inner, cos) / (distance * distance)
+            }
+            LIGHT_AREA => {
+                // The radiance over the solid angle of the sample, the area is the inverse of its pdf
+                let cos = (-direction.dot(self.direction)).max(0.0);
+                self.color * cos * self.u.cross(self.v).length() / (std::f32::consts::PI * distance * distance)
+            }
+            _ => self.color / (distance * distance),
+        };
+        LightSample { direction, distance, irradiance }
+    }
+
+    // The solid-angle pdf of sample() picking the point `distance` along `direction` on an area light. 0 for the
+    // punctual lights, which a ray can't hit
+    pub fn pdf(&self, direction: Vec3, distance: f32) -> f32 {
+        let cos = -direction.dot(self.direction);
+        if self.kind != LIGHT_AREA || cos <= 0.0 {
+            return 0.0;
+        }
+        distance * distance / (cos * self.u.cross(self.v).length())
+    }
+
+    // Where a ray hits the emitting side of an area light, as the distance along the normalized `direction`. The path
+    // tracer adds the radiance of the lights its rays hit, lights don't block rays
+    pub fn intersect(&self, origin: Vec3, direction: Vec3) -> Option<f32> {
+        let cos = direction.dot(self.direction);
+        if self.kind != LIGHT_AREA || cos >= 0.0 {
+            return None;
+        }
+        let t = (self.position - origin).dot(self.direction) / cos;
+        if t <= 0.0 {
+            return None;
+        }
+        // The coordinates of the hit along u and v, by the areas of the parallelograms it spans with v and u
+        let p = origin + direction * t - self.position;
+        let area = self.u.cross(self.v).length();
+        let a = p.cross(self.v).dot(self.direction) / area;
+        let b = self.u.cross(p).dot(self.direction) / area;
+        ((0.0..=1.0).contains(&a) && (0.0..=1.0).contains(&b)).then_some(t)
+    }
+}
+
+// smoothstep(), except that a spot light with equal angles gets a hard edge instead of a division by zero
+fn spot_falloff(edge0: f32, edge1: f32, x: f32) -> f32 {
+    if edge0 == edge1 {
+        return if x >= edge1 { 1.0 } else { 0.0 };
+    }
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_near(a: f32, b: f32) {
+        assert!((a - b).abs() <= 1e-5 * b.abs().max(1.0), "{} != {}", a, b);
+    }
+
+    #[test]
+    fn point_lights_fall_off_with_the_squared_distance() {
+        let light = Light::Point { position: vec3(0.0, 3.0, 0.0), color: Vec3::splat(4.0) }.data();
+        for (distance, irradiance) in [(1.0, 4.0), (2.0, 1.0), (4.0, 0.25)] {
+            let sample = light.sample(vec3(0.0, 3.0 - distance, 0.0), Vec2::ZERO);
+            assert_eq!(sample.direction, Vec3::Y);
+            assert_eq!(sample.distance, distance);
+            assert_eq!(sample.irradiance, Vec3::splat(irradiance));
+        }
+        let sample = Light::default().data().sample(Vec3::ZERO, Vec2::ZERO);
+        assert_eq!((sample.direction, sample.distance), (vec3(0.5, 0.5, -0.5).normalize(), DIRECTIONAL_LIGHT_DISTANCE));
+    }
+
+    #[test]
+    fn spot_lights_fade_between_the_angles() {
+        let light = Light::Spot { position: Vec3::ZERO, direction: -Vec3::Y, inner_angle: 30.0, outer_angle: 60.0, color: Vec3::ONE }.data();
+        // The irradiance at distance 1 and `angle` degrees from the axis
+        let at = |angle: f32| {
+            let (sin, cos) = angle.to_radians().sin_cos();
+            light.sample(vec3(sin, -cos, 0.0), Vec2::ZERO).irradiance.x
+        };
+        assert_near(at(0.0), 1.0);
+        assert_near(at(30.0), 1.0);
+        assert_eq!(at(60.0), 0.0);
+        assert_eq!(at(90.0), 0.0);
+        // smoothstep() of the cosine in between
+        let t = (45f32.to_radians().cos() - 60f32.to_radians().cos()) / (30f32.to_radians().cos() - 60f32.to_radians().cos());
+        assert_near(at(45.0), t * t * (3.0 - 2.0 * t));
+
+        // Equal angles give a hard edge
+        assert_eq!(spot_falloff(0.5, 0.5, 0.5), 1.0);
+        assert_eq!(spot_falloff(0.5, 0.5, 0.49), 0.0);
+    }
+
+    #[test]
+    fn area_light_samples_agree_with_intersect_and_pdf() {
+        // A 2x3 rectangle 2 above the origin, facing down
+        let light = Light::Area { corner: vec3(-1.0, 2.0, -1.0), u: vec3(2.0, 0.0, 0.0), v: vec3(0.0, 0.0, 3.0), color: Vec3::splat(5.0) }.data();
+        assert_eq!(light.direction, -Vec3::Y);
+        let position = vec3(0.5, 0.0, 0.25);
+        for u in [vec2(0.5, 0.5), vec2(0.1, 0.9), vec2(0.99, 0.01), vec2(0.3, 0.0)] {
+            let sample = light.sample(position, u);
+            assert_near(light.intersect(position, sample.direction).unwrap(), sample.distance);
+            // The solid-angle pdf is the area pdf times the squared distance over the cosine at the light
+            let cos = -sample.direction.dot(light.direction);
+            let pdf = light.pdf(sample.direction, sample.distance);
+            assert_near(pdf, sample.distance * sample.distance / (cos * 6.0));
+            // The irradiance is the radiance over pi, weighted by the pdf
+            assert_near(sample.irradiance.x, 5.0 / (std::f32::consts::PI * pdf));
+        }
+
+        // Rays that miss the rectangle or reach it from behind
+        assert_eq!(light.intersect(position, vec3(3.0, 1.0, 0.0).normalize()), None);
+        assert_eq!(light.intersect(position, -Vec3::Y), None);
+        assert_eq!(light.intersect(vec3(0.0, 4.0, 0.0), -Vec3::Y), None);
+        assert_near(light.pdf(Vec3::Y, 2.0), 4.0 / 6.0);
+        assert_eq!(light.pdf(-Vec3::Y, 1.0), 0.0);
+
+        // Punctual lights can't be hit
+        let point = Light::Point { position: vec3(0.0, 2.0, 0.0), color: Vec3::ONE }.data();
+        assert_eq!(point.intersect(Vec3::ZERO, Vec3::Y), None);
+        assert_eq!(point.pdf(Vec3::Y, 2.0), 0.0);
+    }
+
+    #[test]
+    fn validate() {
+        let spot = |direction: Vec3, inner_angle: f32, outer_angle: f32| Light::Spot { position: Vec3::ZERO, direction, inner_angle, outer_angle, color: Vec3::ONE };
+        assert_eq!(Light::default().validate(), Ok(()));
+        assert_eq!(spot(-Vec3::Y, 30.0, 30.0).validate(), Ok(()));
+        assert_eq!(Light::Directional { direction: Vec3::ZERO, color: Vec3::ONE }.validate(), Err("direction is zero".into()));
+        assert_eq!(spot(Vec3::ZERO, 10.0, 20.0).validate(), Err("direction is zero".into()));
+        for (inner, outer) in [(30.0, 20.0), (-1.0, 20.0), (10.0, 180.0), (f32::NAN, 20.0)] {
+            let message = format!("the angles must satisfy 0 <= inner_angle ({}) <= outer_angle ({}) < 180", inner, outer);
+            assert_eq!(spot(-Vec3::Y, inner, outer).validate(), Err(message));
+        }
+        let area = Light::Area { corner: Vec3::ZERO, u: Vec3::X, v: vec3(-2.0, 0.0, 0.0), color: Vec3::ONE };
+        assert_eq!(area.validate(), Err("u and v don't span an area".into()));
+    }
+}
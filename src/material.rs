@@ -0,0 +1,391 @@
+// The surface description the hit programs shade with: a metallic-roughness material with a Lambert diffuse and a
+// GGX specular lobe, which can also spawn reflection and refraction rays. The scene keeps one per instance geometry in
+// scene::Scene::materials, the hit programs find theirs at InstanceID() + GeometryIndex(). MaterialData::eval() is
+// mirrored by evalBrdf() in res/shaders.hlsl
+
+use glam::*;
+use serde::{Deserialize, Serialize};
+
+// Roughness is clamped to this, a perfect mirror would only reflect punctual lights in a single direction
+pub const MIN_ROUGHNESS: f32 = 0.05;
+
+// The reflectance of dielectrics at normal incidence
+const DIELECTRIC_F0: f32 = 0.04;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Material {
+    // The diffuse albedo of dielectrics, the reflectance of metals. Linear
+    pub base_color: Vec3,
+    // 0 for dielectrics, 1 for metals
+    pub metallic: f32,
+    // Perceptual roughness, squared to get the GGX alpha
+    pub roughness: f32,
+    // Radiance added to whatever the surface reflects
+    pub emission: Vec3,
+    pub surface: Surface,
+    // The index of refraction of a dielectric surface, relative to the air around it
+    pub ior: f32,
+    // Indices into scene::Scene::textures, sampled with the texture coordinates of the geometry. The base color
+    // texture multiplies the base color, the metallic-roughness texture the metallic factor with its blue and the
+    // roughness with its green channel, like glTF. The normal map is in tangent space
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub base_color_texture: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metallic_roughness_texture: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub normal_texture: Option<u32>,
+}
+
+// What the hit programs trace from a surface besides the shadow rays. The rays count towards
+// pipeline::PipelineDesc::max_depth, a path that reaches it sees black instead
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Surface {
+    // Only lit by the lights
+    #[default]
+    Opaque,
+    // A perfect reflection tinted by the base color, like chrome. Not lit by the lights
+    Mirror,
+    // Lit like an opaque surface, plus a reflection blurred by the roughness and weighted by the Fresnel term
+    Glossy,
+    // Glass: reflects or refracts with the Fresnel equations for `ior`, the transmission is tinted by the base color
+    Dielectric,
+}
+
+impl Default for Material {
+    fn default() -> Self {
+        Self {
+            base_color: Vec3::splat(0.8),
+            metallic: 0.0,
+            roughness: 0.5,
+            emission: Vec3::ZERO,
+            surface: Surface::Opaque,
+            ior: 1.5,
+            base_color_texture: None,
+            metallic_roughness_texture: None,
+            normal_texture: None,
+        }
+    }
+}
+
+impl Material {
+    // A rough dielectric, what the hit programs shaded everything with before materials
+    pub fn diffuse(base_color: Vec3) -> Self {
+        Self { base_color, roughness: 1.0, ..Default::default() }
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if !(self.base_color.is_finite() && self.emission.is_finite()) {
+            return Err("the colors must be finite".into());
+        }
+        if self.base_color.min_element() < 0.0 || self.emission.min_element() < 0.0 {
+            return Err("the colors can't be negative".into());
+        }
+        if !(0.0..=1.0).contains(&self.metallic) || !(0.0..=1.0).contains(&self.roughness) {
+            return Err(format!("metallic ({}) and roughness ({}) must be between 0 and 1", self.metallic, self.roughness));
+        }
+        if !(self.ior >= 1.0 && self.ior.is_finite()) {
+            return Err(format!("the index of refraction ({}) must be at least 1", self.ior));
+        }
+        Ok(())
+    }
+
+    pub fn textures(&self) -> impl Iterator<Item = u32> {
+        [self.base_color_texture, self.metallic_roughness_texture, self.normal_texture].into_iter().flatten()
+    }
+
+    // Whether the hit programs trace more than shadow rays from the surface
+    pub fn spawns_rays(&self) -> bool {
+        self.surface != Surface::Opaque
+    }
+
+    pub fn data(&self) -> MaterialData {
+        MaterialData {
+            base_color: self.base_color,
+            metallic: self.metallic,
+            emission: self.emission,
+            roughness: self.roughness.max(MIN_ROUGHNESS),
+            normal_texture: self.normal_texture.unwrap_or(NO_TEXTURE),
+            surface: self.surface as u32,
+            ior: self.ior,
+            base_color_texture: self.base_color_texture.unwrap_or(NO_TEXTURE),
+            metallic_roughness_texture: self.metallic_roughness_texture.unwrap_or(NO_TEXTURE),
+            ..Default::default()
+        }
+    }
+}
+
+// The texture indices of MaterialData for materials without the texture
+pub const NO_TEXTURE: u32 = u32::MAX;
+
+// The values of MaterialData::surface, and the defines in res/shaders.hlsl. Nothing compares against the default
+#[allow(dead_code)]
+pub const SURFACE_OPAQUE: u32 = Surface::Opaque as u32;
+pub const SURFACE_MIRROR: u32 = Surface::Mirror as u32;
+pub const SURFACE_GLOSSY: u32 = Surface::Glossy as u32;
+pub const SURFACE_DIELECTRIC: u32 = Surface::Dielectric as u32;
+
+// The struct of the gMaterials structured buffer
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct MaterialData {
+    pub base_color: Vec3,
+    pub metallic: f32,
+    pub emission: Vec3,
+    pub roughness: f32,
+    // Indices into gTextures, or NO_TEXTURE
+    pub normal_texture: u32,
+    pub surface: u32,
+    pub ior: f32,
+    pub base_color_texture: u32,
+    pub metallic_roughness_texture: u32,
+    pub _pad: [u32; 3],
+}
+
+impl MaterialData {
+    // The BRDF times pi for the normal `n`, the direction towards the viewer `v` and towards the light `l`. The lights
+    // are given by what a white Lambertian surface reflects, which is what the factor of pi cancels
+    pub fn eval(&self, base_color: Vec3, n: Vec3, v: Vec3, l: Vec3) -> Vec3 {
+        let n_dot_l = n.dot(l);
+        if n_dot_l <= 0.0 {
+            return Vec3::ZERO;
+        }
+        let n_dot_v = n.dot(v).max(1e-4);
+        let h = (v + l).normalize();
+        let n_dot_h = n.dot(h).max(0.0);
+        let v_dot_h = v.dot(h).max(0.0);
+
+        let fresnel = self.specular_fresnel(base_color, v_dot_h);
+
+        // GGX distribution with the height-correlated Smith visibility term
+        let a2 = (self.roughness * self.roughness).powi(2);
+        let distribution = ggx_distribution(n_dot_h, a2);
+        let visibility = 0.5 / (n_dot_l * (n_dot_v * n_dot_v * (1.0 - a2) + a2).sqrt() + n_dot_v * (n_dot_l * n_dot_l * (1.0 - a2) + a2).sqrt());
+
+        let diffuse = base_color * (1.0 - self.metallic) * (Vec3::ONE - fresnel);
+        diffuse + fresnel * (distribution * visibility * std::f32::consts::PI)
+    }
+
+    // The Schlick Fresnel term of the specular lobe, for the angle between the view and the half vector
+    pub fn specular_fresnel(&self, base_color: Vec3, v_dot_h: f32) -> Vec3 {
+        let f0 = Vec3::splat(DIELECTRIC_F0).lerp(base_color, self.metallic);
+        f0 + (Vec3::ONE - f0) * (1.0 - v_dot_h.max(0.0)).powi(5)
+    }
+
+    // How often the path tracer samples the specular lobe instead of the cosine-weighted hemisphere. Metals have no
+    // diffuse lobe, so they always sample the specular one
+    pub fn specular_probability(&self) -> f32 {
+        0.5 + 0.5 * self.metallic
+    }
+
+    // The direction the path tracer continues in from a surface with the normal `n`, seen from `v`. `lobe` picks the
+    // lobe, `u` the direction in it. The result can point below the surface, which ends the path
+    pub fn sample(&self, n: Vec3, v: Vec3, u: Vec2, lobe: f32) -> Vec3 {
+        if lobe < self.specular_probability() {
+            reflect(-v, sample_ggx(n, self.roughness * self.roughness, u))
+        } else {
+            sample_cosine(n, u)
+        }
+    }
+
+    // The solid-angle pdf of sample() returning `l`, for the multiple importance sampling against the lights
+    pub fn pdf(&self, n: Vec3, v: Vec3, l: Vec3) -> f32 {
+        let n_dot_l = n.dot(l);
+        if n_dot_l <= 0.0 {
+            return 0.0;
+        }
+        // The half vectors are distributed like the GGX normals, reflecting them scales the pdf by 1 / (4 v.h)
+        let h = (v + l).normalize();
+        let a2 = (self.roughness * self.roughness).powi(2);
+        let specular = ggx_distribution(n.dot(h).max(0.0), a2) * n.dot(h).max(0.0) / (4.0 * v.dot(h).max(1e-4));
+        let diffuse = n_dot_l / std::f32::consts::PI;
+        let p = self.specular_probability();
+        p * specular + (1.0 - p) * diffuse
+    }
+}
+
+// The GGX normal distribution for the squared alpha `a2`
+fn ggx_distribution(n_dot_h: f32, a2: f32) -> f32 {
+    let d = n_dot_h * n_dot_h * (a2 - 1.0) + 1.0;
+    a2 / (std::f32::consts::PI * d * d)
+}
+
+// The unpolarized Fresnel reflectance of a dielectric boundary. `cos_i` is the cosine of the incident angle and `eta`
+// the ratio of the indices of refraction, incident over transmitted. 1 on total internal reflection
+pub fn dielectric_fresnel(cos_i: f32, eta: f32) -> f32 {
+    let sin2_t = eta * eta * (1.0 - cos_i * cos_i);
+    if sin2_t >= 1.0 {
+        return 1.0;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    let r_s = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    let r_p = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    0.5 * (r_s * r_s + r_p * r_p)
+}
+
+// Like HLSL's reflect() and refract(). refract() returns zero on total internal reflection
+pub fn reflect(i: Vec3, n: Vec3) -> Vec3 {
+    i - 2.0 * n.dot(i) * n
+}
+
+pub fn refract(i: Vec3, n: Vec3, eta: f32) -> Vec3 {
+    let cos_i = -n.dot(i);
+    let k = 1.0 - eta * eta * (1.0 - cos_i * cos_i);
+    if k < 0.0 { Vec3::ZERO } else { eta * i + (eta * cos_i - k.sqrt()) * n }
+}
+
+// The direction around `n` with the given angles, in an orthonormal basis around n (Duff et al., "Building an
+// Orthonormal Basis, Revisited")
+fn around_normal(n: Vec3, cos_theta: f32, phi: f32) -> Vec3 {
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    let sign = 1.0f32.copysign(n.z);
+    let a = -1.0 / (sign + n.z);
+    let b = n.x * n.y * a;
+    let t = vec3(1.0 + sign * n.x * n.x * a, sign * b, -sign * n.x);
+    let bt = vec3(b, sign + n.y * n.y * a, -n.y);
+    (t * (sin_theta * phi.cos()) + bt * (sin_theta * phi.sin()) + n * cos_theta).normalize()
+}
+
+// A half vector around `n` distributed like the GGX normals of roughness `alpha`, for the glossy reflection
+pub fn sample_ggx(n: Vec3, alpha: f32, u: Vec2) -> Vec3 {
+    let cos_theta = ((1.0 - u.y) / (1.0 + (alpha * alpha - 1.0) * u.y)).sqrt();
+    around_normal(n, cos_theta, 2.0 * std::f32::consts::PI * u.x)
+}
+
+// A direction around `n` with the pdf cos(theta) / pi
+pub fn sample_cosine(n: Vec3, u: Vec2) -> Vec3 {
+    around_normal(n, (1.0 - u.y).sqrt(), 2.0 * std::f32::consts::PI * u.x)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::PI;
+
+    // The midpoints of a size x size grid on the unit square
+    fn grid(size: u32) -> impl Iterator<Item = Vec2> {
+        (0..size * size).map(move |i| (vec2((i % size) as f32, (i / size) as f32) + 0.5) / size as f32)
+    }
+
+    // Integrates `f` over the hemisphere around +Z, uniform in the cosine and the angle around the normal
+    fn hemisphere_integral(f: impl Fn(Vec3) -> f32) -> f32 {
+        const SIZE: u32 = 512;
+        grid(SIZE).map(|u| f(around_normal(Vec3::Z, u.y, 2.0 * PI * u.x))).sum::<f32>() * 2.0 * PI / (SIZE * SIZE) as f32
+    }
+
+    fn material(base_color: Vec3, metallic: f32, roughness: f32) -> MaterialData {
+        Material { base_color, metallic, roughness, ..Default::default() }.data()
+    }
+
+    // The directions towards the viewer the tests look from
+    fn views() -> [Vec3; 3] {
+        [Vec3::Z, vec3(0.5, 0.0, 1.0).normalize(), vec3(-1.0, 2.0, 1.0).normalize()]
+    }
+
+    #[test]
+    fn lambert_eval_is_the_base_color() {
+        // With the factor of pi the diffuse lobe is the albedo, less what the specular lobe reflects
+        let base_color = vec3(0.9, 0.5, 0.1);
+        let m = material(base_color, 0.0, 1.0);
+        for v in views() {
+            for l in [Vec3::Z, vec3(1.0, 1.0, 1.0).normalize(), vec3(0.0, -3.0, 1.0).normalize()] {
+                let fresnel = m.specular_fresnel(base_color, v.dot((v + l).normalize()));
+                let diffuse = m.eval(base_color, Vec3::Z, v, l) - m.eval(Vec3::ZERO, Vec3::Z, v, l);
+                assert!(diffuse.abs_diff_eq(base_color * (Vec3::ONE - fresnel), 1e-5), "{} for v {} and l {}", diffuse, v, l);
+            }
+            assert_eq!(m.eval(base_color, Vec3::Z, v, -Vec3::Z), Vec3::ZERO);
+        }
+    }
+
+    #[test]
+    fn pdf_integrates_to_one() {
+        for (metallic, roughness) in [(0.0, 1.0), (0.0, 0.5), (1.0, 0.6), (1.0, 1.0)] {
+            let m = material(Vec3::ONE, metallic, roughness);
+            for v in views() {
+                // The GGX reflections sample() returns below the surface end the path, so they are the part of the
+                // distribution the pdf doesn't cover
+                let below = grid(256).enumerate().filter(|&(i, u)| m.sample(Vec3::Z, v, u, (i as f32 * 0.618034).fract()).z <= 0.0).count();
+                let integral = hemisphere_integral(|l| m.pdf(Vec3::Z, v, l)) + below as f32 / (256 * 256) as f32;
+                assert!((integral - 1.0).abs() < 0.01, "{} for metallic {}, roughness {} and v {}", integral, metallic, roughness, v);
+            }
+        }
+    }
+
+    // What sample() weighted by eval() and pdf() estimates, against the reflected fraction integrated directly
+    #[test]
+    fn samples_agree_with_pdf_and_eval() {
+        let base_color = vec3(0.95, 0.64, 0.54);
+        let weight = |m: &MaterialData, v: Vec3, l: Vec3| {
+            let pdf = m.pdf(Vec3::Z, v, l);
+            if pdf <= 0.0 { Vec3::ZERO } else { m.eval(base_color, Vec3::Z, v, l) * l.z / (PI * pdf) }
+        };
+
+        // A rough metal and a rough dielectric
+        for m in [material(base_color, 1.0, 0.6), material(base_color, 0.0, 0.7)] {
+            for v in views() {
+                let sampled = grid(256).enumerate().map(|(i, u)| weight(&m, v, m.sample(Vec3::Z, v, u, (i as f32 * 0.618034).fract()))).sum::<Vec3>() / (256 * 256) as f32;
+                let integrated = hemisphere_integral(|l| m.eval(base_color, Vec3::Z, v, l).x * l.z / PI);
+                assert!((sampled.x - integrated).abs() < 0.01, "{} != {} for v {}", sampled.x, integrated, v);
+            }
+        }
+
+        // A polished metal reflects close to the mirror direction, on average weighted by the Fresnel term
+        let m = material(base_color, 1.0, 0.0);
+        for v in views() {
+            let mut sum = Vec3::ZERO;
+            for u in grid(16) {
+                let l = m.sample(Vec3::Z, v, u, 0.0);
+                assert!(l.angle_between(reflect(-v, Vec3::Z)) < 0.05, "{} for v {}", l, v);
+                sum += weight(&m, v, l);
+            }
+            let fresnel = m.specular_fresnel(base_color, v.z);
+            assert!((sum / 256.0).abs_diff_eq(fresnel, 0.01), "{} != {} for v {}", sum / 256.0, fresnel, v);
+        }
+    }
+
+    #[test]
+    fn fresnel_at_normal_incidence() {
+        // ((1 - 1.5) / (1 + 1.5))^2 from either side
+        assert!((dielectric_fresnel(1.0, 1.0 / 1.5) - 0.04).abs() < 1e-6);
+        assert!((dielectric_fresnel(1.0, 1.5) - 0.04).abs() < 1e-6);
+        assert_eq!(dielectric_fresnel(1.0, 1.0), 0.0);
+        // Everything is reflected at grazing angles
+        assert!(dielectric_fresnel(1e-4, 1.0 / 1.5) > 0.99);
+    }
+
+    #[test]
+    fn total_internal_reflection() {
+        // Leaving glass, the critical angle is asin(1 / 1.5)
+        let critical = (1.0f32 / 1.5).asin();
+        for angle in [critical + 1e-3, 60f32.to_radians(), 89f32.to_radians()] {
+            let i = vec3(angle.sin(), 0.0, -angle.cos());
+            assert_eq!(dielectric_fresnel(angle.cos(), 1.5), 1.0);
+            assert_eq!(refract(i, Vec3::Z, 1.5), Vec3::ZERO);
+        }
+        // Just below it, almost everything is reflected already
+        let angle = critical - 1e-3;
+        assert!(dielectric_fresnel(angle.cos(), 1.5) > 0.5);
+        assert_ne!(refract(vec3(angle.sin(), 0.0, -angle.cos()), Vec3::Z, 1.5), Vec3::ZERO);
+    }
+
+    #[test]
+    fn reflect_and_refract_leaving_the_medium() {
+        let eta = 1.5;
+        for angle in [0.0f32, 10.0, 30.0, 40.0] {
+            let (sin_i, cos_i) = angle.to_radians().sin_cos();
+            // Inside the glass towards its surface, with the normal against the ray like the hit programs flip it
+            let i = vec3(sin_i, 0.0, -cos_i);
+            let r = reflect(i, Vec3::Z);
+            assert!(r.abs_diff_eq(vec3(sin_i, 0.0, cos_i), 1e-6), "{}", r);
+
+            // Snell's law, and the way back through the surface leads to where the ray came from
+            let t = refract(i, Vec3::Z, eta);
+            assert!((t.length() - 1.0).abs() < 1e-5 && t.z < 0.0, "{}", t);
+            assert!((t.x - eta * sin_i).abs() < 1e-5, "{} for {} degrees", t, angle);
+            assert!(refract(-t, -Vec3::Z, 1.0 / eta).abs_diff_eq(-i, 1e-5));
+
+            // The reflectance is the same for both directions through the boundary
+            assert!((dielectric_fresnel(cos_i, eta) - dielectric_fresnel(-t.z, 1.0 / eta)).abs() < 1e-5);
+        }
+    }
+}
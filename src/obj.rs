@@ -0,0 +1,429 @@
+use glam::*;
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::pipeline::*;
+use crate::light::Light;
+use crate::material::Material;
+use crate::scene::{Blas, Geometry, Instance, Scene};
+use crate::scene_file::validate_geometry;
+use crate::shader_table::{ShaderRecord, ShaderTable};
+use crate::texture::{ColorSpace, Texture};
+
+// A Wavefront OBJ loader for positions, texture coordinates, normals, faces, objects and material colors. Materials
+// are read from Kd and Ke, the Pm/Pr extension for metallic-roughness, and map_Kd and norm (or map_Bump, which
+// exporters use for normal maps too) for the textures.
+
+// The color of faces without a material, and the MTL default for Kd
+const DEFAULT_DIFFUSE: Vec3 = Vec3::new(0.8, 0.8, 0.8);
+
+#[derive(Clone, Debug)]
+pub struct ObjMaterial {
+    pub name: String,
+    pub material: Material,
+    // The texture files, relative to the OBJ file. ObjFile::load() loads them and sets the texture indices
+    pub base_color_map: Option<String>,
+    pub normal_map: Option<String>,
+}
+
+// The faces of an object that share a material. Each mesh becomes one geometry of the object's BLAS
+pub struct ObjMesh {
+    pub material: Option<usize>,
+    pub geometry: Geometry,
+}
+
+pub struct ObjObject {
+    pub name: String,
+    pub meshes: Vec<ObjMesh>,
+}
+
+pub struct ObjFile {
+    pub objects: Vec<ObjObject>,
+    pub materials: Vec<ObjMaterial>,
+    pub textures: Vec<Texture>,
+}
+
+impl ObjFile {
+    // Loads the OBJ and the MTL files and textures it references, which are looked up next to it
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let source = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let dir = path.parent().unwrap_or(Path::new(""));
+        let mut file = Self::parse(&source, |name| {
+            let mtl_path = dir.join(name);
+            std::fs::read_to_string(&mtl_path).map_err(|e| format!("{}: {}", mtl_path.display(), e))
+        })
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
+        file.load_textures(dir)?;
+        Ok(file)
+    }
+
+    // Loads the maps of the materials, each file once per color space
+    fn load_textures(&mut self, dir: &Path) -> Result<(), String> {
+        let mut loaded: HashMap<(String, ColorSpace), u32> = HashMap::new();
+        let mut load = |name: &Option<String>, color_space: ColorSpace, textures: &mut Vec<Texture>| -> Result<Option<u32>, String> {
+            let Some(name) = name else { return Ok(None) };
+            if let Some(index) = loaded.get(&(name.clone(), color_space)) {
+                return Ok(Some(*index));
+            }
+            textures.push(Texture::load(&dir.join(name), color_space)?);
+            let index = textures.len() as u32 - 1;
+            loaded.insert((name.clone(), color_space), index);
+            Ok(Some(index))
+        };
+        for material in &mut self.materials {
+            material.material.base_color_texture = load(&material.base_color_map, ColorSpace::Srgb, &mut self.textures)?;
+            material.material.normal_texture = load(&material.normal_map, ColorSpace::Linear, &mut self.textures)?;
+        }
+        Ok(())
+    }
+
+    // `read_mtl` returns the contents of a material library referenced with mtllib
+    pub fn parse(source: &str, mut read_mtl: impl FnMut(&str) -> Result<String, String>) -> Result<Self, String> {
+        let mut attributes = ObjAttributes::default();
+        let mut materials: Vec<ObjMaterial> = Vec::new();
+        let mut builder = ObjectBuilder::new("default".into());
+        let mut objects = Vec::new();
+
+        for (line_number, line) in source.lines().enumerate() {
+            let error = |msg: String| format!("line {}: {}", line_number + 1, msg);
+            let line = line.split('#').next().unwrap().trim();
+            let mut tokens = line.split_whitespace();
+            let Some(keyword) = tokens.next() else { continue };
+            match keyword {
+                "v" => attributes.positions.push(parse_vec3(&mut tokens).map_err(error)?),
+                // OBJ puts the origin of the texture at the bottom left, D3D at the top left. A third coordinate is ignored
+                "vt" => {
+                    let u = parse_f32(&mut tokens).map_err(error)?;
+                    let v = tokens.next().map_or(Ok(0.0), |t| t.parse().map_err(|_| format!("invalid number: {}", t))).map_err(error)?;
+                    attributes.tex_coords.push(vec2(u, 1.0 - v));
+                }
+                "vn" => attributes.normals.push(parse_vec3(&mut tokens).map_err(error)?),
+                "f" => {
+                    let corners = tokens.map(|t| parse_corner(t, &attributes)).collect::<Result<Vec<_>, _>>().map_err(error)?;
+                    if corners.len() < 3 {
+                        return Err(error(format!("a face needs at least 3 vertices, got {}", corners.len())));
+                    }
+                    // Polygons are triangulated as a fan around the first vertex
+                    for i in 1..corners.len() - 1 {
+                        builder.add_triangle([corners[0], corners[i], corners[i + 1]], &attributes);
+                    }
+                }
+                "o" | "g" => {
+                    let name = tokens.collect::<Vec<_>>().join(" ");
+                    let next = ObjectBuilder { material: builder.material, ..ObjectBuilder::new(name) };
+                    objects.extend(std::mem::replace(&mut builder, next).finish());
+                }
+                "usemtl" => {
+                    let name = tokens.next().ok_or_else(|| error("usemtl needs a material name".into()))?;
+                    // Unknown materials fall back to the default color, like most viewers do
+                    builder.material = materials.iter().position(|m| m.name == name);
+                }
+                "mtllib" => {
+                    for name in tokens {
+                        let source = read_mtl(name).map_err(error)?;
+                        materials.extend(parse_mtl(&source).map_err(|e| error(format!("{}: {}", name, e)))?);
+                    }
+                }
+                "vp" | "s" | "l" | "p" => {}
+                other => return Err(error(format!("unsupported OBJ statement: {}", other))),
+            }
+        }
+        objects.extend(builder.finish());
+
+        Ok(Self { objects, materials, textures: Vec::new() })
+    }
+
+    pub fn material(&self, mesh: &ObjMesh) -> Material {
+        mesh.material.map_or(Material::diffuse(DEFAULT_DIFFUSE), |m| self.materials[m].material)
+    }
+
+    // One BLAS and one instance per object, one geometry per mesh, shaded by the plane hit group with the mesh's
+    // material. OBJ has no lights, the scene gets the default one
+    // Fails if a mesh has geometry the GPU can't take, e.g. non-finite coordinates, which the parser accepts
+    pub fn scene(&self) -> Result<(Scene, ShaderTable), String> {
+        let mut scene = Scene { lights: vec![Light::default()], textures: self.textures.clone(), ..Default::default() };
+        let mut shader_table = ShaderTable::with_default_programs();
+        for object in &self.objects {
+            let first_material = scene.materials.len() as u32;
+            let mut records = Vec::new();
+            for (geometry, mesh) in object.meshes.iter().enumerate() {
+                validate_geometry(&mesh.geometry).map_err(|e| format!("object \"{}\": mesh {}: {}", object.name, geometry, e))?;
+                scene.materials.push(self.material(mesh));
+                records.push(ShaderRecord::plane_hit(scene.blas.len(), geometry));
+                records.push(ShaderRecord::new(SHADOW_HIT_GROUP, vec![]));
+            }
+            shader_table.add_instance(records);
+            scene.instances.push(Instance::new(first_material, scene.blas.len(), Mat4::IDENTITY));
+            scene.blas.push(Blas { geometries: object.meshes.iter().map(|m| m.geometry.clone()).collect() });
+        }
+        Ok((scene, shader_table))
+    }
+}
+
+// The vertex data of the file so far, which the faces index
+#[derive(Default)]
+struct ObjAttributes {
+    positions: Vec<Vec3>,
+    tex_coords: Vec<Vec2>,
+    normals: Vec<Vec3>,
+}
+
+// A face vertex: the 0-based indices of its position and, if given, its texture coordinates and normal
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+struct Corner {
+    position: usize,
+    tex_coord: Option<usize>,
+    normal: Option<usize>,
+}
+
+// Collects the faces of the current object. OBJ indices are global and separate for every attribute, so every mesh
+// gets a vertex for each combination of them it uses
+struct ObjectBuilder {
+    name: String,
+    material: Option<usize>,
+    meshes: Vec<ObjMesh>,
+    remap: HashMap<Corner, u32>,
+}
+
+impl ObjectBuilder {
+    fn new(name: String) -> Self {
+        Self { name, material: None, meshes: Vec::new(), remap: HashMap::new() }
+    }
+
+    // Meshes only get normals or texture coordinates if one of their faces has them, the vertices without get zeros.
+    // A zero normal makes the hit programs fall back to the face normal
+    fn add_triangle(&mut self, corners: [Corner; 3], attributes: &ObjAttributes) {
+        // Start a new mesh whenever the material changed since the last face
+        if self.meshes.last().is_none_or(|m| m.material != self.material) {
+            self.meshes.push(ObjMesh { material: self.material, geometry: Geometry::indexed(Vec::new(), Vec::new()) });
+            self.remap.clear();
+        }
+        let geometry = &mut self.meshes.last_mut().unwrap().geometry;
+        for corner in corners {
+            let vertex_count = geometry.vertices.len();
+            if corner.tex_coord.is_some() {
+                geometry.tex_coords.get_or_insert_with(|| vec![Vec2::ZERO; vertex_count]);
+            }
+            if corner.normal.is_some() {
+                geometry.normals.get_or_insert_with(|| vec![Vec3::ZERO; vertex_count]);
+            }
+            let index = *self.remap.entry(corner).or_insert_with(|| {
+                geometry.vertices.push(attributes.positions[corner.position]);
+                if let Some(tex_coords) = &mut geometry.tex_coords {
+                    tex_coords.push(corner.tex_coord.map_or(Vec2::ZERO, |t| attributes.tex_coords[t]));
+                }
+                if let Some(normals) = &mut geometry.normals {
+                    normals.push(corner.normal.map_or(Vec3::ZERO, |n| attributes.normals[n]));
+                }
+                vertex_count as u32
+            });
+            geometry.indices.as_mut().unwrap().push(index);
+        }
+    }
+
+    fn finish(self) -> Option<ObjObject> {
+        if self.meshes.is_empty() {
+            return None;
+        }
+        // Faces using the same material in separate runs end up in separate meshes, which is fine for a BLAS
+        Some(ObjObject { name: self.name, meshes: self.meshes })
+    }
+}
+
+pub fn parse_mtl(source: &str) -> Result<Vec<ObjMaterial>, String> {
+    let mut materials: Vec<ObjMaterial> = Vec::new();
+    for (line_number, line) in source.lines().enumerate() {
+        let error = |msg: String| format!("line {}: {}", line_number + 1, msg);
+        let line = line.split('#').next().unwrap().trim();
+        let mut tokens = line.split_whitespace();
+        match tokens.next() {
+            Some("newmtl") => {
+                let name = tokens.next().ok_or_else(|| error("newmtl needs a material name".into()))?;
+                materials.push(ObjMaterial { name: name.into(), material: Material::diffuse(DEFAULT_DIFFUSE), base_color_map: None, normal_map: None });
+            }
+            Some(keyword @ ("Kd" | "Ke" | "Pm" | "Pr")) => {
+                let material = &mut materials.last_mut().ok_or_else(|| error(format!("{} before newmtl", keyword)))?.material;
+                match keyword {
+                    "Kd" => material.base_color = parse_vec3(&mut tokens).map_err(error)?,
+                    "Ke" => material.emission = parse_vec3(&mut tokens).map_err(error)?,
+                    "Pm" => material.metallic = parse_f32(&mut tokens).map_err(error)?.clamp(0.0, 1.0),
+                    _ => material.roughness = parse_f32(&mut tokens).map_err(error)?.clamp(0.0, 1.0),
+                }
+            }
+            // The options of a map statement come before the file name, which we assume has no spaces
+            Some(keyword @ ("map_Kd" | "norm" | "map_Bump" | "bump")) => {
+                let material = materials.last_mut().ok_or_else(|| error(format!("{} before newmtl", keyword)))?;
+                let file = tokens.last().ok_or_else(|| error(format!("{} needs a file name", keyword)))?;
+                match keyword {
+                    "map_Kd" => material.base_color_map = Some(file.into()),
+                    _ => material.normal_map = Some(file.into()),
+                }
+            }
+            // Everything else describes shading we don't do yet
+            _ => {}
+        }
+    }
+    Ok(materials)
+}
+
+fn parse_vec3<'a>(tokens: &mut impl Iterator<Item = &'a str>) -> Result<Vec3, String> {
+    let mut v = [0f32; 3];
+    for c in &mut v {
+        let token = tokens.next().ok_or("expected 3 numbers")?;
+        *c = token.parse().map_err(|_| format!("invalid number: {}", token))?;
+    }
+    Ok(Vec3::from(v))
+}
+
+fn parse_f32<'a>(tokens: &mut impl Iterator<Item = &'a str>) -> Result<f32, String> {
+    let token = tokens.next().ok_or("expected a number")?;
+    token.parse().map_err(|_| format!("invalid number: {}", token))
+}
+
+// Parses a face vertex: v, v/vt, v//vn or v/vt/vn
+fn parse_corner(token: &str, attributes: &ObjAttributes) -> Result<Corner, String> {
+    let mut parts = token.split('/');
+    let position = resolve_index(parts.next().unwrap(), attributes.positions.len(), token)?;
+    let optional = |part: Option<&str>, count: usize| match part {
+        None | Some("") => Ok(None),
+        Some(index) => resolve_index(index, count, token).map(Some),
+    };
+    let tex_coord = optional(parts.next(), attributes.tex_coords.len())?;
+    let normal = optional(parts.next(), attributes.normals.len())?;
+    Ok(Corner { position, tex_coord, normal })
+}
+
+// Returns the 0-based index of one of the indices of a face vertex. Negative indices count back from the last element
+fn resolve_index(index: &str, count: usize, token: &str) -> Result<usize, String> {
+    let index: i64 = index.parse().map_err(|_| format!("invalid face vertex: {}", token))?;
+    let resolved = match index {
+        i if i > 0 => i - 1,
+        i if i < 0 => count as i64 + i,
+        _ => -1,
+    };
+    if resolved < 0 || resolved >= count as i64 {
+        return Err(format!("face vertex {} is out of range, there are {} elements", token, count));
+    }
+    Ok(resolved as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(source: &str) -> Result<ObjFile, String> {
+        ObjFile::parse(source, |name| match name {
+            "materials.mtl" => Ok("newmtl red\nKd 1 0 0\nnewmtl blue\nKd 0 0 1\n".into()),
+            _ => Err(format!("{}: no such file", name)),
+        })
+    }
+
+    fn load(source: &str) -> Result<(Scene, ShaderTable), String> {
+        parse(source)?.scene()
+    }
+
+    fn geometry(file: &ObjFile, mesh: usize) -> &Geometry {
+        &file.objects[0].meshes[mesh].geometry
+    }
+
+    const TRIANGLE: &str = "v 0 0 0\nv 1 0 0\nv 0 1 0\n";
+
+    #[test]
+    fn negative_indices() {
+        // Relative to the vertices read so far, so the same index means another vertex after more are added
+        let file = parse(&format!("{}f -3 -2 -1\nv 1 1 0\nf -4 -1 -3\n", TRIANGLE)).unwrap();
+        let geometry = geometry(&file, 0);
+        assert_eq!(geometry.vertices, [vec3(0.0, 0.0, 0.0), vec3(1.0, 0.0, 0.0), vec3(0.0, 1.0, 0.0), vec3(1.0, 1.0, 0.0)]);
+        assert_eq!(geometry.indices.as_deref(), Some(&[0, 1, 2, 0, 3, 1][..]));
+    }
+
+    #[test]
+    fn triangulates_polygons_as_fans() {
+        let file = parse("v 0 0 0\nv 1 0 0\nv 2 1 0\nv 1 2 0\nv 0 1 0\nf 1 2 3 4\nf 1 2 3 4 5\n").unwrap();
+        let geometry = geometry(&file, 0);
+        assert_eq!(geometry.vertices.len(), 5);
+        assert_eq!(geometry.indices.as_deref(), Some(&[0, 1, 2, 0, 2, 3, 0, 1, 2, 0, 2, 3, 0, 3, 4][..]));
+        assert_eq!(parse("v 0 0 0\nv 1 0 0\nf 1 2\n").err().as_deref(), Some("line 3: a face needs at least 3 vertices, got 2"));
+    }
+
+    #[test]
+    fn shares_vertices_with_the_same_indices() {
+        // Corners only share a vertex if the position, texture coordinate and normal indices all match
+        let source = format!("{}vt 0 0\nvt 1 0\nvn 0 0 1\nvn 0 0 -1\nf 1/1/1 2/2/1 3/1/1\nf 1/1/1 3/1/1 1/2/1\nf 1/1/2 2/2/1 3/1/1\n", TRIANGLE);
+        let file = parse(&source).unwrap();
+        let geometry = geometry(&file, 0);
+        assert_eq!(geometry.vertices, [vec3(0.0, 0.0, 0.0), vec3(1.0, 0.0, 0.0), vec3(0.0, 1.0, 0.0), vec3(0.0, 0.0, 0.0), vec3(0.0, 0.0, 0.0)]);
+        assert_eq!(geometry.indices.as_deref(), Some(&[0, 1, 2, 0, 2, 3, 4, 1, 2][..]));
+        assert_eq!(geometry.tex_coords.as_deref(), Some(&[vec2(0.0, 1.0), vec2(1.0, 1.0), vec2(0.0, 1.0), vec2(1.0, 1.0), vec2(0.0, 1.0)][..]));
+        assert_eq!(geometry.normals.as_deref(), Some(&[Vec3::Z, Vec3::Z, Vec3::Z, Vec3::Z, -Vec3::Z][..]));
+
+        // A mesh without any texture coordinates or normals doesn't get them
+        let plain = parse(&format!("{}f 1 2 3\n", TRIANGLE)).unwrap();
+        let plain = &plain.objects[0].meshes[0].geometry;
+        assert!(plain.tex_coords.is_none() && plain.normals.is_none());
+    }
+
+    #[test]
+    fn flips_texture_coordinates() {
+        // OBJ's v goes up from the bottom, ours down from the top. A missing v is 0, a third coordinate is ignored
+        let file = parse(&format!("{}vt 0.25 0.75\nvt 0.5\nvt 0.5 0.25 1\nf 1/1 2/2 3/3\n", TRIANGLE)).unwrap();
+        assert_eq!(geometry(&file, 0).tex_coords.as_deref(), Some(&[vec2(0.25, 0.25), vec2(0.5, 1.0), vec2(0.5, 0.75)][..]));
+    }
+
+    #[test]
+    fn splits_meshes_at_material_changes() {
+        let source = format!("mtllib materials.mtl\n{}usemtl red\nf 1 2 3\nf 1 2 3\nusemtl blue\nf 1 2 3\nusemtl red\nf 1 2 3\nusemtl green\nf 1 2 3\n", TRIANGLE);
+        let file = parse(&source).unwrap();
+        let meshes = &file.objects[0].meshes;
+        assert_eq!(meshes.iter().map(|m| m.material).collect::<Vec<_>>(), [Some(0), Some(1), Some(0), None]);
+        assert_eq!(meshes.iter().map(|m| m.geometry.triangle_count()).collect::<Vec<_>>(), [2, 1, 1, 1]);
+        // Unknown materials get the default color
+        assert_eq!(meshes.iter().map(|m| file.material(m).base_color).collect::<Vec<_>>(), [Vec3::X, Vec3::Z, Vec3::X, DEFAULT_DIFFUSE]);
+
+        // The material carries over to the next object
+        let file = parse(&format!("mtllib materials.mtl\n{}usemtl blue\no a\nf 1 2 3\no b\nf 1 2 3\n", TRIANGLE)).unwrap();
+        assert_eq!(file.objects.iter().map(|o| (o.name.as_str(), o.meshes[0].material)).collect::<Vec<_>>(), [("a", Some(1)), ("b", Some(1))]);
+    }
+
+    #[test]
+    fn parses_materials() {
+        let materials = parse_mtl(
+            "# exported\nnewmtl metal\nKd 0.5 0.25 1\nKe 0 2 0\nPm 1.5\nPr 0.25\nmap_Kd -s 2 2 1 albedo.png\nnorm normal.png\nNs 250\nnewmtl plain\n",
+        )
+        .unwrap();
+        assert_eq!(materials.len(), 2);
+        let metal = &materials[0];
+        assert_eq!(metal.name, "metal");
+        assert_eq!(metal.material.base_color, vec3(0.5, 0.25, 1.0));
+        assert_eq!(metal.material.emission, vec3(0.0, 2.0, 0.0));
+        assert_eq!((metal.material.metallic, metal.material.roughness), (1.0, 0.25));
+        assert_eq!((metal.base_color_map.as_deref(), metal.normal_map.as_deref()), (Some("albedo.png"), Some("normal.png")));
+        assert_eq!(materials[1].material, Material::diffuse(DEFAULT_DIFFUSE));
+        assert_eq!(materials[1].base_color_map, None);
+
+        assert_eq!(parse_mtl("Kd 1 1 1\n").err().as_deref(), Some("line 1: Kd before newmtl"));
+        assert_eq!(parse_mtl("newmtl a\nKd 1 x 1\n").err().as_deref(), Some("line 2: invalid number: x"));
+        assert_eq!(parse_mtl("newmtl a\nmap_Kd\n").err().as_deref(), Some("line 2: map_Kd needs a file name"));
+        // The errors of a material library name the line of the mtllib statement and the file
+        assert_eq!(parse("\nmtllib other.mtl\n").err().as_deref(), Some("line 2: other.mtl: no such file"));
+    }
+
+    #[test]
+    fn reports_out_of_range_indices() {
+        let error = |source: &str| parse(&format!("{}{}", TRIANGLE, source)).err();
+        assert_eq!(error("\nf 1 2 4\n").as_deref(), Some("line 5: face vertex 4 is out of range, there are 3 elements"));
+        assert_eq!(error("f 1 2 0\n").as_deref(), Some("line 4: face vertex 0 is out of range, there are 3 elements"));
+        assert_eq!(error("f 1 2 -4\n").as_deref(), Some("line 4: face vertex -4 is out of range, there are 3 elements"));
+        assert_eq!(error("vt 0 0\nf 1/1 2/2 3/1\n").as_deref(), Some("line 5: face vertex 2/2 is out of range, there are 1 elements"));
+        assert_eq!(error("f 1//1 2//1 3//1\n").as_deref(), Some("line 4: face vertex 1//1 is out of range, there are 0 elements"));
+        assert_eq!(error("f 1 2 x\n").as_deref(), Some("line 4: invalid face vertex: x"));
+    }
+
+    #[test]
+    fn validates_geometry() {
+        let (scene, _) = load("o quad\nv 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nf 1 2 3 4\n").unwrap();
+        assert_eq!(scene.blas[0].geometries[0].triangle_count(), 2);
+        assert_eq!(load("o broken\nv 0 0 0\nv nan 0 0\nv 0 1 0\nf 1 2 3\n").err().as_deref(), Some("object \"broken\": mesh 0: a vertex is not finite"));
+        assert_eq!(load("o broken\nv 0 0 0\nv 1 0 0\nv 0 1 0\nvn 0 0 inf\nf 1//1 2//1 3//1\n").err().as_deref(), Some("object \"broken\": mesh 0: a vertex attribute is not finite"));
+    }
+}
@@ -0,0 +1,233 @@
+use std::path::PathBuf;
+
+use crate::backend::MAX_FRAMES_IN_FLIGHT;
+use crate::color::{ColorSettings, Encoding, ToneMapper};
+use crate::integrator::{Integrator, IntegratorSettings};
+use crate::pipeline::{DEFAULT_MAX_DEPTH, MAX_RECURSION_DEPTH};
+use crate::sampler::SamplerKind;
+use crate::texture::MAX_TEXTURE_SIZE;
+
+const USAGE: &str = "usage: dxr_tutorials_rs [--headless] [--backend d3d12|cpu] [--width N] [--height N] [--frames N] [--output FILE.png|ppm|exr] [--scene FILE.ron|obj|gltf|glb] [--stats] [--spp N] [--max-samples N] [--sampler pcg|sobol|blue-noise] [--no-animation] [--max-depth N] [--integrator direct|path] [--max-bounces N] [--exposure EV] [--tone-map none|reinhard|aces|agx] [--encoding srgb|rec709|pq] [--profile FILE.csv|json] [--frames-in-flight N] [--compile-shaders] [--compare GOLDEN.png]";
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum BackendKind {
+    D3D12,
+    Cpu,
+}
+
+#[derive(Debug)]
+pub struct Options {
+    // Render without a window and write the last frame to `output`
+    pub headless: bool,
+    // None means D3D12 with a fallback to the CPU tracer if no DXR device is available
+    pub backend: Option<BackendKind>,
+    pub width: u32,
+    pub height: u32,
+    pub frames: u32,
+    pub output: PathBuf,
+    // The scene to trace, a scene description or a mesh file to import
+    pub scene: PathBuf,
+    // Print acceleration-structure traversal statistics after a headless render
+    pub stats: bool,
+    // Subsamples per pixel and frame, and the most samples the frames accumulate
+    pub samples_per_frame: u32,
+    pub max_samples: u32,
+    // Draws the random numbers of the jitter, the lens, the lights and the bounces
+    pub sampler: SamplerKind,
+    // Keep the instances still, so the frames of a headless render converge
+    pub no_animation: bool,
+    // The reflection and refraction rays a path traces at most, 0 turns them off
+    pub max_depth: u32,
+    // The direct integrator or the path tracer, and the bounces of its paths
+    pub integrator: IntegratorSettings,
+    // Exposure, tone mapping and the encoding of the 8-bit output. An .exr output gets the linear color
+    pub color: ColorSettings,
+    // Write the pass timings of the last frames to this file, as CSV or as a Chrome trace
+    pub profile: Option<PathBuf>,
+    // How many frames the D3D12 backend records ahead of the GPU, 1 to MAX_FRAMES_IN_FLIGHT
+    pub frames_in_flight: usize,
+    // Compile the shader library into its DXIL cache and exit, for builds that ship without dxcompiler.dll
+    pub compile_shaders: bool,
+    // Compare the headless render with this image and fail if they differ, for regression tests. res/golden/tutorial.png
+    // is the CPU tracer's render of the tutorial scene with the default size and --no-animation
+    pub compare: Option<PathBuf>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            headless: false,
+            backend: None,
+            width: 640,
+            height: 360,
+            frames: 1,
+            output: PathBuf::from("screenshot.png"),
+            scene: PathBuf::from("res/tutorial.ron"),
+            stats: false,
+            samples_per_frame: 1,
+            max_samples: 1024,
+            sampler: SamplerKind::Pcg,
+            no_animation: false,
+            max_depth: DEFAULT_MAX_DEPTH,
+            integrator: IntegratorSettings::default(),
+            color: ColorSettings::default(),
+            profile: None,
+            frames_in_flight: 2,
+            compile_shaders: false,
+            compare: None,
+        }
+    }
+}
+
+impl Options {
+    pub fn from_args(args: impl IntoIterator<Item = String>) -> Result<Self, String> {
+        let mut options = Options::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let mut value = |name: &str| args.next().ok_or(format!("missing value for {}\n{}", name, USAGE));
+            match arg.as_str() {
+                "--headless" => options.headless = true,
+                "--backend" => {
+                    options.backend = Some(match value("--backend")?.as_str() {
+                        "d3d12" => BackendKind::D3D12,
+                        "cpu" => BackendKind::Cpu,
+                        other => return Err(format!("unknown backend: {}\n{}", other, USAGE)),
+                    })
+                }
+                "--width" => options.width = parse_size("--width", &value("--width")?)?,
+                "--height" => options.height = parse_size("--height", &value("--height")?)?,
+                "--frames" => options.frames = parse_count("--frames", &value("--frames")?)?,
+                "--output" => options.output = PathBuf::from(value("--output")?),
+                "--scene" => options.scene = PathBuf::from(value("--scene")?),
+                "--stats" => options.stats = true,
+                "--spp" => options.samples_per_frame = parse_count("--spp", &value("--spp")?)?,
+                "--max-samples" => options.max_samples = parse_count("--max-samples", &value("--max-samples")?)?,
+                "--sampler" => {
+                    let name = value("--sampler")?;
+                    options.sampler = SamplerKind::from_name(&name).ok_or(format!("unknown sampler: {}\n{}", name, USAGE))?;
+                }
+                "--no-animation" => options.no_animation = true,
+                "--max-depth" => {
+                    let depth = value("--max-depth")?;
+                    options.max_depth = match depth.parse::<u32>() {
+                        // The primary ray and the shadow rays of the last hit take 2 levels of the recursion
+                        Ok(v) if v <= MAX_RECURSION_DEPTH - 2 => v,
+                        _ => return Err(format!("--max-depth expects an integer from 0 to {}, got {}", MAX_RECURSION_DEPTH - 2, depth)),
+                    }
+                }
+                "--integrator" => {
+                    let name = value("--integrator")?;
+                    options.integrator.integrator = Integrator::from_name(&name).ok_or(format!("unknown integrator: {}\n{}", name, USAGE))?;
+                }
+                "--max-bounces" => options.integrator.max_bounces = parse_count("--max-bounces", &value("--max-bounces")?)?,
+                "--exposure" => {
+                    let exposure = value("--exposure")?;
+                    options.color.exposure = match exposure.parse::<f32>() {
+                        Ok(v) if v.is_finite() => v,
+                        _ => return Err(format!("--exposure expects a number of stops, got {}", exposure)),
+                    }
+                }
+                "--tone-map" => {
+                    let name = value("--tone-map")?;
+                    options.color.tone_mapper = ToneMapper::from_name(&name).ok_or(format!("unknown tone mapper: {}\n{}", name, USAGE))?;
+                }
+                "--encoding" => {
+                    let name = value("--encoding")?;
+                    options.color.encoding = Encoding::from_name(&name).ok_or(format!("unknown encoding: {}\n{}", name, USAGE))?;
+                }
+                "--profile" => options.profile = Some(PathBuf::from(value("--profile")?)),
+                "--frames-in-flight" => {
+                    let count = parse_count("--frames-in-flight", &value("--frames-in-flight")?)? as usize;
+                    if count > MAX_FRAMES_IN_FLIGHT {
+                        return Err(format!("--frames-in-flight can be at most {}", MAX_FRAMES_IN_FLIGHT));
+                    }
+                    options.frames_in_flight = count;
+                }
+                "--compile-shaders" => options.compile_shaders = true,
+                "--compare" => options.compare = Some(PathBuf::from(value("--compare")?)),
+                "--help" | "-h" => return Err(USAGE.into()),
+                other => return Err(format!("unknown argument: {}\n{}", other, USAGE)),
+            }
+        }
+        // The comparison is made with the 8-bit render
+        if options.compare.is_some() && (!options.headless || options.output.extension().is_some_and(|e| e.eq_ignore_ascii_case("exr"))) {
+            return Err(format!("--compare needs --headless and a .png or .ppm output\n{}", USAGE));
+        }
+        Ok(options)
+    }
+}
+
+fn parse_count(name: &str, value: &str) -> Result<u32, String> {
+    match value.parse() {
+        Ok(v) if v > 0 => Ok(v),
+        _ => Err(format!("{} expects a positive integer, got {}", name, value)),
+    }
+}
+
+// The output is a texture, so it is limited to the largest one D3D12 can create
+fn parse_size(name: &str, value: &str) -> Result<u32, String> {
+    match parse_count(name, value)? {
+        size if size > MAX_TEXTURE_SIZE => Err(format!("{} can be at most {}, got {}", name, MAX_TEXTURE_SIZE, size)),
+        size => Ok(size),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &str) -> Result<Options, String> {
+        Options::from_args(args.split_whitespace().map(String::from))
+    }
+
+    // The first line of the error, without the usage
+    fn error(args: &str) -> String {
+        parse(args).unwrap_err().lines().next().unwrap().to_string()
+    }
+
+    #[test]
+    fn defaults_and_values() {
+        let options = parse("").unwrap();
+        assert_eq!((options.width, options.height, options.frames_in_flight), (640, 360, 2));
+        let options = parse("--headless --backend cpu --width 16384 --height 9 --max-depth 29 --frames-in-flight 3 --compare golden.png").unwrap();
+        assert!(options.headless);
+        assert_eq!(options.backend, Some(BackendKind::Cpu));
+        assert_eq!((options.width, options.height, options.max_depth, options.frames_in_flight), (16384, 9, 29, 3));
+        assert_eq!(options.compare, Some(PathBuf::from("golden.png")));
+    }
+
+    #[test]
+    fn sizes_are_bounded() {
+        assert_eq!(error("--width 65536 --height 16384"), "--width can be at most 16384, got 65536");
+        assert_eq!(error("--height 16385"), "--height can be at most 16384, got 16385");
+        assert_eq!(error("--width 0"), "--width expects a positive integer, got 0");
+        assert_eq!(error("--height -1"), "--height expects a positive integer, got -1");
+        assert_eq!(error("--width"), "missing value for --width");
+    }
+
+    #[test]
+    fn depth_and_frames_in_flight_are_bounded() {
+        assert_eq!(parse("--max-depth 0").unwrap().max_depth, 0);
+        assert_eq!(error("--max-depth 30"), "--max-depth expects an integer from 0 to 29, got 30");
+        assert_eq!(error("--max-depth -1"), "--max-depth expects an integer from 0 to 29, got -1");
+        assert_eq!(parse("--frames-in-flight 1").unwrap().frames_in_flight, 1);
+        assert_eq!(error("--frames-in-flight 4"), "--frames-in-flight can be at most 3");
+        assert_eq!(error("--frames-in-flight 0"), "--frames-in-flight expects a positive integer, got 0");
+    }
+
+    #[test]
+    fn compare_needs_a_headless_8_bit_output() {
+        let message = "--compare needs --headless and a .png or .ppm output";
+        assert_eq!(error("--compare golden.png"), message);
+        assert_eq!(error("--headless --output out.EXR --compare golden.png"), message);
+        assert!(parse("--headless --output out.ppm --compare golden.png").is_ok());
+    }
+
+    #[test]
+    fn unknown_arguments() {
+        assert_eq!(error("--headless --fullscreen"), "unknown argument: --fullscreen");
+        assert_eq!(error("--backend vulkan"), "unknown backend: vulkan");
+        assert_eq!(error("--sampler halton"), "unknown sampler: halton");
+        assert!(parse("--help").unwrap_err().starts_with("usage: "));
+    }
+}
@@ -0,0 +1,127 @@
+// A platform-independent description of the raytracing pipeline. The D3D12 backend turns it into a state object,
+// the CPU backend uses it to find the Rust implementation of each program.
+
+use std::mem::size_of;
+use std::path::PathBuf;
+
+use crate::material::Material;
+use crate::shader_compiler::CompileOptions;
+
+pub const RAY_GEN_SHADER: &str = "rayGen";
+pub const MISS_SHADER: &str = "miss";
+pub const TRIANGLE_CHS: &str = "triangleChs";
+pub const PLANE_CHS: &str = "planeChs";
+pub const TRI_HIT_GROUP: &str = "TriHitGroup";
+pub const PLANE_HIT_GROUP: &str = "PlaneHitGroup";
+pub const SHADOW_CHS: &str = "shadowChs";
+pub const SHADOW_MISS: &str = "shadowMiss";
+pub const SHADOW_HIT_GROUP: &str = "ShadowHitGroup";
+
+// The local root signatures used by the programs. These match the register declarations in res/shaders.hlsl
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum LocalRootSignature {
+    // A descriptor table with the output UAVs (u0-u2) and the TLAS SRV (t0), followed by a root CBV with the camera (b1)
+    RayGen,
+    // A root CBV (b0), a descriptor table with the TLAS SRV (t0), root constants with the index size and attribute flags
+    // (b2) and root SRVs with the vertices, indices, normals, tangents and texture coordinates (t1-t5)
+    TriangleHit,
+    // A descriptor table with the TLAS SRV (t0), then the same root constants (b2) and root SRVs (t1-t5) as TriangleHit
+    PlaneHit,
+    Empty,
+}
+
+// The payloads of res/shaders.hlsl, so the shader config is sized from their layout
+#[repr(C)]
+struct RayPayload {
+    color: [f32; 3],
+    sample_index: u32,
+    throughput: [f32; 3],
+    depth: u32,
+    mode: u32,
+    position: [f32; 3],
+    material: u32,
+    normal: [f32; 3],
+    base_color: [f32; 3],
+    metallic: f32,
+    roughness: f32,
+    cone_width: f32,
+    cone_spread: f32,
+}
+
+#[repr(C)]
+struct ShadowPayload {
+    // An HLSL bool is 4 bytes
+    hit: u32,
+}
+
+// The reflection and refraction rays a path traces at most, unless configured otherwise
+pub const DEFAULT_MAX_DEPTH: u32 = 4;
+
+// The TraceRay() recursion D3D12 allows, D3D12_RAYTRACING_MAX_DECLARABLE_TRACE_RECURSION_DEPTH
+pub const MAX_RECURSION_DEPTH: u32 = 31;
+
+#[derive(Clone)]
+pub struct HitGroupDesc {
+    pub name: String,
+    pub closest_hit: String,
+}
+
+#[cfg_attr(not(windows), allow(dead_code))]
+#[derive(Clone)]
+pub struct PipelineDesc {
+    // The HLSL library and the entry points we export from it
+    pub library: String,
+    pub compile_options: CompileOptions,
+    pub exports: Vec<String>,
+    pub hit_groups: Vec<HitGroupDesc>,
+    pub local_root_signatures: Vec<(LocalRootSignature, Vec<String>)>,
+    pub max_payload_size: u32,
+    pub max_attribute_size: u32,
+    pub max_recursion_depth: u32,
+    // The reflection and refraction rays a path may trace, the shaders get it as gMaxDepth
+    pub max_depth: u32,
+}
+
+fn names(names: &[&str]) -> Vec<String> {
+    names.iter().map(|s| s.to_string()).collect()
+}
+
+impl PipelineDesc {
+    pub fn tutorial() -> Self {
+        Self {
+            library: "res/shaders.hlsl".into(),
+            compile_options: CompileOptions { include_dirs: vec![PathBuf::from("res")], ..Default::default() },
+            exports: names(&[RAY_GEN_SHADER, MISS_SHADER, PLANE_CHS, TRIANGLE_CHS, SHADOW_CHS, SHADOW_MISS]),
+            hit_groups: vec![
+                HitGroupDesc { name: TRI_HIT_GROUP.into(), closest_hit: TRIANGLE_CHS.into() },
+                HitGroupDesc { name: PLANE_HIT_GROUP.into(), closest_hit: PLANE_CHS.into() },
+                HitGroupDesc { name: SHADOW_HIT_GROUP.into(), closest_hit: SHADOW_CHS.into() },
+            ],
+            local_root_signatures: vec![
+                (LocalRootSignature::RayGen, names(&[RAY_GEN_SHADER])),
+                (LocalRootSignature::TriangleHit, names(&[TRIANGLE_CHS])),
+                (LocalRootSignature::PlaneHit, names(&[PLANE_CHS])),
+                (LocalRootSignature::Empty, names(&[SHADOW_CHS, SHADOW_MISS, MISS_SHADER])),
+            ],
+            // The attributes are the barycentrics
+            max_payload_size: size_of::<RayPayload>().max(size_of::<ShadowPayload>()) as _,
+            max_attribute_size: (size_of::<f32>() * 2) as _,
+            // The closest-hit shaders fire the shadow rays
+            max_recursion_depth: 2,
+            max_depth: 0,
+        }
+    }
+
+    // Sizes the recursion for the materials of the scene. Every reflection or refraction ray is traced from a
+    // closest-hit shader and can fire shadow rays itself, so it adds a level. Without such materials the shaders never
+    // trace them, and the pipeline doesn't reserve the stack
+    pub fn with_materials(mut self, materials: &[Material], max_depth: u32) -> Self {
+        self.max_depth = if materials.iter().any(|m| m.spawns_rays()) { max_depth.min(MAX_RECURSION_DEPTH - 2) } else { 0 };
+        self.max_recursion_depth = 2 + self.max_depth;
+        self
+    }
+
+    pub fn closest_hit(&self, hit_group: &str) -> Option<&str> {
+        self.hit_groups.iter().find(|h| h.name == hit_group).map(|h| h.closest_hit.as_str())
+    }
+}
@@ -0,0 +1,231 @@
+// Per-pass frame timings. The backends measure the passes of a frame (D3D12 with timestamp queries, the CPU tracer
+// with a clock) and hand them over once the frame is done; the Profiler keeps a rolling window of frames for the
+// statistics and the CSV/Chrome trace exports. Nothing here talks to an API, so it can be fed with any timings
+
+use std::collections::VecDeque;
+use std::fmt::Write;
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Pass {
+    TlasRefit,
+    DispatchRays,
+    // Copying the output UAV to the back buffer, only when there is a swap chain
+    BackBufferCopy,
+}
+
+impl Pass {
+    pub const ALL: [Pass; 3] = [Pass::TlasRefit, Pass::DispatchRays, Pass::BackBufferCopy];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Pass::TlasRefit => "TLAS refit",
+            Pass::DispatchRays => "DispatchRays",
+            Pass::BackBufferCopy => "Back-buffer copy",
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct PassTiming {
+    pub pass: Pass,
+    // Relative to a start time of the backend's choice, the same for all frames
+    pub start_ms: f64,
+    pub duration_ms: f64,
+}
+
+// The passes of one frame, in the order they ran
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct FrameTimings {
+    pub passes: Vec<PassTiming>,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct PassStats {
+    pub pass: Pass,
+    pub min_ms: f64,
+    pub avg_ms: f64,
+    pub max_ms: f64,
+    // The number of frames in the window that ran the pass
+    pub samples: usize,
+}
+
+pub struct Profiler {
+    window: usize,
+    // The frame number and timings of the last `window` frames
+    frames: VecDeque<(u64, FrameTimings)>,
+    frame_count: u64,
+}
+
+impl Profiler {
+    pub fn new(window: usize) -> Self {
+        assert!(window > 0, "the profiler window can't be empty");
+        Self { window, frames: VecDeque::with_capacity(window), frame_count: 0 }
+    }
+
+    pub fn record(&mut self, timings: FrameTimings) {
+        if self.frames.len() == self.window {
+            self.frames.pop_front();
+        }
+        self.frames.push_back((self.frame_count, timings));
+        self.frame_count += 1;
+    }
+
+    // Min/avg/max over the window, for the passes that ran at least once. A pass that ran more than once in a
+    // frame counts with its total
+    pub fn stats(&self) -> Vec<PassStats> {
+        Pass::ALL
+            .iter()
+            .filter_map(|&pass| {
+                let durations: Vec<f64> = self
+                    .frames
+                    .iter()
+                    .filter(|(_, frame)| frame.passes.iter().any(|p| p.pass == pass))
+                    .map(|(_, frame)| frame.passes.iter().filter(|p| p.pass == pass).map(|p| p.duration_ms).sum())
+                    .collect();
+                if durations.is_empty() {
+                    return None;
+                }
+                Some(PassStats {
+                    pass,
+                    min_ms: durations.iter().copied().fold(f64::INFINITY, f64::min),
+                    avg_ms: durations.iter().sum::<f64>() / durations.len() as f64,
+                    max_ms: durations.iter().copied().fold(f64::NEG_INFINITY, f64::max),
+                    samples: durations.len(),
+                })
+            })
+            .collect()
+    }
+
+    // The averages in one line, e.g. for the window title
+    #[cfg(any(windows, test))]
+    pub fn summary(&self) -> String {
+        self.stats().iter().map(|s| format!("{} {:.2} ms", s.pass.name(), s.avg_ms)).collect::<Vec<_>>().join(" | ")
+    }
+
+    // One row per pass and frame
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("frame,pass,start_ms,duration_ms\n");
+        for (frame, timings) in &self.frames {
+            for pass in &timings.passes {
+                writeln!(csv, "{},{},{:.6},{:.6}", frame, pass.pass.name(), pass.start_ms, pass.duration_ms).unwrap();
+            }
+        }
+        csv
+    }
+
+    // The Trace Event Format of chrome://tracing and Perfetto. Every pass is a complete ("X") event in microseconds
+    pub fn to_chrome_trace(&self) -> String {
+        let mut events = Vec::new();
+        for (frame, timings) in &self.frames {
+            for pass in &timings.passes {
+                events.push(format!(
+                    r#"{{"name":"{}","cat":"pass","ph":"X","ts":{:.3},"dur":{:.3},"pid":0,"tid":0,"args":{{"frame":{}}}}}"#,
+                    pass.pass.name(),
+                    pass.start_ms * 1000.0,
+                    pass.duration_ms * 1000.0,
+                    frame
+                ));
+            }
+        }
+        format!("{{\"traceEvents\":[\n{}\n]}}\n", events.join(",\n"))
+    }
+
+    // Picks the format from the extension, .csv or .json
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        let data = match path.extension().and_then(|e| e.to_str()).map(|e| e.to_ascii_lowercase()).as_deref() {
+            Some("csv") => self.to_csv(),
+            Some("json") => self.to_chrome_trace(),
+            _ => return Err(Error::new(ErrorKind::InvalidInput, format!("unsupported profile format: {}", path.display()))),
+        };
+        std::fs::write(path, data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(passes: &[(Pass, f64, f64)]) -> FrameTimings {
+        FrameTimings { passes: passes.iter().map(|&(pass, start_ms, duration_ms)| PassTiming { pass, start_ms, duration_ms }).collect() }
+    }
+
+    fn stats(profiler: &Profiler, pass: Pass) -> PassStats {
+        *profiler.stats().iter().find(|s| s.pass == pass).unwrap()
+    }
+
+    #[test]
+    fn stats_cover_the_window() {
+        let mut profiler = Profiler::new(3);
+        for duration in [10.0, 1.0, 2.0, 3.0] {
+            profiler.record(frame(&[(Pass::DispatchRays, 0.0, duration)]));
+        }
+        // The first frame rolled out of the window
+        assert_eq!(profiler.stats(), vec![PassStats { pass: Pass::DispatchRays, min_ms: 1.0, avg_ms: 2.0, max_ms: 3.0, samples: 3 }]);
+    }
+
+    #[test]
+    fn repeated_passes_are_summed() {
+        let mut profiler = Profiler::new(4);
+        profiler.record(frame(&[(Pass::TlasRefit, 0.0, 0.25), (Pass::DispatchRays, 0.25, 4.0), (Pass::TlasRefit, 4.25, 0.5)]));
+        assert_eq!(stats(&profiler, Pass::TlasRefit), PassStats { pass: Pass::TlasRefit, min_ms: 0.75, avg_ms: 0.75, max_ms: 0.75, samples: 1 });
+    }
+
+    #[test]
+    fn missing_passes_only_count_where_they_ran() {
+        let mut profiler = Profiler::new(4);
+        profiler.record(frame(&[(Pass::TlasRefit, 0.0, 1.0), (Pass::DispatchRays, 1.0, 4.0)]));
+        profiler.record(frame(&[(Pass::DispatchRays, 0.0, 6.0)]));
+        profiler.record(frame(&[(Pass::TlasRefit, 0.0, 3.0), (Pass::DispatchRays, 3.0, 5.0)]));
+        assert_eq!(stats(&profiler, Pass::TlasRefit), PassStats { pass: Pass::TlasRefit, min_ms: 1.0, avg_ms: 2.0, max_ms: 3.0, samples: 2 });
+        assert_eq!(stats(&profiler, Pass::DispatchRays).samples, 3);
+        // Passes that never ran have no stats
+        assert!(profiler.stats().iter().all(|s| s.pass != Pass::BackBufferCopy));
+        assert_eq!(profiler.summary(), "TLAS refit 2.00 ms | DispatchRays 5.00 ms");
+    }
+
+    fn two_frames() -> Profiler {
+        let mut profiler = Profiler::new(2);
+        profiler.record(frame(&[(Pass::TlasRefit, 0.0, 0.125), (Pass::DispatchRays, 0.125, 2.5)]));
+        profiler.record(frame(&[(Pass::DispatchRays, 16.0, 2.25), (Pass::BackBufferCopy, 18.25, 0.0625)]));
+        profiler
+    }
+
+    #[test]
+    fn csv() {
+        assert_eq!(
+            two_frames().to_csv(),
+            "frame,pass,start_ms,duration_ms\n\
+             0,TLAS refit,0.000000,0.125000\n\
+             0,DispatchRays,0.125000,2.500000\n\
+             1,DispatchRays,16.000000,2.250000\n\
+             1,Back-buffer copy,18.250000,0.062500\n"
+        );
+    }
+
+    #[test]
+    fn chrome_trace() {
+        let expected = r#"{"traceEvents":[
+{"name":"TLAS refit","cat":"pass","ph":"X","ts":0.000,"dur":125.000,"pid":0,"tid":0,"args":{"frame":0}},
+{"name":"DispatchRays","cat":"pass","ph":"X","ts":125.000,"dur":2500.000,"pid":0,"tid":0,"args":{"frame":0}},
+{"name":"DispatchRays","cat":"pass","ph":"X","ts":16000.000,"dur":2250.000,"pid":0,"tid":0,"args":{"frame":1}},
+{"name":"Back-buffer copy","cat":"pass","ph":"X","ts":18250.000,"dur":62.500,"pid":0,"tid":0,"args":{"frame":1}}
+]}
+"#;
+        assert_eq!(two_frames().to_chrome_trace(), expected);
+    }
+
+    #[test]
+    fn save_rejects_unknown_extensions() {
+        let profiler = two_frames();
+        for name in ["profile.txt", "profile"] {
+            let path = std::env::temp_dir().join(name);
+            assert_eq!(profiler.save(&path).unwrap_err().kind(), ErrorKind::InvalidInput, "{}", name);
+        }
+        let path = std::env::temp_dir().join(format!("dxr_tutorials_rs_profile_{}.CSV", std::process::id()));
+        profiler.save(&path).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), profiler.to_csv());
+        std::fs::remove_file(path).unwrap();
+    }
+}
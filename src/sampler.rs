@@ -0,0 +1,299 @@
+// The random numbers of every sampling decision: the pixel jitter, the lens, the lights and the bounces of a path. A
+// sample is addressed by the pixel (DispatchRaysIndex()), the sample index, which counts the subsamples of every frame
+// so far, and a dimension that says what the numbers are for. res/sampler.hlsli implements the same functions with
+// the same integer arithmetic, so the CPU tracer draws exactly the numbers the GPU does
+
+use glam::*;
+use std::sync::OnceLock;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum SamplerKind {
+    // Independent PCG hashes of the pixel, the sample and the dimension
+    #[default]
+    Pcg,
+    // The 2D Sobol sequence with Owen scrambling. Every pixel and dimension shuffles and scrambles it with its own seed
+    // (Burley, "Practical Hash-based Owen Scrambling")
+    Sobol,
+    // blue_noise_tile() at an offset per dimension, advanced along the R2 sequence with every sample. Neighboring
+    // pixels get different numbers, which turns the noise of the first samples into a high-frequency pattern
+    BlueNoise,
+}
+
+impl SamplerKind {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "pcg" => Some(SamplerKind::Pcg),
+            "sobol" => Some(SamplerKind::Sobol),
+            "blue-noise" => Some(SamplerKind::BlueNoise),
+            _ => None,
+        }
+    }
+}
+
+// The values of FrameConstants::sampler, and the defines in res/sampler.hlsli. Nothing compares against the default
+#[allow(dead_code)]
+pub const SAMPLER_PCG: u32 = SamplerKind::Pcg as u32;
+pub const SAMPLER_SOBOL: u32 = SamplerKind::Sobol as u32;
+pub const SAMPLER_BLUE_NOISE: u32 = SamplerKind::BlueNoise as u32;
+
+// The dimensions of the camera ray
+pub const DIMENSION_PIXEL: u32 = 0;
+pub const DIMENSION_LENS: u32 = 1;
+
+// The dimensions every bounce of a path, or every hit of the direct integrator, takes through bounce_dimension(): the
+// BRDF sample of the next direction, then one that picks the lobe (or between reflection and refraction) with x and
+// plays the Russian roulette with y. The lights take one each after that
+pub const DIMENSION_DIRECTION: u32 = 0;
+pub const DIMENSION_LOBE: u32 = 1;
+pub const DIMENSION_LIGHTS: u32 = 2;
+
+pub fn bounce_dimension(bounce: u32, dimension: u32) -> u32 {
+    (bounce << 16).wrapping_add(dimension).wrapping_add(2)
+}
+
+// PCG hash (Jarzynski and Olano, "Hash Functions for GPU Rendering")
+pub fn pcg_hash(v: u32) -> u32 {
+    let state = v.wrapping_mul(747796405).wrapping_add(2891336453);
+    let word = ((state >> ((state >> 28) + 4)) ^ state).wrapping_mul(277803737);
+    (word >> 22) ^ word
+}
+
+fn pixel_hash(pixel: UVec2, v: u32) -> u32 {
+    pcg_hash(pixel.x ^ pcg_hash(pixel.y ^ pcg_hash(v)))
+}
+
+// The top 24 bits, which a float holds exactly, so the result stays below 1. Converting all 32 rounds the largest
+// hashes up to 1.0
+fn unorm2(h0: u32, h1: u32) -> Vec2 {
+    vec2((h0 >> 8) as f32, (h1 >> 8) as f32) * (1.0 / 16777216.0)
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Sampler {
+    pub kind: SamplerKind,
+    pub pixel: UVec2,
+    pub sample_index: u32,
+}
+
+impl Sampler {
+    pub fn new(kind: SamplerKind, pixel: UVec2, sample_index: u32) -> Self {
+        Self { kind, pixel, sample_index }
+    }
+
+    // Two numbers in [0, 1) for `dimension`, like sample2d() in res/sampler.hlsli
+    pub fn get_2d(&self, dimension: u32) -> Vec2 {
+        let (x, y) = match self.kind {
+            SamplerKind::Pcg => {
+                let h0 = pcg_hash(pixel_hash(self.pixel, self.sample_index) ^ pcg_hash(dimension.wrapping_add(0x3c6e_f372)));
+                (h0, pcg_hash(h0))
+            }
+            SamplerKind::Sobol => {
+                let seed = pixel_hash(self.pixel, dimension.wrapping_add(0x9e37_79b9));
+                let (x, y) = sobol_2d(nested_uniform_scramble(self.sample_index, seed));
+                (nested_uniform_scramble(x, pcg_hash(seed ^ 1)), nested_uniform_scramble(y, pcg_hash(seed ^ 2)))
+            }
+            SamplerKind::BlueNoise => blue_noise_2d(blue_noise_tile(), self.pixel, self.sample_index, dimension),
+        };
+        unorm2(x, y)
+    }
+}
+
+// The first two dimensions of the Sobol sequence as 32-bit fractions. The first is the van der Corput sequence, the
+// direction numbers of the second follow from v ^ (v >> 1)
+pub fn sobol_2d(index: u32) -> (u32, u32) {
+    let mut y = 0;
+    let mut v = 1 << 31;
+    for bit in 0..32 {
+        if index & (1 << bit) != 0 {
+            y ^= v;
+        }
+        v ^= v >> 1;
+    }
+    (index.reverse_bits(), y)
+}
+
+// Owen scrambling of a 32-bit fraction: a hash that only mixes every bit with the bits above it, applied to the
+// reversed bits (Laine and Karras, "Stratified Sampling for Stochastic Transparency")
+pub fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+    let mut x = x.reverse_bits().wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50_b47c);
+    x ^= x.wrapping_mul(0xb82f_1e52);
+    x ^= x.wrapping_mul(0xc7af_e638);
+    x ^= x.wrapping_mul(0x8d22_f6e6);
+    x.reverse_bits()
+}
+
+// The width and height of the blue-noise tile, which repeats across the image
+pub const BLUE_NOISE_SIZE: u32 = 64;
+
+// The steps of the R2 sequence as 32-bit fractions, 1 / g and 1 / g^2 for the plastic number g
+const R2_STEP: (u32, u32) = (0xc13f_a9a9, 0x91e1_0da6);
+
+fn blue_noise_2d(tile: &[u32], pixel: UVec2, sample_index: u32, dimension: u32) -> (u32, u32) {
+    // Each coordinate reads the tile at its own offset, 6 bits per axis
+    let offset = pcg_hash(dimension.wrapping_add(0x3c6e_f372));
+    let rank = |shift: u32| {
+        let x = pixel.x.wrapping_add(offset >> shift) % BLUE_NOISE_SIZE;
+        let y = pixel.y.wrapping_add(offset >> (shift + 6)) % BLUE_NOISE_SIZE;
+        tile[(y * BLUE_NOISE_SIZE + x) as usize]
+    };
+    // The 12-bit ranks are the top bits, a hash fills the bits below so the numbers aren't quantized
+    let h0 = pcg_hash(pixel_hash(pixel, sample_index) ^ offset);
+    let x = (rank(0) << 20 | h0 >> 12).wrapping_add(sample_index.wrapping_mul(R2_STEP.0));
+    let y = (rank(12) << 20 | pcg_hash(h0) >> 12).wrapping_add(sample_index.wrapping_mul(R2_STEP.1));
+    (x, y)
+}
+
+// The rank of every pixel of a tileable blue-noise mask, BLUE_NOISE_SIZE^2 values in rows. Generated on first use
+// with the void-and-cluster method, the GPU gets it in gBlueNoise
+pub fn blue_noise_tile() -> &'static [u32] {
+    static TILE: OnceLock<Vec<u32>> = OnceLock::new();
+    TILE.get_or_init(|| void_and_cluster(BLUE_NOISE_SIZE))
+}
+
+// Ulichney, "The void-and-cluster method for dither array generation". The energy of a pixel is the Gaussian-weighted
+// count of the set pixels around it on the torus. The tightest cluster is the set pixel with the most energy, the
+// largest void the free pixel with the least
+fn void_and_cluster(size: u32) -> Vec<u32> {
+    let n = (size * size) as usize;
+    let sigma = 1.5f32;
+    // The weight of every toroidal offset
+    let kernel: Vec<f32> = (0..n)
+        .map(|i| {
+            let wrap = |d: u32| d.min(size - d) as f32;
+            let (dx, dy) = (wrap(i as u32 % size), wrap(i as u32 / size));
+            (-(dx * dx + dy * dy) / (2.0 * sigma * sigma)).exp()
+        })
+        .collect();
+    let mut energy = vec![0.0f32; n];
+    let mut set = vec![false; n];
+    let toggle = |set: &mut [bool], energy: &mut [f32], p: usize| {
+        set[p] = !set[p];
+        let sign = if set[p] { 1.0 } else { -1.0 };
+        let (px, py) = (p as u32 % size, p as u32 / size);
+        for (q, e) in energy.iter_mut().enumerate() {
+            let dx = (q as u32 % size + size - px) % size;
+            let dy = (q as u32 / size + size - py) % size;
+            *e += sign * kernel[(dy * size + dx) as usize];
+        }
+    };
+    let tightest_cluster = |set: &[bool], energy: &[f32]| (0..n).filter(|&p| set[p]).max_by(|&a, &b| energy[a].total_cmp(&energy[b])).unwrap();
+    let largest_void = |set: &[bool], energy: &[f32]| (0..n).filter(|&p| !set[p]).min_by(|&a, &b| energy[a].total_cmp(&energy[b])).unwrap();
+
+    // A random initial pattern with a tenth of the pixels set, relaxed by moving the tightest cluster into the largest
+    // void until that is the pixel it came from
+    let initial = (0..n).filter(|&p| pcg_hash(p as u32) < u32::MAX / 10).collect::<Vec<_>>();
+    for &p in &initial {
+        toggle(&mut set, &mut energy, p);
+    }
+    loop {
+        let cluster = tightest_cluster(&set, &energy);
+        toggle(&mut set, &mut energy, cluster);
+        let void = largest_void(&set, &energy);
+        toggle(&mut set, &mut energy, void);
+        if void == cluster {
+            break;
+        }
+    }
+    let prototype = set.clone();
+    let prototype_energy = energy.clone();
+
+    // The set pixels are ranked by removing the tightest clusters, the rest by filling the largest voids
+    let mut rank = vec![0u32; n];
+    let count = initial.len() as u32;
+    for r in (0..count).rev() {
+        let cluster = tightest_cluster(&set, &energy);
+        toggle(&mut set, &mut energy, cluster);
+        rank[cluster] = r;
+    }
+    let (mut set, mut energy) = (prototype, prototype_energy);
+    for r in count..n as u32 {
+        let void = largest_void(&set, &energy);
+        toggle(&mut set, &mut energy, void);
+        rank[void] = r;
+    }
+    rank
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The expected values are from a Python port of res/sampler.hlsli
+
+    #[test]
+    fn pcg_hash_vectors() {
+        assert_eq!(pcg_hash(0), 0x07bb_2fe2);
+        assert_eq!(pcg_hash(1), 0xa8be_ea3c);
+        assert_eq!(pcg_hash(42), 0x48f4_32ff);
+        assert_eq!(pcg_hash(0xdead_beef), 0x6729_9972);
+        assert_eq!(pcg_hash(u32::MAX), 0xe62a_4902);
+    }
+
+    #[test]
+    fn sobol_2d_is_the_sobol_sequence() {
+        // The first 8 points of the first two Sobol dimensions, in halves, quarters and eighths
+        let expected = [(0, 0), (4, 4), (2, 6), (6, 2), (1, 5), (5, 1), (3, 3), (7, 7)];
+        for (index, (x, y)) in expected.into_iter().enumerate() {
+            assert_eq!(sobol_2d(index as u32), (x << 29, y << 29), "point {}", index);
+        }
+    }
+
+    #[test]
+    fn nested_uniform_scramble_vectors() {
+        assert_eq!(nested_uniform_scramble(0, 0), 0);
+        assert_eq!(nested_uniform_scramble(1, 0), 1);
+        assert_eq!(nested_uniform_scramble(0x8000_0000, 0x1234_5678), 0xb911_fa0b);
+        assert_eq!(nested_uniform_scramble(0xdead_beef, 0xcafe_f00d), 0x152a_7113);
+    }
+
+    #[test]
+    fn nested_uniform_scramble_keeps_the_strata() {
+        // The first 2^k points of the van der Corput sequence fill every interval of size 2^-k once, and scrambling
+        // only permutes the intervals
+        for seed in [0, 1, 0x9e37_79b9, u32::MAX] {
+            let mut strata: Vec<u32> = (0..64).map(|i| nested_uniform_scramble(sobol_2d(i).0, seed) >> 26).collect();
+            strata.sort();
+            assert_eq!(strata, (0..64).collect::<Vec<_>>(), "seed {:#x}", seed);
+        }
+    }
+
+    fn assert_2d(kind: SamplerKind, expected: [(u32, u32, u32, u32, u32, u32); 3]) {
+        for (px, py, sample_index, dimension, x, y) in expected {
+            let sampler = Sampler::new(kind, uvec2(px, py), sample_index);
+            let expected = vec2((x >> 8) as f32, (y >> 8) as f32) / 16777216.0;
+            assert_eq!(sampler.get_2d(dimension), expected, "{:?} at ({}, {}), sample {}, dimension {}", kind, px, py, sample_index, dimension);
+        }
+    }
+
+    #[test]
+    fn unorm2_stays_below_one() {
+        assert_eq!(unorm2(0, 0xff), Vec2::ZERO);
+        assert_eq!(unorm2(0x8000_0000, 0x0000_0100), vec2(0.5, 1.0 / 16777216.0));
+        assert_eq!(unorm2(u32::MAX, u32::MAX), Vec2::splat(1.0 - 1.0 / 16777216.0));
+    }
+
+    #[test]
+    fn get_2d_vectors() {
+        assert_2d(
+            SamplerKind::Pcg,
+            [(0, 0, 0, 0, 0x95e5_781b, 0x1ced_5857), (17, 5, 3, 2, 0x7375_ef6a, 0xf83f_967a), (640, 480, 1000, bounce_dimension(1, 2), 0xdb93_d452, 0x356f_8bea)],
+        );
+        assert_2d(
+            SamplerKind::Sobol,
+            [(0, 0, 0, 0, 0x6f91_444a, 0xe920_7d69), (17, 5, 3, 2, 0x7c73_f93d, 0xf635_7c7e), (640, 480, 1000, bounce_dimension(1, 2), 0x199b_8873, 0xc33c_d3d8)],
+        );
+        // These also pin the ranks of the tile the expected values were computed with
+        assert_2d(
+            SamplerKind::BlueNoise,
+            [(0, 0, 0, 0, 0x7e59_5e57, 0x4811_ced5), (17, 5, 3, 2, 0xdde6_3459, 0x7cb2_aceb), (640, 480, 1000, bounce_dimension(1, 2), 0x496c_7565, 0xb900_a768)],
+        );
+    }
+
+    #[test]
+    fn blue_noise_tile_is_a_permutation() {
+        let mut ranks = blue_noise_tile().to_vec();
+        ranks.sort();
+        assert_eq!(ranks, (0..BLUE_NOISE_SIZE * BLUE_NOISE_SIZE).collect::<Vec<_>>());
+    }
+}
@@ -0,0 +1,131 @@
+use glam::*;
+use serde::{Deserialize, Serialize};
+
+use crate::camera::Camera;
+use crate::light::Light;
+use crate::material::Material;
+use crate::texture::Texture;
+
+// The scene being rendered. Both the D3D12 path and the CPU reference tracer consume these,
+// so they are guaranteed to see the same geometry. Scenes are loaded from files, see scene_file.rs
+
+// A triangle list. With indices every 3 indices form a triangle, otherwise every 3 vertices. The optional vertex
+// attributes have one element per vertex
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Geometry {
+    pub vertices: Vec<Vec3>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub indices: Option<Vec<u32>>,
+    // Shading normals, interpolated across the triangles. Without them the hit programs shade with the face normals
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub normals: Option<Vec<Vec3>>,
+    // The tangent in xyz and the handedness of the bitangent in w, like glTF
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tangents: Option<Vec<Vec4>>,
+    // With the origin at the top left, like D3D textures
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tex_coords: Option<Vec<Vec2>>,
+}
+
+impl Geometry {
+    pub fn new(vertices: &[Vec3]) -> Self {
+        Self { vertices: vertices.to_vec(), indices: None, normals: None, tangents: None, tex_coords: None }
+    }
+
+    pub fn indexed(vertices: Vec<Vec3>, indices: Vec<u32>) -> Self {
+        Self { vertices, indices: Some(indices), normals: None, tangents: None, tex_coords: None }
+    }
+
+    pub fn triangle_count(&self) -> usize {
+        self.indices.as_ref().map_or(self.vertices.len(), |i| i.len()) / 3
+    }
+
+    // The vertex indices of triangle `i`
+    pub fn triangle_indices(&self, i: usize) -> [usize; 3] {
+        match &self.indices {
+            Some(indices) => [0, 1, 2].map(|v| indices[i * 3 + v] as usize),
+            None => [0, 1, 2].map(|v| i * 3 + v),
+        }
+    }
+
+    pub fn triangle(&self, i: usize) -> [Vec3; 3] {
+        self.triangle_indices(i).map(|v| self.vertices[v])
+    }
+
+    // The bytes per index of the index buffer the GPU gets: 16-bit whenever the vertices can be addressed with them
+    #[cfg(windows)]
+    pub fn index_size(&self) -> u32 {
+        if self.vertices.len() <= 1 << 16 { 2 } else { 4 }
+    }
+
+    // The GEOMETRY_* flags of the attributes the geometry has
+    #[cfg(windows)]
+    pub fn attribute_flags(&self) -> u32 {
+        let flag = |present: bool, flag: u32| if present { flag } else { 0 };
+        flag(self.normals.is_some(), GEOMETRY_NORMALS) | flag(self.tangents.is_some(), GEOMETRY_TANGENTS) | flag(self.tex_coords.is_some(), GEOMETRY_TEX_COORDS)
+    }
+}
+
+// The vertex attribute flags of shader_table::RootArgument::GeometryConstants, and the defines in res/shaders.hlsl
+#[cfg(windows)]
+pub const GEOMETRY_NORMALS: u32 = 1;
+#[cfg(windows)]
+pub const GEOMETRY_TANGENTS: u32 = 2;
+#[cfg(windows)]
+pub const GEOMETRY_TEX_COORDS: u32 = 4;
+
+// A bottom-level acceleration structure - a list of triangle-list geometries
+#[derive(Clone)]
+pub struct Blas {
+    pub geometries: Vec<Geometry>,
+}
+
+// Mirrors D3D12_RAYTRACING_INSTANCE_DESC
+#[derive(Clone)]
+pub struct Instance {
+    pub transform: Mat4,
+    // The index of the material of the instance's first geometry in Scene::materials. The hit programs add
+    // GeometryIndex() to InstanceID() to find theirs. 24 bits
+    pub instance_id: u32,
+    pub instance_mask: u8,
+    // Offset of the instance's records from the start of the hit-group table
+    pub instance_contribution: u32,
+    pub blas: usize,
+    // The transform at frame 0 and how fast the instance spins around its Y axis, in radians per frame
+    pub base_transform: Mat4,
+    pub spin: f32,
+}
+
+impl Instance {
+    pub fn new(instance_id: u32, blas: usize, transform: Mat4) -> Self {
+        Self {
+            transform,
+            instance_id,
+            instance_mask: 0xFF,
+            instance_contribution: 0,
+            blas,
+            base_transform: transform,
+            spin: 0.0,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Scene {
+    pub blas: Vec<Blas>,
+    pub instances: Vec<Instance>,
+    pub camera: Camera,
+    pub lights: Vec<Light>,
+    // One material per geometry of every instance, see Instance::instance_id
+    pub materials: Vec<Material>,
+    // What the texture indices of the materials refer to
+    pub textures: Vec<Texture>,
+}
+
+impl Scene {
+    pub fn animate(&mut self, frame: u32) {
+        for instance in &mut self.instances {
+            instance.transform = instance.base_transform * Mat4::from_rotation_y(instance.spin * frame as f32);
+        }
+    }
+}
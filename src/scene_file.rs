@@ -0,0 +1,346 @@
+use glam::*;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use crate::camera::Camera;
+use crate::pipeline::*;
+use crate::light::Light;
+use crate::material;
+use crate::scene::{Blas, Geometry, Instance, Scene};
+use crate::shader_table::{ShaderRecord, ShaderTable};
+use crate::texture::{ColorSpace, Texture};
+
+// The RON scene description. Meshes and materials are named, instances refer to them by name. Pbr materials refer
+// to textures by their index in the texture list.
+// See res/tutorial.ron for an example.
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Material {
+    // One color per triangle corner, blended with the barycentrics. A rough dielectric otherwise
+    Barycentric([Vec3; 3]),
+    // A rough dielectric
+    Flat(Vec3),
+    // Light gray, lit like the others
+    ShadowCatcher,
+    Pbr(material::Material),
+}
+
+impl Material {
+    // The material of the buffer, and the hit record
+    fn build(&self, blas: usize, geometry: usize) -> (material::Material, ShaderRecord) {
+        match self {
+            Material::Barycentric(colors) => (material::Material::diffuse(Vec3::ONE), ShaderRecord::triangle_hit(colors.map(|c| c.extend(1.0)), blas, geometry)),
+            Material::Flat(color) => (material::Material::diffuse(*color), ShaderRecord::plane_hit(blas, geometry)),
+            Material::ShadowCatcher => (material::Material::diffuse(Vec3::splat(0.8)), ShaderRecord::plane_hit(blas, geometry)),
+            Material::Pbr(material) => (*material, ShaderRecord::plane_hit(blas, geometry)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Transform {
+    pub translation: Vec3,
+    // Euler angles in degrees, applied in Y, X, Z order
+    pub rotation: Vec3,
+    pub scale: Vec3,
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self { translation: Vec3::ZERO, rotation: Vec3::ZERO, scale: Vec3::ONE }
+    }
+}
+
+impl Transform {
+    pub fn matrix(&self) -> Mat4 {
+        let r = self.rotation * (std::f32::consts::PI / 180.0);
+        Mat4::from_scale_rotation_translation(self.scale, Quat::from_euler(EulerRot::YXZ, r.y, r.x, r.z), self.translation)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct InstanceDesc {
+    pub mesh: String,
+    // One material per geometry of the mesh
+    pub materials: Vec<String>,
+    #[serde(default)]
+    pub transform: Transform,
+    #[serde(default = "default_mask")]
+    pub mask: u8,
+    // Radians per frame around the instance's Y axis
+    #[serde(default)]
+    pub spin: f32,
+}
+
+fn default_mask() -> u8 {
+    0xFF
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TextureDesc {
+    // Relative to the scene file
+    pub path: String,
+    // Linear for normal and metallic-roughness maps
+    #[serde(default)]
+    pub color_space: ColorSpace,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SceneFile {
+    #[serde(default)]
+    pub camera: Camera,
+    #[serde(default)]
+    pub lights: Vec<Light>,
+    pub materials: BTreeMap<String, Material>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub textures: Vec<TextureDesc>,
+    // Each mesh is a list of geometries and becomes one BLAS
+    pub meshes: BTreeMap<String, Vec<Geometry>>,
+    pub instances: Vec<InstanceDesc>,
+}
+
+impl SceneFile {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let source = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        Self::from_ron(&source).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    #[cfg(test)]
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        std::fs::write(path, self.to_ron()?).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    pub fn from_ron(source: &str) -> Result<Self, String> {
+        let file: Self = ron::from_str(source).map_err(|e| e.to_string())?;
+        file.validate()?;
+        Ok(file)
+    }
+
+    #[cfg(test)]
+    pub fn to_ron(&self) -> Result<String, String> {
+        let config = ron::ser::PrettyConfig::new().struct_names(true);
+        ron::ser::to_string_pretty(self, config).map_err(|e| e.to_string())
+    }
+
+    // Checks everything serde can't: references between the sections, index ranges and degenerate values
+    pub fn validate(&self) -> Result<(), String> {
+        self.camera.validate().map_err(|e| format!("camera: {}", e))?;
+        for (i, light) in self.lights.iter().enumerate() {
+            light.validate().map_err(|e| format!("lights[{}]: {}", i, e))?;
+        }
+
+        for (name, material) in &self.materials {
+            if let Material::Pbr(material) = material {
+                material.validate().map_err(|e| format!("materials[\"{}\"]: {}", name, e))?;
+                if let Some(texture) = material.textures().find(|t| *t as usize >= self.textures.len()) {
+                    return Err(format!("materials[\"{}\"]: texture {} is out of range, there are {} textures", name, texture, self.textures.len()));
+                }
+            }
+        }
+
+        for (name, geometries) in &self.meshes {
+            for (i, geometry) in geometries.iter().enumerate() {
+                validate_geometry(geometry).map_err(|e| format!("meshes[\"{}\"][{}]: {}", name, i, e))?;
+            }
+        }
+
+        for (i, instance) in self.instances.iter().enumerate() {
+            let error = |msg: String| format!("instances[{}]: {}", i, msg);
+            let geometries = self.meshes.get(&instance.mesh).ok_or_else(|| error(format!("mesh \"{}\" is not defined", instance.mesh)))?;
+            if instance.materials.len() != geometries.len() {
+                return Err(error(format!(
+                    "mesh \"{}\" has {} geometries but {} materials are listed",
+                    instance.mesh,
+                    geometries.len(),
+                    instance.materials.len()
+                )));
+            }
+            for material in &instance.materials {
+                if !self.materials.contains_key(material) {
+                    return Err(error(format!("material \"{}\" is not defined", material)));
+                }
+            }
+            if !instance.transform.matrix().is_finite() {
+                return Err(error("the transform is not finite".into()));
+            }
+        }
+        Ok(())
+    }
+
+    // Loads the textures of the texture list, relative to `dir`
+    pub fn load_textures(&self, dir: &Path) -> Result<Vec<Texture>, String> {
+        self.textures.iter().map(|desc| Texture::load(&dir.join(&desc.path), desc.color_space)).collect()
+    }
+
+    // One BLAS per mesh and one instance per instance description. Each geometry gets the record of its material
+    // followed by a shadow record, and its material in the material buffer. `textures` are the ones of load_textures()
+    pub fn scene(&self, textures: Vec<Texture>) -> (Scene, ShaderTable) {
+        let mesh_names: Vec<&String> = self.meshes.keys().collect();
+        let mut scene = Scene {
+            blas: self.meshes.values().map(|geometries| Blas { geometries: geometries.clone() }).collect(),
+            camera: self.camera,
+            lights: self.lights.clone(),
+            textures,
+            ..Default::default()
+        };
+        let mut shader_table = ShaderTable::with_default_programs();
+        for desc in &self.instances {
+            let blas = mesh_names.iter().position(|name| **name == desc.mesh).unwrap();
+            let first_material = scene.materials.len() as u32;
+            let mut records = Vec::new();
+            for (geometry, material) in desc.materials.iter().enumerate() {
+                let (material, record) = self.materials[material].build(blas, geometry);
+                scene.materials.push(material);
+                records.push(record);
+                records.push(ShaderRecord::new(SHADOW_HIT_GROUP, vec![]));
+            }
+            shader_table.add_instance(records);
+
+            scene.instances.push(Instance {
+                instance_mask: desc.mask,
+                spin: desc.spin,
+                ..Instance::new(first_material, blas, desc.transform.matrix())
+            });
+        }
+        (scene, shader_table)
+    }
+}
+
+// Shared with the importers, whose geometry serde doesn't check either
+pub(crate) fn validate_geometry(geometry: &Geometry) -> Result<(), String> {
+    if geometry.vertices.iter().any(|v| !v.is_finite()) {
+        return Err("a vertex is not finite".into());
+    }
+    match &geometry.indices {
+        Some(indices) => {
+            if !indices.len().is_multiple_of(3) {
+                return Err(format!("{} indices is not a multiple of 3", indices.len()));
+            }
+            if let Some(index) = indices.iter().find(|i| **i as usize >= geometry.vertices.len()) {
+                return Err(format!("index {} is out of range, there are {} vertices", index, geometry.vertices.len()));
+            }
+        }
+        None if !geometry.vertices.len().is_multiple_of(3) => {
+            return Err(format!("{} vertices is not a multiple of 3", geometry.vertices.len()));
+        }
+        None => {}
+    }
+    let attributes = [
+        ("normals", geometry.normals.as_ref().map(|n| n.len())),
+        ("tangents", geometry.tangents.as_ref().map(|t| t.len())),
+        ("texture coordinates", geometry.tex_coords.as_ref().map(|t| t.len())),
+    ];
+    for (name, len) in attributes {
+        if let Some(len) = len.filter(|len| *len != geometry.vertices.len()) {
+            return Err(format!("{} {} for {} vertices", len, name, geometry.vertices.len()));
+        }
+    }
+    let finite = geometry.normals.iter().flatten().all(|n| n.is_finite())
+        && geometry.tangents.iter().flatten().all(|t| t.is_finite())
+        && geometry.tex_coords.iter().flatten().all(|t| t.is_finite());
+    if !finite {
+        return Err("a vertex attribute is not finite".into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TUTORIAL: &str = include_str!("../res/tutorial.ron");
+
+    #[test]
+    fn tutorial_round_trips() {
+        let original = SceneFile::from_ron(TUTORIAL).unwrap();
+        assert_eq!(SceneFile::from_ron(&original.to_ron().unwrap()).unwrap(), original);
+
+        let path = std::env::temp_dir().join(format!("dxr_tutorials_rs_scene_{}.ron", std::process::id()));
+        original.save(&path).unwrap();
+        let loaded = SceneFile::load(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.unwrap(), original);
+    }
+
+    #[test]
+    fn tutorial_scene() {
+        let (scene, shader_table) = SceneFile::from_ron(TUTORIAL).unwrap().scene(Vec::new());
+        // The meshes are sorted by name, and every geometry has a material and two hit records
+        assert_eq!(scene.blas.iter().map(|b| b.geometries.len()).collect::<Vec<_>>(), [1, 2]);
+        assert_eq!(scene.instances.iter().map(|i| (i.blas, i.instance_id)).collect::<Vec<_>>(), [(1, 0), (0, 2), (0, 3)]);
+        assert_eq!(shader_table.instance_contributions, [0, 4, 6]);
+        assert_eq!(shader_table.validate(&scene), Ok(()));
+    }
+
+    // The tutorial scene after `edit`, validated
+    fn validate(edit: impl FnOnce(&mut SceneFile)) -> String {
+        let mut file = SceneFile::from_ron(TUTORIAL).unwrap();
+        edit(&mut file);
+        file.validate().unwrap_err()
+    }
+
+    fn triangle(file: &mut SceneFile) -> &mut Geometry {
+        &mut file.meshes.get_mut("triangle").unwrap()[0]
+    }
+
+    #[test]
+    fn undefined_mesh() {
+        assert_eq!(validate(|f| f.instances[1].mesh = "cube".into()), "instances[1]: mesh \"cube\" is not defined");
+    }
+
+    #[test]
+    fn undefined_material() {
+        assert_eq!(validate(|f| f.instances[2].materials[0] = "gold".into()), "instances[2]: material \"gold\" is not defined");
+    }
+
+    #[test]
+    fn material_count() {
+        assert_eq!(
+            validate(|f| f.instances[0].materials.truncate(1)),
+            "instances[0]: mesh \"triangle_and_plane\" has 2 geometries but 1 materials are listed"
+        );
+    }
+
+    #[test]
+    fn index_out_of_range() {
+        assert_eq!(
+            validate(|f| triangle(f).indices = Some(vec![0, 1, 3])),
+            "meshes[\"triangle\"][0]: index 3 is out of range, there are 3 vertices"
+        );
+        assert_eq!(validate(|f| triangle(f).indices = Some(vec![0, 1])), "meshes[\"triangle\"][0]: 2 indices is not a multiple of 3");
+        assert_eq!(validate(|f| triangle(f).vertices.push(Vec3::ZERO)), "meshes[\"triangle\"][0]: 4 vertices is not a multiple of 3");
+    }
+
+    #[test]
+    fn texture_out_of_range() {
+        let error = validate(|f| {
+            let material = material::Material { base_color_texture: Some(0), ..material::Material::diffuse(Vec3::ONE) };
+            f.materials.insert("textured".into(), Material::Pbr(material));
+        });
+        assert_eq!(error, "materials[\"textured\"]: texture 0 is out of range, there are 0 textures");
+    }
+
+    #[test]
+    fn attribute_length() {
+        assert_eq!(validate(|f| triangle(f).normals = Some(vec![Vec3::Z; 2])), "meshes[\"triangle\"][0]: 2 normals for 3 vertices");
+        assert_eq!(validate(|f| triangle(f).tex_coords = Some(vec![Vec2::ZERO; 4])), "meshes[\"triangle\"][0]: 4 texture coordinates for 3 vertices");
+        assert_eq!(
+            validate(|f| triangle(f).tangents = Some(vec![Vec4::new(f32::NAN, 0.0, 0.0, 1.0); 3])),
+            "meshes[\"triangle\"][0]: a vertex attribute is not finite"
+        );
+    }
+
+    #[test]
+    fn non_finite_transform() {
+        assert_eq!(validate(|f| f.instances[1].transform.translation.x = f32::INFINITY), "instances[1]: the transform is not finite");
+        assert_eq!(validate(|f| f.instances[2].transform.scale = Vec3::splat(f32::NAN)), "instances[2]: the transform is not finite");
+    }
+
+    #[test]
+    fn parse_errors_name_the_position() {
+        let error = SceneFile::from_ron("SceneFile(materials: {}, meshes: {}, instances: [InstanceDesc(mesh: 1)])").unwrap_err();
+        assert!(error.starts_with("1:"), "{}", error);
+    }
+}
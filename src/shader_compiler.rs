@@ -0,0 +1,258 @@
+// The platform-independent half of shader compilation: the options we hand to DXC and the diagnostics we get back.
+// The D3D12 backend turns CompileOptions into DXC arguments and parses the error buffer with parse_diagnostics().
+
+#[cfg(any(windows, test))]
+use std::fmt;
+use std::path::PathBuf;
+
+#[cfg_attr(not(windows), allow(dead_code))]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Optimization {
+    // -Od
+    Disabled,
+    // -O0 to -O3
+    Level(u8),
+}
+
+#[cfg_attr(not(windows), allow(dead_code))]
+#[derive(Clone, Debug)]
+pub struct CompileOptions {
+    // Passed as DxcDefines. An empty value defines the name as 1, like -D NAME
+    pub defines: Vec<(String, String)>,
+    // Searched by #include, after the directory of the including file
+    pub include_dirs: Vec<PathBuf>,
+    pub optimization: Optimization,
+    // -Zi, and -Qembed_debug to keep the debug info in the DXIL so tools like PIX find it
+    pub debug_info: bool,
+    pub embed_debug: bool,
+    // -HV, e.g. 2018 or 2021. None uses the compiler's default
+    pub hlsl_version: Option<u32>,
+}
+
+impl Default for CompileOptions {
+    fn default() -> Self {
+        Self {
+            defines: Vec::new(),
+            include_dirs: Vec::new(),
+            optimization: Optimization::Level(3),
+            debug_info: cfg!(debug_assertions),
+            embed_debug: cfg!(debug_assertions),
+            hlsl_version: None,
+        }
+    }
+}
+
+impl CompileOptions {
+    // The command-line arguments for everything except the defines, which have their own parameter
+    #[cfg(any(windows, test))]
+    pub fn arguments(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        for dir in &self.include_dirs {
+            args.push("-I".to_string());
+            args.push(dir.display().to_string());
+        }
+        args.push(match self.optimization {
+            Optimization::Disabled => "-Od".to_string(),
+            Optimization::Level(level) => format!("-O{}", level.min(3)),
+        });
+        if self.debug_info {
+            args.push("-Zi".to_string());
+        }
+        if self.embed_debug {
+            args.push("-Qembed_debug".to_string());
+        }
+        if let Some(version) = self.hlsl_version {
+            args.push("-HV".to_string());
+            args.push(version.to_string());
+        }
+        args
+    }
+
+    // The names and values of the DxcDefines
+    #[cfg(any(windows, test))]
+    pub fn define_values(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.defines.iter().map(|(name, value)| (name.as_str(), if value.is_empty() { "1" } else { value.as_str() }))
+    }
+
+    #[cfg(any(windows, test))]
+    pub fn validate(&self) -> Result<(), String> {
+        if let Optimization::Level(level) = self.optimization {
+            if level > 3 {
+                return Err(format!("optimization level {} is out of range, DXC supports -O0 to -O3", level));
+            }
+        }
+        if self.embed_debug && !self.debug_info {
+            return Err("embed_debug requires debug_info".into());
+        }
+        if let Some((name, _)) = self.defines.iter().find(|(name, _)| name.is_empty() || name.contains(char::is_whitespace)) {
+            return Err(format!("invalid define name '{}'", name));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(any(windows, test))]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Severity {
+    Error,
+    Warning,
+    Note,
+}
+
+// One message from the DXC output. Messages that don't refer to a location (e.g. a bad argument) have no file
+// and a line and column of 0
+#[cfg(any(windows, test))]
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Diagnostic {
+    pub file: String,
+    pub line: u32,
+    pub column: u32,
+    pub severity: Severity,
+    pub message: String,
+}
+
+#[cfg(any(windows, test))]
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+            Severity::Note => "note",
+        };
+        if self.file.is_empty() {
+            write!(f, "{}: {}", severity, self.message)
+        } else {
+            write!(f, "{}:{}:{}: {}: {}", self.file, self.line, self.column, severity, self.message)
+        }
+    }
+}
+
+// The markers DXC (clang) puts between the location and the message
+#[cfg(any(windows, test))]
+const SEVERITIES: [(&str, Severity); 4] =
+    [("fatal error: ", Severity::Error), ("error: ", Severity::Error), ("warning: ", Severity::Warning), ("note: ", Severity::Note)];
+
+// Parses the DXC error buffer. Every message is formatted like clang's, `file:line:column: severity: message`,
+// followed by the source line and a caret line, which we skip. The file can be a Windows path with a drive letter
+#[cfg(any(windows, test))]
+pub fn parse_diagnostics(output: &str) -> Vec<Diagnostic> {
+    output.lines().filter_map(parse_line).collect()
+}
+
+#[cfg(any(windows, test))]
+fn parse_line(line: &str) -> Option<Diagnostic> {
+    for (marker, severity) in SEVERITIES {
+        // A message without a location starts with the marker
+        if let Some(message) = line.strip_prefix(marker) {
+            return Some(Diagnostic { file: String::new(), line: 0, column: 0, severity, message: message.trim().into() });
+        }
+        let Some(pos) = line.find(&format!(": {}", marker)) else { continue };
+        // The location is the part before the marker, split from the right because of the drive letter
+        let mut location = line[..pos].rsplitn(3, ':');
+        let (Some(column), Some(line_number), Some(file)) = (location.next(), location.next(), location.next()) else { continue };
+        let (Ok(column), Ok(line_number)) = (column.trim().parse(), line_number.trim().parse()) else { continue };
+        let message = line[pos + 2 + marker.len()..].trim().into();
+        return Some(Diagnostic { file: file.into(), line: line_number, column, severity, message });
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Hand-written in the format of DXC's error buffer (clang's `file:line:column: severity: message`, the source
+    // line and a caret line), not captured from a real compile
+    const DXC_OUTPUT: &str = r#"C:\Users\dev\dxr_tutorials_rs\res\shaders.hlsl:12:5: error: use of undeclared identifier 'foo'
+    foo = 1;
+    ^
+C:\Users\dev\dxr_tutorials_rs\res\shaders.hlsl:40:17: warning: implicit truncation of vector type [-Wconversion]
+    float2 uv = gTexCoords[indices.x].xyz;
+                ^
+In file included from C:\Users\dev\dxr_tutorials_rs\res\shaders.hlsl:14:
+C:\Users\dev\dxr_tutorials_rs\res\sampler.hlsli:28:6: note: previous definition is here
+uint pcgHash(uint v) {
+     ^
+"#;
+
+    #[test]
+    fn parses_windows_paths_and_severities() {
+        let file = r"C:\Users\dev\dxr_tutorials_rs\res\shaders.hlsl";
+        assert_eq!(
+            parse_diagnostics(DXC_OUTPUT),
+            vec![
+                Diagnostic { file: file.into(), line: 12, column: 5, severity: Severity::Error, message: "use of undeclared identifier 'foo'".into() },
+                Diagnostic {
+                    file: file.into(),
+                    line: 40,
+                    column: 17,
+                    severity: Severity::Warning,
+                    message: "implicit truncation of vector type [-Wconversion]".into(),
+                },
+                Diagnostic {
+                    file: r"C:\Users\dev\dxr_tutorials_rs\res\sampler.hlsli".into(),
+                    line: 28,
+                    column: 6,
+                    severity: Severity::Note,
+                    message: "previous definition is here".into(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_fatal_errors() {
+        let output = "res/shaders.hlsl:14:10: fatal error: 'sampler.hlsli' file not found\n#include \"sampler.hlsli\"\n         ^~~~~~~~~~~~~~~\n";
+        assert_eq!(
+            parse_diagnostics(output),
+            vec![Diagnostic { file: "res/shaders.hlsl".into(), line: 14, column: 10, severity: Severity::Error, message: "'sampler.hlsli' file not found".into() }]
+        );
+    }
+
+    #[test]
+    fn parses_messages_without_a_location() {
+        let diagnostics = parse_diagnostics("error: missing entry point definition\n");
+        assert_eq!(diagnostics, vec![Diagnostic { file: String::new(), line: 0, column: 0, severity: Severity::Error, message: "missing entry point definition".into() }]);
+        assert_eq!(diagnostics[0].to_string(), "error: missing entry point definition");
+    }
+
+    #[test]
+    fn skips_source_and_caret_lines() {
+        // A multi-line error: the message, the source line and the caret line with its fix-it hint
+        let output = "res/shaders.hlsl:7:23: error: expected ';' after expression\n    float3 color = 0.5f\n                      ^\n                      ;\n1 error generated.\n";
+        let diagnostics = parse_diagnostics(output);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].to_string(), "res/shaders.hlsl:7:23: error: expected ';' after expression");
+    }
+
+    #[test]
+    fn arguments() {
+        let options = CompileOptions {
+            defines: vec![("MAX_LIGHTS".into(), "8".into()), ("USE_TEXTURES".into(), String::new())],
+            include_dirs: vec![PathBuf::from("res"), PathBuf::from("shaders/common")],
+            optimization: Optimization::Disabled,
+            debug_info: true,
+            embed_debug: true,
+            hlsl_version: Some(2021),
+        };
+        assert_eq!(options.arguments(), ["-I", "res", "-I", "shaders/common", "-Od", "-Zi", "-Qembed_debug", "-HV", "2021"]);
+        // The defines go to DXC separately, an empty value defines the name as 1
+        assert_eq!(options.define_values().collect::<Vec<_>>(), [("MAX_LIGHTS", "8"), ("USE_TEXTURES", "1")]);
+
+        let release = CompileOptions { optimization: Optimization::Level(3), debug_info: false, embed_debug: false, ..CompileOptions::default() };
+        assert_eq!(release.arguments(), ["-O3"]);
+    }
+
+    #[test]
+    fn validate() {
+        let options = |f: fn(&mut CompileOptions)| {
+            let mut options = CompileOptions { debug_info: true, ..CompileOptions::default() };
+            f(&mut options);
+            options.validate()
+        };
+        assert!(options(|_| ()).is_ok());
+        assert!(options(|o| o.optimization = Optimization::Level(4)).is_err());
+        assert!(options(|o| (o.debug_info, o.embed_debug) = (false, true)).is_err());
+        assert!(options(|o| o.defines.push(("BAD NAME".into(), String::new()))).is_err());
+    }
+}
@@ -0,0 +1,391 @@
+use glam::*;
+
+use crate::pipeline::*;
+use crate::scene::Scene;
+
+// Slots in the SRV/UAV heap. The ray-gen descriptor table starts at the output UAV and covers the first four, the
+// plane's starts at the TLAS SRV
+pub const OUTPUT_UAV_SLOT: u32 = 0;
+pub const TLAS_SRV_SLOT: u32 = 1;
+#[cfg(windows)]
+pub const ACCUMULATION_UAV_SLOT: u32 = 2;
+#[cfg(windows)]
+pub const HDR_OUTPUT_UAV_SLOT: u32 = 3;
+// The first of the scene's texture SRVs, which the global root signature binds as gTextures
+#[cfg(windows)]
+pub const TEXTURE_SRV_SLOT: u32 = 4;
+
+// The D3D12 values, duplicated so the layout can be computed without the Windows headers
+pub const SHADER_IDENTIFIER_SIZE_IN_BYTES: u32 = 32; // D3D12_SHADER_IDENTIFIER_SIZE_IN_BYTES
+pub const SHADER_RECORD_BYTE_ALIGNMENT: u32 = 32; // D3D12_RAYTRACING_SHADER_RECORD_BYTE_ALIGNMENT
+pub const SHADER_TABLE_BYTE_ALIGNMENT: u32 = 64; // D3D12_RAYTRACING_SHADER_TABLE_BYTE_ALIGNMENT
+pub const MAX_SHADER_RECORD_STRIDE: u32 = 4096; // D3D12_RAYTRACING_MAX_SHADER_RECORD_STRIDE
+
+// The number of ray types. Every geometry has one hit record per ray type (primary followed by shadow),
+// which is the MultiplierForGeometryContributionToHitGroupIndex passed to TraceRay()
+pub const RAY_TYPE_COUNT: u32 = 2;
+
+// Every root argument is a D3D12_GPU_DESCRIPTOR_HANDLE, a D3D12_GPU_VIRTUAL_ADDRESS or two root constants
+pub const ROOT_ARGUMENT_SIZE: u32 = 8;
+
+pub const fn align_to(alignment: u32, val: u32) -> u32 {
+    val.div_ceil(alignment) * alignment
+}
+
+#[cfg_attr(not(windows), allow(dead_code))]
+pub enum RootArgument {
+    // A descriptor table starting at the given slot of the SRV/UAV heap
+    DescriptorTable(u32),
+    // A root CBV. The backend uploads the data and binds the address of the buffer
+    ConstantBuffer(Vec<Vec4>),
+    // A root CBV with the frame::FrameConstants, which the backend updates every frame
+    FrameConstants,
+    // A root SRV with one of the buffers of a geometry of scene::Scene::blas
+    GeometryBuffer { blas: usize, geometry: usize, buffer: GeometryBuffer },
+    // Two root constants that say how to read the buffers of a geometry: the bytes per index
+    // (scene::Geometry::index_size()) in the low 32 bits, scene::Geometry::attribute_flags() in the high
+    GeometryConstants { blas: usize, geometry: usize },
+}
+
+// The buffers of a geometry the hit programs read. Geometries without indices get a sequential index buffer, so
+// every triangle is read the same way. The attributes are only bound when the geometry has them
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum GeometryBuffer {
+    // StructuredBuffer<float3>
+    Vertices,
+    // ByteAddressBuffer of 16 or 32-bit indices
+    Indices,
+    // StructuredBuffer<float3>
+    Normals,
+    // StructuredBuffer<float4>
+    Tangents,
+    // StructuredBuffer<float2>
+    TexCoords,
+}
+
+// The root arguments every hit group ends with, in the order of their parameters: the constants, then the buffers
+fn geometry_arguments(blas: usize, geometry: usize) -> Vec<RootArgument> {
+    let buffers = [GeometryBuffer::Vertices, GeometryBuffer::Indices, GeometryBuffer::Normals, GeometryBuffer::Tangents, GeometryBuffer::TexCoords];
+    std::iter::once(RootArgument::GeometryConstants { blas, geometry })
+        .chain(buffers.map(|buffer| RootArgument::GeometryBuffer { blas, geometry, buffer }))
+        .collect()
+}
+
+pub struct ShaderRecord {
+    // A shader or hit-group export name
+    pub export: String,
+    // In the order of the parameters of the local root signature
+    pub root_arguments: Vec<RootArgument>,
+}
+
+impl ShaderRecord {
+    pub fn new(export: &str, root_arguments: Vec<RootArgument>) -> Self {
+        Self { export: export.into(), root_arguments }
+    }
+    // The triangle hit group: the colors blended with the barycentrics, which tint the base color of the material, the
+    // TLAS for the shadow rays and the geometry the normal is interpolated from. Colors are float4 because of the HLSL
+    // packing of the 3 float3
+    pub fn triangle_hit(colors: [Vec4; 3], blas: usize, geometry: usize) -> Self {
+        let mut arguments = vec![RootArgument::ConstantBuffer(colors.to_vec()), RootArgument::DescriptorTable(TLAS_SRV_SLOT)];
+        arguments.extend(geometry_arguments(blas, geometry));
+        Self::new(TRI_HIT_GROUP, arguments)
+    }
+    // The plane hit group, which shades with the material alone: the TLAS for the shadow rays and the geometry
+    pub fn plane_hit(blas: usize, geometry: usize) -> Self {
+        let mut arguments = vec![RootArgument::DescriptorTable(TLAS_SRV_SLOT)];
+        arguments.extend(geometry_arguments(blas, geometry));
+        Self::new(PLANE_HIT_GROUP, arguments)
+    }
+    fn size(&self) -> u32 {
+        SHADER_IDENTIFIER_SIZE_IN_BYTES + self.root_arguments.len() as u32 * ROOT_ARGUMENT_SIZE
+    }
+}
+
+pub struct ShaderTable {
+    pub ray_gen: ShaderRecord,
+    pub miss: Vec<ShaderRecord>,
+    pub hit_groups: Vec<ShaderRecord>,
+    // The index of each instance's first hit record. This is the instance contribution stored in the TLAS
+    pub instance_contributions: Vec<u32>,
+}
+
+impl ShaderTable {
+    pub fn new(ray_gen: ShaderRecord, miss: Vec<ShaderRecord>) -> Self {
+        Self {
+            ray_gen,
+            miss,
+            hit_groups: Vec::new(),
+            instance_contributions: Vec::new(),
+        }
+    }
+
+    // Appends the hit records of the next instance - RAY_TYPE_COUNT records for each of its geometries - and returns its instance contribution
+    pub fn add_instance(&mut self, records: Vec<ShaderRecord>) -> u32 {
+        let contribution = self.hit_groups.len() as u32;
+        self.hit_groups.extend(records);
+        self.instance_contributions.push(contribution);
+        contribution
+    }
+
+    // The ray-gen and miss records. Scenes only differ in their hit records
+    pub fn with_default_programs() -> Self {
+        Self::new(
+            ShaderRecord::new(RAY_GEN_SHADER, vec![RootArgument::DescriptorTable(OUTPUT_UAV_SLOT), RootArgument::FrameConstants]),
+            vec![ShaderRecord::new(MISS_SHADER, vec![]), ShaderRecord::new(SHADOW_MISS, vec![])],
+        )
+    }
+
+    // Checks that every instance of the scene has a hit record for each of its geometries and ray types, and that the
+    // geometries the records refer to exist
+    pub fn validate(&self, scene: &Scene) -> Result<(), String> {
+        if self.instance_contributions.len() != scene.instances.len() {
+            return Err(format!("the shader table has records for {} instances, the scene has {}", self.instance_contributions.len(), scene.instances.len()));
+        }
+        for (i, instance) in scene.instances.iter().enumerate() {
+            let geometries = scene.blas[instance.blas].geometries.len() as u32;
+            let end = instance.instance_contribution + geometries * RAY_TYPE_COUNT;
+            if end as usize > self.hit_groups.len() {
+                return Err(format!("instance {} needs hit records up to {}, but the table only has {}", i, end, self.hit_groups.len()));
+            }
+        }
+        for (i, record) in self.hit_groups.iter().enumerate() {
+            for argument in &record.root_arguments {
+                let (RootArgument::GeometryBuffer { blas, geometry, .. } | RootArgument::GeometryConstants { blas, geometry }) = *argument else { continue };
+                if scene.blas.get(blas).is_none_or(|b| geometry >= b.geometries.len()) {
+                    return Err(format!("hit record {} refers to geometry {} of BLAS {}, which doesn't exist", i, geometry, blas));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// One of the three shader-table sections. All records in a section share the same stride
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct ShaderTableSection {
+    pub offset: u64,
+    pub stride: u32,
+    pub count: u32,
+}
+
+impl ShaderTableSection {
+    pub fn size(&self) -> u64 {
+        self.stride as u64 * self.count as u64
+    }
+
+    fn new(offset: u64, records: &[ShaderRecord]) -> Result<Self, String> {
+        let largest = records.iter().map(|r| r.size()).max().unwrap_or(SHADER_IDENTIFIER_SIZE_IN_BYTES);
+        let stride = align_to(SHADER_RECORD_BYTE_ALIGNMENT, largest);
+        if stride > MAX_SHADER_RECORD_STRIDE {
+            return Err(format!("shader record stride {} exceeds the maximum of {}", stride, MAX_SHADER_RECORD_STRIDE));
+        }
+        Ok(Self { offset, stride, count: records.len() as u32 })
+    }
+}
+
+// The address ranges of D3D12_DISPATCH_RAYS_DESC
+#[cfg(any(windows, test))]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct GpuRange {
+    pub start_address: u64,
+    pub size_in_bytes: u64,
+    pub stride_in_bytes: u64,
+}
+
+#[cfg(any(windows, test))]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct DispatchRanges {
+    pub ray_gen: GpuRange,
+    pub miss: GpuRange,
+    pub hit_group: GpuRange,
+}
+
+// Where each record of a ShaderTable lives in the shader-table buffer. Each section gets a stride large enough
+// for its largest record and starts on a D3D12_RAYTRACING_SHADER_TABLE_BYTE_ALIGNMENT boundary
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct ShaderTableLayout {
+    pub ray_gen: ShaderTableSection,
+    pub miss: ShaderTableSection,
+    pub hit_group: ShaderTableSection,
+    pub size: u64,
+}
+
+impl ShaderTableLayout {
+    pub fn new(table: &ShaderTable) -> Result<Self, String> {
+        if table.miss.is_empty() {
+            return Err("the shader table needs at least one miss record".into());
+        }
+        let align = |offset: u64| offset.div_ceil(SHADER_TABLE_BYTE_ALIGNMENT as u64) * SHADER_TABLE_BYTE_ALIGNMENT as u64;
+
+        let ray_gen = ShaderTableSection::new(0, std::slice::from_ref(&table.ray_gen))?;
+        let miss = ShaderTableSection::new(align(ray_gen.size()), &table.miss)?;
+        let hit_group = ShaderTableSection::new(align(miss.offset + miss.size()), &table.hit_groups)?;
+        let size = hit_group.offset + hit_group.size();
+        Ok(Self { ray_gen, miss, hit_group, size })
+    }
+
+    // Writes the records. `shader_identifier` returns the identifier of an export, `root_argument` the 8 bytes
+    // stored for a root argument. It is called in record order
+    #[cfg(any(windows, test))]
+    pub fn serialize(
+        &self,
+        table: &ShaderTable,
+        mut shader_identifier: impl FnMut(&str) -> [u8; SHADER_IDENTIFIER_SIZE_IN_BYTES as usize],
+        mut root_argument: impl FnMut(&RootArgument) -> u64,
+    ) -> Vec<u8> {
+        let mut data = vec![0u8; self.size as usize];
+        let sections = [
+            (self.ray_gen, std::slice::from_ref(&table.ray_gen)),
+            (self.miss, table.miss.as_slice()),
+            (self.hit_group, table.hit_groups.as_slice()),
+        ];
+        for (section, records) in sections {
+            for (i, record) in records.iter().enumerate() {
+                let offset = (section.offset + i as u64 * section.stride as u64) as usize;
+                let id_end = offset + SHADER_IDENTIFIER_SIZE_IN_BYTES as usize;
+                data[offset..id_end].copy_from_slice(&shader_identifier(&record.export));
+                for (i, argument) in record.root_arguments.iter().enumerate() {
+                    let start = id_end + i * ROOT_ARGUMENT_SIZE as usize;
+                    data[start..start + ROOT_ARGUMENT_SIZE as usize].copy_from_slice(&root_argument(argument).to_le_bytes());
+                }
+            }
+        }
+        data
+    }
+
+    #[cfg(any(windows, test))]
+    pub fn dispatch_ranges(&self, start_address: u64) -> DispatchRanges {
+        let range = |section: ShaderTableSection| GpuRange {
+            start_address: start_address + section.offset,
+            size_in_bytes: section.size(),
+            stride_in_bytes: section.stride as u64,
+        };
+        DispatchRanges {
+            ray_gen: GpuRange { stride_in_bytes: 0, ..range(self.ray_gen) },
+            miss: range(self.miss),
+            hit_group: range(self.hit_group),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The default programs, then two instances: a triangle with its shadow record and a plane with its own
+    fn tutorial_table() -> ShaderTable {
+        let mut table = ShaderTable::with_default_programs();
+        table.add_instance(vec![ShaderRecord::triangle_hit([Vec4::ONE; 3], 0, 0), ShaderRecord::new(SHADOW_HIT_GROUP, vec![])]);
+        table.add_instance(vec![ShaderRecord::plane_hit(1, 0), ShaderRecord::new(SHADOW_HIT_GROUP, vec![])]);
+        table
+    }
+
+    #[test]
+    fn section_strides_are_rounded_to_the_record_alignment() {
+        let layout = ShaderTableLayout::new(&tutorial_table()).unwrap();
+        // The identifier and two root arguments
+        assert_eq!(layout.ray_gen, ShaderTableSection { offset: 0, stride: 64, count: 1 });
+        // Identifiers only
+        assert_eq!(layout.miss, ShaderTableSection { offset: 64, stride: 32, count: 2 });
+        // The triangle record has the identifier and 8 root arguments, 96 bytes, which the other records are padded to
+        assert_eq!(layout.hit_group, ShaderTableSection { offset: 128, stride: 96, count: 4 });
+        assert_eq!(layout.size, 128 + 4 * 96);
+    }
+
+    #[test]
+    fn sections_start_on_the_table_alignment() {
+        let mut table = ShaderTable::new(
+            ShaderRecord::new(RAY_GEN_SHADER, vec![RootArgument::FrameConstants]),
+            vec![ShaderRecord::new(MISS_SHADER, vec![]), ShaderRecord::new(SHADOW_MISS, vec![]), ShaderRecord::new(MISS_SHADER, vec![])],
+        );
+        table.add_instance(vec![ShaderRecord::new(SHADOW_HIT_GROUP, vec![RootArgument::DescriptorTable(TLAS_SRV_SLOT)])]);
+        let layout = ShaderTableLayout::new(&table).unwrap();
+        assert_eq!(layout.ray_gen, ShaderTableSection { offset: 0, stride: 64, count: 1 });
+        assert_eq!(layout.miss, ShaderTableSection { offset: 64, stride: 32, count: 3 });
+        // The miss records end at 160
+        assert_eq!(layout.hit_group, ShaderTableSection { offset: 192, stride: 64, count: 1 });
+        assert_eq!(layout.size, 256);
+    }
+
+    #[test]
+    fn dispatch_ranges() {
+        let layout = ShaderTableLayout::new(&tutorial_table()).unwrap();
+        let ranges = layout.dispatch_ranges(0x10000);
+        assert_eq!(ranges.ray_gen, GpuRange { start_address: 0x10000, size_in_bytes: 64, stride_in_bytes: 0 });
+        assert_eq!(ranges.miss, GpuRange { start_address: 0x10040, size_in_bytes: 64, stride_in_bytes: 32 });
+        assert_eq!(ranges.hit_group, GpuRange { start_address: 0x10080, size_in_bytes: 384, stride_in_bytes: 96 });
+    }
+
+    #[test]
+    fn instance_contributions() {
+        let mut table = tutorial_table();
+        assert_eq!(table.instance_contributions, vec![0, 2]);
+        // An instance with two geometries starts after the records of the previous ones
+        let records = (0..2).flat_map(|g| [ShaderRecord::plane_hit(2, g), ShaderRecord::new(SHADOW_HIT_GROUP, vec![])]).collect();
+        assert_eq!(table.add_instance(records), 4);
+        assert_eq!(table.add_instance(vec![ShaderRecord::plane_hit(1, 0), ShaderRecord::new(SHADOW_HIT_GROUP, vec![])]), 8);
+        assert_eq!(table.instance_contributions, vec![0, 2, 4, 8]);
+    }
+
+    #[test]
+    fn serialized_layout() {
+        let table = tutorial_table();
+        let layout = ShaderTableLayout::new(&table).unwrap();
+        let exports = [RAY_GEN_SHADER, MISS_SHADER, SHADOW_MISS, TRI_HIT_GROUP, PLANE_HIT_GROUP, SHADOW_HIT_GROUP];
+        // Every byte of an identifier is the index of its export
+        let identifier = |export: &str| [exports.iter().position(|e| *e == export).unwrap() as u8 + 1; SHADER_IDENTIFIER_SIZE_IN_BYTES as usize];
+        let argument = |argument: &RootArgument| match argument {
+            RootArgument::DescriptorTable(slot) => 0x1000 + *slot as u64,
+            RootArgument::ConstantBuffer(_) => 0x2000,
+            RootArgument::FrameConstants => 0x3000,
+            RootArgument::GeometryBuffer { blas, geometry, buffer } => 0x4000_0000 | (*blas as u64) << 16 | (*geometry as u64) << 8 | *buffer as u64,
+            RootArgument::GeometryConstants { blas, geometry } => 0x5000_0000 | (*blas as u64) << 16 | (*geometry as u64) << 8,
+        };
+        let data = layout.serialize(&table, identifier, argument);
+        assert_eq!(data.len(), layout.size as usize);
+        let u64_at = |offset: usize| u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap());
+
+        // The ray-gen record: the output table and the frame constants, then padding
+        assert_eq!(&data[0..32], &[1; 32]);
+        assert_eq!(u64_at(32), 0x1000 + OUTPUT_UAV_SLOT as u64);
+        assert_eq!(u64_at(40), 0x3000);
+        assert!(data[48..64].iter().all(|b| *b == 0));
+        // The miss records
+        assert_eq!(&data[64..96], &[2; 32]);
+        assert_eq!(&data[96..128], &[3; 32]);
+        // The triangle: colors, TLAS, geometry constants and the 5 buffers
+        assert_eq!(&data[128..160], &[4; 32]);
+        assert_eq!(u64_at(160), 0x2000);
+        assert_eq!(u64_at(168), 0x1000 + TLAS_SRV_SLOT as u64);
+        assert_eq!(u64_at(176), 0x5000_0000);
+        let buffers = [GeometryBuffer::Vertices, GeometryBuffer::Indices, GeometryBuffer::Normals, GeometryBuffer::Tangents, GeometryBuffer::TexCoords];
+        for (i, buffer) in buffers.into_iter().enumerate() {
+            assert_eq!(u64_at(184 + i * 8), 0x4000_0000 | buffer as u64);
+        }
+        // The shadow record after it
+        assert_eq!(&data[224..256], &[6; 32]);
+        assert!(data[256..320].iter().all(|b| *b == 0));
+        // The plane of BLAS 1: TLAS and geometry constants right after the identifier
+        assert_eq!(&data[320..352], &[5; 32]);
+        assert_eq!(u64_at(352), 0x1000 + TLAS_SRV_SLOT as u64);
+        assert_eq!(u64_at(360), 0x5001_0000);
+        assert_eq!(u64_at(368), 0x4001_0000 | GeometryBuffer::Vertices as u64);
+    }
+
+    #[test]
+    fn record_stride_limit() {
+        let table = |arguments: usize| {
+            let ray_gen = ShaderRecord::new(RAY_GEN_SHADER, (0..arguments).map(|_| RootArgument::FrameConstants).collect());
+            ShaderTable::new(ray_gen, vec![ShaderRecord::new(MISS_SHADER, vec![])])
+        };
+        // 32 + 508 * 8 bytes is exactly the maximum
+        assert_eq!(ShaderTableLayout::new(&table(508)).unwrap().ray_gen.stride, MAX_SHADER_RECORD_STRIDE);
+        assert_eq!(ShaderTableLayout::new(&table(509)).unwrap_err(), "shader record stride 4128 exceeds the maximum of 4096");
+    }
+
+    #[test]
+    fn tables_need_a_miss_record() {
+        let table = ShaderTable::new(ShaderRecord::new(RAY_GEN_SHADER, vec![]), vec![]);
+        assert!(ShaderTableLayout::new(&table).is_err());
+    }
+}
@@ -0,0 +1,564 @@
+// The textures the materials sample: decoded from PNG, JPEG, Radiance HDR or DDS files, or from the images of a glTF
+// file, with a complete mip chain. The GPU gets them in the bindless gTextures table and samples them with a trilinear,
+// wrapping static sampler. Texture::sample() does the same for the CPU tracer
+
+use glam::*;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+use crate::color::srgb_oetf;
+use crate::framebuffer::{float_to_unorm8, srgb_to_linear};
+
+// D3D12_REQ_TEXTURE2D_U_OR_V_DIMENSION
+pub const MAX_TEXTURE_SIZE: u32 = 16384;
+
+// How the texels of an 8-bit texture are stored. Colors are sRGB, data like normals and roughness linear. The GPU
+// reads sRGB textures through an _SRGB format, which converts them to linear
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum ColorSpace {
+    #[default]
+    Srgb,
+    Linear,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TextureFormat {
+    Rgba8Unorm,
+    Rgba8Srgb,
+    // Every float format is widened to this, HDR images are rare enough that the size doesn't matter
+    Rgba32Float,
+}
+
+impl TextureFormat {
+    pub fn bytes_per_texel(&self) -> u32 {
+        match self {
+            TextureFormat::Rgba8Unorm | TextureFormat::Rgba8Srgb => 4,
+            TextureFormat::Rgba32Float => 16,
+        }
+    }
+
+    fn rgba8(color_space: ColorSpace) -> Self {
+        match color_space {
+            ColorSpace::Srgb => TextureFormat::Rgba8Srgb,
+            ColorSpace::Linear => TextureFormat::Rgba8Unorm,
+        }
+    }
+}
+
+// The texels of one mip level in rows, without padding. Rgba32Float texels are in native byte order
+#[derive(Clone, Debug, PartialEq)]
+pub struct MipLevel {
+    pub width: u32,
+    pub height: u32,
+    pub data: Vec<u8>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Texture {
+    pub format: TextureFormat,
+    // Level 0 first, each half the size of the one before and at least 1x1
+    pub mips: Vec<MipLevel>,
+}
+
+impl Texture {
+    // A texture with a single level. `data` has width * height texels of the format
+    pub fn new(format: TextureFormat, width: u32, height: u32, data: Vec<u8>) -> Result<Self, String> {
+        if width == 0 || height == 0 || width > MAX_TEXTURE_SIZE || height > MAX_TEXTURE_SIZE {
+            return Err(format!("{}x{} is not a valid texture size, the maximum is {}", width, height, MAX_TEXTURE_SIZE));
+        }
+        // In usize, 16384x16384 Rgba32Float texels are 4 GiB
+        if data.len() != width as usize * height as usize * format.bytes_per_texel() as usize {
+            return Err(format!("{} bytes for {}x{} {:?} texels", data.len(), width, height, format));
+        }
+        Ok(Self { format, mips: vec![MipLevel { width, height, data }] })
+    }
+
+    pub fn from_rgba8(width: u32, height: u32, data: Vec<u8>, color_space: ColorSpace) -> Result<Self, String> {
+        Self::new(TextureFormat::rgba8(color_space), width, height, data)
+    }
+
+    pub fn from_rgba32f(width: u32, height: u32, texels: &[Vec4]) -> Result<Self, String> {
+        let data = texels.iter().flat_map(|t| t.to_array()).flat_map(f32::to_ne_bytes).collect();
+        Self::new(TextureFormat::Rgba32Float, width, height, data)
+    }
+
+    // Loads an image file, see decode()
+    pub fn load(path: &Path, color_space: ColorSpace) -> Result<Self, String> {
+        let bytes = std::fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        Self::decode(&bytes, color_space).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    // Decodes a DDS file, with its mips if it has them, or a PNG, JPEG or Radiance HDR image. Images without mips get
+    // a generated chain. `color_space` only applies to 8-bit formats that don't say theirs: float images are always
+    // linear, and the DXGI format of a DDS file with a DX10 header decides between sRGB and linear
+    pub fn decode(bytes: &[u8], color_space: ColorSpace) -> Result<Self, String> {
+        let texture = if bytes.starts_with(DDS_MAGIC) {
+            parse_dds(bytes, color_space)?
+        } else {
+            let image = image::load_from_memory(bytes).map_err(|e| e.to_string())?;
+            let (width, height) = (image.width(), image.height());
+            if matches!(image.color(), image::ColorType::Rgb32F | image::ColorType::Rgba32F) {
+                let texels: Vec<Vec4> = image.into_rgba32f().pixels().map(|p| Vec4::from(p.0)).collect();
+                Self::from_rgba32f(width, height, &texels)?
+            } else {
+                Self::from_rgba8(width, height, image.into_rgba8().into_raw(), color_space)?
+            }
+        };
+        Ok(if texture.mips.len() == 1 { texture.with_mips() } else { texture })
+    }
+
+    pub fn width(&self) -> u32 {
+        self.mips[0].width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.mips[0].height
+    }
+
+    // Replaces the levels below the first with a chain down to 1x1, each texel the average of the 2x2 texels above it.
+    // The average is taken in linear space, odd sizes repeat the last row or column
+    pub fn with_mips(mut self) -> Self {
+        self.mips.truncate(1);
+        while self.mips.last().is_some_and(|m| m.width > 1 || m.height > 1) {
+            let above = self.mips.last().unwrap();
+            let (width, height) = ((above.width / 2).max(1), (above.height / 2).max(1));
+            let level = self.mips.len() - 1;
+            let mut texels = Vec::with_capacity((width * height) as usize);
+            for y in 0..height {
+                for x in 0..width {
+                    let texel = |dx: u32, dy: u32| self.texel(level, (2 * x + dx).min(above.width - 1), (2 * y + dy).min(above.height - 1));
+                    texels.push((texel(0, 0) + texel(1, 0) + texel(0, 1) + texel(1, 1)) * 0.25);
+                }
+            }
+            let data = texels.iter().flat_map(|t| self.encode(*t)).collect();
+            self.mips.push(MipLevel { width, height, data });
+        }
+        self
+    }
+
+    // The linear value of a texel, like a load through the texture's format
+    pub fn texel(&self, level: usize, x: u32, y: u32) -> Vec4 {
+        let mip = &self.mips[level];
+        let size = self.format.bytes_per_texel() as usize;
+        let start = (y * mip.width + x) as usize * size;
+        let bytes = &mip.data[start..start + size];
+        match self.format {
+            TextureFormat::Rgba8Unorm => Vec4::from_array([0, 1, 2, 3].map(|c| bytes[c] as f32 / 255.0)),
+            TextureFormat::Rgba8Srgb => vec4(srgb_to_linear(bytes[0]), srgb_to_linear(bytes[1]), srgb_to_linear(bytes[2]), bytes[3] as f32 / 255.0),
+            TextureFormat::Rgba32Float => Vec4::from_array([0, 1, 2, 3].map(|c| f32::from_ne_bytes(bytes[c * 4..c * 4 + 4].try_into().unwrap()))),
+        }
+    }
+
+    fn encode(&self, texel: Vec4) -> Vec<u8> {
+        match self.format {
+            TextureFormat::Rgba8Unorm => texel.to_array().map(float_to_unorm8).to_vec(),
+            TextureFormat::Rgba8Srgb => vec![
+                float_to_unorm8(srgb_oetf(texel.x)),
+                float_to_unorm8(srgb_oetf(texel.y)),
+                float_to_unorm8(srgb_oetf(texel.z)),
+                float_to_unorm8(texel.w),
+            ],
+            TextureFormat::Rgba32Float => texel.to_array().iter().flat_map(|c| c.to_ne_bytes()).collect(),
+        }
+    }
+
+    // SampleLevel() with a trilinear filter that wraps around the edges, like the static sampler of the global root
+    // signature. The level of detail is clamped to the mips there are
+    pub fn sample(&self, uv: Vec2, lod: f32) -> Vec4 {
+        let lod = if lod.is_finite() { lod.clamp(0.0, (self.mips.len() - 1) as f32) } else { 0.0 };
+        let level = lod.floor() as usize;
+        let above = self.bilinear(level, uv);
+        if level + 1 == self.mips.len() {
+            return above;
+        }
+        above.lerp(self.bilinear(level + 1, uv), lod - level as f32)
+    }
+
+    fn bilinear(&self, level: usize, uv: Vec2) -> Vec4 {
+        let mip = &self.mips[level];
+        // Texel centers are at half-integer coordinates
+        let p = uv * vec2(mip.width as f32, mip.height as f32) - 0.5;
+        let (p0, f) = (p.floor(), p - p.floor());
+        let wrap = |v: f32, size: u32| (v as i64).rem_euclid(size as i64) as u32;
+        let (x0, y0) = (wrap(p0.x, mip.width), wrap(p0.y, mip.height));
+        let (x1, y1) = (wrap(p0.x + 1.0, mip.width), wrap(p0.y + 1.0, mip.height));
+        let top = self.texel(level, x0, y0).lerp(self.texel(level, x1, y0), f.x);
+        let bottom = self.texel(level, x0, y1).lerp(self.texel(level, x1, y1), f.x);
+        top.lerp(bottom, f.y)
+    }
+}
+
+// The mip level a texture of `size` texels is sampled at, for a ray cone `cone_width` wide that hits a triangle at
+// `cos` to its normal. `uv_area` and `world_area` are the areas of the triangle in texture and world space (Akenine-Möller
+// et al., "Improved Shader and Texture Level of Detail Using Ray Cones"). Mirrored by textureLod() in res/shaders.hlsl
+pub fn cone_lod(cone_width: f32, cos: f32, uv_area: f32, world_area: f32, size: UVec2) -> f32 {
+    0.5 * (uv_area * size.x as f32 * size.y as f32 / world_area).log2() + (cone_width / cos.abs().max(1e-3)).log2()
+}
+
+const DDS_MAGIC: &[u8] = b"DDS ";
+
+// The fields of DDS_HEADER and DDS_PIXELFORMAT we need, as byte offsets from the start of the file
+const DDS_HEIGHT: usize = 12;
+const DDS_WIDTH: usize = 16;
+const DDS_MIP_MAP_COUNT: usize = 28;
+const DDS_PF_FLAGS: usize = 80;
+const DDS_PF_FOURCC: usize = 84;
+const DDS_PF_RGB_BIT_COUNT: usize = 88;
+const DDS_PF_R_BIT_MASK: usize = 92;
+const DDS_CAPS2: usize = 112;
+const DDS_HEADER_END: usize = 128;
+// DDS_HEADER_DXT10, after the header when the FourCC is DX10
+const DX10_FORMAT: usize = 128;
+const DX10_RESOURCE_DIMENSION: usize = 132;
+const DX10_ARRAY_SIZE: usize = 140;
+const DX10_HEADER_END: usize = 148;
+
+const DDPF_FOURCC: u32 = 0x4;
+const DDPF_RGB: u32 = 0x40;
+// DDSCAPS2_CUBEMAP and DDSCAPS2_VOLUME
+const DDSCAPS2_CUBEMAP_OR_VOLUME: u32 = 0x200 | 0x20_0000;
+const D3D10_RESOURCE_DIMENSION_TEXTURE2D: u32 = 3;
+
+// The texel layouts we read, all 4 channels
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum DdsLayout {
+    Rgba8,
+    Bgra8,
+    Rgba16Float,
+    Rgba32Float,
+}
+
+impl DdsLayout {
+    // The layout and the color space of a DXGI format. The color space only matters for the 8-bit layouts
+    fn from_dxgi_format(format: u32) -> Result<(Self, ColorSpace), String> {
+        match format {
+            // DXGI_FORMAT_R32G32B32A32_FLOAT, DXGI_FORMAT_R16G16B16A16_FLOAT
+            2 => Ok((DdsLayout::Rgba32Float, ColorSpace::Linear)),
+            10 => Ok((DdsLayout::Rgba16Float, ColorSpace::Linear)),
+            // DXGI_FORMAT_R8G8B8A8_UNORM and _UNORM_SRGB
+            28 => Ok((DdsLayout::Rgba8, ColorSpace::Linear)),
+            29 => Ok((DdsLayout::Rgba8, ColorSpace::Srgb)),
+            // DXGI_FORMAT_B8G8R8A8_UNORM and _UNORM_SRGB
+            87 => Ok((DdsLayout::Bgra8, ColorSpace::Linear)),
+            91 => Ok((DdsLayout::Bgra8, ColorSpace::Srgb)),
+            other => Err(format!("DXGI format {} is not supported, only uncompressed RGBA formats are", other)),
+        }
+    }
+
+    fn bytes_per_texel(&self) -> usize {
+        match self {
+            DdsLayout::Rgba8 | DdsLayout::Bgra8 => 4,
+            DdsLayout::Rgba16Float => 8,
+            DdsLayout::Rgba32Float => 16,
+        }
+    }
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+// A 2D DDS texture in one of the DdsLayout formats, with every mip level the file has. Compressed formats, cube maps,
+// volumes and arrays aren't supported. `color_space` is for the legacy headers, which don't say whether 8-bit texels
+// are sRGB
+pub fn parse_dds(bytes: &[u8], color_space: ColorSpace) -> Result<Texture, String> {
+    if bytes.len() < DDS_HEADER_END || !bytes.starts_with(DDS_MAGIC) {
+        return Err("not a DDS file".into());
+    }
+    let (width, height) = (read_u32(bytes, DDS_WIDTH), read_u32(bytes, DDS_HEIGHT));
+    let mip_count = read_u32(bytes, DDS_MIP_MAP_COUNT).max(1);
+    if read_u32(bytes, DDS_CAPS2) & DDSCAPS2_CUBEMAP_OR_VOLUME != 0 {
+        return Err("cube maps and volume textures are not supported".into());
+    }
+    let pf_flags = read_u32(bytes, DDS_PF_FLAGS);
+    let fourcc = read_u32(bytes, DDS_PF_FOURCC);
+    let (layout, color_space, mut offset) = if pf_flags & DDPF_FOURCC != 0 && &fourcc.to_le_bytes() == b"DX10" {
+        if bytes.len() < DX10_HEADER_END {
+            return Err("the DX10 header is cut off".into());
+        }
+        if read_u32(bytes, DX10_RESOURCE_DIMENSION) != D3D10_RESOURCE_DIMENSION_TEXTURE2D || read_u32(bytes, DX10_ARRAY_SIZE) > 1 {
+            return Err("only single 2D textures are supported".into());
+        }
+        let (layout, color_space) = DdsLayout::from_dxgi_format(read_u32(bytes, DX10_FORMAT))?;
+        (layout, color_space, DX10_HEADER_END)
+    } else if pf_flags & DDPF_FOURCC != 0 {
+        // The D3DFORMAT values of the legacy float formats
+        let layout = match fourcc {
+            113 => DdsLayout::Rgba16Float,
+            116 => DdsLayout::Rgba32Float,
+            _ => return Err(format!("FourCC {:?} is not supported, only uncompressed RGBA formats are", String::from_utf8_lossy(&fourcc.to_le_bytes()))),
+        };
+        (layout, color_space, DDS_HEADER_END)
+    } else if pf_flags & DDPF_RGB != 0 && read_u32(bytes, DDS_PF_RGB_BIT_COUNT) == 32 {
+        let layout = match read_u32(bytes, DDS_PF_R_BIT_MASK) {
+            0xff => DdsLayout::Rgba8,
+            0xff_0000 => DdsLayout::Bgra8,
+            mask => return Err(format!("the red mask {:#x} is not supported", mask)),
+        };
+        (layout, color_space, DDS_HEADER_END)
+    } else {
+        return Err("only uncompressed 32-bit RGBA and float formats are supported".into());
+    };
+
+    let mut texture: Option<Texture> = None;
+    let (mut w, mut h) = (width, height);
+    for _ in 0..mip_count {
+        let size = w as usize * h as usize * layout.bytes_per_texel();
+        let level = bytes.get(offset..offset + size).ok_or("the texel data is cut off")?;
+        offset += size;
+        let mip = match layout {
+            DdsLayout::Rgba8 => Texture::from_rgba8(w, h, level.to_vec(), color_space)?,
+            DdsLayout::Bgra8 => Texture::from_rgba8(w, h, level.chunks_exact(4).flat_map(|t| [t[2], t[1], t[0], t[3]]).collect(), color_space)?,
+            DdsLayout::Rgba16Float => {
+                let half = |t: &[u8], c: usize| f16_to_f32(u16::from_le_bytes([t[c * 2], t[c * 2 + 1]]));
+                let texels: Vec<Vec4> = level.chunks_exact(8).map(|t| vec4(half(t, 0), half(t, 1), half(t, 2), half(t, 3))).collect();
+                Texture::from_rgba32f(w, h, &texels)?
+            }
+            DdsLayout::Rgba32Float => {
+                let float = |t: &[u8], c: usize| f32::from_le_bytes(t[c * 4..c * 4 + 4].try_into().unwrap());
+                let texels: Vec<Vec4> = level.chunks_exact(16).map(|t| vec4(float(t, 0), float(t, 1), float(t, 2), float(t, 3))).collect();
+                Texture::from_rgba32f(w, h, &texels)?
+            }
+        };
+        match &mut texture {
+            Some(texture) => texture.mips.extend(mip.mips),
+            None => texture = Some(mip),
+        }
+        if w == 1 && h == 1 {
+            break;
+        }
+        (w, h) = ((w / 2).max(1), (h / 2).max(1));
+    }
+    Ok(texture.unwrap())
+}
+
+// IEEE 754 half to single precision
+pub fn f16_to_f32(h: u16) -> f32 {
+    let sign = if h & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = ((h >> 10) & 0x1f) as i32;
+    let mantissa = (h & 0x3ff) as f32;
+    sign * match exponent {
+        0 => mantissa * 2f32.powi(-24),
+        31 if mantissa == 0.0 => f32::INFINITY,
+        31 => f32::NAN,
+        e => (1.0 + mantissa / 1024.0) * 2f32.powi(e - 15),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sizes(texture: &Texture) -> Vec<(u32, u32)> {
+        texture.mips.iter().map(|m| (m.width, m.height)).collect()
+    }
+
+    #[test]
+    fn mip_chains_of_odd_and_non_square_sizes() {
+        let chain = |width: u32, height: u32| sizes(&Texture::from_rgba8(width, height, vec![0; (width * height * 4) as usize], ColorSpace::Linear).unwrap().with_mips());
+        assert_eq!(chain(5, 3), [(5, 3), (2, 1), (1, 1)]);
+        assert_eq!(chain(1, 6), [(1, 6), (1, 3), (1, 1)]);
+        assert_eq!(chain(8, 8), [(8, 8), (4, 4), (2, 2), (1, 1)]);
+        assert_eq!(chain(1, 1), [(1, 1)]);
+    }
+
+    #[test]
+    fn data_size_of_the_largest_textures() {
+        // The size in bytes is 2^32, which wraps to an empty texture in u32
+        let error = Texture::new(TextureFormat::Rgba32Float, MAX_TEXTURE_SIZE, MAX_TEXTURE_SIZE, Vec::new()).err();
+        assert_eq!(error.as_deref(), Some("0 bytes for 16384x16384 Rgba32Float texels"));
+        assert!(Texture::new(TextureFormat::Rgba8Unorm, MAX_TEXTURE_SIZE + 1, 1, vec![0; 4 * (MAX_TEXTURE_SIZE as usize + 1)]).is_err());
+    }
+
+    #[test]
+    fn mips_average_the_texels_above() {
+        // Odd sizes repeat the last column: the first texel of 3x1 -> 1x1 averages columns 0 and 1
+        let texels = [Vec4::splat(1.0), Vec4::splat(3.0), Vec4::splat(100.0)];
+        let texture = Texture::from_rgba32f(3, 1, &texels).unwrap().with_mips();
+        assert_eq!(texture.texel(1, 0, 0), Vec4::splat(2.0));
+    }
+
+    #[test]
+    fn srgb_mips_average_in_linear_space() {
+        // Black and white average to linear 0.5, which sRGB encodes as 188 rather than 128
+        let texture = Texture::from_rgba8(2, 1, vec![0, 0, 0, 0, 255, 255, 255, 255], ColorSpace::Srgb).unwrap().with_mips();
+        assert_eq!(texture.mips[1].data, [188, 188, 188, 128]);
+        assert!((texture.texel(1, 0, 0).x - 0.5).abs() < 0.01);
+        // The same bytes as linear data
+        let linear = Texture::from_rgba8(2, 1, vec![0, 0, 0, 0, 255, 255, 255, 255], ColorSpace::Linear).unwrap().with_mips();
+        assert_eq!(linear.mips[1].data, [128; 4]);
+    }
+
+    #[test]
+    fn sample_wraps() {
+        let texture = Texture::from_rgba32f(2, 1, &[Vec4::ZERO, Vec4::ONE]).unwrap();
+        // The centers of the texels
+        assert_eq!(texture.sample(vec2(0.25, 0.5), 0.0), Vec4::ZERO);
+        assert_eq!(texture.sample(vec2(0.75, 0.5), 0.0), Vec4::ONE);
+        // The left edge is halfway between the first texel and the last one, wrapped around
+        assert_eq!(texture.sample(vec2(0.0, 0.5), 0.0), Vec4::splat(0.5));
+        for uv in [vec2(0.1, 0.3), vec2(0.6, 0.9)] {
+            for offset in [vec2(1.0, 0.0), vec2(-2.0, 3.0)] {
+                assert!(texture.sample(uv + offset, 0.0).abs_diff_eq(texture.sample(uv, 0.0), 1e-5), "{} + {}", uv, offset);
+            }
+        }
+    }
+
+    #[test]
+    fn sample_clamps_the_lod() {
+        let texture = Texture::from_rgba32f(2, 1, &[Vec4::ZERO, Vec4::ONE]).unwrap().with_mips();
+        assert_eq!(texture.sample(vec2(0.25, 0.5), 1.0), Vec4::splat(0.5));
+        assert_eq!(texture.sample(vec2(0.25, 0.5), 10.0), Vec4::splat(0.5));
+        assert_eq!(texture.sample(vec2(0.25, 0.5), 0.5), Vec4::splat(0.25));
+        assert_eq!(texture.sample(vec2(0.25, 0.5), -3.0), Vec4::ZERO);
+        assert_eq!(texture.sample(vec2(0.25, 0.5), f32::NAN), Vec4::ZERO);
+    }
+
+    // A DDS header for a width x height texture with `mips` levels. `pixel_format` writes the DDS_PIXELFORMAT
+    fn dds_header(width: u32, height: u32, mips: u32, pixel_format: impl FnOnce(&mut [u8])) -> Vec<u8> {
+        let mut header = vec![0u8; DDS_HEADER_END];
+        header[..4].copy_from_slice(DDS_MAGIC);
+        let mut write = |offset: usize, value: u32| header[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+        write(4, 124);
+        write(DDS_HEIGHT, height);
+        write(DDS_WIDTH, width);
+        write(DDS_MIP_MAP_COUNT, mips);
+        write(76, 32);
+        pixel_format(&mut header);
+        header
+    }
+
+    fn write_u32(bytes: &mut [u8], offset: usize, value: u32) {
+        bytes[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+    }
+
+    // A legacy header with 32-bit RGB masks, red in the lowest byte or in the third
+    fn legacy_dds(width: u32, height: u32, mips: u32, red_mask: u32) -> Vec<u8> {
+        dds_header(width, height, mips, |header| {
+            // DDPF_RGB | DDPF_ALPHAPIXELS
+            write_u32(header, DDS_PF_FLAGS, DDPF_RGB | 0x1);
+            write_u32(header, DDS_PF_RGB_BIT_COUNT, 32);
+            write_u32(header, DDS_PF_R_BIT_MASK, red_mask);
+        })
+    }
+
+    fn dx10_dds(width: u32, height: u32, mips: u32, dxgi_format: u32) -> Vec<u8> {
+        let mut bytes = dds_header(width, height, mips, |header| {
+            write_u32(header, DDS_PF_FLAGS, DDPF_FOURCC);
+            header[DDS_PF_FOURCC..DDS_PF_FOURCC + 4].copy_from_slice(b"DX10");
+        });
+        bytes.resize(DX10_HEADER_END, 0);
+        write_u32(&mut bytes, DX10_FORMAT, dxgi_format);
+        write_u32(&mut bytes, DX10_RESOURCE_DIMENSION, D3D10_RESOURCE_DIMENSION_TEXTURE2D);
+        write_u32(&mut bytes, DX10_ARRAY_SIZE, 1);
+        bytes
+    }
+
+    #[test]
+    fn dds_legacy_rgb_masks() {
+        let mut rgba = legacy_dds(2, 1, 1, 0xff);
+        rgba.extend([10, 20, 30, 40, 50, 60, 70, 80]);
+        let texture = parse_dds(&rgba, ColorSpace::Linear).unwrap();
+        assert_eq!(texture.format, TextureFormat::Rgba8Unorm);
+        assert_eq!(texture.mips, [MipLevel { width: 2, height: 1, data: vec![10, 20, 30, 40, 50, 60, 70, 80] }]);
+
+        // BGRA is swizzled, and the caller's color space applies
+        let mut bgra = legacy_dds(2, 1, 1, 0xff_0000);
+        bgra.extend([10, 20, 30, 40, 50, 60, 70, 80]);
+        let texture = parse_dds(&bgra, ColorSpace::Srgb).unwrap();
+        assert_eq!(texture.format, TextureFormat::Rgba8Srgb);
+        assert_eq!(texture.mips[0].data, [30, 20, 10, 40, 70, 60, 50, 80]);
+
+        let mut other = legacy_dds(1, 1, 1, 0xf800);
+        other.extend([0; 4]);
+        assert!(parse_dds(&other, ColorSpace::Linear).is_err());
+    }
+
+    #[test]
+    fn dds_dx10_formats_and_mips() {
+        // 2x2 and 1x1 levels of DXGI_FORMAT_R32G32B32A32_FLOAT
+        let mut bytes = dx10_dds(2, 2, 2, 2);
+        for value in 0..5 {
+            bytes.extend(Vec4::splat(value as f32).to_array().iter().flat_map(|c| c.to_le_bytes()));
+        }
+        let texture = parse_dds(&bytes, ColorSpace::Srgb).unwrap();
+        assert_eq!(texture.format, TextureFormat::Rgba32Float);
+        assert_eq!(sizes(&texture), [(2, 2), (1, 1)]);
+        assert_eq!(texture.texel(0, 1, 1), Vec4::splat(3.0));
+        assert_eq!(texture.texel(1, 0, 0), Vec4::splat(4.0));
+
+        // DXGI_FORMAT_R16G16B16A16_FLOAT: 1, -2, 0.5, 65504
+        let mut bytes = dx10_dds(1, 1, 1, 10);
+        bytes.extend([0x3c00u16, 0xc000, 0x3800, 0x7bff].iter().flat_map(|h| h.to_le_bytes()));
+        assert_eq!(parse_dds(&bytes, ColorSpace::Srgb).unwrap().texel(0, 0, 0), vec4(1.0, -2.0, 0.5, 65504.0));
+    }
+
+    #[test]
+    fn dds_dx10_color_space_overrides_the_callers() {
+        for (dxgi_format, format) in [(28, TextureFormat::Rgba8Unorm), (29, TextureFormat::Rgba8Srgb), (87, TextureFormat::Rgba8Unorm), (91, TextureFormat::Rgba8Srgb)] {
+            let mut bytes = dx10_dds(1, 1, 1, dxgi_format);
+            bytes.extend([1, 2, 3, 4]);
+            for color_space in [ColorSpace::Srgb, ColorSpace::Linear] {
+                assert_eq!(parse_dds(&bytes, color_space).unwrap().format, format, "DXGI format {} as {:?}", dxgi_format, color_space);
+            }
+        }
+        // BC1 is compressed
+        let mut bytes = dx10_dds(4, 4, 1, 71);
+        bytes.extend([0; 8]);
+        assert!(parse_dds(&bytes, ColorSpace::Srgb).is_err());
+    }
+
+    #[test]
+    fn dds_truncated_files() {
+        let mut file = legacy_dds(2, 2, 2, 0xff);
+        file.extend([0; 16 + 4]);
+        assert!(parse_dds(&file, ColorSpace::Linear).is_ok());
+        // The header, the DX10 header, the first level and the second level cut short
+        assert!(parse_dds(&file[..DDS_HEADER_END - 1], ColorSpace::Linear).is_err());
+        assert!(parse_dds(&dx10_dds(1, 1, 1, 28)[..DX10_HEADER_END - 4], ColorSpace::Linear).is_err());
+        assert!(parse_dds(&file[..DDS_HEADER_END + 15], ColorSpace::Linear).is_err());
+        assert!(parse_dds(&file[..file.len() - 1], ColorSpace::Linear).is_err());
+        assert!(Texture::decode(&file[..DDS_HEADER_END + 15], ColorSpace::Linear).is_err());
+    }
+
+    #[test]
+    fn dds_rejects_cube_maps_and_arrays() {
+        let mut cube = legacy_dds(1, 1, 1, 0xff);
+        write_u32(&mut cube, DDS_CAPS2, 0x200);
+        cube.extend([0; 24]);
+        assert!(parse_dds(&cube, ColorSpace::Linear).is_err());
+        let mut array = dx10_dds(1, 1, 1, 28);
+        write_u32(&mut array, DX10_ARRAY_SIZE, 2);
+        array.extend([0; 8]);
+        assert!(parse_dds(&array, ColorSpace::Linear).is_err());
+    }
+
+    #[test]
+    fn half_floats() {
+        assert_eq!(f16_to_f32(0x3c00), 1.0);
+        assert_eq!(f16_to_f32(0xc000), -2.0);
+        assert_eq!(f16_to_f32(0x7bff), 65504.0);
+        // The smallest and the largest denormal
+        assert_eq!(f16_to_f32(0x0001), 2f32.powi(-24));
+        assert_eq!(f16_to_f32(0x03ff), 1023.0 * 2f32.powi(-24));
+        assert_eq!(f16_to_f32(0x8001), -(2f32.powi(-24)));
+        // Signed zeros
+        assert_eq!(f16_to_f32(0x0000).to_bits(), 0.0f32.to_bits());
+        assert_eq!(f16_to_f32(0x8000).to_bits(), (-0.0f32).to_bits());
+        assert_eq!(f16_to_f32(0x7c00), f32::INFINITY);
+        assert_eq!(f16_to_f32(0xfc00), f32::NEG_INFINITY);
+        assert!(f16_to_f32(0x7e00).is_nan());
+        assert!(f16_to_f32(0xfc01).is_nan());
+    }
+
+    #[test]
+    fn decodes_png_with_generated_mips() {
+        let mut png = Vec::new();
+        let image = image::RgbaImage::from_raw(4, 2, (0..32).collect()).unwrap();
+        image.write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png).unwrap();
+        let texture = Texture::decode(&png, ColorSpace::Srgb).unwrap();
+        assert_eq!(texture.format, TextureFormat::Rgba8Srgb);
+        assert_eq!(sizes(&texture), [(4, 2), (2, 1), (1, 1)]);
+        assert_eq!(texture.mips[0].data, (0..32).collect::<Vec<u8>>());
+    }
+}
@@ -0,0 +1,298 @@
+use std::path::Path;
+
+use crate::backend::Backend;
+use crate::bvh::TraversalStats;
+#[cfg(windows)]
+use crate::camera::InputEvent;
+use crate::camera::{Camera, CameraController};
+use crate::color::ColorSettings;
+use crate::dxil_cache;
+use crate::error::{Error, Result};
+use crate::file_watcher::FileWatcher;
+use crate::frame::FrameConstants;
+use crate::framebuffer::{HdrImage, Image};
+use crate::integrator::IntegratorSettings;
+use crate::pipeline::PipelineDesc;
+use crate::profiler::Profiler;
+use crate::sampler::SamplerKind;
+use crate::scene::Scene;
+use crate::shader_table::ShaderTable;
+
+#[cfg(windows)]
+pub struct FrameStats {
+    start_time: std::time::Instant,
+    elapsed_time: f32,
+    frame_count: u32,
+}
+
+#[cfg(windows)]
+impl FrameStats {
+    pub fn new() -> Self {
+        Self {
+            start_time: std::time::Instant::now(),
+            elapsed_time: 0f32,
+            frame_count: 0,
+        }
+    }
+    pub fn frame_stats(&mut self) -> Option<f32> {
+        self.frame_count += 1;
+        let now = self.start_time.elapsed().as_secs_f32();
+        let diff = now - self.elapsed_time;
+        if diff > 1f32 {
+            let fps = self.frame_count as f32 / diff;
+            self.frame_count = 0;
+            self.elapsed_time = now;
+            Some(fps)
+        } else {
+            None
+        }
+    }
+}
+
+// The number of frames the pass statistics and the profile exports cover
+const PROFILER_WINDOW: usize = 300;
+
+// Progressive accumulation. Every frame traces `samples_per_frame` jittered subsamples per pixel and averages them
+// with the previous frames, until `max_samples` are accumulated. Moving the camera or the instances, resizing or
+// reloading the shaders starts over. `sampler` draws the random numbers of the subsamples
+#[derive(Clone, Copy, Debug)]
+pub struct Sampling {
+    pub samples_per_frame: u32,
+    pub max_samples: u32,
+    pub sampler: SamplerKind,
+}
+
+impl Default for Sampling {
+    fn default() -> Self {
+        Self { samples_per_frame: 1, max_samples: 1024, sampler: SamplerKind::Pcg }
+    }
+}
+
+// The scene logic of the tutorial. Everything API specific lives behind the Backend
+pub struct Tutorial {
+    backend: Box<dyn Backend>,
+    scene: Scene,
+    pipeline: PipelineDesc,
+    shader_table: ShaderTable,
+    // Set when the pipeline library is reloaded on changes
+    shader_watcher: Option<FileWatcher>,
+    camera: Camera,
+    controller: CameraController,
+    frame: u32,
+    // Off to keep the instances still, e.g. so a headless render converges
+    animate: bool,
+    sampling: Sampling,
+    // Applied to the accumulated color when it is written to the output, so changing it doesn't start over
+    color: ColorSettings,
+    integrator: IntegratorSettings,
+    // The samples in the accumulation buffer, and the view they were traced from
+    accumulated_samples: u32,
+    accumulated_view: Option<(Camera, (u32, u32))>,
+    // Every sample traced so far, seeds the jitter and lens samples so they don't repeat between frames
+    sample_count: u32,
+    #[cfg(windows)]
+    frame_stats: FrameStats,
+    profiler: Profiler,
+    last_frame_time: std::time::Instant,
+}
+
+impl Tutorial {
+    // `max_depth` is the number of reflection and refraction rays a path may trace
+    pub fn on_load(mut backend: Box<dyn Backend>, mut scene: Scene, shader_table: ShaderTable, max_depth: u32) -> Result<Self> {
+        for (instance, contribution) in scene.instances.iter_mut().zip(&shader_table.instance_contributions) {
+            instance.instance_contribution = *contribution;
+        }
+        shader_table.validate(&scene).map_err(Error::InvalidScene)?;
+
+        let pipeline = PipelineDesc::tutorial().with_materials(&scene.materials, max_depth);
+        backend.build_acceleration_structures(&scene)?;
+        backend.create_pipeline(&pipeline)?;
+        backend.bind_shader_table(&shader_table)?;
+        Ok(Self {
+            backend,
+            camera: scene.camera,
+            scene,
+            pipeline,
+            shader_table,
+            shader_watcher: None,
+            controller: CameraController::default(),
+            frame: 0,
+            animate: true,
+            sampling: Sampling::default(),
+            color: ColorSettings::default(),
+            integrator: IntegratorSettings::default(),
+            accumulated_samples: 0,
+            accumulated_view: None,
+            sample_count: 0,
+            #[cfg(windows)]
+            frame_stats: FrameStats::new(),
+            profiler: Profiler::new(PROFILER_WINDOW),
+            last_frame_time: std::time::Instant::now(),
+        })
+    }
+    // Reloads the pipeline whenever its HLSL library or one of the files it includes changes. Until the library can
+    // be read, it is the only file watched
+    pub fn watch_shaders(&mut self) {
+        let library = Path::new(&self.pipeline.library);
+        let files = dxil_cache::source_files(library, &self.pipeline.compile_options).unwrap_or_else(|_| vec![library.to_path_buf()]);
+        self.shader_watcher = Some(FileWatcher::new(files));
+    }
+    pub fn set_animation(&mut self, animate: bool) {
+        self.animate = animate;
+    }
+    pub fn set_sampling(&mut self, sampling: Sampling) {
+        self.sampling = sampling;
+        self.accumulated_samples = 0;
+    }
+    pub fn set_color(&mut self, color: ColorSettings) {
+        self.color = color;
+    }
+    // The samples of one integrator don't average with the other's, so this starts over
+    pub fn set_integrator(&mut self, integrator: IntegratorSettings) {
+        if integrator != self.integrator {
+            self.accumulated_samples = 0;
+        }
+        self.integrator = integrator;
+    }
+    // A failed reload isn't fatal, the previous pipeline keeps rendering until the next change
+    fn reload_changed_shaders(&mut self) {
+        let Some(watcher) = &mut self.shader_watcher else { return };
+        let Some(changed) = watcher.changed().map(Path::to_path_buf) else { return };
+        // The change may have added or removed includes
+        self.watch_shaders();
+        match self.backend.reload_pipeline(&self.pipeline, &self.shader_table) {
+            Ok(()) => {
+                eprintln!("Reloaded {} after {} changed", self.pipeline.library, changed.display());
+                self.accumulated_samples = 0;
+            }
+            Err(e) => eprintln!("{}\nKeeping the previous pipeline", e),
+        }
+    }
+    #[cfg(windows)]
+    pub fn on_input(&mut self, event: InputEvent) {
+        self.controller.handle(&mut self.camera, event);
+    }
+    pub fn on_frame_render(&mut self) -> Result<()> {
+        self.reload_changed_shaders();
+
+        // Move the camera with the held keys
+        let now = std::time::Instant::now();
+        self.controller.update(&mut self.camera, (now - self.last_frame_time).as_secs_f32());
+        self.last_frame_time = now;
+        let size = self.backend.output_size();
+
+        // The accumulated samples are only valid for the view and the instance transforms they were traced with
+        let moving = self.animate && self.scene.instances.iter().any(|instance| instance.spin != 0.0);
+        if moving || self.accumulated_view != Some((self.camera, size)) {
+            self.accumulated_samples = 0;
+            self.accumulated_view = Some((self.camera, size));
+        }
+
+        // Upload the frame constants. Once max_samples are accumulated the frames only rewrite the output
+        let samples = self.sampling.samples_per_frame.min(self.sampling.max_samples.saturating_sub(self.accumulated_samples));
+        let constants = FrameConstants {
+            sample_index: self.sample_count,
+            accumulated_samples: self.accumulated_samples,
+            samples_per_frame: samples,
+            sampler: self.sampling.sampler as u32,
+            exposure: self.color.exposure_scale(),
+            tone_mapper: self.color.tone_mapper as u32,
+            encoding: self.color.encoding as u32,
+            integrator: self.integrator.integrator as u32,
+            max_bounces: self.integrator.max_bounces,
+            ..self.camera.constants(size.0 as f32 / size.1 as f32)
+        };
+        self.backend.update_frame_constants(&constants);
+
+        // Refit the top-level acceleration structure
+        if self.animate {
+            self.scene.animate(self.frame);
+        }
+        self.backend.refit_tlas(&self.scene.instances)?;
+        self.frame += 1;
+
+        // Let's raytrace
+        self.backend.dispatch_rays()?;
+        self.accumulated_samples += samples;
+        self.sample_count = self.sample_count.wrapping_add(samples);
+        for timings in self.backend.take_frame_timings() {
+            self.profiler.record(timings);
+        }
+        Ok(())
+    }
+    // The camera picks up the new aspect ratio with the next frame
+    #[cfg(windows)]
+    pub fn on_resize(&mut self, width: u32, height: u32) -> Result<()> {
+        self.backend.resize(width, height)
+    }
+    #[cfg(windows)]
+    pub fn frame_stats(&mut self) -> Option<f32> {
+        self.frame_stats.frame_stats()
+    }
+    pub fn profiler(&self) -> &Profiler {
+        &self.profiler
+    }
+    pub fn readback(&mut self) -> Result<Image> {
+        self.backend.readback()
+    }
+    pub fn readback_hdr(&mut self) -> Result<HdrImage> {
+        self.backend.readback_hdr()
+    }
+    pub fn traversal_stats(&self) -> Option<TraversalStats> {
+        self.backend.traversal_stats()
+    }
+    pub fn on_shutdown(&mut self) -> Result<()> {
+        self.backend.shutdown()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::cpu::CpuBackend;
+    use crate::color::srgb_oetf;
+    use crate::framebuffer::float_to_unorm8;
+    use crate::pipeline::DEFAULT_MAX_DEPTH;
+    use crate::scene_file::SceneFile;
+
+    // What `--headless --backend cpu --no-animation` renders: res/golden/tutorial.png is this at the default 640x360.
+    // Regenerate it with those options and `--output res/golden/tutorial.png` when the output is meant to change
+    fn render_tutorial(width: u32, height: u32) -> Image {
+        let file = SceneFile::load(Path::new("res/tutorial.ron")).unwrap();
+        let (scene, shader_table) = file.scene(file.load_textures(Path::new("res")).unwrap());
+        let mut tutorial = Tutorial::on_load(Box::new(CpuBackend::new(width, height)), scene, shader_table, DEFAULT_MAX_DEPTH).unwrap();
+        tutorial.set_animation(false);
+        tutorial.on_frame_render().unwrap();
+        let image = tutorial.readback().unwrap();
+        tutorial.on_shutdown().unwrap();
+        image
+    }
+
+    #[test]
+    fn tutorial_pixels() {
+        let image = render_tutorial(64, 36);
+        let gray = |v: u8| [v, v, v, 255];
+
+        // The miss color, sRGB encoded
+        let sky = [0.4, 0.6, 0.2].map(|c| float_to_unorm8(srgb_oetf(c)));
+        assert_eq!(image.pixel(32, 2), [sky[0], sky[1], sky[2], 255]);
+
+        // The center triangle's vertex colors all have full red, green and blue follow the barycentrics
+        let corners = [image.pixel(32, 14), image.pixel(30, 19), image.pixel(35, 19)];
+        assert_eq!(corners, [[212, 109, 102, 255], [212, 122, 156, 255], [212, 174, 96, 255]]);
+
+        // The plane, lit and in the shadow of the triangles
+        assert_eq!(image.pixel(32, 34), gray(193));
+        assert_eq!(image.pixel(5, 30), gray(193));
+        assert_eq!(image.pixel(14, 24), gray(80));
+        assert_eq!(image.pixel(26, 24), gray(80));
+    }
+
+    #[test]
+    fn tutorial_matches_the_golden_image() {
+        let golden = Image::load_png(Path::new("res/golden/tutorial.png")).unwrap();
+        let image = render_tutorial(golden.width, golden.height);
+        let (max_diff, pixels) = image.diff(&golden).unwrap();
+        assert!(max_diff <= crate::COMPARE_TOLERANCE, "{} pixels differ by up to {}", pixels, max_diff);
+    }
+}
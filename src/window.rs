@@ -0,0 +1,295 @@
+use windows::{
+    core::*, Win32::Foundation::*, Win32::Graphics::Gdi::*, Win32::System::LibraryLoader::*,
+    Win32::UI::Input::KeyboardAndMouse::*, Win32::UI::WindowsAndMessaging::*,
+};
+
+use std::cell::RefCell;
+
+use crate::camera::{InputEvent, Key, MouseButton};
+use crate::error::{Error, Result};
+use crate::tutorial::Tutorial;
+
+// wndproc() can't reach the tutorial, so it queues the camera input for msg_loop()
+#[derive(Default)]
+struct InputState {
+    events: Vec<InputEvent>,
+    // The button being dragged and the last cursor position
+    drag: Option<(MouseButton, i32, i32)>,
+}
+
+// The size changes wndproc() saw, applied by msg_loop() before the next frame
+#[derive(Default)]
+struct WindowState {
+    // The client size of the last WM_SIZE
+    resize: Option<(u32, u32)>,
+    // Nothing is rendered while the window is minimized or has no client area
+    minimized: bool,
+    // Where the window was before going fullscreen, None while windowed
+    windowed_placement: Option<WINDOWPLACEMENT>,
+}
+
+thread_local! {
+    static INPUT: RefCell<InputState> = RefCell::new(InputState::default());
+    static WINDOW: RefCell<WindowState> = RefCell::new(WindowState::default());
+}
+
+fn push_input(event: InputEvent) {
+    INPUT.with(|input| input.borrow_mut().events.push(event));
+}
+
+fn camera_key(wparam: WPARAM) -> Option<Key> {
+    match VIRTUAL_KEY(wparam.0 as u16) {
+        VK_W => Some(Key::Forward),
+        VK_S => Some(Key::Back),
+        VK_A => Some(Key::Left),
+        VK_D => Some(Key::Right),
+        VK_E => Some(Key::Up),
+        VK_Q => Some(Key::Down),
+        VK_SHIFT => Some(Key::Fast),
+        _ => None,
+    }
+}
+
+// The client coordinates of a mouse message
+fn cursor_pos(lparam: LPARAM) -> (i32, i32) {
+    ((lparam.0 & 0xFFFF) as i16 as i32, ((lparam.0 >> 16) & 0xFFFF) as i16 as i32)
+}
+
+unsafe fn begin_drag(window: HWND, button: MouseButton, lparam: LPARAM) {
+    let (x, y) = cursor_pos(lparam);
+    INPUT.with(|input| input.borrow_mut().drag = Some((button, x, y)));
+    SetCapture(window);
+}
+
+unsafe fn end_drag(button: MouseButton) {
+    let dragging = INPUT.with(|input| {
+        let mut input = input.borrow_mut();
+        let dragging = matches!(input.drag, Some((b, _, _)) if b == button);
+        if dragging {
+            input.drag = None;
+        }
+        dragging
+    });
+    if dragging {
+        ReleaseCapture();
+    }
+}
+
+// Switches between the window and a borderless window covering its monitor. The swap chain stays windowed, so going
+// fullscreen is just another resize
+unsafe fn toggle_fullscreen(window: HWND) {
+    match WINDOW.with(|state| state.borrow_mut().windowed_placement.take()) {
+        Some(placement) => {
+            SetWindowLongPtrW(window, GWL_STYLE, WS_OVERLAPPEDWINDOW.0 as isize);
+            SetWindowPlacement(window, &placement);
+            SetWindowPos(window, None, 0, 0, 0, 0, SWP_FRAMECHANGED | SWP_NOMOVE | SWP_NOSIZE | SWP_NOZORDER | SWP_NOOWNERZORDER);
+        }
+        None => {
+            let mut placement = WINDOWPLACEMENT { length: std::mem::size_of::<WINDOWPLACEMENT>() as u32, ..Default::default() };
+            let mut monitor_info = MONITORINFO { cbSize: std::mem::size_of::<MONITORINFO>() as u32, ..Default::default() };
+            if !GetWindowPlacement(window, &mut placement).as_bool()
+                || !GetMonitorInfoW(MonitorFromWindow(window, MONITOR_DEFAULTTONEAREST), &mut monitor_info).as_bool()
+            {
+                return;
+            }
+            WINDOW.with(|state| state.borrow_mut().windowed_placement = Some(placement));
+            let r = monitor_info.rcMonitor;
+            SetWindowLongPtrW(window, GWL_STYLE, (WS_POPUP | WS_VISIBLE).0 as isize);
+            SetWindowPos(window, HWND_TOP, r.left, r.top, r.right - r.left, r.bottom - r.top, SWP_FRAMECHANGED | SWP_NOOWNERZORDER);
+        }
+    }
+}
+
+pub unsafe fn msg_box(msg: &str) {
+    let msg: HSTRING = msg.into();
+    MessageBoxW(None, &msg, w!("Error"), MB_OK);
+}
+
+extern "system" fn wndproc(
+    window: HWND,
+    message: u32,
+    wparam: WPARAM,
+    lparam: LPARAM,
+) -> LRESULT {
+    unsafe {
+        match message {
+            WM_CLOSE => {
+                DestroyWindow(window);
+                LRESULT::default()
+            }
+            WM_DESTROY => {
+                PostQuitMessage(0);
+                LRESULT::default()
+            }
+            WM_SIZE => {
+                let (width, height) = ((lparam.0 & 0xFFFF) as u32, ((lparam.0 >> 16) & 0xFFFF) as u32);
+                WINDOW.with(|state| {
+                    let mut state = state.borrow_mut();
+                    state.minimized = wparam.0 == SIZE_MINIMIZED as usize || width == 0 || height == 0;
+                    if !state.minimized {
+                        state.resize = Some((width, height));
+                    }
+                });
+                LRESULT::default()
+            }
+            // Alt-enter. Everything else, alt-F4 included, goes to DefWindowProc()
+            WM_SYSKEYDOWN if wparam.0 == VK_RETURN.0 as usize && lparam.0 & (1 << 29) != 0 => {
+                toggle_fullscreen(window);
+                LRESULT::default()
+            }
+            WM_KEYDOWN => {
+                if wparam.0 == VK_ESCAPE.0 as usize {
+                    PostQuitMessage(0);
+                } else if wparam.0 == VK_F11.0 as usize {
+                    toggle_fullscreen(window);
+                } else if let Some(key) = camera_key(wparam) {
+                    push_input(InputEvent::KeyDown(key));
+                }
+                LRESULT::default()
+            }
+            WM_KEYUP => {
+                if let Some(key) = camera_key(wparam) {
+                    push_input(InputEvent::KeyUp(key));
+                }
+                LRESULT::default()
+            }
+            WM_KILLFOCUS => {
+                // We won't see the key-up messages, so release everything
+                for key in [Key::Forward, Key::Back, Key::Left, Key::Right, Key::Up, Key::Down, Key::Fast] {
+                    push_input(InputEvent::KeyUp(key));
+                }
+                LRESULT::default()
+            }
+            WM_LBUTTONDOWN => {
+                begin_drag(window, MouseButton::Left, lparam);
+                LRESULT::default()
+            }
+            WM_RBUTTONDOWN => {
+                begin_drag(window, MouseButton::Right, lparam);
+                LRESULT::default()
+            }
+            WM_LBUTTONUP => {
+                end_drag(MouseButton::Left);
+                LRESULT::default()
+            }
+            WM_RBUTTONUP => {
+                end_drag(MouseButton::Right);
+                LRESULT::default()
+            }
+            WM_MOUSEMOVE => {
+                let (x, y) = cursor_pos(lparam);
+                INPUT.with(|input| {
+                    let mut input = input.borrow_mut();
+                    if let Some((button, last_x, last_y)) = input.drag {
+                        input.drag = Some((button, x, y));
+                        input.events.push(InputEvent::MouseDrag { button, dx: (x - last_x) as f32, dy: (y - last_y) as f32 });
+                    }
+                });
+                LRESULT::default()
+            }
+            WM_MOUSEWHEEL => {
+                let delta = ((wparam.0 >> 16) & 0xFFFF) as i16;
+                push_input(InputEvent::Wheel(delta as f32 / WHEEL_DELTA as f32));
+                LRESULT::default()
+            }
+            _ => {
+                DefWindowProcW(window, message, wparam, lparam)
+            }
+        }
+    }
+}
+
+pub unsafe fn create_window(win_title: &str, width: i32, height: i32) -> Result<HWND> {
+    let class_name = w!("DxrTutorialWindowClass");
+
+    let instance = GetModuleHandleW(None).map_err(|e| Error::Window(format!("GetModuleHandle() failed: {}", e)))?;
+
+    // Register the window class
+    let wc = WNDCLASSEXW {
+        cbSize: std::mem::size_of::<WNDCLASSEXW>() as u32,
+        style: CS_HREDRAW | CS_VREDRAW,
+        lpfnWndProc: Some(wndproc),
+        hInstance: instance,
+        lpszClassName: class_name,
+        ..Default::default()
+    };
+
+    if RegisterClassExW(&wc) == 0 {
+        return Err(Error::Window(format!("RegisterClass() failed: {}", windows::core::Error::from_win32())));
+    }
+
+    // Window size we have is for client area, calculate actual window size
+    let mut r = RECT {
+        left: 0,
+        top: 0,
+        right: width,
+        bottom: height,
+    };
+    AdjustWindowRect(&mut r, WS_OVERLAPPEDWINDOW, false);
+
+    let window_width = r.right - r.left;
+    let window_height = r.bottom - r.top;
+
+    // create the window
+    let w_title: HSTRING = win_title.into();
+
+    let hwnd = CreateWindowExW(
+        WINDOW_EX_STYLE::default(),
+        class_name,
+        &w_title,
+        WS_OVERLAPPEDWINDOW,
+        CW_USEDEFAULT,
+        CW_USEDEFAULT,
+        window_width,
+        window_height,
+        None, // no parent window
+        None, // no menus
+        instance,
+        None,
+    );
+
+    if hwnd.0 == 0 {
+        return Err(Error::Window(format!("CreateWindowEx() failed: {}", windows::core::Error::from_win32())));
+    }
+
+    Ok(hwnd)
+
+}
+
+// Returns when the window is closed or a frame fails
+pub unsafe fn msg_loop(hwnd: HWND, tutorial: &mut Tutorial) -> Result<()> {
+    let mut message = MSG::default();
+    loop {
+        if PeekMessageW(&mut message, None, 0, 0, PM_REMOVE).into() {
+            if message.message == WM_QUIT {
+                break;
+            }
+            TranslateMessage(&message);
+            DispatchMessageW(&message);
+        } else {
+            let (resize, minimized) = WINDOW.with(|state| {
+                let mut state = state.borrow_mut();
+                (state.resize.take(), state.minimized)
+            });
+            if let Some((width, height)) = resize {
+                tutorial.on_resize(width, height)?;
+            }
+            // Sleep until the window comes back instead of spinning
+            if minimized {
+                WaitMessage();
+                continue;
+            }
+            for event in INPUT.with(|input| std::mem::take(&mut input.borrow_mut().events)) {
+                tutorial.on_input(event);
+            }
+            tutorial.on_frame_render()?;
+            if let Some(fps) = tutorial.frame_stats() {
+                // The GPU time of every pass next to the frame rate
+                let summary = tutorial.profiler().summary();
+                let title: HSTRING = if summary.is_empty() { format!("fps: {}", fps) } else { format!("fps: {} | {}", fps, summary) }.into();
+                SetWindowTextW(hwnd, &title);
+            }
+        }
+    }
+    Ok(())
+}